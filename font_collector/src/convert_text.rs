@@ -7,7 +7,7 @@ use rustybuzz::{
     },
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PreferredLanguage {
     Japanese,
     UnitedStates,
@@ -105,7 +105,7 @@ pub fn get_font_name(
 ) -> Option<String> {
     let target_record = names
         .into_iter()
-        .filter(|name| name.name_id == name_id.into())
+        .filter(|name| name.name_id == u16::from(name_id))
         .flat_map(|name| {
             score_encoding(&name, preferred_language)
                 .map(|(score, encoding)| (score, encoding, name))
@@ -0,0 +1,367 @@
+//! フォントのファイルを開かずに探せるように、フェイスごとのファミリー名やスタイル、収録している文字を覚えておく。
+//!
+//! インデックスはファイルの更新日時と大きさで管理し、変わったファイルだけを読み直す。
+//! システムのフォントは数が多いので、インデックスはディスクに保存して次の起動で使う。
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use log::{info, warn};
+use rustybuzz::{Face, ttf_parser::fonts_in_collection};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    FontStyle,
+    convert_text::{NameId, PreferredLanguage, get_font_name},
+    family_name,
+};
+
+/// フォントのファイルの中のひとつのフェイスの情報
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FontFaceInfo {
+    pub path: PathBuf,
+    /// フォントコレクションの中のフェイスの番号
+    pub index: u32,
+    pub full_name: String,
+    pub family: String,
+    pub style: FontStyle,
+    // 収録している文字のコードポイントの範囲。両端を含み、昇順に並ぶ
+    coverage: Vec<(u32, u32)>,
+}
+
+impl FontFaceInfo {
+    fn from_face(
+        path: &Path,
+        index: u32,
+        face: &Face,
+        language: Option<PreferredLanguage>,
+    ) -> Option<Self> {
+        let full_name = get_font_name(&face.names(), NameId::FullFontName, language)?;
+        let family = family_name(face, language).unwrap_or_else(|| full_name.clone());
+        Some(Self {
+            path: path.to_path_buf(),
+            index,
+            full_name,
+            family,
+            style: FontStyle::from_face(face),
+            coverage: coverage(face),
+        })
+    }
+
+    /// 文字を収録しているか
+    pub fn covers(&self, c: char) -> bool {
+        let c = c as u32;
+        self.coverage
+            .binary_search_by(|(start, end)| {
+                if *end < c {
+                    std::cmp::Ordering::Less
+                } else if *start > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    /// 言語の文章を表示できるか。言語ごとの代表的な文字をすべて収録していれば表示できるとみなす
+    pub fn supports_language(&self, language: PreferredLanguage) -> bool {
+        language_sample(language).chars().all(|c| self.covers(c))
+    }
+}
+
+// cmap に書かれた文字を、連続する範囲にまとめる
+fn coverage(face: &Face) -> Vec<(u32, u32)> {
+    let mut codepoints = BTreeSet::new();
+    if let Some(cmap) = face.tables().cmap {
+        for subtable in cmap.subtables {
+            if subtable.is_unicode() {
+                subtable.codepoints(|codepoint| {
+                    codepoints.insert(codepoint);
+                });
+            }
+        }
+    }
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for codepoint in codepoints {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == codepoint => *end = codepoint,
+            _ => ranges.push((codepoint, codepoint)),
+        }
+    }
+    ranges
+}
+
+/// 主な用字系の代表的な文字。表示できるフォールバックのフォントを自動で選ぶときに使う
+pub const SCRIPT_SAMPLE_CHARS: &str = "AaÀĀαЯאاกअ가あア漢→∀";
+
+fn language_sample(language: PreferredLanguage) -> &'static str {
+    match language {
+        PreferredLanguage::Japanese => "あアー漢字",
+        PreferredLanguage::UnitedStates => "Aaz",
+    }
+}
+
+/// フォントを探す条件。指定の無い条件は問わない
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FontQuery {
+    /// ファミリー名か、フェイスのフルネーム
+    pub family: Option<String>,
+    pub style: FontStyle,
+    pub language: Option<PreferredLanguage>,
+}
+
+impl FontQuery {
+    pub fn family(family: &str) -> Self {
+        Self {
+            family: Some(family.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_style(self, style: FontStyle) -> Self {
+        Self { style, ..self }
+    }
+
+    pub fn with_language(self, language: PreferredLanguage) -> Self {
+        Self {
+            language: Some(language),
+            ..self
+        }
+    }
+
+    fn matches(&self, face: &FontFaceInfo) -> bool {
+        let family_matches = self.family.as_ref().is_none_or(|family| {
+            face.family.eq_ignore_ascii_case(family) || face.full_name.eq_ignore_ascii_case(family)
+        });
+        let language_matches = self
+            .language
+            .is_none_or(|language| face.supports_language(language));
+        family_matches && language_matches
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct IndexedFile {
+    // 更新日時(UNIX エポックからのナノ秒)と大きさが変わったら読み直す
+    modified: u64,
+    size: u64,
+    faces: Vec<FontFaceInfo>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FontIndex {
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+impl FontIndex {
+    /// 保存したインデックスを読み込む。読み込めない場合は空のインデックスを返す
+    pub fn load(path: &Path) -> Self {
+        let Ok(data) = std::fs::read(path) else {
+            return Self::default();
+        };
+        serde_json::from_slice(&data).unwrap_or_else(|e| {
+            warn!("font index is broken. path:{:?}, error:{}", path, e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec(self)?)
+    }
+
+    /// フォントのファイルの一覧に合わせて、増えたファイルや変わったファイルを読み込み、無くなったファイルを取り除く。
+    /// インデックスが変わった場合は true を返す
+    pub fn update(&mut self, files: &[PathBuf], language: Option<PreferredLanguage>) -> bool {
+        let mut changed = false;
+        let before = self.files.len();
+        self.files.retain(|path, _| files.contains(path));
+        changed |= before != self.files.len();
+
+        for path in files {
+            let Some((modified, size)) = file_stamp(path) else {
+                continue;
+            };
+            if self
+                .files
+                .get(path)
+                .is_some_and(|file| file.modified == modified && file.size == size)
+            {
+                continue;
+            }
+            info!("index font file:{:?}", path);
+            let faces = std::fs::read(path)
+                .map(|data| read_faces(path, &data, language))
+                .unwrap_or_default();
+            self.files.insert(
+                path.clone(),
+                IndexedFile {
+                    modified,
+                    size,
+                    faces,
+                },
+            );
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn faces(&self) -> impl Iterator<Item = &FontFaceInfo> {
+        self.files.values().flat_map(|file| file.faces.iter())
+    }
+
+    /// 条件に合うフェイスのうち、スタイルが最も近いものを返す
+    pub fn find(&self, query: &FontQuery) -> Option<&FontFaceInfo> {
+        self.faces()
+            .filter(|face| query.matches(face))
+            .min_by_key(|face| (face.style.distance(query.style), &face.full_name))
+    }
+
+    /// 同じファミリーでスタイルの違うフェイス
+    pub fn family_faces(&self, family: &str) -> Vec<&FontFaceInfo> {
+        self.faces().filter(|face| face.family == family).collect()
+    }
+
+    /// 文字をなるべく少ないフォントで表示できるように、収録している文字の多い順にフェイスを選ぶ。
+    /// language を指定した場合は、その言語の文章を表示できるフェイスを優先する。
+    /// どのフェイスにも無い文字は無視する
+    pub fn fallback_chain(
+        &self,
+        chars: impl IntoIterator<Item = char>,
+        style: FontStyle,
+        language: Option<PreferredLanguage>,
+    ) -> Vec<&FontFaceInfo> {
+        let mut uncovered: BTreeSet<char> = chars.into_iter().collect();
+        let mut chain: Vec<&FontFaceInfo> = Vec::new();
+        while !uncovered.is_empty() {
+            let best = self
+                .faces()
+                .filter(|face| !chain.iter().any(|selected| selected.family == face.family))
+                .map(|face| {
+                    let count = uncovered.iter().filter(|c| face.covers(**c)).count();
+                    let language_matches =
+                        language.is_some_and(|language| face.supports_language(language));
+                    (face, language_matches, count)
+                })
+                .filter(|(_, _, count)| *count > 0)
+                .max_by(|(l, l_language, l_count), (r, r_language, r_count)| {
+                    l_language
+                        .cmp(r_language)
+                        .then_with(|| l_count.cmp(r_count))
+                        .then_with(|| r.style.distance(style).cmp(&l.style.distance(style)))
+                        .then_with(|| r.full_name.cmp(&l.full_name))
+                });
+            let Some((face, _, _)) = best else {
+                break;
+            };
+            uncovered.retain(|c| !face.covers(*c));
+            chain.push(face);
+        }
+        chain
+    }
+}
+
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos() as u64;
+    Some((modified, metadata.len()))
+}
+
+fn read_faces(path: &Path, data: &[u8], language: Option<PreferredLanguage>) -> Vec<FontFaceInfo> {
+    (0..fonts_in_collection(data).unwrap_or(1))
+        .filter_map(|index| {
+            let face = Face::from_slice(data, index)?;
+            FontFaceInfo::from_face(path, index, &face, language)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMOJI_FONT_PATH: &str = "../fonts/NotoEmoji-Regular.ttf";
+
+    fn index() -> FontIndex {
+        let mut index = FontIndex::default();
+        assert!(index.update(
+            &[PathBuf::from(EMOJI_FONT_PATH)],
+            Some(PreferredLanguage::Japanese)
+        ));
+        index
+    }
+
+    #[test]
+    fn test_update() {
+        let mut index = index();
+        // 変わっていないファイルは読み直さない
+        assert!(!index.update(&[PathBuf::from(EMOJI_FONT_PATH)], None));
+        // 一覧から無くなったファイルは取り除く
+        assert!(index.update(&[], None));
+        assert_eq!(index.faces().count(), 0);
+    }
+
+    #[test]
+    fn test_find() {
+        let index = index();
+        let face = index.faces().next().unwrap();
+        assert!(face.covers('🐖'));
+        assert!(!face.covers('あ'));
+
+        let family = face.family.clone();
+        assert_eq!(
+            index.find(&FontQuery::family(&family.to_lowercase())),
+            Some(face)
+        );
+        assert_eq!(
+            index.find(&FontQuery::family(&family).with_style(FontStyle::BOLD)),
+            Some(face)
+        );
+        assert_eq!(index.find(&FontQuery::family("unknown")), None);
+        assert_eq!(
+            index.find(&FontQuery::default().with_language(PreferredLanguage::Japanese)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_fallback_chain() {
+        let index = index();
+        let chain = index.fallback_chain("🐖あ".chars(), FontStyle::REGULAR, None);
+        assert_eq!(chain.len(), 1);
+        assert!(chain[0].covers('🐖'));
+        assert!(
+            index
+                .fallback_chain(
+                    "あ".chars(),
+                    FontStyle::REGULAR,
+                    Some(PreferredLanguage::Japanese)
+                )
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let index = index();
+        let path =
+            std::env::temp_dir().join(format!("font_index_test_{}.json", std::process::id()));
+        index.save(&path).unwrap();
+        let loaded = FontIndex::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            loaded.faces().collect::<Vec<_>>(),
+            index.faces().collect::<Vec<_>>()
+        );
+    }
+}
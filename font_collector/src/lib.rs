@@ -1,16 +1,100 @@
 pub mod convert_text;
+pub mod font_index;
 
+use std::collections::{HashMap, HashSet};
 use std::env::consts::OS;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use convert_text::PreferredLanguage;
+use convert_text::{NameId, PreferredLanguage, get_font_name};
+use font_index::{FontFaceInfo, FontIndex, FontQuery};
 use log::{info, warn};
+use rustybuzz::{Face, ttf_parser::Width};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
 pub struct FontData {
     pub font_name: String,
     pub binary: Vec<u8>,
     pub index: u32,
+    /// 読み込んだフォントファイル。埋め込みのフォントなどバイナリから作った場合は None
+    pub path: Option<PathBuf>,
+    /// 同じファミリーで太さや幅、斜体の違うフォント。可変フォントの軸で表せないスタイルに使う
+    pub styles: Vec<(FontStyle, FontData)>,
+}
+
+/// フォントの太さ、幅、斜体の指定。
+/// 太さは OS/2 テーブルの usWeightClass と同じ 100 から 900 の値、幅は wdth 軸と同じ百分率で表す
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FontStyle {
+    pub weight: u16,
+    pub width: u16,
+    pub italic: bool,
+}
+
+impl Default for FontStyle {
+    fn default() -> Self {
+        Self::REGULAR
+    }
+}
+
+impl FontStyle {
+    pub const REGULAR: Self = Self {
+        weight: 400,
+        width: 100,
+        italic: false,
+    };
+    pub const BOLD: Self = Self {
+        weight: 700,
+        ..Self::REGULAR
+    };
+    pub const ITALIC: Self = Self {
+        italic: true,
+        ..Self::REGULAR
+    };
+
+    pub fn with_weight(self, weight: u16) -> Self {
+        Self { weight, ..self }
+    }
+
+    pub fn with_width(self, width: u16) -> Self {
+        Self { width, ..self }
+    }
+
+    pub fn with_italic(self, italic: bool) -> Self {
+        Self { italic, ..self }
+    }
+
+    /// スタイルの近さ。斜体かどうかを太さや幅よりも優先する
+    pub fn distance(&self, other: FontStyle) -> u32 {
+        let italic = if self.italic == other.italic { 0 } else { 1000 };
+        italic + self.weight.abs_diff(other.weight) as u32 + self.width.abs_diff(other.width) as u32
+    }
+
+    /// SemiBold 以上を太字とみなす
+    pub fn is_bold(&self) -> bool {
+        self.weight >= 600
+    }
+
+    /// フォントの OS/2 テーブルに書かれたスタイル
+    pub fn from_face(face: &Face) -> Self {
+        let width = match face.width() {
+            Width::UltraCondensed => 50,
+            Width::ExtraCondensed => 62,
+            Width::Condensed => 75,
+            Width::SemiCondensed => 87,
+            Width::Normal => 100,
+            Width::SemiExpanded => 112,
+            Width::Expanded => 125,
+            Width::ExtraExpanded => 150,
+            Width::UltraExpanded => 200,
+        };
+        Self {
+            weight: face.weight().to_number(),
+            width,
+            italic: face.is_italic() || face.is_oblique(),
+        }
+    }
 }
 
 #[derive(Default)]
@@ -19,6 +103,8 @@ pub struct FontRepository {
     primary_font: Option<FontData>,
     ascii_override_font: Option<FontData>,
     fallback_fonts: Vec<FontData>,
+    // どのフォントにも無かった文字
+    unresolved_chars: HashSet<char>,
 }
 
 impl FontRepository {
@@ -28,6 +114,7 @@ impl FontRepository {
             primary_font: None,
             ascii_override_font: None,
             fallback_fonts: Vec::new(),
+            unresolved_chars: HashSet::new(),
         }
     }
 
@@ -82,11 +169,61 @@ impl FontRepository {
     pub fn list_font_names(&self) -> Vec<String> {
         self.font_collector.list_font_names()
     }
+
+    /// 今のフォントで表示できない文字があれば、表示できるフォントをシステムのフォントから探してフォールバックに加える。
+    /// フォントを加えた場合は true を返す
+    pub fn add_fallback_fonts_for_chars(&mut self, chars: impl IntoIterator<Item = char>) -> bool {
+        let chars: Vec<char> = chars
+            .into_iter()
+            .filter(|c| !self.unresolved_chars.contains(c))
+            .collect();
+        if chars.is_empty() {
+            return false;
+        }
+        let fonts = self.get_fonts();
+        let faces: Vec<Face> = self
+            .ascii_override_font
+            .iter()
+            .chain(fonts.iter())
+            .filter_map(|font| Face::from_slice(&font.binary, font.index))
+            .collect();
+        let uncovered: Vec<char> = chars
+            .into_iter()
+            .filter(|c| faces.iter().all(|face| face.glyph_index(*c).is_none()))
+            .collect();
+        if uncovered.is_empty() {
+            return false;
+        }
+        let fallback_fonts = self
+            .font_collector
+            .fallback_fonts(uncovered.iter().copied(), FontStyle::REGULAR);
+        // どのフォントにも無い文字は、表示するたびに探し直さないように覚えておく
+        let added_faces: Vec<Face> = fallback_fonts
+            .iter()
+            .filter_map(|font| Face::from_slice(&font.binary, font.index))
+            .collect();
+        self.unresolved_chars.extend(uncovered.iter().filter(|c| {
+            added_faces
+                .iter()
+                .all(|face| face.glyph_index(**c).is_none())
+        }));
+
+        let added = !fallback_fonts.is_empty();
+        for font in fallback_fonts {
+            info!("add fallback font:{}", font.font_name);
+            self.fallback_fonts.push(font);
+        }
+        added
+    }
 }
 
 pub struct FontCollector {
     font_paths: Vec<PathBuf>,
     preffered_language: Option<PreferredLanguage>,
+    // フォントのファイルを読み直さずに探すためのインデックス
+    index: Mutex<FontIndex>,
+    // インデックスを保存するファイル。None のときはメモリの上だけで持つ
+    index_path: Option<PathBuf>,
 }
 
 impl Default for FontCollector {
@@ -94,15 +231,25 @@ impl Default for FontCollector {
         Self {
             font_paths: Vec::new(),
             preffered_language: Some(PreferredLanguage::Japanese),
+            index: Mutex::new(FontIndex::default()),
+            index_path: None,
         }
     }
 }
 
 impl FontCollector {
     pub fn add_system_fonts(&mut self) {
-        self.font_paths.push(system_font_dir());
-        if let Some(path) = user_font_dir() {
-            self.font_paths.push(path)
+        let font_dirs = system_font_dirs().into_iter().chain(user_font_dirs());
+        for font_dir in font_dirs {
+            if font_dir.exists() && !self.font_paths.contains(&font_dir) {
+                self.font_paths.push(font_dir);
+            }
+        }
+        // システムのフォントは数が多いので、インデックスをディスクに保存して次の起動から使う
+        if self.index_path.is_none()
+            && let Some(path) = default_index_path()
+        {
+            self.set_index_path(path);
         }
     }
 
@@ -110,8 +257,15 @@ impl FontCollector {
         self.font_paths.push(path);
     }
 
+    /// インデックスを保存するファイルを指定し、保存してあるインデックスを読み込む
+    pub fn set_index_path(&mut self, path: PathBuf) {
+        self.index = Mutex::new(FontIndex::load(&path));
+        self.index_path = Some(path);
+    }
+
     fn list_font_files(&self) -> Vec<PathBuf> {
         let mut fonts = Vec::new();
+        let mut visited_dirs = HashSet::new();
 
         for font_path in &self.font_paths {
             if !font_path.exists() {
@@ -122,55 +276,74 @@ impl FontCollector {
                 fonts.push(font_path.clone());
                 continue;
             }
-            for entry in std::fs::read_dir(font_path).unwrap() {
-                let entry = entry.unwrap();
-                let path = entry.path();
-                if path.is_file() {
-                    fonts.push(path);
-                }
-            }
+            collect_font_files(font_path, &mut visited_dirs, &mut fonts);
         }
         fonts
     }
 
-    fn list_fonts(&self) -> Vec<Font> {
-        let font_file_paths = self.list_font_files();
-        let mut fonts = Vec::new();
-        for font_path in font_file_paths {
-            let font_names = font_names(&font_path, self.preffered_language);
-            if font_names.is_empty() {
-                continue;
-            }
-            info!("font_path:{:?}, names:{:?}", font_path, font_names);
-            fonts.push(Font::File(font_path.clone(), font_names));
+    // フォントのファイルの増減を反映したインデックスを使う
+    fn with_index<R>(&self, f: impl FnOnce(&FontIndex) -> R) -> R {
+        let mut index = self.index.lock().expect("font index lock poisoned");
+        if index.update(&self.list_font_files(), self.preffered_language)
+            && let Some(path) = self.index_path.as_ref()
+            && let Err(e) = index.save(path)
+        {
+            warn!("failed to save font index. path:{:?}, error:{}", path, e);
         }
-        fonts
+        f(&index)
     }
 
     pub fn list_font_names(&self) -> Vec<String> {
-        let mut font_names = self
-            .list_fonts()
-            .into_iter()
-            .flat_map(|font| match font {
-                Font::File(_path, names) => names,
-                Font::InMemory(_id, names) => names,
-            })
-            .collect::<Vec<String>>();
+        let mut font_names = self.with_index(|index| {
+            index
+                .faces()
+                .map(|face| face.full_name.clone())
+                .collect::<Vec<String>>()
+        });
         font_names.sort();
+        font_names.dedup();
         font_names
     }
 
+    /// フェイスのフルネームが一致するフォントを読み込む。無ければファミリー名の一致する通常のスタイルのフォントを読み込む
     pub fn load_font(&self, font_name: &str) -> Option<FontData> {
-        self.list_fonts()
+        let (face, style_faces) = self.with_index(|index| {
+            let face = index
+                .faces()
+                .find(|face| face.full_name == font_name)
+                .or_else(|| index.find(&FontQuery::family(font_name)))?;
+            Some((face.clone(), style_faces(index, face)))
+        })?;
+        load_face(&face, &style_faces)
+    }
+
+    /// ファミリー名やスタイル、言語の条件に合うフォントを読み込む
+    pub fn find_font(&self, query: &FontQuery) -> Option<FontData> {
+        let (face, style_faces) = self.with_index(|index| {
+            let face = index.find(query)?;
+            Some((face.clone(), style_faces(index, face)))
+        })?;
+        load_face(&face, &style_faces)
+    }
+
+    /// 文字を表示できるフォントを、収録している文字の多い順になるべく少ない数で選んで読み込む。
+    /// 優先する言語の文章を表示できるフォントを先に選ぶ
+    pub fn fallback_fonts(
+        &self,
+        chars: impl IntoIterator<Item = char>,
+        style: FontStyle,
+    ) -> Vec<FontData> {
+        let faces = self.with_index(|index| {
+            index
+                .fallback_chain(chars, style, self.preffered_language)
+                .into_iter()
+                .map(|face| (face.clone(), style_faces(index, face)))
+                .collect::<Vec<_>>()
+        });
+        faces
             .iter()
-            .map(|f| (f, f.font_index(font_name)))
-            .filter(|(_, idx)| idx.is_some())
-            .map(|(f, idx)| FontData {
-                font_name: String::from(font_name),
-                binary: f.data(),
-                index: idx.unwrap(),
-            })
-            .next()
+            .filter_map(|(face, style_faces)| load_face(face, style_faces))
+            .collect()
     }
 
     pub fn convert_font(&self, data: Vec<u8>, font_name: Option<String>) -> Option<FontData> {
@@ -184,33 +357,132 @@ impl FontCollector {
                 font_name: name,
                 binary: data,
                 index: idx as u32,
+                path: None,
+                styles: Vec::new(),
             })
     }
 }
 
-fn system_font_dir() -> PathBuf {
+// 同じファミリーで太さや幅、斜体の違うフェイス。同じスタイルのフェイスは一つだけにする
+fn style_faces(index: &FontIndex, face: &FontFaceInfo) -> Vec<FontFaceInfo> {
+    let mut faces: Vec<FontFaceInfo> = Vec::new();
+    for style_face in index.family_faces(&face.family) {
+        if style_face.style != face.style && faces.iter().all(|f| f.style != style_face.style) {
+            faces.push(style_face.clone());
+        }
+    }
+    faces
+}
+
+// フォントのファイルはすべてを読まず、インデックスで選んだフェイスのファイルだけを読む。
+// フォントコレクションでは同じファイルにスタイルの違うフェイスが入っているので、ファイルは一度だけ読む
+fn load_face(face: &FontFaceInfo, style_faces: &[FontFaceInfo]) -> Option<FontData> {
+    let mut binaries: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+    let mut font_data = read_face(face, &mut binaries)?;
+    for style_face in style_faces {
+        if let Some(style_font) = read_face(style_face, &mut binaries) {
+            info!(
+                "style font:{}, style:{:?}",
+                style_font.font_name, style_face.style
+            );
+            font_data.styles.push((style_face.style, style_font));
+        }
+    }
+    Some(font_data)
+}
+
+fn read_face(face: &FontFaceInfo, binaries: &mut HashMap<PathBuf, Vec<u8>>) -> Option<FontData> {
+    let binary = match binaries.get(&face.path) {
+        Some(binary) => binary.clone(),
+        None => match std::fs::read(&face.path) {
+            Ok(binary) => {
+                binaries.insert(face.path.clone(), binary.clone());
+                binary
+            }
+            Err(e) => {
+                warn!("failed to read font. path:{:?}, error:{}", face.path, e);
+                return None;
+            }
+        },
+    };
+    Some(FontData {
+        font_name: face.full_name.clone(),
+        binary,
+        index: face.index,
+        path: Some(face.path.clone()),
+        styles: Vec::new(),
+    })
+}
+
+// サブディレクトリもたどってフォントのファイルを集める。シンボリックリンクで同じディレクトリを二度たどらないようにする
+fn collect_font_files(dir: &Path, visited_dirs: &mut HashSet<PathBuf>, fonts: &mut Vec<PathBuf>) {
+    let Ok(canonical) = dir.canonicalize() else {
+        return;
+    };
+    if !visited_dirs.insert(canonical) {
+        return;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        warn!("font_path:{:?} can not read", dir);
+        return;
+    };
+    let mut paths: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            collect_font_files(&path, visited_dirs, fonts);
+        } else if is_font_file(&path) {
+            fonts.push(path);
+        }
+    }
+}
+
+fn is_font_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ["ttf", "otf", "ttc", "otc"]
+                .iter()
+                .any(|font_extension| extension.eq_ignore_ascii_case(font_extension))
+        })
+}
+
+fn system_font_dirs() -> Vec<PathBuf> {
     match OS {
-        "windows" => PathBuf::from("C:\\Windows\\Fonts"),
-        "macos" => PathBuf::from("/System/Library/Fonts"),
-        // FIXME: Linuxの場合はフォントがサブディレクトリに分かれていることがあるのでこのままではよろしくない
-        "linux" => PathBuf::from("/usr/share/fonts"),
-        _ => PathBuf::new(),
+        "windows" => vec![PathBuf::from("C:\\Windows\\Fonts")],
+        "macos" => vec![
+            PathBuf::from("/System/Library/Fonts"),
+            PathBuf::from("/Library/Fonts"),
+        ],
+        "linux" => vec![
+            PathBuf::from("/usr/share/fonts"),
+            PathBuf::from("/usr/local/share/fonts"),
+        ],
+        _ => Vec::new(),
     }
 }
 
-fn user_font_dir() -> Option<PathBuf> {
+fn user_font_dirs() -> Vec<PathBuf> {
+    let mut font_dirs = Vec::new();
     if let Some(font_dir) = dirs::font_dir() {
-        return Some(font_dir);
+        font_dirs.push(font_dir);
     }
-    if let Some(user_dir) = dirs::home_dir() {
-        return Some(user_dir.join("AppData/Local/Microsoft/Windows/Fonts"));
+    if let Some(home_dir) = dirs::home_dir() {
+        match OS {
+            "windows" => font_dirs.push(home_dir.join("AppData/Local/Microsoft/Windows/Fonts")),
+            // XDG_DATA_HOME を変えていても、よく使われる場所は探す
+            "linux" => {
+                font_dirs.push(home_dir.join(".local/share/fonts"));
+                font_dirs.push(home_dir.join(".fonts"));
+            }
+            _ => {}
+        }
     }
-    None
+    font_dirs
 }
 
-fn font_names(font_path: &PathBuf, preferred_language: Option<PreferredLanguage>) -> Vec<String> {
-    let data = std::fs::read(font_path).unwrap();
-    font_names_from_data(data.as_slice(), preferred_language)
+fn default_index_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("kashikishi").join("font_index.json"))
 }
 
 fn font_names_from_data(data: &[u8], preferred_language: Option<PreferredLanguage>) -> Vec<String> {
@@ -223,33 +495,14 @@ fn font_names_from_data(data: &[u8], preferred_language: Option<PreferredLanguag
     font_names
 }
 
-#[derive(Debug, Clone)]
-pub enum Font {
-    File(PathBuf, Vec<String>),
-    InMemory(u16, Vec<String>),
-}
-
-impl Font {
-    fn names(&self) -> Vec<String> {
-        match self {
-            Font::File(_path, names) => names.clone(),
-            Font::InMemory(_id, names) => names.clone(),
-        }
-    }
-
-    fn font_index(&self, font_name: &str) -> Option<u32> {
-        self.names()
-            .iter()
-            .position(|name| name == font_name)
-            .map(|idx| idx as u32)
-    }
-
-    fn data(&self) -> Vec<u8> {
-        match self {
-            Font::File(path, _names) => std::fs::read(path).unwrap(),
-            Font::InMemory(_id, _names) => todo!(),
-        }
-    }
+// 太字などのスタイルの違うフォントで共通のファミリー名
+fn family_name(face: &Face, preferred_language: Option<PreferredLanguage>) -> Option<String> {
+    get_font_name(
+        &face.names(),
+        NameId::TypographicFamilyName,
+        preferred_language,
+    )
+    .or_else(|| get_font_name(&face.names(), NameId::FontFamilyName, preferred_language))
 }
 
 #[cfg(test)]
@@ -280,6 +533,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_load_font_reads_only_family_faces() {
+        let mut collector = FontCollector::default();
+        collector.add_font_path(PathBuf::from("../fonts"));
+        let font = collector
+            .find_font(&FontQuery::family("Noto Emoji"))
+            .expect("Noto Emoji が見つからない");
+        assert!(
+            font.path
+                .as_ref()
+                .is_some_and(|path| path.ends_with("NotoEmoji-Regular.ttf"))
+        );
+        // 別のファミリーのフォントはスタイルとして読み込まない
+        assert!(font.styles.is_empty());
+    }
+
+    #[test]
+    fn test_font_style_from_face() {
+        let data = std::fs::read("../fonts/NotoEmoji-Regular.ttf").unwrap();
+        let face = Face::from_slice(&data, 0).unwrap();
+        assert_eq!(FontStyle::from_face(&face), FontStyle::REGULAR);
+        assert!(FontStyle::BOLD.is_bold());
+        assert_eq!(
+            FontStyle::REGULAR.with_weight(700).with_italic(true),
+            FontStyle {
+                italic: true,
+                ..FontStyle::BOLD
+            }
+        );
+    }
+
     #[test]
     fn test_no_fonts_found() {
         let collector = FontCollector::default();
@@ -1,23 +1,88 @@
-use std::sync::{Arc, LazyLock};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 
-use cached::cached;
 use font_collector::FontData;
 use log::debug;
 use phisical_layouter::CharWidthResolver;
 use rustybuzz::Face;
+use unicode_script::{Script, UnicodeScript};
 use unicode_width::UnicodeWidthChar;
 
+/// East Asian Width が Ambiguous なラテン文字・ギリシャ文字・キリル文字の幅の扱い。
+/// ○ や罫線などの記号はこの指定によらずフォントのグリフの大きさで決める
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AmbiguousWidth {
+    /// ギリシャ文字は全角、é などのラテン文字とキリル文字は半角
+    #[default]
+    Auto,
+    Narrow,
+    Wide,
+}
+
+impl AmbiguousWidth {
+    // ラテン文字・ギリシャ文字・キリル文字でなければ None を返し、フォントで幅を決める
+    fn alphabet_width(self, c: char) -> Option<CharWidth> {
+        let script = c.script();
+        if !matches!(script, Script::Latin | Script::Greek | Script::Cyrillic) {
+            return None;
+        }
+        match (self, script) {
+            (AmbiguousWidth::Wide, _) | (AmbiguousWidth::Auto, Script::Greek) => {
+                Some(CharWidth::Wide)
+            }
+            _ => Some(CharWidth::Regular),
+        }
+    }
+}
+
+/// フォントの組み合わせごとの文字の幅。
+/// 求めた幅はインスタンスごとに覚えておくので、フォントを変えたら作り直す
 pub struct CharWidthCalculator {
     faces: Arc<Vec<FontData>>,
+    ambiguous_width: AmbiguousWidth,
+    // 文字ごとの幅。すべてのフォントを順に調べるので覚えておく
+    widths: Mutex<HashMap<char, CharWidth>>,
 }
 
 impl CharWidthCalculator {
     pub fn new(faces: Arc<Vec<FontData>>) -> Self {
-        Self { faces }
+        Self {
+            faces,
+            ambiguous_width: AmbiguousWidth::default(),
+            widths: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// East Asian Width が Ambiguous の文字の幅の扱いを指定する
+    pub fn with_ambiguous_width(self, ambiguous_width: AmbiguousWidth) -> Self {
+        Self {
+            ambiguous_width,
+            widths: Mutex::new(HashMap::new()),
+            ..self
+        }
+    }
+
+    pub fn ambiguous_width(&self) -> AmbiguousWidth {
+        self.ambiguous_width
     }
 
     pub fn get_width(&self, c: char) -> CharWidth {
-        inner_get_width(&self.faces, c)
+        if let Some(width) = self
+            .widths
+            .lock()
+            .expect("char widths lock poisoned")
+            .get(&c)
+        {
+            return *width;
+        }
+        let width = inner_get_width(&self.faces, self.ambiguous_width, c);
+        self.widths
+            .lock()
+            .expect("char widths lock poisoned")
+            .insert(c, width);
+        width
     }
 
     pub fn len(&self, text: &str) -> usize {
@@ -30,26 +95,23 @@ impl CharWidthCalculator {
     }
 }
 
-static SPECIAL_WIDE_CHARS: LazyLock<Vec<char>> = LazyLock::new(|| {
-    let mut v = Vec::new();
-    v.push('　');
-    // 割と雑だが、ギリシャ文字は全角として扱う
-    ('Α'..='Ω').for_each(|c| v.push(c));
-    ('α'..='ω').for_each(|c| v.push(c));
-    v
-});
-
-#[cached(key = "char", convert = "{ c }")]
-fn inner_get_width(faces: &[FontData], c: char) -> CharWidth {
+// East Asian Width が Ambiguous の文字は、東アジアの文脈かどうかで幅が変わる
+fn is_ambiguous(c: char) -> bool {
+    UnicodeWidthChar::width(c) != UnicodeWidthChar::width_cjk(c)
+}
+
+fn inner_get_width(faces: &[FontData], ambiguous_width: AmbiguousWidth, c: char) -> CharWidth {
     debug!("char:{:?}", c);
-    if SPECIAL_WIDE_CHARS.contains(&c) {
-        debug!("reson:special_wide_chars");
-        return CharWidth::Wide;
-    }
     if c.is_ascii() {
         debug!("reson:ascii");
         return CharWidth::Regular;
     }
+    if is_ambiguous(c)
+        && let Some(width) = ambiguous_width.alphabet_width(c)
+    {
+        debug!("reson:ambiguous");
+        return width;
+    }
     for face in faces
         .iter()
         .flat_map(|f| Face::from_slice(&f.binary, f.index))
@@ -127,7 +189,7 @@ mod test {
 
     use font_collector::FontCollector;
 
-    use super::{CharWidth, CharWidthCalculator};
+    use super::{AmbiguousWidth, CharWidth, CharWidthCalculator, is_ambiguous};
 
     const FONT_DATA: &[u8] = include_bytes!("../../fonts/BIZUDMincho-Regular.ttf");
     const EMOJI_FONT_DATA: &[u8] = include_bytes!("../../fonts/NotoEmoji-Regular.ttf");
@@ -169,12 +231,14 @@ mod test {
             .map(|c| (c, CharWidth::Wide))
             .collect::<Vec<_>>();
         cases.append(&mut zen_alpha_cases);
-        // ギリシャ文字は CharWidth::Wide
+        // ギリシャ文字は East Asian Width が Ambiguous なので CharWidth::Wide
         let mut zen_upper_greek_cases = ('Α'..='Ω')
+            .filter(|c| is_ambiguous(*c))
             .map(|c| (c, CharWidth::Wide))
             .collect::<Vec<_>>();
         cases.append(&mut zen_upper_greek_cases);
         let mut zen_lower_greek_cases = ('α'..='ω')
+            .filter(|c| is_ambiguous(*c))
             .map(|c| (c, CharWidth::Wide))
             .collect::<Vec<_>>();
         cases.append(&mut zen_lower_greek_cases);
@@ -183,4 +247,58 @@ mod test {
             assert_eq!(actual, expected, "char:{}", c);
         }
     }
+
+    #[test]
+    fn get_width_ambiguous_default() {
+        let collector = FontCollector::default();
+        let font_binaries = Arc::new(vec![
+            collector.convert_font(FONT_DATA.to_vec(), None).unwrap(),
+        ]);
+        let auto = CharWidthCalculator::new(font_binaries.clone());
+        let wide =
+            CharWidthCalculator::new(font_binaries).with_ambiguous_width(AmbiguousWidth::Wide);
+        assert_eq!(auto.ambiguous_width(), AmbiguousWidth::Auto);
+
+        // Latin-1 のアクセント付きの文字とキリル文字は Ambiguous なので、既定では CharWidth::Regular
+        for c in ['é', 'ü', 'ß', 'à', 'Д', 'ж', 'Я', 'ё'] {
+            assert!(is_ambiguous(c), "char:{}", c);
+            assert_eq!(auto.get_width(c), CharWidth::Regular, "char:{}", c);
+            assert_eq!(wide.get_width(c), CharWidth::Wide, "char:{}", c);
+        }
+        assert_eq!(auto.len("Crème"), 5);
+        assert_eq!(auto.len("Привет"), 6);
+
+        // 記号はフォントで全角のグリフなら CharWidth::Wide
+        for c in ['○', '※', '→', '×', '÷', '①', '★', '─', '│'] {
+            assert!(is_ambiguous(c), "char:{}", c);
+            assert_eq!(auto.get_width(c), CharWidth::Wide, "char:{}", c);
+        }
+    }
+
+    #[test]
+    fn get_width_ambiguous_narrow() {
+        let collector = FontCollector::default();
+        let font_binaries = Arc::new(vec![
+            collector.convert_font(FONT_DATA.to_vec(), None).unwrap(),
+        ]);
+        let converter =
+            CharWidthCalculator::new(font_binaries).with_ambiguous_width(AmbiguousWidth::Narrow);
+
+        let cases = vec![
+            // Ambiguous のギリシャ文字やラテン文字は CharWidth::Regular
+            ('α', CharWidth::Regular),
+            ('Ω', CharWidth::Regular),
+            ('é', CharWidth::Regular),
+            // 記号はフォントのグリフの大きさで決まる
+            ('○', CharWidth::Wide),
+            // Ambiguous でない文字は変わらない
+            ('a', CharWidth::Regular),
+            ('あ', CharWidth::Wide),
+            ('　', CharWidth::Wide),
+        ];
+        for (c, expected) in cases {
+            let actual = converter.get_width(c);
+            assert_eq!(actual, expected, "char:{}", c);
+        }
+    }
 }
@@ -0,0 +1,36 @@
+//! グリフキャッシュをあらかじめ作っておくための、よく使う文字の集合
+
+/// JIS X 0208 の第一水準漢字
+pub fn jis_level1_kanji() -> impl Iterator<Item = char> {
+    include_str!("../data/jis_level1_kanji.txt")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+}
+
+/// ひらがなとカタカナ
+pub fn kana() -> impl Iterator<Item = char> {
+    ('\u{3041}'..='\u{3096}').chain('\u{30A1}'..='\u{30FA}')
+}
+
+/// ASCII の表示できる文字
+pub fn ascii() -> impl Iterator<Item = char> {
+    '!'..='~'
+}
+
+/// 日本語の文章でよく使う文字
+pub fn japanese_common() -> impl Iterator<Item = char> {
+    ascii().chain(kana()).chain(jis_level1_kanji())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jis_level1_kanji() {
+        let kanji = jis_level1_kanji().collect::<Vec<_>>();
+        assert_eq!(kanji.len(), 2965);
+        assert_eq!(kanji.first(), Some(&'亜'));
+        assert_eq!(kanji.last(), Some(&'腕'));
+    }
+}
@@ -0,0 +1,415 @@
+//! COLR/CPAL や SVG のテーブルで色を付けたグリフを、色ごとの層の頂点に変換する。
+//!
+//! 層は下から順に並べ、描くときも下から順に重ねる。前景色で描く層は色を持たず、文字の色で描く。
+//! 頂点は単色で塗るため、COLR v1 のグラデーションは色の平均で塗り、クリップは一番内側のグリフの形だけを使う。
+use rustybuzz::{
+    Face,
+    ttf_parser::{
+        GlyphId, OutlineBuilder, RgbaColor, Transform,
+        colr::{ClipBox, CompositeMode, Paint, Painter},
+    },
+};
+use ttf_overlap_remover::OverlapRemoveOutlineBuilder;
+use usvg::tiny_skia_path::PathSegment;
+
+use crate::{
+    color_theme::Color,
+    vector_vertex::{VectorVertex, VectorVertexBuilder},
+};
+
+// 前景色の代わりに渡す色。パレットの色と区別できるように、まず使われない透明に近い色にする
+const FOREGROUND_COLOR: RgbaColor = RgbaColor {
+    red: 0x01,
+    green: 0x02,
+    blue: 0x03,
+    alpha: 0x01,
+};
+
+/// 色付きのグリフのひとつの層
+pub(crate) struct ColorLayer {
+    /// 層の色。None の場合は文字の色で描く
+    pub(crate) color: Option<[f32; 3]>,
+    pub(crate) vertex: VectorVertex,
+}
+
+impl ColorLayer {
+    /// 層の色を頂点に持たせた頂点
+    pub(crate) fn into_vertex(self) -> VectorVertex {
+        match self.color {
+            Some(color) => self.vertex.with_layer_color(color),
+            None => self.vertex,
+        }
+    }
+}
+
+/// グリフが COLR か SVG のテーブルで色を付けたグリフであれば、層に分ける。
+/// new_builder はフォントの座標系でグリフの中心に合わせたビルダーを返す
+pub(crate) fn color_layers(
+    face: &Face,
+    glyph_id: GlyphId,
+    remove_overlap: bool,
+    new_builder: &dyn Fn() -> VectorVertexBuilder,
+) -> Option<Vec<ColorLayer>> {
+    if face.is_color_glyph(glyph_id) {
+        let mut painter = LayerPainter::new(face, remove_overlap, new_builder);
+        face.paint_color_glyph(glyph_id, 0, FOREGROUND_COLOR, &mut painter)?;
+        if !painter.layers.is_empty() {
+            return Some(painter.layers);
+        }
+    }
+    let document = face.glyph_svg_image(glyph_id)?;
+    svg_document_layers(document.data, glyph_id, remove_overlap, new_builder)
+}
+
+// COLR の描画命令を受け取り、塗るたびにクリップしているグリフの形を層にする
+struct LayerPainter<'a, 'b> {
+    face: &'b Face<'a>,
+    remove_overlap: bool,
+    new_builder: &'b dyn Fn() -> VectorVertexBuilder,
+    // 次に塗るグリフと、その時点の変換
+    outline: Option<(GlyphId, Transform)>,
+    // クリップしているグリフ。クリップボックスは形を持たないので None とする
+    clips: Vec<Option<(GlyphId, Transform)>>,
+    transforms: Vec<Transform>,
+    layers: Vec<ColorLayer>,
+}
+
+impl<'a, 'b> LayerPainter<'a, 'b> {
+    fn new(
+        face: &'b Face<'a>,
+        remove_overlap: bool,
+        new_builder: &'b dyn Fn() -> VectorVertexBuilder,
+    ) -> Self {
+        Self {
+            face,
+            remove_overlap,
+            new_builder,
+            outline: None,
+            clips: Vec::new(),
+            transforms: Vec::new(),
+            layers: Vec::new(),
+        }
+    }
+
+    fn current_transform(&self) -> Transform {
+        self.transforms.last().copied().unwrap_or_default()
+    }
+
+    fn gradient_color(&self, stops: impl Iterator<Item = RgbaColor>) -> Option<RgbaColor> {
+        let stops: Vec<RgbaColor> = stops.collect();
+        if stops.is_empty() {
+            return None;
+        }
+        let average = |channel: fn(&RgbaColor) -> u8| {
+            (stops.iter().map(|c| channel(c) as u32).sum::<u32>() / stops.len() as u32) as u8
+        };
+        Some(RgbaColor::new(
+            average(|c| c.red),
+            average(|c| c.green),
+            average(|c| c.blue),
+            average(|c| c.alpha),
+        ))
+    }
+}
+
+impl<'a> Painter<'a> for LayerPainter<'a, '_> {
+    fn outline_glyph(&mut self, glyph_id: GlyphId) {
+        self.outline = Some((glyph_id, self.current_transform()));
+    }
+
+    fn paint(&mut self, paint: Paint<'a>) {
+        // 可変フォントの軸は変えないので、既定の座標で色を求める
+        let color = match paint {
+            Paint::Solid(color) => Some(color),
+            Paint::LinearGradient(gradient) => {
+                self.gradient_color(gradient.stops(0, &[]).map(|stop| stop.color))
+            }
+            Paint::RadialGradient(gradient) => {
+                self.gradient_color(gradient.stops(0, &[]).map(|stop| stop.color))
+            }
+            Paint::SweepGradient(gradient) => {
+                self.gradient_color(gradient.stops(0, &[]).map(|stop| stop.color))
+            }
+        };
+        // COLR v0 は塗る直前に、v1 はクリップに積んでからグリフを指定する
+        let shape = self
+            .outline
+            .take()
+            .or_else(|| self.clips.iter().rev().flatten().next().copied());
+        let (Some(color), Some((glyph_id, transform))) = (color, shape) else {
+            return;
+        };
+        if color.alpha == 0 {
+            return;
+        }
+
+        let mut builder = (self.new_builder)();
+        let drawn = if self.remove_overlap {
+            let mut overlap_builder = OverlapRemoveOutlineBuilder::default();
+            let drawn = self
+                .face
+                .outline_glyph(
+                    glyph_id,
+                    &mut TransformOutlineBuilder::new(&mut overlap_builder, transform),
+                )
+                .is_some();
+            overlap_builder.outline(&mut builder);
+            drawn
+        } else {
+            self.face
+                .outline_glyph(
+                    glyph_id,
+                    &mut TransformOutlineBuilder::new(&mut builder, transform),
+                )
+                .is_some()
+        };
+        if drawn {
+            self.layers.push(ColorLayer {
+                color: layer_color(color),
+                vertex: builder.build(),
+            });
+        }
+    }
+
+    fn push_clip(&mut self) {
+        self.clips.push(self.outline.take());
+    }
+
+    fn push_clip_box(&mut self, _clipbox: ClipBox) {
+        self.clips.push(None);
+    }
+
+    fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+
+    // 層の合成方法は扱わず、常に上に重ねる
+    fn push_layer(&mut self, _mode: CompositeMode) {}
+
+    fn pop_layer(&mut self) {}
+
+    fn push_transform(&mut self, transform: Transform) {
+        self.transforms
+            .push(Transform::combine(self.current_transform(), transform));
+    }
+
+    fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+}
+
+// 前景色であれば None、それ以外はインスタンスの色と同じ色空間の色にする
+fn layer_color(color: RgbaColor) -> Option<[f32; 3]> {
+    if color == FOREGROUND_COLOR {
+        return None;
+    }
+    Some(
+        Color::Custom {
+            r: color.red,
+            g: color.green,
+            b: color.blue,
+        }
+        .get_color(),
+    )
+}
+
+// アウトラインの座標を変換してから次のビルダーに渡す
+struct TransformOutlineBuilder<'a> {
+    inner: &'a mut dyn OutlineBuilder,
+    transform: Transform,
+}
+
+impl<'a> TransformOutlineBuilder<'a> {
+    fn new(inner: &'a mut dyn OutlineBuilder, transform: Transform) -> Self {
+        Self { inner, transform }
+    }
+
+    fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let Transform { a, b, c, d, e, f } = self.transform;
+        (a * x + c * y + e, b * x + d * y + f)
+    }
+}
+
+impl OutlineBuilder for TransformOutlineBuilder<'_> {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.apply(x, y);
+        self.inner.move_to(x, y);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.apply(x, y);
+        self.inner.line_to(x, y);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x1, y1) = self.apply(x1, y1);
+        let (x, y) = self.apply(x, y);
+        self.inner.quad_to(x1, y1, x, y);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (x1, y1) = self.apply(x1, y1);
+        let (x2, y2) = self.apply(x2, y2);
+        let (x, y) = self.apply(x, y);
+        self.inner.curve_to(x1, y1, x2, y2, x, y);
+    }
+
+    fn close(&mut self) {
+        self.inner.close();
+    }
+}
+
+/// SVG のテーブルの文書から、id が glyph{グリフ ID} の要素を塗りの色ごとの層に分ける。
+/// SVG のグリフはフォントの単位でベースラインを原点とし、Y 軸が下向きなので上下を反転する
+fn svg_document_layers(
+    data: &[u8],
+    glyph_id: GlyphId,
+    remove_overlap: bool,
+    new_builder: &dyn Fn() -> VectorVertexBuilder,
+) -> Option<Vec<ColorLayer>> {
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default())
+        .inspect_err(|err| log::warn!("svg glyph parse error: {:?}", err))
+        .ok()?;
+    let node = tree.node_by_id(&format!("glyph{}", glyph_id.0))?;
+    let mut paths = Vec::new();
+    collect_filled_paths(node, &mut paths);
+
+    let layers: Vec<ColorLayer> = paths
+        .into_iter()
+        .map(|(path, color)| {
+            let mut builder = new_builder();
+            if remove_overlap {
+                let mut overlap_builder = OverlapRemoveOutlineBuilder::default();
+                outline_svg_path(path, &mut overlap_builder);
+                overlap_builder.outline(&mut builder);
+            } else {
+                outline_svg_path(path, &mut builder);
+            }
+            ColorLayer {
+                color: Some(color),
+                vertex: builder.build(),
+            }
+        })
+        .collect();
+    (!layers.is_empty()).then_some(layers)
+}
+
+fn collect_filled_paths<'a>(node: &'a usvg::Node, paths: &mut Vec<(&'a usvg::Path, [f32; 3])>) {
+    match node {
+        usvg::Node::Group(group) => {
+            for child in group.children() {
+                collect_filled_paths(child, paths);
+            }
+        }
+        usvg::Node::Path(path) if path.is_visible() => {
+            if let Some(color) = path.fill().and_then(|fill| svg_paint_color(fill.paint())) {
+                paths.push((path, color));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn svg_paint_color(paint: &usvg::Paint) -> Option<[f32; 3]> {
+    let colors: Vec<usvg::Color> = match paint {
+        usvg::Paint::Color(color) => vec![*color],
+        usvg::Paint::LinearGradient(gradient) => {
+            gradient.stops().iter().map(|stop| stop.color()).collect()
+        }
+        usvg::Paint::RadialGradient(gradient) => {
+            gradient.stops().iter().map(|stop| stop.color()).collect()
+        }
+        usvg::Paint::Pattern(_) => Vec::new(),
+    };
+    if colors.is_empty() {
+        return None;
+    }
+    let average = |channel: fn(&usvg::Color) -> u8| {
+        (colors.iter().map(|c| channel(c) as u32).sum::<u32>() / colors.len() as u32) as u8
+    };
+    Some(
+        Color::Custom {
+            r: average(|c| c.red),
+            g: average(|c| c.green),
+            b: average(|c| c.blue),
+        }
+        .get_color(),
+    )
+}
+
+fn outline_svg_path(path: &usvg::Path, builder: &mut dyn OutlineBuilder) {
+    let transform = path.abs_transform();
+    let map = |mut point: usvg::tiny_skia_path::Point| {
+        transform.map_point(&mut point);
+        (point.x, -point.y)
+    };
+    for segment in path.data().segments() {
+        match segment {
+            PathSegment::MoveTo(point) => {
+                let (x, y) = map(point);
+                builder.move_to(x, y);
+            }
+            PathSegment::LineTo(point) => {
+                let (x, y) = map(point);
+                builder.line_to(x, y);
+            }
+            PathSegment::QuadTo(point1, point) => {
+                let (x1, y1) = map(point1);
+                let (x, y) = map(point);
+                builder.quad_to(x1, y1, x, y);
+            }
+            PathSegment::CubicTo(point1, point2, point) => {
+                let (x1, y1) = map(point1);
+                let (x2, y2) = map(point2);
+                let (x, y) = map(point);
+                builder.curve_to(x1, y1, x2, y2, x, y);
+            }
+            PathSegment::Close => builder.close(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg_document_layers() {
+        let svg = r##"
+            <svg xmlns="http://www.w3.org/2000/svg">
+                <g id="glyph3">
+                    <path d="M 0 0 L 100 0 L 100 -100 Z" fill="#ff0000" />
+                    <path d="M 10 -10 L 50 -10 L 50 -50 Z" fill="#0000ff" />
+                    <path d="M 0 0 L 1 0 L 1 -1 Z" fill="none" />
+                </g>
+                <g id="glyph4">
+                    <path d="M 0 0 L 100 0 L 100 -100 Z" />
+                </g>
+            </svg>
+        "##;
+        let layers =
+            svg_document_layers(svg.as_bytes(), GlyphId(3), false, &VectorVertexBuilder::new)
+                .unwrap();
+        let colors: Vec<_> = layers.iter().map(|layer| layer.color).collect();
+        assert_eq!(
+            colors,
+            vec![
+                Some(Color::Custom { r: 255, g: 0, b: 0 }.get_color()),
+                Some(Color::Custom { r: 0, g: 0, b: 255 }.get_color()),
+            ]
+        );
+        assert!(
+            svg_document_layers(svg.as_bytes(), GlyphId(5), false, &VectorVertexBuilder::new)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_layer_color() {
+        assert_eq!(layer_color(FOREGROUND_COLOR), None);
+        assert_eq!(
+            layer_color(RgbaColor::new(0, 0, 0, 255)),
+            Some([0.0, 0.0, 0.0])
+        );
+    }
+}
@@ -4,13 +4,14 @@ use font_collector::FontRepository;
 
 use crate::{
     char_width_calcurator::CharWidthCalculator, color_theme::ColorTheme,
-    glyph_vertex_buffer::Direction,
+    glyph_vertex_buffer::Direction, shaping::GlyphShaper,
 };
 
 pub struct StateContext {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub char_width_calcurator: Arc<CharWidthCalculator>,
+    pub glyph_shaper: Arc<GlyphShaper>,
     pub color_theme: ColorTheme,
     pub window_size: WindowSize,
     pub global_direction: Direction,
@@ -25,6 +26,7 @@ impl StateContext {
         device: wgpu::Device,
         queue: wgpu::Queue,
         char_width_calcurator: Arc<CharWidthCalculator>,
+        glyph_shaper: Arc<GlyphShaper>,
         color_theme: ColorTheme,
         window_size: WindowSize,
         global_direction: Direction,
@@ -34,6 +36,7 @@ impl StateContext {
             device,
             queue,
             char_width_calcurator,
+            glyph_shaper,
             color_theme,
             window_size,
             global_direction,
@@ -7,6 +7,8 @@ pub enum FontRasterizerError {
     GlyphNotFound(char),
     #[error("glyph index not found")]
     GlyphIndexNotFound,
+    #[error("font not found. font_index: {0}")]
+    FontNotFound(u16),
     #[error("ensure buffer capacity failed. kind:{0:?}")]
     EnsureBufferCapacityFailed(BufferKind),
     #[error("outline glyph is failed. glyph_id:{0:?}")]
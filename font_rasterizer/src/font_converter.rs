@@ -1,191 +1,94 @@
 use std::sync::Arc;
 
-use font_collector::FontData;
-use log::info;
 use rustybuzz::{
-    Direction, Face, UnicodeBuffer, shape,
-    ttf_parser::{GlyphId, Tag},
+    Face,
+    ttf_parser::{GlyphId, Rect},
 };
 use ttf_overlap_remover::OverlapRemoveOutlineBuilder;
 
 use crate::{
-    char_width_calcurator::CharWidth,
+    color_glyph::{ColorLayer, color_layers},
     debug_mode::DEBUG_FLAGS,
     errors::FontRasterizerError,
+    shaping::{GlyphKey, GlyphShaper, NOTDEF_GLYPH_ID},
     vector_vertex::{CoordinateSystem, VectorVertex, VectorVertexBuilder, VertexBuilderOptions},
 };
 
 pub(crate) struct FontVertexConverter {
-    fonts: Arc<Vec<FontData>>,
-    ascii_override_font: Option<FontData>,
+    shaper: Arc<GlyphShaper>,
     #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
     cache: Option<crate::glyph_cache::GlyphCache>,
 }
 
 impl FontVertexConverter {
-    pub(crate) fn new(fonts: Arc<Vec<FontData>>, ascii_override_font: Option<FontData>) -> Self {
+    pub(crate) fn new(shaper: Arc<GlyphShaper>) -> Self {
         #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
-        let cache = {
-            let fonts = if let Some(ascii_override_font) = &ascii_override_font {
-                let mut v = vec![ascii_override_font.clone()];
-                v.append(&mut (*fonts).clone());
-                Arc::new(v)
-            } else {
-                fonts.clone()
-            };
-            crate::glyph_cache::GlyphCache::open(&fonts)
-        };
+        let cache = crate::glyph_cache::GlyphCache::open(&shaper.fonts());
         Self {
-            fonts,
-            ascii_override_font,
+            shaper,
             #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
             cache,
         }
     }
 
-    fn is_remove_outline_fontname(fontname: &str) -> bool {
-        // Noto 系の文字を全部オーバーラップ除去対象にしてみる
-        // TODO おそらくひたすら遅くなるはずなのでオーバーラップ除去処理結果をキャッシュする実装を追加したい
-        //["Noto Emoji Regular"].contains(&fontname)
-        fontname.contains("Noto")
-    }
-
-    fn font_data_to_face(font_data: &'_ FontData) -> Option<(Face<'_>, bool)> {
-        Face::from_slice(&font_data.binary, font_data.index).map(|face| {
-            // variable font の際に wght を Noto 系の Regular で指定されがちな 400 に指定する
-            // なぜなら、デフォルトだと 100 になってしまっておりやたら細くなってしまうからだ
-            // TODO 固定の指定ではなくて柔軟に、wght 以外のタグに指定できるようにしていく必要がある
-            let mut face = face.clone();
-            if face.is_variable() {
-                for axis in face.variation_axes() {
-                    info!("variation: {}={}", axis.tag, axis.def_value);
-                }
-                for cood in face.variation_coordinates() {
-                    info!("coordinate: {}", cood.get());
-                }
-
-                info!("set weight");
-                let _ = face.set_variation(Tag::from_bytes(b"wght"), 400.0);
-            } else {
-                info!("not variable");
-            }
-
-            (face, Self::is_remove_outline_fontname(&font_data.font_name))
-        })
-    }
-
-    fn faces(&'_ self) -> Vec<(Face<'_>, bool)> {
-        self.fonts
-            .iter()
-            .filter_map(Self::font_data_to_face)
-            .collect::<Vec<_>>()
-    }
-
-    fn ascii_override_face(&'_ self) -> Option<(Face<'_>, bool)> {
-        self.ascii_override_font
-            .as_ref()
-            .and_then(Self::font_data_to_face)
-    }
-
-    fn glyph_ids_for_face(face: &Face, c: char) -> Option<CharGlyphIds> {
-        if let Some(horizontal_glyph_id) = face.glyph_index(c) {
-            let mut buf = UnicodeBuffer::new();
-            buf.set_direction(Direction::TopToBottom);
-            buf.add(c, 0);
-            let vertical_glyph_buffer = shape(face, &[], buf);
-            let vertical_glyph_id = GlyphId(vertical_glyph_buffer.glyph_infos()[0].glyph_id as u16);
-            let vertical_glyph_id = if horizontal_glyph_id == vertical_glyph_id {
-                None
-            } else {
-                Some(vertical_glyph_id)
-            };
-            return Some(CharGlyphIds {
-                horizontal_glyph_id,
-                vertical_glyph_id,
-            });
+    /// グリフキャッシュを新しく作ったか。キャッシュを使わない場合は false を返す
+    pub(crate) fn is_cache_created(&self) -> bool {
+        #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
+        if let Some(cache) = &self.cache {
+            return cache.is_created();
         }
-        None
+        false
     }
 
-    fn get_face_and_glyph_ids(
-        &'_ self,
-        c: char,
-    ) -> Result<(Face<'_>, bool, CharGlyphIds), FontRasterizerError> {
-        if c.is_ascii()
-            && let Some((face, remove_overlap)) = self.ascii_override_face()
-            && let Some(ids) = Self::glyph_ids_for_face(&face, c)
-        {
-            return Ok((face, remove_overlap, ids));
+    /// まだ書き込んでいないグリフをキャッシュに書き込む
+    pub(crate) fn flush_cache(&self) {
+        #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
+        if let Some(cache) = &self.cache {
+            cache.flush();
         }
+    }
 
-        for (face, remove_overlap) in self.faces().into_iter() {
-            if let Some(ids) = Self::glyph_ids_for_face(&face, c) {
-                return Ok((face, remove_overlap, ids));
-            }
-        }
-        Err(FontRasterizerError::GlyphNotFound(c))
+    fn is_remove_outline_fontname(fontname: &str) -> bool {
+        // Noto 系の文字を全部オーバーラップ除去対象にしてみる
+        // TODO おそらくひたすら遅くなるはずなのでオーバーラップ除去処理結果をキャッシュする実装を追加したい
+        //["Noto Emoji Regular"].contains(&fontname)
+        fontname.contains("Noto")
     }
 
-    pub(crate) fn convert(
-        &self,
-        c: char,
-        width: CharWidth,
-    ) -> Result<GlyphVertex, FontRasterizerError> {
+    /// グリフを頂点に変換する。色付きのグリフは色ごとの層に分け、下の層から順に並べる
+    pub(crate) fn convert(&self, key: GlyphKey) -> Result<Vec<VectorVertex>, FontRasterizerError> {
         // キャッシュヒット時はそのまま返す
         #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
         if let Some(cache) = &self.cache
-            && let Some(glyph) = cache.get(c, width)
+            && let Some(layers) = cache.get(key)
         {
-            return Ok(glyph);
+            return Ok(layers);
         }
 
-        let result = self.convert_inner(c, width)?;
+        let result = self.convert_inner(key)?;
 
         // コンバート結果をキャッシュに保存
         #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
         if let Some(cache) = &self.cache {
-            cache.set(&result, width);
+            cache.set(key, &result);
         }
 
         Ok(result)
     }
 
-    fn convert_inner(&self, c: char, width: CharWidth) -> Result<GlyphVertex, FontRasterizerError> {
-        let (
-            face,
-            remove_overlap,
-            CharGlyphIds {
-                horizontal_glyph_id,
-                vertical_glyph_id,
-            },
-        ) = self.get_face_and_glyph_ids(c)?;
-        let h_vertex =
-            GlyphVertexBuilder::new().build(horizontal_glyph_id, width, &face, remove_overlap)?;
-        let v_vertex = vertical_glyph_id.and_then(|vertical_glyph_id| {
-            GlyphVertexBuilder::new()
-                .build(vertical_glyph_id, width, &face, remove_overlap)
-                .ok()
-        });
-        Ok(GlyphVertex {
-            c,
-            h_vertex,
-            v_vertex,
-        })
+    fn convert_inner(&self, key: GlyphKey) -> Result<Vec<VectorVertex>, FontRasterizerError> {
+        let (font, face, _) = self
+            .shaper
+            .styled_face(key.font_index, key.style)
+            .ok_or(FontRasterizerError::FontNotFound(key.font_index))?;
+        GlyphVertexBuilder::new().build(
+            GlyphId(key.glyph_id),
+            &face,
+            Self::is_remove_outline_fontname(&font.font_name),
+        )
     }
 }
 
-struct CharGlyphIds {
-    horizontal_glyph_id: GlyphId,
-    vertical_glyph_id: Option<GlyphId>,
-}
-
-#[derive(Debug)]
-pub(crate) struct GlyphVertex {
-    pub(crate) c: char,
-    pub(crate) h_vertex: VectorVertex,
-    pub(crate) v_vertex: Option<VectorVertex>,
-}
-
 pub struct GlyphVertexBuilder {}
 
 impl GlyphVertexBuilder {
@@ -196,12 +99,9 @@ impl GlyphVertexBuilder {
     pub(crate) fn build(
         self,
         glyph_id: GlyphId,
-        _width: CharWidth,
         face: &Face,
         remove_overlap: bool,
-    ) -> Result<VectorVertex, FontRasterizerError> {
-        let builder = VectorVertexBuilder::new();
-
+    ) -> Result<Vec<VectorVertex>, FontRasterizerError> {
         let rect_em = face.units_per_em() as f32;
         let center_x = face.glyph_hor_advance(glyph_id).unwrap() as f32 / 2.0;
         let center_y = face
@@ -210,14 +110,28 @@ impl GlyphVertexBuilder {
             .unwrap_or((face.units_per_em() as f32 * 0.8) as i16) as f32
             / 2.0;
 
-        let mut builder = builder.with_options(VertexBuilderOptions::new(
-            [center_x, center_y],
-            rect_em,
-            CoordinateSystem::Font,
-            None,
-        ));
+        let new_builder = || {
+            VectorVertexBuilder::new().with_options(VertexBuilderOptions::new(
+                [center_x, center_y],
+                rect_em,
+                CoordinateSystem::Font,
+                None,
+            ))
+        };
+
+        // 色付きのグリフは色ごとの層に分ける
+        if let Some(layers) = color_layers(face, glyph_id, remove_overlap, &new_builder) {
+            return Ok(layers.into_iter().map(ColorLayer::into_vertex).collect());
+        }
+
+        let mut builder = new_builder();
 
-        let rect = if remove_overlap {
+        let rect = if glyph_id == GlyphId(NOTDEF_GLYPH_ID)
+            && face.glyph_bounding_box(glyph_id).is_none()
+        {
+            // .notdef の字形が無いフォントでも、表示できない文字があるとわかるように四角(豆腐)を描く
+            tofu_outline(&mut builder, center_x * 2.0, center_y * 2.0, rect_em)
+        } else if remove_overlap {
             let mut overlap_builder = OverlapRemoveOutlineBuilder::default();
             let rect = face
                 .outline_glyph(glyph_id, &mut overlap_builder)
@@ -255,51 +169,40 @@ impl GlyphVertexBuilder {
             builder.line_to(x - 100.0, y);
         }
 
-        Ok(builder.build())
+        Ok(vec![builder.build()])
+    }
+}
+
+// 進み幅と高さに合わせた四角の枠を描く。内側の四角を逆回りにして枠だけを塗る
+fn tofu_outline(builder: &mut VectorVertexBuilder, advance: f32, height: f32, em: f32) -> Rect {
+    let margin = advance * 0.1;
+    let stroke = em * 0.05;
+    let (x_min, x_max, y_min, y_max) = (margin, advance - margin, 0.0, height);
+    builder.move_to(x_min, y_min);
+    builder.line_to(x_max, y_min);
+    builder.line_to(x_max, y_max);
+    builder.line_to(x_min, y_max);
+    builder.close();
+    builder.move_to(x_min + stroke, y_min + stroke);
+    builder.line_to(x_min + stroke, y_max - stroke);
+    builder.line_to(x_max - stroke, y_max - stroke);
+    builder.line_to(x_max - stroke, y_min + stroke);
+    builder.close();
+    Rect {
+        x_min: x_min as i16,
+        y_min: y_min as i16,
+        x_max: x_max as i16,
+        y_max: y_max as i16,
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
-
-    use font_collector::FontCollector;
     use rustybuzz::ttf_parser::Face;
 
-    use super::FontVertexConverter;
-
     const FONT_DATA: &[u8] = include_bytes!("../../fonts/BIZUDMincho-Regular.ttf");
     const EMOJI_FONT_DATA: &[u8] = include_bytes!("../../fonts/NotoEmoji-Regular.ttf");
 
-    #[test]
-    fn get_char_glyph_ids_test() {
-        let collector = FontCollector::default();
-        let font_binaries = vec![
-            collector.convert_font(FONT_DATA.to_vec(), None).unwrap(),
-            collector
-                .convert_font(EMOJI_FONT_DATA.to_vec(), None)
-                .unwrap(),
-        ];
-        let converter = FontVertexConverter::new(Arc::new(font_binaries), None);
-
-        let cases = vec![
-            // 縦書きでも同じグリフが使われる文字
-            ('a', false),
-            ('あ', false),
-            ('🐖', false),
-            // 縦書きでは別のグリフが使われる文字
-            ('。', true),
-            ('「', true),
-            ('ー', true),
-        ];
-        for (c, expected) in cases {
-            let (_, _, ids) = converter
-                .get_face_and_glyph_ids(c)
-                .expect("get char glyph ids");
-            assert_eq!(ids.vertical_glyph_id.is_some(), expected);
-        }
-    }
-
     #[test]
     fn font_info_test() {
         let faces = [
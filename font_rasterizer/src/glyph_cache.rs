@@ -1,62 +1,101 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, Once,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use font_collector::FontData;
-use redb::{Database, ReadableDatabase, TableDefinition, TypeName, Value};
+use redb::{Database, DatabaseError, ReadableDatabase, StorageError, TableDefinition};
 
 use crate::{
-    char_width_calcurator::CharWidth,
-    font_converter::GlyphVertex,
+    DEFAULT_GLYPH_CACHE_MAX_TOTAL_SIZE,
+    shaping::GlyphKey,
     vector_vertex::{VectorVertex, Vertex},
 };
 
-const GLYPH_TABLE: TableDefinition<&str, GlyphVertex> = TableDefinition::new("glyphs");
+// キャッシュの保存形式の版。頂点の保存形式を変えたら上げる。版の違うキャッシュは作り直す
+const SCHEMA_VERSION: u32 = 1;
 
-impl Value for GlyphVertex {
-    type SelfType<'a>
-        = GlyphVertex
-    where
-        Self: 'a;
+const META_TABLE: TableDefinition<&str, u32> = TableDefinition::new("meta");
+const SCHEMA_VERSION_KEY: &str = "schema_version";
 
-    type AsBytes<'a>
-        = Vec<u8>
-    where
-        Self: 'a;
+// グリフごとに層の頂点をバイト列で保存する。読めないデータはキャッシュに無いものとして扱う
+const GLYPH_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("glyph_layers");
 
-    fn fixed_width() -> Option<usize> {
-        None
-    }
+// 書き込みのトランザクションは重いので、この数のグリフがたまるまでまとめてから書き込む
+const WRITE_BATCH_SIZE: usize = 64;
 
-    fn from_bytes<'a>(data: &'a [u8]) -> Self::SelfType<'a>
-    where
-        Self: 'a,
-    {
-        deserialize_glyph_vertex(data).expect("deserialize glyph vertex from bytes")
-    }
+static MAX_TOTAL_SIZE: AtomicU64 = AtomicU64::new(DEFAULT_GLYPH_CACHE_MAX_TOTAL_SIZE);
+
+// 上限を超えたキャッシュを削除するのは、起動して最初にキャッシュを開くときだけにする
+static PRUNE_ON_STARTUP: Once = Once::new();
+
+/// グリフキャッシュのファイルの合計サイズの上限を設定する。
+/// 起動して最初にキャッシュを開くときに上限を超えていたら、最後に使ってから長いファイルから削除する
+pub fn set_glyph_cache_max_total_size(max_total_size: u64) {
+    MAX_TOTAL_SIZE.store(max_total_size, Ordering::Relaxed);
+}
+
+// 実行間で安定した値を返す FNV-1a ハッシュ
+struct FnvHasher(u64);
+
+impl FnvHasher {
+    const PRIME: u64 = 1099511628211;
+    const OFFSET: u64 = 14695981039346656037;
 
-    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> Self::AsBytes<'a>
-    where
-        Self: 'b,
-    {
-        serialize_glyph_vertex(value)
+    fn new() -> Self {
+        Self(Self::OFFSET)
     }
 
-    fn type_name() -> redb::TypeName {
-        TypeName::new("GlyphVertex")
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
     }
 }
 
-/// フォントバイナリの FNV-1a ハッシュを計算する（実行間で安定した値を返す）
-fn fonts_hash(fonts: &[FontData]) -> u64 {
-    const FNV_PRIME: u64 = 1099511628211;
-    const FNV_OFFSET: u64 = 14695981039346656037;
-    let mut hash = FNV_OFFSET;
+/// フォントの組み合わせのハッシュを計算する。
+/// ファイルから読んだフォントはパスと更新日時と大きさを使い、バイナリ全体は読まない。
+/// 埋め込みのフォントなどファイルの無いフォントはバイナリを使う
+fn fonts_hash(fonts: &[&FontData]) -> u64 {
+    let mut hasher = FnvHasher::new();
+    // 太字などのスタイルのフォントもグリフの字形に関わるので含める
+    let fonts = fonts
+        .iter()
+        .flat_map(|font| std::iter::once(*font).chain(font.styles.iter().map(|(_, style)| style)));
     for font in fonts {
-        for &byte in &font.binary {
-            hash ^= byte as u64;
-            hash = hash.wrapping_mul(FNV_PRIME);
+        hasher.write(&font.index.to_le_bytes());
+        match font.path.as_deref().and_then(font_file_identity) {
+            Some((path, modified, size)) => {
+                hasher.write(path.as_bytes());
+                hasher.write(&modified.to_le_bytes());
+                hasher.write(&size.to_le_bytes());
+            }
+            None => hasher.write(&font.binary),
         }
     }
-    hash
+    hasher.0
+}
+
+// フォントファイルのパスと更新日時(UNIX エポックからのナノ秒)と大きさ
+fn font_file_identity(path: &Path) -> Option<(String, u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos() as u64;
+    Some((
+        path.to_string_lossy().into_owned(),
+        modified,
+        metadata.len(),
+    ))
 }
 
 fn cache_dir() -> PathBuf {
@@ -65,14 +104,23 @@ fn cache_dir() -> PathBuf {
         .join("kashikishi")
 }
 
-fn cache_db_path(fonts: &[FontData]) -> PathBuf {
+fn cache_db_path(fonts: &[&FontData]) -> PathBuf {
     let hash = fonts_hash(fonts);
     cache_dir().join(format!("glyph_cache_{hash:016x}.redb"))
 }
 
-/// グリフキャッシュ（`glyph_cache_*.redb`）をすべて削除する。
-/// フォントの組み合わせごとに別ファイルが存在するため、全ファイルを対象とする。
-pub fn clear_glyph_cache() {
+/// グリフキャッシュのファイル
+#[derive(Debug, Clone)]
+pub struct GlyphCacheFile {
+    pub path: PathBuf,
+    /// ファイルの大きさ(バイト)
+    pub size: u64,
+    /// 最後にキャッシュを開いた日時
+    pub last_used: SystemTime,
+}
+
+/// グリフキャッシュ（`glyph_cache_*.redb`）の一覧を、最後に使った日時の新しい順に返す。
+pub fn list_glyph_caches() -> Vec<GlyphCacheFile> {
     let dir = cache_dir();
     let entries = match std::fs::read_dir(&dir) {
         Ok(entries) => entries,
@@ -81,41 +129,134 @@ pub fn clear_glyph_cache() {
                 "グリフキャッシュディレクトリが存在しません: {}",
                 dir.display()
             );
-            return;
+            return Vec::new();
         }
         Err(e) => {
             log::warn!("グリフキャッシュディレクトリの読み込みに失敗: {e}");
-            return;
+            return Vec::new();
         }
     };
 
+    let mut caches: Vec<GlyphCacheFile> = entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with("glyph_cache_") && name.ends_with(".redb"))
+        })
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some(GlyphCacheFile {
+                path: entry.path(),
+                size: metadata.len(),
+                last_used: metadata.modified().unwrap_or(UNIX_EPOCH),
+            })
+        })
+        .collect();
+    caches.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+    caches
+}
+
+/// グリフキャッシュ（`glyph_cache_*.redb`）をすべて削除する。
+/// フォントの組み合わせごとに別ファイルが存在するため、全ファイルを対象とする。
+pub fn clear_glyph_cache() {
+    let removed = remove_caches(&list_glyph_caches());
+    log::info!("グリフキャッシュを {removed} 件削除しました");
+}
+
+/// グリフキャッシュの合計サイズが max_total_size 以下になるまで、最後に使ってから長いファイルから削除する。
+/// 削除したファイルを返す
+pub fn prune_glyph_cache(max_total_size: u64) -> Vec<GlyphCacheFile> {
+    prune_glyph_cache_except(max_total_size, None)
+}
+
+fn prune_glyph_cache_except(max_total_size: u64, keep: Option<&Path>) -> Vec<GlyphCacheFile> {
+    let evicted = caches_to_evict(list_glyph_caches(), max_total_size, keep);
+    let removed = remove_caches(&evicted);
+    if removed > 0 {
+        log::info!("グリフキャッシュを上限に合わせて {removed} 件削除しました");
+    }
+    evicted
+}
+
+// caches は最後に使った日時の新しい順に並べておく。keep のファイルは上限を超えていても残す
+fn caches_to_evict(
+    caches: Vec<GlyphCacheFile>,
+    max_total_size: u64,
+    keep: Option<&Path>,
+) -> Vec<GlyphCacheFile> {
+    let mut total_size: u64 = caches.iter().map(|cache| cache.size).sum();
+    let mut evicted = Vec::new();
+    for cache in caches.into_iter().rev() {
+        if total_size <= max_total_size {
+            break;
+        }
+        if keep == Some(cache.path.as_path()) {
+            continue;
+        }
+        total_size -= cache.size;
+        evicted.push(cache);
+    }
+    evicted
+}
+
+// 他のプロセスが開いているキャッシュか
+fn is_in_use(path: &Path) -> bool {
+    matches!(
+        Database::open(path),
+        Err(DatabaseError::DatabaseAlreadyOpen)
+    )
+}
+
+fn remove_caches(caches: &[GlyphCacheFile]) -> usize {
     let mut removed = 0;
-    for entry in entries.flatten() {
-        let path = entry.path();
-        let is_cache_file = path
-            .file_name()
-            .and_then(|name| name.to_str())
-            .is_some_and(|name| name.starts_with("glyph_cache_") && name.ends_with(".redb"));
-        if is_cache_file {
-            match std::fs::remove_file(&path) {
-                Ok(()) => {
-                    log::info!("グリフキャッシュを削除しました: {}", path.display());
-                    removed += 1;
-                }
-                Err(e) => log::warn!("グリフキャッシュの削除に失敗 ({}): {e}", path.display()),
+    for cache in caches {
+        // 使っているプロセスのキャッシュが壊れないよう、開いているファイルは消さずに残す
+        if is_in_use(&cache.path) {
+            log::info!(
+                "グリフキャッシュは使われているので削除しません: {}",
+                cache.path.display()
+            );
+            continue;
+        }
+        match std::fs::remove_file(&cache.path) {
+            Ok(()) => {
+                log::info!("グリフキャッシュを削除しました: {}", cache.path.display());
+                removed += 1;
             }
+            Err(e) => log::warn!(
+                "グリフキャッシュの削除に失敗 ({}): {e}",
+                cache.path.display()
+            ),
         }
     }
-    log::info!("グリフキャッシュを {removed} 件削除しました");
+    removed
+}
+
+// ファイルの更新日時を最後に使った日時として LRU に使う
+fn touch(path: &Path) {
+    let result = std::fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    if let Err(e) = result {
+        log::warn!("グリフキャッシュの更新日時の変更に失敗: {e}");
+    }
 }
 
 pub(crate) struct GlyphCache {
     db: Database,
+    // 既にあるキャッシュを使わず、新しく作ったか
+    created: bool,
+    // まだ書き込んでいないグリフの頂点データ
+    pending_writes: Mutex<HashMap<String, Vec<u8>>>,
 }
 
 impl GlyphCache {
     /// キャッシュを開く。失敗した場合は None を返しログに警告を出す。
-    pub(crate) fn open(fonts: &[FontData]) -> Option<Self> {
+    /// 保存形式の版が違う場合は作り直し、起動して最初に開くときは合計サイズが上限を超えていたら古いキャッシュを削除する。
+    pub(crate) fn open(fonts: &[&FontData]) -> Option<Self> {
         let path = cache_db_path(fonts);
         if let Some(parent) = path.parent()
             && let Err(e) = std::fs::create_dir_all(parent)
@@ -123,64 +264,179 @@ impl GlyphCache {
             log::warn!("グリフキャッシュディレクトリの作成に失敗: {e}");
             return None;
         }
-        match Database::create(&path) {
-            Ok(db) => {
-                log::info!("グリフキャッシュを開きました: {}", path.display());
-                Some(Self { db })
-            }
+        if path.exists() {
+            touch(&path);
+        }
+        PRUNE_ON_STARTUP.call_once(|| {
+            prune_glyph_cache_except(MAX_TOTAL_SIZE.load(Ordering::Relaxed), Some(&path));
+        });
+
+        let (db, created) = match Self::open_db(&path) {
+            Ok(opened) => opened,
             Err(e) => {
                 log::warn!("グリフキャッシュのオープンに失敗: {e}");
-                None
+                return None;
             }
+        };
+        log::info!("グリフキャッシュを開きました: {}", path.display());
+        Some(Self::new(db, created))
+    }
+
+    fn new(db: Database, created: bool) -> Self {
+        Self {
+            db,
+            created,
+            pending_writes: Mutex::new(HashMap::new()),
         }
     }
 
-    fn make_key(c: char, width: CharWidth) -> String {
-        format!(
-            "{}:{}",
-            c as u32,
-            match width {
-                CharWidth::Regular => "R",
-                CharWidth::Wide => "W",
+    /// 新しく作ったキャッシュか
+    pub(crate) fn is_created(&self) -> bool {
+        self.created
+    }
+
+    // キャッシュのデータベースと、新しく作ったかを返す。
+    // 作り直すのは保存形式の版が違う場合と壊れている場合だけで、
+    // 他のプロセスが開いている場合などはファイルを残したままエラーを返す
+    fn open_db(path: &Path) -> Result<(Database, bool), Box<dyn std::error::Error + Send + Sync>> {
+        if path.exists() {
+            match Database::create(path) {
+                Ok(db) if Self::schema_version(&db) == Some(SCHEMA_VERSION) => {
+                    return Ok((db, false));
+                }
+                Ok(db) => {
+                    drop(db);
+                    log::info!(
+                        "グリフキャッシュの版が違うので作り直します: {}",
+                        path.display()
+                    );
+                }
+                Err(
+                    e @ (DatabaseError::Storage(StorageError::Corrupted(_))
+                    | DatabaseError::UpgradeRequired(_)),
+                ) => log::warn!("グリフキャッシュが読めないので作り直します: {e}"),
+                Err(e) => return Err(e.into()),
             }
-        )
+            std::fs::remove_file(path)?;
+        }
+        let db = Database::create(path)?;
+        let write_txn = db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(META_TABLE)?;
+            table.insert(SCHEMA_VERSION_KEY, SCHEMA_VERSION)?;
+        }
+        write_txn.commit()?;
+        Ok((db, true))
     }
 
-    /// キャッシュからグリフ頂点データを取得する。存在しない場合は None を返す。
-    pub(crate) fn get(&self, c: char, width: CharWidth) -> Option<GlyphVertex> {
-        let read_txn = self.db.begin_read().ok()?;
-        let table = read_txn.open_table(GLYPH_TABLE).ok()?;
+    fn schema_version(db: &Database) -> Option<u32> {
+        let read_txn = db.begin_read().ok()?;
+        let table = read_txn.open_table(META_TABLE).ok()?;
         table
-            .get(Self::make_key(c, width).as_str())
+            .get(SCHEMA_VERSION_KEY)
             .ok()
             .flatten()
             .map(|entry| entry.value())
     }
 
-    /// グリフ頂点データをキャッシュに保存する。失敗した場合はログに警告を出す。
-    pub(crate) fn set(&self, glyph: &GlyphVertex, width: CharWidth) {
-        if let Err(e) = self.set_inner(Self::make_key(glyph.c, width).as_str(), glyph) {
+    fn make_key(key: GlyphKey) -> String {
+        let GlyphKey {
+            font_index,
+            glyph_id,
+            style,
+        } = key;
+        format!(
+            "{font_index}:{glyph_id}:{}:{}:{}",
+            style.weight, style.width, style.italic
+        )
+    }
+
+    /// キャッシュからグリフの層ごとの頂点データを取得する。存在しない場合や読めない場合は None を返す。
+    pub(crate) fn get(&self, key: GlyphKey) -> Option<Vec<VectorVertex>> {
+        let key_str = Self::make_key(key);
+        if let Some(data) = self.pending_writes.lock().unwrap().get(&key_str) {
+            return deserialize_glyph_layers(data, &mut 0);
+        }
+        let read_txn = self.db.begin_read().ok()?;
+        let table = read_txn.open_table(GLYPH_TABLE).ok()?;
+        let entry = table.get(key_str.as_str()).ok().flatten()?;
+        let layers = deserialize_glyph_layers(entry.value(), &mut 0);
+        if layers.is_none() {
+            log::warn!("グリフキャッシュのデータが読めません: {key:?}");
+        }
+        layers
+    }
+
+    /// グリフの層ごとの頂点データをキャッシュに保存する。
+    /// WRITE_BATCH_SIZE 個たまるまではメモリに置いておき、まとめて書き込む
+    pub(crate) fn set(&self, key: GlyphKey, layers: &[VectorVertex]) {
+        let mut buf = Vec::new();
+        serialize_glyph_layers(layers, &mut buf);
+        let mut pending_writes = self.pending_writes.lock().unwrap();
+        pending_writes.insert(Self::make_key(key), buf);
+        if pending_writes.len() >= WRITE_BATCH_SIZE {
+            self.write_pending(&mut pending_writes);
+        }
+    }
+
+    /// まだ書き込んでいないグリフをキャッシュに書き込む
+    pub(crate) fn flush(&self) {
+        self.write_pending(&mut self.pending_writes.lock().unwrap());
+    }
+
+    // 失敗した場合はログに警告を出し、書き込めなかったグリフは捨てる
+    fn write_pending(&self, pending_writes: &mut HashMap<String, Vec<u8>>) {
+        if pending_writes.is_empty() {
+            return;
+        }
+        if let Err(e) = self.write_all(pending_writes) {
             log::warn!("グリフキャッシュへの書き込みに失敗: {e}");
         }
+        pending_writes.clear();
     }
 
-    fn set_inner(
+    fn write_all(
         &self,
-        key: &str,
-        glyph: &GlyphVertex,
+        entries: &HashMap<String, Vec<u8>>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let write_txn = self.db.begin_write()?;
         {
             let mut table = write_txn.open_table(GLYPH_TABLE)?;
-            table.insert(key, glyph)?;
+            for (key, buf) in entries {
+                table.insert(key.as_str(), buf.as_slice())?;
+            }
         }
         write_txn.commit()?;
         Ok(())
     }
 }
 
+impl Drop for GlyphCache {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
 // ---- シリアライズ / デシリアライズ ----
 
+fn serialize_glyph_layers(layers: &[VectorVertex], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(layers.len() as u32).to_le_bytes());
+    for layer in layers {
+        serialize_vector_vertex(layer, buf);
+    }
+}
+
+fn deserialize_glyph_layers(data: &[u8], pos: &mut usize) -> Option<Vec<VectorVertex>> {
+    let layer_len = u32::from_le_bytes(data.get(*pos..*pos + 4)?.try_into().ok()?) as usize;
+    *pos += 4;
+    // 壊れたデータで大きな領域を確保しないように、容量はデータの大きさまでにする
+    let mut layers = Vec::with_capacity(layer_len.min(data.len()));
+    for _ in 0..layer_len {
+        layers.push(deserialize_vector_vertex(data, pos)?);
+    }
+    Some(layers)
+}
+
 fn serialize_vector_vertex(v: &VectorVertex, buf: &mut Vec<u8>) {
     // Vertex は bytemuck::Pod なのでそのままバイト列に変換できる
     let vertex_bytes: &[u8] = bytemuck::cast_slice(&v.vertex);
@@ -196,7 +452,7 @@ fn deserialize_vector_vertex(data: &[u8], pos: &mut usize) -> Option<VectorVerte
     let vertex_len = u32::from_le_bytes(data.get(*pos..*pos + 4)?.try_into().ok()?) as usize;
     *pos += 4;
     // bytemuck::cast_slice はアライメントを要求するため、フィールドを個別に読み出す
-    let mut vertex = Vec::with_capacity(vertex_len);
+    let mut vertex = Vec::with_capacity(vertex_len.min(data.len()));
     for _ in 0..vertex_len {
         let x = f32::from_le_bytes(data.get(*pos..*pos + 4)?.try_into().ok()?);
         *pos += 4;
@@ -212,7 +468,7 @@ fn deserialize_vector_vertex(data: &[u8], pos: &mut usize) -> Option<VectorVerte
 
     let index_len = u32::from_le_bytes(data.get(*pos..*pos + 4)?.try_into().ok()?) as usize;
     *pos += 4;
-    let mut index = Vec::with_capacity(index_len);
+    let mut index = Vec::with_capacity(index_len.min(data.len()));
     for _ in 0..index_len {
         index.push(u32::from_le_bytes(
             data.get(*pos..*pos + 4)?.try_into().ok()?,
@@ -222,34 +478,161 @@ fn deserialize_vector_vertex(data: &[u8], pos: &mut usize) -> Option<VectorVerte
     Some(VectorVertex { vertex, index })
 }
 
-fn serialize_glyph_vertex(g: &GlyphVertex) -> Vec<u8> {
-    let mut buf = Vec::new();
-    buf.extend_from_slice(&(g.c as u32).to_le_bytes());
-    serialize_vector_vertex(&g.h_vertex, &mut buf);
-    match &g.v_vertex {
-        Some(v) => {
-            buf.push(1);
-            serialize_vector_vertex(v, &mut buf);
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use redb::ReadableTableMetadata;
+
+    use super::*;
+
+    fn cache_file(name: &str, size: u64, last_used_secs: u64) -> GlyphCacheFile {
+        GlyphCacheFile {
+            path: PathBuf::from(name),
+            size,
+            last_used: UNIX_EPOCH + Duration::from_secs(last_used_secs),
         }
-        None => buf.push(0),
     }
-    buf
-}
 
-fn deserialize_glyph_vertex(data: &[u8]) -> Option<GlyphVertex> {
-    let mut pos = 0;
-    let c = char::from_u32(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))?;
-    pos += 4;
-    let h_vertex = deserialize_vector_vertex(data, &mut pos)?;
-    let v_vertex = if *data.get(pos)? == 1 {
-        pos += 1;
-        Some(deserialize_vector_vertex(data, &mut pos)?)
-    } else {
-        None
-    };
-    Some(GlyphVertex {
-        c,
-        h_vertex,
-        v_vertex,
-    })
+    #[test]
+    fn test_caches_to_evict() {
+        let caches = vec![
+            cache_file("new", 100, 3),
+            cache_file("middle", 100, 2),
+            cache_file("old", 100, 1),
+        ];
+        let names = |evicted: Vec<GlyphCacheFile>| {
+            evicted
+                .into_iter()
+                .map(|cache| cache.path.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert!(caches_to_evict(caches.clone(), 300, None).is_empty());
+        assert_eq!(
+            names(caches_to_evict(caches.clone(), 150, None)),
+            ["old", "middle"]
+        );
+        assert_eq!(
+            names(caches_to_evict(caches.clone(), 150, Some(Path::new("old")))),
+            ["middle", "new"]
+        );
+        assert_eq!(
+            names(caches_to_evict(caches, 0, Some(Path::new("new")))),
+            ["old", "middle"]
+        );
+    }
+
+    #[test]
+    fn test_open_db_keeps_locked_cache() {
+        let path =
+            std::env::temp_dir().join(format!("glyph_cache_lock_test_{}.redb", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (db, created) = GlyphCache::open_db(&path).unwrap();
+        assert!(created);
+
+        // 開いている間は開けないが、ファイルは消さない
+        assert!(GlyphCache::open_db(&path).is_err());
+        assert!(path.exists());
+
+        drop(db);
+        let (db, created) = GlyphCache::open_db(&path).unwrap();
+        assert!(!created);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_remove_caches_keeps_opened_cache() {
+        let path = std::env::temp_dir().join(format!(
+            "glyph_cache_remove_test_{}.redb",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let (db, _) = GlyphCache::open_db(&path).unwrap();
+        let caches = [GlyphCacheFile {
+            path: path.clone(),
+            size: 0,
+            last_used: UNIX_EPOCH,
+        }];
+
+        // 開いている間は消さない
+        assert_eq!(remove_caches(&caches), 0);
+        assert!(path.exists());
+
+        drop(db);
+        assert_eq!(remove_caches(&caches), 1);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_set_writes_in_batches() {
+        let path = std::env::temp_dir().join(format!(
+            "glyph_cache_batch_test_{}.redb",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let (db, _) = GlyphCache::open_db(&path).unwrap();
+        let cache = GlyphCache::new(db, true);
+        let key = |glyph_id| GlyphKey {
+            font_index: 0,
+            glyph_id,
+            style: font_collector::FontStyle::REGULAR,
+        };
+        let stored = |cache: &GlyphCache| {
+            let read_txn = cache.db.begin_read().unwrap();
+            read_txn
+                .open_table(GLYPH_TABLE)
+                .map(|table| table.len().unwrap())
+                .unwrap_or_default()
+        };
+        let layers = vec![VectorVertex {
+            vertex: Vec::new(),
+            index: vec![0, 1, 2],
+        }];
+
+        // 書き込む前でも読める
+        cache.set(key(0), &layers);
+        assert_eq!(cache.get(key(0)).unwrap()[0].index, vec![0, 1, 2]);
+        assert_eq!(stored(&cache), 0);
+
+        for glyph_id in 1..WRITE_BATCH_SIZE as u16 {
+            cache.set(key(glyph_id), &layers);
+        }
+        assert_eq!(stored(&cache), WRITE_BATCH_SIZE as u64);
+
+        cache.set(key(WRITE_BATCH_SIZE as u16), &layers);
+        cache.flush();
+        assert_eq!(stored(&cache), WRITE_BATCH_SIZE as u64 + 1);
+        assert!(cache.get(key(WRITE_BATCH_SIZE as u16)).is_some());
+
+        drop(cache);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_glyph_layers_roundtrip() {
+        let layers = vec![
+            VectorVertex {
+                vertex: vec![Vertex {
+                    position: [1.0, 2.0],
+                    vertex_type: 3,
+                }],
+                index: vec![0, 0, 0],
+            },
+            VectorVertex {
+                vertex: Vec::new(),
+                index: Vec::new(),
+            },
+        ];
+        let mut buf = Vec::new();
+        serialize_glyph_layers(&layers, &mut buf);
+        let restored = deserialize_glyph_layers(&buf, &mut 0).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored[0].vertex[0].position, [1.0, 2.0]);
+        assert_eq!(restored[0].vertex[0].vertex_type, 3);
+        assert_eq!(restored[0].index, vec![0, 0, 0]);
+
+        // 途中で切れたデータは読めない
+        assert!(deserialize_glyph_layers(&buf[..buf.len() - 1], &mut 0).is_none());
+    }
 }
@@ -1,11 +1,14 @@
 use crate::{
     glyph_vertex_buffer::Direction,
+    shaping::GlyphKey,
     vector_instances::{InstanceAttributes, InstanceKey, VectorInstances},
 };
 
 pub struct GlyphInstances {
     pub c: char,
     pub direction: Direction,
+    /// シェーピングで決まったグリフ。None の場合は c を単独で表示するときのグリフを描く
+    pub glyph: Option<GlyphKey>,
     instances: VectorInstances<char>,
 }
 
@@ -14,10 +17,19 @@ impl GlyphInstances {
         Self {
             c,
             direction: Direction::Horizontal,
+            glyph: None,
             instances: VectorInstances::new(c, device),
         }
     }
 
+    /// シェーピングで決まったグリフを描くインスタンス。c はグリフが表す文字の先頭の文字
+    pub fn new_glyph(c: char, glyph: GlyphKey, device: &wgpu::Device) -> Self {
+        Self {
+            glyph: Some(glyph),
+            ..Self::new(c, device)
+        }
+    }
+
     pub fn set_direction(&mut self, direction: &Direction) {
         self.direction = *direction;
     }
@@ -1,17 +1,32 @@
-use std::{collections::HashSet, fmt::Debug, sync::Arc};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+};
 
-use font_collector::FontData;
+use font_collector::FontStyle;
 use log::debug;
 use phisical_layouter::CharWidthResolver;
 
 use crate::{
     char_width_calcurator::{CharWidth, CharWidthCalculator},
     errors::FontRasterizerError,
-    font_converter::{FontVertexConverter, GlyphVertex},
+    font_converter::FontVertexConverter,
+    shaping::{GlyphKey, GlyphShaper, NOTDEF_GLYPH_ID},
+    vector_vertex::VectorVertex,
     vector_vertex_buffer::{DrawInfo, VectorVertexBuffer},
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+// 別のスレッドでの変換が終わるまで代わりに描くグリフ
+const PLACEHOLDER_GLYPH: GlyphKey = GlyphKey {
+    font_index: 0,
+    glyph_id: NOTDEF_GLYPH_ID,
+    style: FontStyle::REGULAR,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
 pub enum Direction {
     #[default]
     Horizontal,
@@ -27,27 +42,177 @@ impl Direction {
     }
 }
 
+// 文字を単独で表示するときのグリフ
+#[derive(Debug, Clone, Copy)]
+struct CharGlyphs {
+    horizontal: GlyphKey,
+    // 縦書きで別の字形を使う場合のグリフ
+    vertical: Option<GlyphKey>,
+}
+
+// 別のスレッドで変換したグリフ。変換できなかった場合は None
+#[cfg(not(target_arch = "wasm32"))]
+type ConvertedGlyph = (GlyphKey, Option<Vec<VectorVertex>>);
+
+// 別のスレッドでの変換を待っているグリフと、変換の終わったグリフを受け取るチャネル
+#[cfg(not(target_arch = "wasm32"))]
+struct BackgroundConversion {
+    pending: HashSet<GlyphKey>,
+    sender: Sender<ConvertedGlyph>,
+    receiver: Receiver<ConvertedGlyph>,
+}
+
 pub struct GlyphVertexBuffer {
-    font_vertex_converter: FontVertexConverter,
+    font_vertex_converter: Arc<FontVertexConverter>,
+    glyph_shaper: Arc<GlyphShaper>,
     char_width_calculator: Arc<CharWidthCalculator>,
 
-    registerd_chars: HashSet<char>,
-    vector_vertex_buffer: VectorVertexBuffer<(char, Direction)>,
+    char_glyphs: HashMap<char, CharGlyphs>,
+    // グリフの頂点。色付きのグリフは一番下の層の頂点
+    vector_vertex_buffer: VectorVertexBuffer<GlyphKey>,
+    // 色付きのグリフの 2 層目からの頂点。層は 1 から数える
+    color_layer_buffer: VectorVertexBuffer<(GlyphKey, usize)>,
+    // 色付きのグリフの 2 層目からの層の数
+    color_layer_counts: HashMap<GlyphKey, usize>,
+    #[cfg(not(target_arch = "wasm32"))]
+    background_conversion: Option<BackgroundConversion>,
 }
 
 impl GlyphVertexBuffer {
     pub fn new(
-        font_binaries: Arc<Vec<FontData>>,
-        ascii_override_font: Option<FontData>,
+        glyph_shaper: Arc<GlyphShaper>,
         char_width_calculator: Arc<CharWidthCalculator>,
     ) -> GlyphVertexBuffer {
-        let font_vertex_converter = FontVertexConverter::new(font_binaries, ascii_override_font);
+        let font_vertex_converter = Arc::new(FontVertexConverter::new(glyph_shaper.clone()));
         GlyphVertexBuffer {
             font_vertex_converter,
+            glyph_shaper,
             char_width_calculator,
-            registerd_chars: HashSet::new(),
+            char_glyphs: HashMap::new(),
             vector_vertex_buffer: VectorVertexBuffer::new(),
+            color_layer_buffer: VectorVertexBuffer::new(),
+            color_layer_counts: HashMap::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            background_conversion: None,
+        }
+    }
+
+    /// グリフの変換を別のスレッドで行うようにする。変換が終わるまでは代わりのグリフを描く。
+    /// 変換の終わったグリフは receive_converted_glyphs でバッファに登録する
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_background_conversion(mut self) -> Self {
+        let (sender, receiver) = channel();
+        self.background_conversion = Some(BackgroundConversion {
+            pending: HashSet::new(),
+            sender,
+            receiver,
+        });
+        self
+    }
+
+    /// グリフの変換を別のスレッドで行うか
+    pub fn is_background_conversion(&self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.background_conversion.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// 別のスレッドでの変換を待っているグリフがあるか
+    pub fn has_pending_glyphs(&self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(background) = &self.background_conversion {
+            return !background.pending.is_empty();
+        }
+        false
+    }
+
+    fn is_pending(&self, key: &GlyphKey) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(background) = &self.background_conversion {
+            return background.pending.contains(key);
         }
+        let _ = key;
+        false
+    }
+
+    /// 別のスレッドで変換の終わったグリフをバッファに登録する。登録したグリフがあれば true を返す
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn receive_converted_glyphs(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<bool, FontRasterizerError> {
+        let Some(background) = &mut self.background_conversion else {
+            return Ok(false);
+        };
+        let converted = background.receiver.try_iter().collect::<Vec<_>>();
+        for (key, _) in &converted {
+            background.pending.remove(key);
+        }
+        let received = !converted.is_empty();
+        for (key, layers) in converted {
+            if let Some(layers) = layers {
+                self.register_layers(device, queue, key, layers)?;
+            }
+        }
+        // 変換を待っているグリフが無くなったら、まとめてキャッシュに書き込む
+        if received && !self.has_pending_glyphs() {
+            let converter = self.font_vertex_converter.clone();
+            rayon::spawn(move || converter.flush_cache());
+        }
+        Ok(received)
+    }
+
+    /// 文字の通常のグリフを別のスレッドで変換し、グリフキャッシュに保存しておく。
+    /// 初めて起動したときに、よく使う文字をあらかじめ変換するために使う。
+    /// 表示する文字の変換を待たせないよう、rayon のグローバルなスレッドプールとは別の少ないスレッドで行う。
+    /// 既にあるグリフキャッシュを開いた場合や、グリフキャッシュを使わない場合は何もしない
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn warm_up_cache(&self, chars: Vec<char>) {
+        use rayon::prelude::*;
+
+        if !self.font_vertex_converter.is_cache_created() {
+            return;
+        }
+        let num_threads = std::thread::available_parallelism()
+            .map(|parallelism| parallelism.get() / 4)
+            .unwrap_or_default()
+            .max(1);
+        let pool = match rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|index| format!("glyph-warm-up-{index}"))
+            .build()
+        {
+            Ok(pool) => pool,
+            Err(e) => {
+                log::warn!("グリフキャッシュの先読みのスレッドを作れません: {e}");
+                return;
+            }
+        };
+        let converter = self.font_vertex_converter.clone();
+        let glyph_shaper = self.glyph_shaper.clone();
+        // pool を手放しても、spawn した処理が終わるまでスレッドは残る
+        pool.spawn(move || {
+            chars.par_iter().for_each(|c| {
+                for direction in [Direction::Horizontal, Direction::Vertical] {
+                    if let Some(key) = glyph_shaper.nominal_glyph(*c, direction) {
+                        let _ = converter.convert(key);
+                    }
+                }
+            });
+            converter.flush_cache();
+            debug!("warm up glyph cache. chars:{}", chars.len());
+        });
+    }
+
+    // 変換の終わっていないグリフは代わりのグリフで描く
+    fn vertex_draw_info(&'_ self, key: &GlyphKey) -> Result<DrawInfo<'_>, FontRasterizerError> {
+        if self.is_pending(key) {
+            return self.vector_vertex_buffer.draw_info(&PLACEHOLDER_GLYPH);
+        }
+        self.vector_vertex_buffer.draw_info(key)
     }
 
     pub(crate) fn draw_info(
@@ -55,19 +220,99 @@ impl GlyphVertexBuffer {
         c: &char,
         direction: &Direction,
     ) -> Result<DrawInfo<'_>, FontRasterizerError> {
+        let glyphs = self
+            .char_glyphs
+            .get(c)
+            .ok_or(FontRasterizerError::GlyphNotFound(*c))?;
         if direction == &Direction::Vertical
-            && let Ok(info) = self
-                .vector_vertex_buffer
-                .draw_info(&(*c, Direction::Vertical))
+            && let Some(vertical) = glyphs.vertical
+            && let Ok(info) = self.vector_vertex_buffer.draw_info(&vertical)
         {
             return Ok(info);
         }
-        self.vector_vertex_buffer
-            .draw_info(&(*c, Direction::Horizontal))
+        self.vertex_draw_info(&glyphs.horizontal)
+    }
+
+    // 描くグリフ。縦書きで別の字形を使う場合はそのグリフ
+    fn resolve_glyph(
+        &self,
+        c: &char,
+        glyph: Option<&GlyphKey>,
+        direction: &Direction,
+    ) -> Option<GlyphKey> {
+        if let Some(glyph) = glyph {
+            return Some(*glyph);
+        }
+        let glyphs = self.char_glyphs.get(c)?;
+        if direction == &Direction::Vertical
+            && let Some(vertical) = glyphs.vertical
+            && self.vector_vertex_buffer.has_key(&vertical)
+        {
+            return Some(vertical);
+        }
+        Some(glyphs.horizontal)
+    }
+
+    /// 色付きのグリフの 2 層目からの層の数。色付きでないグリフは 0 を返す
+    pub(crate) fn color_layer_count(
+        &self,
+        c: &char,
+        glyph: Option<&GlyphKey>,
+        direction: &Direction,
+    ) -> usize {
+        self.resolve_glyph(c, glyph, direction)
+            .and_then(|key| self.color_layer_counts.get(&key))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// 色付きのグリフの層を描画するための情報。layer は 1 から数える
+    pub(crate) fn color_layer_draw_info(
+        &'_ self,
+        c: &char,
+        glyph: Option<&GlyphKey>,
+        direction: &Direction,
+        layer: usize,
+    ) -> Result<DrawInfo<'_>, FontRasterizerError> {
+        let key = self
+            .resolve_glyph(c, glyph, direction)
+            .ok_or(FontRasterizerError::GlyphNotFound(*c))?;
+        self.color_layer_buffer.draw_info(&(key, layer))
+    }
+
+    /// シェーピングで求めたグリフを描画するための情報
+    pub(crate) fn glyph_draw_info(
+        &'_ self,
+        key: &GlyphKey,
+    ) -> Result<DrawInfo<'_>, FontRasterizerError> {
+        self.vertex_draw_info(key)
     }
 
     pub fn registerd_chars(&self) -> HashSet<char> {
-        self.registerd_chars.clone()
+        self.char_glyphs.keys().copied().collect()
+    }
+
+    /// フォントを末尾に加えたシェーパと文字の幅に差し替える。
+    /// 登録済みのグリフはフォントの番号が変わらないのでそのまま使い、どのフォントにも無かった文字だけ登録し直す
+    pub fn append_fonts(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        glyph_shaper: Arc<GlyphShaper>,
+        char_width_calculator: Arc<CharWidthCalculator>,
+    ) -> Result<(), FontRasterizerError> {
+        let missing_chars = self
+            .char_glyphs
+            .keys()
+            .copied()
+            .filter(|c| !self.glyph_shaper.has_glyph(*c))
+            .collect::<HashSet<_>>();
+        self.char_glyphs.retain(|c, _| !missing_chars.contains(c));
+        // グリフキャッシュはフォントの組み合わせごとなので、新しいシェーパで開き直す
+        self.font_vertex_converter = Arc::new(FontVertexConverter::new(glyph_shaper.clone()));
+        self.glyph_shaper = glyph_shaper;
+        self.char_width_calculator = char_width_calculator;
+        self.append_chars(device, queue, missing_chars)
     }
 
     pub fn append_chars(
@@ -79,42 +324,110 @@ impl GlyphVertexBuffer {
         // 既にバッファに登録済みの char は除外する。
         let chars = chars
             .into_iter()
-            .filter(|c| !self.registerd_chars.contains(c))
+            .filter(|c| !self.char_glyphs.contains_key(c))
             .collect::<HashSet<_>>();
         if chars.is_empty() {
             return Ok(());
         }
 
-        debug!("registerd_chars:{:?}", self.registerd_chars);
         debug!("chars:{:?}", chars);
 
-        // char を全て Glyph 情報に変換する
-        let mut glyphs = chars
-            .iter()
-            .flat_map(|c| {
-                let width = self.char_width_calculator.get_width(*c);
-                self.font_vertex_converter.convert(*c, width)
-            })
+        // char を横書きと縦書きのグリフに変換する
+        let mut keys = HashSet::new();
+        for c in chars {
+            let Some(horizontal) = self.glyph_shaper.nominal_glyph(c, Direction::Horizontal) else {
+                continue;
+            };
+            let vertical = self
+                .glyph_shaper
+                .nominal_glyph(c, Direction::Vertical)
+                .filter(|vertical| *vertical != horizontal);
+            keys.insert(horizontal);
+            keys.extend(vertical);
+            self.char_glyphs.insert(
+                c,
+                CharGlyphs {
+                    horizontal,
+                    vertical,
+                },
+            );
+        }
+        self.append_glyphs(device, queue, keys)
+    }
+
+    /// シェーピングで求めたグリフをバッファに登録する
+    pub fn append_glyphs(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        keys: HashSet<GlyphKey>,
+    ) -> Result<(), FontRasterizerError> {
+        // 既にバッファに登録済みのグリフや、別のスレッドで変換中のグリフは除外する。
+        let keys = keys
+            .into_iter()
+            .filter(|key| !self.vector_vertex_buffer.has_key(key) && !self.is_pending(key))
             .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Ok(());
+        }
 
-        while let Some(GlyphVertex {
-            c,
-            h_vertex,
-            v_vertex,
-        }) = glyphs.pop()
-        {
-            self.vector_vertex_buffer.append(
-                device,
-                queue,
-                (c, Direction::Horizontal),
-                h_vertex,
-            )?;
-            v_vertex.and_then(|v_vertex| {
-                self.vector_vertex_buffer
-                    .append(device, queue, (c, Direction::Vertical), v_vertex)
-                    .ok()
-            });
-            self.registerd_chars.insert(c);
+        debug!("glyphs:{:?}", keys);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.background_conversion.is_some() {
+            // 代わりに描くグリフは先に登録しておく
+            if !self.vector_vertex_buffer.has_key(&PLACEHOLDER_GLYPH)
+                && let Ok(layers) = self.font_vertex_converter.convert(PLACEHOLDER_GLYPH)
+            {
+                self.register_layers(device, queue, PLACEHOLDER_GLYPH, layers)?;
+            }
+            if let Some(background) = &mut self.background_conversion {
+                for key in keys {
+                    if key == PLACEHOLDER_GLYPH {
+                        continue;
+                    }
+                    background.pending.insert(key);
+                    let converter = self.font_vertex_converter.clone();
+                    let sender = background.sender.clone();
+                    rayon::spawn(move || {
+                        let _ = sender.send((key, converter.convert(key).ok()));
+                    });
+                }
+            }
+            return Ok(());
+        }
+
+        for key in keys {
+            let Ok(layers) = self.font_vertex_converter.convert(key) else {
+                continue;
+            };
+            self.register_layers(device, queue, key, layers)?;
+        }
+        Ok(())
+    }
+
+    // 層ごとの頂点をバッファに登録する。2 層目からは色付きのグリフの層として登録する
+    pub(crate) fn register_layers(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        key: GlyphKey,
+        layers: Vec<VectorVertex>,
+    ) -> Result<(), FontRasterizerError> {
+        let mut layers = layers.into_iter();
+        let Some(vertex) = layers.next() else {
+            return Ok(());
+        };
+        self.vector_vertex_buffer
+            .append(device, queue, key, vertex)?;
+        let mut layer_count = 0;
+        for (index, layer) in layers.enumerate() {
+            self.color_layer_buffer
+                .append(device, queue, (key, index + 1), layer)?;
+            layer_count = index + 1;
+        }
+        if layer_count > 0 {
+            self.color_layer_counts.insert(key, layer_count);
         }
         Ok(())
     }
@@ -0,0 +1,40 @@
+use image::DynamicImage;
+
+use crate::{screen_texture::BackgroundImageTexture, vector_instances::VectorInstances};
+
+/// テクスチャを貼った四角形として描く画像と、その画像のインスタンス。
+///
+/// 四角形は一辺の長さが 1 なので、インスタンスの instance_scale で画像の幅と高さを指定する。
+pub struct ImageInstances {
+    pub(crate) texture: BackgroundImageTexture,
+    instances: VectorInstances<String>,
+}
+
+impl ImageInstances {
+    pub fn new(
+        key: String,
+        image: &DynamicImage,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let texture = BackgroundImageTexture::new_with_min_filter(
+            device,
+            queue,
+            image,
+            Some(&format!("Image Texture. key:{}", key)),
+            wgpu::FilterMode::Linear,
+        );
+        Self {
+            texture,
+            instances: VectorInstances::new(key, device),
+        }
+    }
+
+    pub fn instances(&self) -> &VectorInstances<String> {
+        &self.instances
+    }
+
+    pub fn instances_mut(&mut self) -> &mut VectorInstances<String> {
+        &mut self.instances
+    }
+}
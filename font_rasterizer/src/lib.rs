@@ -1,6 +1,8 @@
 mod background_bind_group;
 pub mod builtin_shader_art;
 pub mod char_width_calcurator;
+pub mod charset;
+mod color_glyph;
 pub mod color_theme;
 pub mod context;
 mod debug_mode;
@@ -9,9 +11,16 @@ pub mod font_converter;
 #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
 mod glyph_cache;
 #[cfg(all(feature = "cache", not(target_arch = "wasm32")))]
-pub use glyph_cache::clear_glyph_cache;
+pub use glyph_cache::{
+    GlyphCacheFile, clear_glyph_cache, list_glyph_caches, prune_glyph_cache,
+    set_glyph_cache_max_total_size,
+};
+/// グリフキャッシュのファイルの合計サイズの既定の上限。
+/// コマンドラインの引数の既定値にも使うので、キャッシュを使わない場合も定義しておく
+pub const DEFAULT_GLYPH_CACHE_MAX_TOTAL_SIZE: u64 = 512 * 1024 * 1024;
 pub mod glyph_instances;
 pub mod glyph_vertex_buffer;
+pub mod image_instances;
 pub mod motion;
 mod outline_bind_group;
 mod overlap_bind_group;
@@ -21,6 +30,7 @@ mod screen_bind_group;
 mod screen_texture;
 mod screen_vertex_buffer;
 pub mod shader_art_bind_group;
+pub mod shaping;
 pub mod svg;
 pub mod time;
 pub mod vector_instances;
@@ -5,6 +5,7 @@ use crate::{
     background_bind_group::BackgroundImageBindGroup,
     glyph_instances::GlyphInstances,
     glyph_vertex_buffer::GlyphVertexBuffer,
+    image_instances::ImageInstances,
     rasterizer_renderrer::{OutlineFillRule, RasterizerRenderrer},
     screen_bind_group::ScreenBindGroup,
     screen_texture::{BackgroundImageTexture, ScreenTexture},
@@ -44,6 +45,7 @@ pub struct Buffers<'a> {
         &'a VectorVertexBuffer<String>,
         &'a [&'a VectorInstances<String>],
     )>,
+    pub image_buffers: Option<&'a [&'a ImageInstances]>,
 }
 
 /// フォントをラスタライズするためのパイプラインを提供する。
@@ -55,6 +57,7 @@ pub struct Buffers<'a> {
 ///
 /// 1 つめはフォントを構成するポリゴンを重ねていく処理
 /// 2 つめはポリゴンの重ねた結果からフォントの輪郭を抽出する処理
+///   抽出した輪郭の上に、画像をテクスチャを貼った四角形として重ねる
 /// 3 つめは輪郭を抽出したテクスチャをスクリーンに描画する処理
 ///   2 が 3 よりも解像度が高ければオーバーサンプリングでクオリティが高くなり
 ///   その逆であればドット絵の品質になるよう調整
@@ -357,18 +360,20 @@ impl RasterizerPipeline {
         modal_buffers: Buffers,
         screen_view: wgpu::TextureView,
     ) {
-        let has_modal_background =
-            modal_buffers.glyph_buffers.is_some() || modal_buffers.vector_buffers.is_some();
+        let has_modal_background = modal_buffers.glyph_buffers.is_some()
+            || modal_buffers.vector_buffers.is_some()
+            || modal_buffers.image_buffers.is_some();
 
         self.rasterizer_renderrer.prepare(device, queue, view_proj);
         self.rasterizer_renderrer
-            .render(encoder, buffers, &self.outline_texture.view);
+            .render(encoder, device, buffers, &self.outline_texture.view);
 
         if has_modal_background {
             self.rasterizer_renderrer_for_modal
                 .prepare(device, queue, view_proj);
             self.rasterizer_renderrer_for_modal.render(
                 encoder,
+                device,
                 modal_buffers,
                 &self.outline_texture_for_modal.view,
             );
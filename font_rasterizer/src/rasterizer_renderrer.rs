@@ -3,9 +3,11 @@ use std::{collections::BTreeMap, fs};
 use wgpu::include_wgsl;
 
 use crate::{
+    background_bind_group::BackgroundImageBindGroup,
     debug_mode::DEBUG_FLAGS,
     glyph_instances::GlyphInstances,
     glyph_vertex_buffer::GlyphVertexBuffer,
+    image_instances::ImageInstances,
     outline_bind_group::OutlineBindGroup,
     overlap_bind_group::OverlapBindGroup,
     rasterizer_pipeline::Buffers,
@@ -20,6 +22,8 @@ const OVERLAP_SHADER_DESCRIPTOR: wgpu::ShaderModuleDescriptor =
     include_wgsl!("shader/overlap_shader.wgsl");
 const OUTLINE_SHADER_DESCRIPTOR: wgpu::ShaderModuleDescriptor =
     include_wgsl!("shader/outline_shader.wgsl");
+const IMAGE_SHADER_DESCRIPTOR: wgpu::ShaderModuleDescriptor =
+    include_wgsl!("shader/image_shader.wgsl");
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OutlineFillRule {
@@ -41,7 +45,12 @@ pub struct RasterizerRenderrer {
 
     pub(crate) outline_bind_group: OutlineBindGroup,
     pub(crate) outline_render_pipeline: wgpu::RenderPipeline,
+    pub(crate) outline_layer_render_pipeline: wgpu::RenderPipeline,
     pub(crate) outline_vertex_buffer: ScreenVertexBuffer,
+
+    // 3 ステージ目(画像)。輪郭を抽出した後にテクスチャを貼った四角形を重ねる
+    pub(crate) image_bind_group: BackgroundImageBindGroup,
+    pub(crate) image_render_pipeline: wgpu::RenderPipeline,
 }
 impl RasterizerRenderrer {
     /// Create all unchanging resources here.
@@ -182,9 +191,9 @@ impl RasterizerRenderrer {
             OutlineFillRule::NonZero => "fs_main_non_zero",
         };
 
-        let outline_render_pipeline =
+        let create_outline_render_pipeline = |label: &str, blend: wgpu::BlendState| {
             device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Outline Render Pipeline"),
+                label: Some(label),
                 layout: Some(&outline_render_pipeline_layout),
                 vertex: wgpu::VertexState {
                     module: &outline_shader,
@@ -197,7 +206,7 @@ impl RasterizerRenderrer {
                     entry_point: Some(outline_fragment_entry),
                     targets: &[Some(wgpu::ColorTargetState {
                         format: target_texture_format,
-                        blend: Some(wgpu::BlendState::REPLACE),
+                        blend: Some(blend),
                         write_mask: wgpu::ColorWrites::ALL,
                     })],
                     compilation_options: Default::default(),
@@ -224,9 +233,72 @@ impl RasterizerRenderrer {
                 // render pipeline cache。起動時間の短縮に有利そうな気配だけどまぁ難しそうなので一旦無しで。
                 cache: None,
                 multiview_mask: None,
-            });
+            })
+        };
+        let outline_render_pipeline =
+            create_outline_render_pipeline("Outline Render Pipeline", wgpu::BlendState::REPLACE);
+        // 色付きのグリフの 2 層目からは、それまでに描いた層の上に重ねる
+        let outline_layer_render_pipeline = create_outline_render_pipeline(
+            "Outline Layer Render Pipeline",
+            wgpu::BlendState::ALPHA_BLENDING,
+        );
         let outline_vertex_buffer = ScreenVertexBuffer::new_buffer(device);
 
+        // image
+        let image_shader = device.create_shader_module(IMAGE_SHADER_DESCRIPTOR);
+        let image_bind_group = BackgroundImageBindGroup::new(device);
+        let image_render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Image Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    Some(&overlap_bind_group.layout),
+                    Some(&image_bind_group.layout),
+                ],
+                immediate_size: 0,
+            });
+        let image_render_pipeline =
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Image Render Pipeline"),
+                layout: Some(&image_render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &image_shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[Some(ScreenVertexBuffer::desc()), Some(InstanceRaw::desc())],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &image_shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: target_texture_format,
+                        // 輪郭のテクスチャと同じくアルファを掛けない色のまま書き込む
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None, // 回転して裏返っても描く
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    // Requires Features::DEPTH_CLIP_CONTROL
+                    unclipped_depth: false,
+                    // Requires Features::CONSERVATIVE_RASTERIZATION
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                // render pipeline cache。起動時間の短縮に有利そうな気配だけどまぁ難しそうなので一旦無しで。
+                cache: None,
+                multiview_mask: None,
+            });
+
         Self {
             enable_antialiasing,
             overlap_bind_group,
@@ -235,7 +307,10 @@ impl RasterizerRenderrer {
             overlap_count_texture,
             outline_bind_group,
             outline_render_pipeline,
+            outline_layer_render_pipeline,
             outline_vertex_buffer,
+            image_bind_group,
+            image_render_pipeline,
         }
     }
 
@@ -260,11 +335,20 @@ impl RasterizerRenderrer {
     pub fn render(
         &self,
         encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
         buffers: Buffers,
         target_view: &wgpu::TextureView,
     ) {
-        self.overlap_stage(encoder, buffers.glyph_buffers, buffers.vector_buffers);
-        self.outline_stage(encoder, target_view);
+        self.overlap_stage(encoder, buffers.glyph_buffers, buffers.vector_buffers, 0);
+        self.outline_stage(encoder, target_view, 0);
+        // 色付きのグリフの層は重なった部分の重なり回数が打ち消し合わないよう、層ごとに輪郭を抽出して下の層から重ねる
+        for layer in 1..=color_layer_count(buffers.glyph_buffers) {
+            self.overlap_stage(encoder, buffers.glyph_buffers, None, layer);
+            self.outline_stage(encoder, target_view, layer);
+        }
+        if let Some(image_buffers) = buffers.image_buffers {
+            self.image_stage(encoder, device, image_buffers, target_view);
+        }
     }
 
     #[inline]
@@ -273,6 +357,7 @@ impl RasterizerRenderrer {
         encoder: &mut wgpu::CommandEncoder,
         glyph_buffers: Option<(&GlyphVertexBuffer, &[&GlyphInstances])>,
         vector_buffers: Option<(&VectorVertexBuffer<String>, &[&VectorInstances<String>])>,
+        layer: usize,
     ) {
         let overlap_bind_group = &self.overlap_bind_group.bind_group;
         let mut overlay_render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -329,7 +414,7 @@ impl RasterizerRenderrer {
             let mut instance_buffers = BTreeMap::new();
             for instance in glyph_instance_buffers {
                 let instances = instance_buffers
-                    .entry((instance.c, instance.direction))
+                    .entry((instance.c, instance.glyph, instance.direction))
                     .or_insert_with(Vec::new);
                 instances.push((instance.len(), instance.to_wgpu_buffer()));
             }
@@ -340,36 +425,62 @@ impl RasterizerRenderrer {
             // 微妙に意味があるかもしれないのでいったん残す。
             let mut vertex_buffer_id = None;
             let mut index_buffer_id = None;
-            for ((c, direction), instances) in instance_buffers.iter() {
+            for ((c, glyph, direction), instances) in instance_buffers.iter() {
+                // 0 層目は通常のグリフか色付きのグリフの一番下の層で、色付きのグリフの 1 層目からは層ごとに描く
+                let draw_info = if layer == 0 {
+                    match glyph {
+                        Some(glyph) => glyph_vertex_buffer.glyph_draw_info(glyph),
+                        None => glyph_vertex_buffer.draw_info(c, direction),
+                    }
+                } else if layer
+                    <= glyph_vertex_buffer.color_layer_count(c, glyph.as_ref(), direction)
+                {
+                    glyph_vertex_buffer.color_layer_draw_info(c, glyph.as_ref(), direction, layer)
+                } else {
+                    continue;
+                };
+                let Ok(draw_info) = draw_info.as_ref() else {
+                    continue;
+                };
                 for (len, buffer) in instances {
-                    if let Ok(draw_info) = glyph_vertex_buffer.draw_info(c, direction) {
-                        // グリフの座標情報(vertex)
-                        if vertex_buffer_id != Some(draw_info.vertex) {
-                            overlay_render_pass.set_vertex_buffer(0, draw_info.vertex.slice(..));
-                            vertex_buffer_id = Some(draw_info.vertex);
-                        }
-                        // グリフの座標情報(index)
-                        if index_buffer_id != Some(draw_info.index) {
-                            overlay_render_pass.set_index_buffer(
-                                draw_info.index.slice(..),
-                                wgpu::IndexFormat::Uint32,
-                            );
-                            index_buffer_id = Some(draw_info.index);
-                        }
-                        // インスタンスの位置
-                        overlay_render_pass.set_vertex_buffer(1, buffer.slice(..));
-                        overlay_render_pass.draw_indexed(
-                            draw_info.index_range.clone(),
-                            0,
-                            0..*len as _,
-                        );
+                    // グリフの座標情報(vertex)
+                    if vertex_buffer_id != Some(draw_info.vertex) {
+                        overlay_render_pass.set_vertex_buffer(0, draw_info.vertex.slice(..));
+                        vertex_buffer_id = Some(draw_info.vertex);
                     }
+                    // グリフの座標情報(index)
+                    if index_buffer_id != Some(draw_info.index) {
+                        overlay_render_pass
+                            .set_index_buffer(draw_info.index.slice(..), wgpu::IndexFormat::Uint32);
+                        index_buffer_id = Some(draw_info.index);
+                    }
+                    // インスタンスの位置
+                    overlay_render_pass.set_vertex_buffer(1, buffer.slice(..));
+                    overlay_render_pass.draw_indexed(
+                        draw_info.index_range.clone(),
+                        0,
+                        0..*len as _,
+                    );
                 }
             }
         }
     }
 
-    fn outline_stage(&self, encoder: &mut wgpu::CommandEncoder, target_view: &wgpu::TextureView) {
+    fn outline_stage(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target_view: &wgpu::TextureView,
+        layer: usize,
+    ) {
+        // 0 層目は画面を消してから描き、色付きのグリフの層はその上に重ねる
+        let (load, pipeline) = if layer == 0 {
+            (
+                wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                &self.outline_render_pipeline,
+            )
+        } else {
+            (wgpu::LoadOp::Load, &self.outline_layer_render_pipeline)
+        };
         {
             let mut outline_render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -377,7 +488,7 @@ impl RasterizerRenderrer {
                     view: target_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        load,
                         store: wgpu::StoreOp::Store,
                     },
                     depth_slice: None,
@@ -387,7 +498,7 @@ impl RasterizerRenderrer {
                 occlusion_query_set: None,
                 multiview_mask: None,
             });
-            outline_render_pass.set_pipeline(&self.outline_render_pipeline);
+            outline_render_pass.set_pipeline(pipeline);
             outline_render_pass.set_bind_group(0, &self.outline_bind_group.bind_group, &[]);
             outline_render_pass
                 .set_vertex_buffer(0, self.outline_vertex_buffer.vertex_buffer.slice(..));
@@ -402,4 +513,237 @@ impl RasterizerRenderrer {
             );
         }
     }
+
+    fn image_stage(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        image_buffers: &[&ImageInstances],
+        target_view: &wgpu::TextureView,
+    ) {
+        let mut image_render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Image Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+            multiview_mask: None,
+        });
+        image_render_pass.set_pipeline(&self.image_render_pipeline);
+        image_render_pass.set_bind_group(0, &self.overlap_bind_group.bind_group, &[]);
+        image_render_pass.set_vertex_buffer(0, self.outline_vertex_buffer.vertex_buffer.slice(..));
+        image_render_pass.set_index_buffer(
+            self.outline_vertex_buffer.index_buffer.slice(..),
+            wgpu::IndexFormat::Uint16,
+        );
+        for image in image_buffers {
+            let instances = image.instances();
+            if instances.is_empty() {
+                continue;
+            }
+            let bind_group = self.image_bind_group.to_bind_group(device, &image.texture);
+            image_render_pass.set_bind_group(1, &bind_group, &[]);
+            image_render_pass.set_vertex_buffer(1, instances.to_wgpu_buffer().slice(..));
+            image_render_pass.draw_indexed(
+                self.outline_vertex_buffer.index_range.clone(),
+                0,
+                0..instances.len() as _,
+            );
+        }
+    }
+}
+
+// 描画するグリフのうち、色付きのグリフの層の数の最大
+fn color_layer_count(glyph_buffers: Option<(&GlyphVertexBuffer, &[&GlyphInstances])>) -> usize {
+    let Some((glyph_vertex_buffer, glyph_instance_buffers)) = glyph_buffers else {
+        return 0;
+    };
+    glyph_instance_buffers
+        .iter()
+        .filter(|instance| !instance.is_empty())
+        .map(|instance| {
+            glyph_vertex_buffer.color_layer_count(
+                &instance.c,
+                instance.glyph.as_ref(),
+                &instance.direction,
+            )
+        })
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::sync::{Arc, mpsc};
+
+    use font_collector::FontStyle;
+    use glam::{Mat4, Vec3};
+
+    use super::*;
+    use crate::{
+        char_width_calcurator::CharWidthCalculator,
+        shaping::{GlyphKey, GlyphShaper},
+        vector_instances::InstanceAttributes,
+        vector_vertex::{VectorVertex, VectorVertexBuilder},
+    };
+
+    const SIZE: u32 = 64;
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+    fn rect(left: f32, right: f32, color: [f32; 3]) -> VectorVertex {
+        let mut builder = VectorVertexBuilder::new();
+        builder.move_to(left, -0.4);
+        builder.line_to(right, -0.4);
+        builder.line_to(right, 0.4);
+        builder.line_to(left, 0.4);
+        builder.close();
+        builder.build().with_layer_color(color)
+    }
+
+    // 赤い層の上に一部が重なるように青い層を重ねた色付きのグリフを描き、横一列の画素を返す
+    fn render_color_glyph(device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<[u8; 4]> {
+        let key = GlyphKey {
+            font_index: 0,
+            glyph_id: 1,
+            style: FontStyle::REGULAR,
+        };
+        let mut glyph_vertex_buffer = GlyphVertexBuffer::new(
+            Arc::new(GlyphShaper::new(Arc::new(vec![]), None)),
+            Arc::new(CharWidthCalculator::new(Arc::new(vec![]))),
+        );
+        glyph_vertex_buffer
+            .register_layers(
+                device,
+                queue,
+                key,
+                vec![
+                    rect(-0.8, 0.4, [1.0, 0.0, 0.0]),
+                    rect(-0.4, 0.8, [0.0, 0.0, 1.0]),
+                ],
+            )
+            .unwrap();
+        let mut instances = GlyphInstances::new_glyph('a', key, device);
+        instances.push(InstanceAttributes {
+            position: Vec3::ZERO,
+            ..Default::default()
+        });
+        instances.update_buffer(device, queue);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Color Glyph Test Texture"),
+            size: wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bytes_per_row = (SIZE * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Color Glyph Test Buffer"),
+            size: (bytes_per_row * SIZE) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut rasterizer =
+            RasterizerRenderrer::new(device, SIZE, SIZE, FORMAT, false, OutlineFillRule::EvenOdd);
+        rasterizer.prepare(
+            device,
+            queue,
+            (
+                Mat4::IDENTITY.to_cols_array_2d(),
+                Mat4::IDENTITY.to_cols_array_2d(),
+            ),
+        );
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Color Glyph Test Encoder"),
+        });
+        rasterizer.render(
+            &mut encoder,
+            device,
+            Buffers {
+                glyph_buffers: Some((&glyph_vertex_buffer, &[&instances])),
+                vector_buffers: None,
+                image_buffers: None,
+            },
+            &view,
+        );
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &output_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(SIZE),
+                },
+            },
+            wgpu::Extent3d {
+                width: SIZE,
+                height: SIZE,
+                depth_or_array_layers: 1,
+            },
+        );
+        let submission_index = queue.submit(Some(encoder.finish()));
+
+        let slice = output_buffer.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device
+            .poll(wgpu::wgt::PollType::Wait {
+                submission_index: Some(submission_index),
+                timeout: None,
+            })
+            .unwrap();
+        rx.recv().unwrap().unwrap();
+        let data = slice.get_mapped_range().unwrap();
+        let offset = (bytes_per_row * (SIZE / 2)) as usize;
+        data[offset..offset + (SIZE * 4) as usize]
+            .chunks_exact(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect()
+    }
+
+    #[test]
+    #[ignore = "GPU のアダプタが必要"]
+    fn test_color_glyph_layers_are_composited() {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle());
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+                .unwrap();
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default())).unwrap();
+
+        let row = render_color_glyph(&device, &queue);
+        // x = -0.6 は赤い層だけ、x = 0.0 は両方の層、x = 0.6 は青い層だけが描かれる。
+        // 重なった部分は偶奇規則で打ち消されず、上の青い層の色になる
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        assert_eq!(row[(SIZE as f32 * 0.2) as usize], red);
+        assert_eq!(row[(SIZE as f32 * 0.5) as usize], blue);
+        assert_eq!(row[(SIZE as f32 * 0.8) as usize], blue);
+        assert_eq!(row[1], [0, 0, 0, 0]);
+    }
 }
@@ -69,6 +69,17 @@ impl BackgroundImageTexture {
         queue: &wgpu::Queue,
         image: &DynamicImage,
         label: Option<&str>,
+    ) -> Self {
+        Self::new_with_min_filter(device, queue, image, label, wgpu::FilterMode::Nearest)
+    }
+
+    /// 縮小して表示するときの補間方法を指定してテクスチャを作る
+    pub fn new_with_min_filter(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &DynamicImage,
+        label: Option<&str>,
+        min_filter: wgpu::FilterMode,
     ) -> Self {
         let (width, height) = image.dimensions();
         let diffuse_rgba = image.to_rgba8();
@@ -113,7 +124,7 @@ impl BackgroundImageTexture {
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
+            min_filter,
             mipmap_filter: wgpu::MipmapFilterMode::Nearest,
             ..Default::default()
         });
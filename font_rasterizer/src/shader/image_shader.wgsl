@@ -0,0 +1,73 @@
+// 画像をテクスチャを貼った四角形として描くシェーダー
+
+// overlap_shader と同じ Uniforms を使う
+struct Uniforms {
+    u_view_proj: mat4x4<f32>,
+    u_default_view_proj: mat4x4<f32>,
+    u_time: u32,
+    u_width: u32,
+    u_antialiasing: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> u_buffer: Uniforms;
+
+@group(1) @binding(0)
+var t_image: texture_2d<f32>;
+@group(1) @binding(1)
+var s_image: sampler;
+
+// スクリーン全体を覆う四角形の頂点 (-1.0 から 1.0) を使う
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct InstancesInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(10) motion: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instances: InstancesInput,
+) -> VertexOutput {
+    let instance_matrix = mat4x4<f32>(
+        instances.model_matrix_0,
+        instances.model_matrix_1,
+        instances.model_matrix_2,
+        instances.model_matrix_3,
+    );
+    // 一辺の長さが 1 の四角形にする
+    let position = vec4<f32>(model.position.xy * 0.5, 0.0, 1.0);
+    // motion の 20 ビット目はカメラを無視するフラグ
+    let ignore_camera = (instances.motion & (1u << 20u)) != 0u;
+
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    if ignore_camera {
+        out.clip_position = u_buffer.u_default_view_proj * instance_matrix * position;
+    } else {
+        out.clip_position = u_buffer.u_view_proj * instance_matrix * position;
+    }
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_image, s_image, in.tex_coords);
+    // 透明な部分は下の文字を消さないように描かない
+    if color.a <= 0.0 {
+        discard;
+    }
+    return color;
+}
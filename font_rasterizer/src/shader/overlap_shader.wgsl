@@ -148,9 +148,23 @@ struct VertexInput {
     // 始点L  : 3
     // 終点L  : 5
     // 制御点 : 6
+    //
+    // 0-3 ビットが座標の種別で、4 ビット目が立っている場合は 8-31 ビットに色付きのグリフの層の色を持つ
     @location(1) vertex_type: u32,
 };
 
+// 色付きのグリフの層の色があればその色、無ければインスタンスの色
+fn vertex_color(vertex_type: u32, instance_color: vec3<f32>) -> vec3<f32> {
+    if !bit_check(vertex_type, 4u) {
+        return instance_color;
+    }
+    return vec3<f32>(
+        f32(bit_range(vertex_type, 31u, 24u)),
+        f32(bit_range(vertex_type, 23u, 16u)),
+        f32(bit_range(vertex_type, 15u, 8u))
+    ) / 255.0;
+}
+
 struct InstancesInput {
     @location(5) model_matrix_0: vec4<f32>,
     @location(6) model_matrix_1: vec4<f32>,
@@ -345,45 +359,46 @@ fn vs_main(
 
     var out: VertexOutput;
 
-    if model.vertex_type == 0u {
+    let vertex_type = bit_range(model.vertex_type, 3u, 0u);
+    if vertex_type == 0u {
         // 原点B
         out.wait = vec3<f32>(1.0, 0.0, 0.0);
         out.triangle_type = vec3<f32>(0.0, 1.0, 0.0);
-    } else if model.vertex_type == 7u {
+    } else if vertex_type == 7u {
         // ベジエ補助直線 始点
         out.wait = vec3<f32>(0.0, 1.0, 0.0);
         out.triangle_type = vec3<f32>(0.0, 1.0, 0.0);
-    } else if model.vertex_type == 8u {
+    } else if vertex_type == 8u {
         // ベジエ補助直線 終点
         out.wait = vec3<f32>(0.0, 0.0, 1.0);
         out.triangle_type = vec3<f32>(0.0, 1.0, 0.0);
-    } else if model.vertex_type == 1u {
+    } else if vertex_type == 1u {
         // 原点L
         out.wait = vec3<f32>(1.0, 0.0, 0.0);
         out.triangle_type = vec3<f32>(0.0, 0.0, 1.0);
-    } else if model.vertex_type == 3u {
+    } else if vertex_type == 3u {
         // 始点L
         out.wait = vec3<f32>(0.0, 1.0, 0.0);
         out.triangle_type = vec3<f32>(0.0, 0.0, 1.0);
-    } else if model.vertex_type == 5u {
+    } else if vertex_type == 5u {
         // 終点L
         out.wait = vec3<f32>(0.0, 0.0, 1.0);
         out.triangle_type = vec3<f32>(0.0, 0.0, 1.0);
-    } else if model.vertex_type == 2u {
+    } else if vertex_type == 2u {
         // 始点B
         out.wait = vec3<f32>(0.0, 1.0, 0.0);
         out.triangle_type = vec3<f32>(1.0, 0.0, 0.0);
-    } else if model.vertex_type == 4u {
+    } else if vertex_type == 4u {
         // 終点B
         out.wait = vec3<f32>(0.0, 0.0, 1.0);
         out.triangle_type = vec3<f32>(1.0, 0.0, 0.0);
-    } else if model.vertex_type == 6u {
+    } else if vertex_type == 6u {
         // 制御点
         out.wait = vec3<f32>(1.0, 0.0, 0.0);
         out.triangle_type = vec3<f32>(1.0, 0.0, 0.0);
     }
 
-    out.color = instances.color;
+    out.color = vertex_color(model.vertex_type, instances.color);
     if ignore_camera {
         out.clip_position = u_buffer.u_default_view_proj * instance_matrix * moved;
     } else {
@@ -412,7 +427,7 @@ fn vs_main_minimum(
     var moved = vec4<f32>(model.position.x, model.position.y, 0.0, 1.0);
 
     var out: VertexOutput;
-    out.color = instances.color;
+    out.color = vertex_color(model.vertex_type, instances.color);
     if ignore_camera {
         out.clip_position = u_buffer.u_default_view_proj * instance_matrix * moved;
     } else {
@@ -0,0 +1,596 @@
+//! 文字列を行などのまとまりごとにシェーピングし、合字や異体字セレクタ、前後の文字で変わる字形を求める。
+//!
+//! グリフはフォントの番号とグリフ ID、スタイルの組で表す。フォントの番号は ASCII を上書きするフォントがあれば 0 とし、
+//! その後に通常のフォントを優先順に並べたものとする。太字や斜体は、同じファミリーのスタイルの違うフォントか、
+//! 可変フォントの軸で表す。
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use font_collector::{FontData, FontStyle};
+use log::debug;
+use rustybuzz::{Face, UnicodeBuffer, script, shape, ttf_parser::Tag};
+use unicode_script::{Script, UnicodeScript};
+
+use crate::glyph_vertex_buffer::Direction;
+
+/// フォントに無い文字を表す .notdef のグリフ ID
+pub const NOTDEF_GLYPH_ID: u16 = 0;
+
+// 斜体の軸が無く傾きの軸だけがある可変フォントで、斜体として指定する角度
+const ITALIC_SLANT_DEGREE: f32 = -12.0;
+
+/// どのフォントのどのグリフかを表すキー。同じグリフでもスタイルが違えば別の字形になる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GlyphKey {
+    pub font_index: u16,
+    pub glyph_id: u16,
+    /// フォントで実際に表示できるスタイル
+    pub style: FontStyle,
+}
+
+/// シェーピングの結果のグリフ
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapedGlyph {
+    pub key: GlyphKey,
+    /// グリフが表す文字の範囲(文字単位)。合字などでは複数の文字をひとつのグリフで表す
+    pub chars: Range<usize>,
+    /// 行の進む方向の進み幅。em を 1 とした値
+    pub advance: f32,
+    /// 行の進む方向と、それに直交する方向のずらし幅。em を 1 とした値。
+    /// 縦書きのずらし幅はグリフの中心を基準にしていないため 0 とする
+    pub offset: [f32; 2],
+}
+
+pub struct GlyphShaper {
+    fonts: Arc<Vec<FontData>>,
+    ascii_override_font: Option<FontData>,
+    // 文字ごとのフォント。すべてのフォントを順に調べるので覚えておく
+    char_fonts: Mutex<HashMap<char, Option<u16>>>,
+    // 文字ごとの通常のグリフ。縦書きのグリフを求めるにはシェーピングが必要なので覚えておく
+    nominal_glyphs: Mutex<HashMap<(char, Direction), Option<GlyphKey>>>,
+    // フォントと指定したスタイルごとの、実際に表示できるスタイル
+    available_styles: Mutex<HashMap<(u16, FontStyle), FontStyle>>,
+}
+
+impl GlyphShaper {
+    pub fn new(fonts: Arc<Vec<FontData>>, ascii_override_font: Option<FontData>) -> Self {
+        Self {
+            fonts,
+            ascii_override_font,
+            char_fonts: Mutex::new(HashMap::new()),
+            nominal_glyphs: Mutex::new(HashMap::new()),
+            available_styles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// フォントの番号の順に並べたフォント
+    pub fn fonts(&self) -> Vec<&FontData> {
+        self.ascii_override_font
+            .iter()
+            .chain(self.fonts.iter())
+            .collect()
+    }
+
+    pub(crate) fn font(&self, font_index: u16) -> Option<&FontData> {
+        self.ascii_override_font
+            .iter()
+            .chain(self.fonts.iter())
+            .nth(font_index as usize)
+    }
+
+    pub(crate) fn face(&self, font_index: u16) -> Option<Face<'_>> {
+        self.styled_face(font_index, FontStyle::REGULAR)
+            .map(|(_, face, _)| face)
+    }
+
+    /// スタイルに最も近いフォントを選び、可変フォントであれば軸を設定した Face と、実際に表示できるスタイルを返す
+    pub(crate) fn styled_face(
+        &self,
+        font_index: u16,
+        style: FontStyle,
+    ) -> Option<(&FontData, Face<'_>, FontStyle)> {
+        let base = self.font(font_index)?;
+        let base_style = FontStyle::from_face(&font_data_to_face(base)?);
+        let (font, mut available) = std::iter::once((base, base_style))
+            .chain(base.styles.iter().map(|(style, font)| (font, *style)))
+            .min_by_key(|(_, font_style)| font_style.distance(style))?;
+        let mut face = font_data_to_face(font)?;
+
+        // 可変フォントの軸で表せるスタイルは軸に設定する。
+        // 軸を指定しないと wght が 100 のように細い値になるフォントがあるので、通常のスタイルでも設定する
+        if face.is_variable() {
+            let axes: Vec<_> = face.variation_axes().into_iter().collect();
+            for axis in axes {
+                debug!("variation: {}={}", axis.tag, axis.def_value);
+                let value = match &axis.tag.to_bytes() {
+                    b"wght" => style.weight as f32,
+                    b"wdth" => style.width as f32,
+                    b"ital" if style.italic => 1.0,
+                    b"slnt" if style.italic => ITALIC_SLANT_DEGREE,
+                    _ => continue,
+                }
+                .clamp(axis.min_value, axis.max_value);
+                if face.set_variation(axis.tag, value).is_none() {
+                    continue;
+                }
+                match &axis.tag.to_bytes() {
+                    b"wght" => available.weight = value as u16,
+                    b"wdth" => available.width = value as u16,
+                    b"ital" => available.italic |= value > 0.0,
+                    b"slnt" => available.italic |= value < 0.0,
+                    _ => {}
+                }
+            }
+        }
+        Some((font, face, available))
+    }
+
+    /// 文字を指定したスタイルで表示するときに、フォントで実際に表示できるスタイル。
+    /// 太字や斜体の字形が無いフォントでは、通常のスタイルのままになる
+    pub fn available_style(&self, c: char, style: FontStyle) -> FontStyle {
+        let Some(font_index) = self.font_index_for_char(c) else {
+            return style;
+        };
+        if let Some(available) = self
+            .available_styles
+            .lock()
+            .expect("available styles lock poisoned")
+            .get(&(font_index, style))
+        {
+            return *available;
+        }
+        let available = self
+            .styled_face(font_index, style)
+            .map(|(_, _, available)| available)
+            .unwrap_or(style);
+        self.available_styles
+            .lock()
+            .expect("available styles lock poisoned")
+            .insert((font_index, style), available);
+        available
+    }
+
+    // 文字を表示するフォント。ASCII は上書きするフォントを優先する
+    fn font_index_for_char(&self, c: char) -> Option<u16> {
+        if let Some(font_index) = self
+            .char_fonts
+            .lock()
+            .expect("char fonts lock poisoned")
+            .get(&c)
+        {
+            return *font_index;
+        }
+        let font_index = self.find_font_index(c);
+        self.char_fonts
+            .lock()
+            .expect("char fonts lock poisoned")
+            .insert(c, font_index);
+        font_index
+    }
+
+    fn find_font_index(&self, c: char) -> Option<u16> {
+        let offset = if self.ascii_override_font.is_some() {
+            if c.is_ascii()
+                && self
+                    .face(0)
+                    .is_some_and(|face| face.glyph_index(c).is_some())
+            {
+                return Some(0);
+            }
+            1
+        } else {
+            0
+        };
+        self.fonts
+            .iter()
+            .position(|font| {
+                font_data_to_face(font).is_some_and(|face| face.glyph_index(c).is_some())
+            })
+            .map(|index| (index + offset) as u16)
+    }
+
+    /// いずれかのフォントに文字のグリフがあるか
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.font_index_for_char(c).is_some()
+    }
+
+    /// 文字を単独で表示するときのグリフ。縦書きでは縦書き用の字形があればそれを返す。
+    /// どのフォントにも無い文字は、表示できないことがわかるように先頭のフォントの .notdef のグリフを返す
+    pub fn nominal_glyph(&self, c: char, direction: Direction) -> Option<GlyphKey> {
+        if let Some(key) = self
+            .nominal_glyphs
+            .lock()
+            .expect("nominal glyphs lock poisoned")
+            .get(&(c, direction))
+        {
+            return *key;
+        }
+
+        let key = match self.font_index_for_char(c) {
+            Some(font_index) => self.nominal_glyph_in_font(c, font_index, direction),
+            None => self
+                .styled_face(0, FontStyle::REGULAR)
+                .map(|(_, _, style)| GlyphKey {
+                    font_index: 0,
+                    glyph_id: NOTDEF_GLYPH_ID,
+                    style,
+                }),
+        };
+        self.nominal_glyphs
+            .lock()
+            .expect("nominal glyphs lock poisoned")
+            .insert((c, direction), key);
+        key
+    }
+
+    fn nominal_glyph_in_font(
+        &self,
+        c: char,
+        font_index: u16,
+        direction: Direction,
+    ) -> Option<GlyphKey> {
+        let (_, face, style) = self.styled_face(font_index, FontStyle::REGULAR)?;
+        let glyph_id = match direction {
+            Direction::Horizontal => face.glyph_index(c)?.0,
+            Direction::Vertical => {
+                let mut buffer = UnicodeBuffer::new();
+                buffer.set_direction(rustybuzz::Direction::TopToBottom);
+                buffer.add(c, 0);
+                let glyph_buffer = shape(&face, &[], buffer);
+                glyph_buffer.glyph_infos().first()?.glyph_id as u16
+            }
+        };
+        Some(GlyphKey {
+            font_index,
+            glyph_id,
+            style,
+        })
+    }
+
+    /// 文字列をシェーピングする。フォントや用字系が変わるところで区切り、それぞれをまとめてシェーピングする
+    pub fn shape(&self, text: &str, direction: Direction) -> Vec<ShapedGlyph> {
+        self.shape_with_styles(text, direction, &[])
+    }
+
+    /// 文字ごとにスタイルを指定して文字列をシェーピングする。styles が足りない文字は通常のスタイルとする
+    pub fn shape_with_styles(
+        &self,
+        text: &str,
+        direction: Direction,
+        styles: &[FontStyle],
+    ) -> Vec<ShapedGlyph> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut result = Vec::new();
+        for run in self.runs(&chars, styles) {
+            let Some((_, face, style)) = self.styled_face(run.font_index, run.style) else {
+                continue;
+            };
+            let mut buffer = UnicodeBuffer::new();
+            for index in run.chars.clone() {
+                buffer.add(chars[index], index as u32);
+            }
+            if let Some(script) = run.script {
+                buffer.set_script(script);
+            }
+            match direction {
+                Direction::Horizontal => buffer.guess_segment_properties(),
+                Direction::Vertical => buffer.set_direction(rustybuzz::Direction::TopToBottom),
+            }
+            let glyph_buffer = shape(&face, &[], buffer);
+
+            // クラスタの値は先頭の文字の位置なので、次に大きいクラスタまでをグリフの文字の範囲とする
+            let mut clusters: Vec<usize> = glyph_buffer
+                .glyph_infos()
+                .iter()
+                .map(|info| info.cluster as usize)
+                .collect();
+            clusters.sort_unstable();
+            clusters.dedup();
+            let units_per_em = face.units_per_em() as f32;
+            for (info, position) in glyph_buffer
+                .glyph_infos()
+                .iter()
+                .zip(glyph_buffer.glyph_positions())
+            {
+                let start = info.cluster as usize;
+                let end = clusters
+                    .iter()
+                    .find(|cluster| **cluster > start)
+                    .copied()
+                    .unwrap_or(run.chars.end);
+                let (advance, offset) = match direction {
+                    Direction::Horizontal => (
+                        position.x_advance as f32 / units_per_em,
+                        [
+                            position.x_offset as f32 / units_per_em,
+                            position.y_offset as f32 / units_per_em,
+                        ],
+                    ),
+                    Direction::Vertical => (-position.y_advance as f32 / units_per_em, [0.0, 0.0]),
+                };
+                result.push(ShapedGlyph {
+                    key: GlyphKey {
+                        font_index: run.font_index,
+                        glyph_id: info.glyph_id as u16,
+                        style,
+                    },
+                    chars: start..end,
+                    advance,
+                    offset,
+                });
+            }
+        }
+        result
+    }
+
+    // 同じフォントとスタイル、用字系の文字が続く範囲に分ける
+    fn runs(&self, chars: &[char], styles: &[FontStyle]) -> Vec<ShapingRun> {
+        let mut runs: Vec<ShapingRun> = Vec::new();
+        for (index, c) in chars.iter().enumerate() {
+            let current = runs.last();
+            // 異体字セレクタや結合文字は前の文字と同じフォントとスタイルでシェーピングする
+            let (font_index, style) = match current {
+                Some(run) if is_joining_char(*c) => (run.font_index, run.style),
+                _ => (
+                    self.font_index_for_char(*c)
+                        .or(current.map(|run| run.font_index))
+                        .unwrap_or_default(),
+                    styles.get(index).copied().unwrap_or_default(),
+                ),
+            };
+            let script = char_script(*c);
+            match runs.last_mut() {
+                Some(run)
+                    if run.font_index == font_index
+                        && run.style == style
+                        && (script.is_none()
+                            || run.script.is_none()
+                            || same_script_class(run.script, script)) =>
+                {
+                    run.chars.end = index + 1;
+                    run.script = run.script.or(script);
+                }
+                _ => runs.push(ShapingRun {
+                    font_index,
+                    style,
+                    script,
+                    chars: index..index + 1,
+                }),
+            }
+        }
+        debug!("shaping runs:{}", runs.len());
+        runs
+    }
+}
+
+struct ShapingRun {
+    font_index: u16,
+    style: FontStyle,
+    // 記号などの用字系を持たない文字だけの間は None
+    script: Option<rustybuzz::Script>,
+    chars: Range<usize>,
+}
+
+// 前の文字に続けてシェーピングする文字。結合文字、ゼロ幅の接合子、異体字セレクタ、絵文字の肌の色とタグ
+fn is_joining_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{200C}'
+            | '\u{200D}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FE20}'..='\u{FE2F}'
+            | '\u{1F3FB}'..='\u{1F3FF}'
+            | '\u{E0020}'..='\u{E007F}'
+            | '\u{E0100}'..='\u{E01EF}'
+    )
+}
+
+// 文字の用字系。記号や結合文字のように前後の文字に従うものは None
+fn char_script(c: char) -> Option<rustybuzz::Script> {
+    match c.script() {
+        Script::Common | Script::Inherited | Script::Unknown => None,
+        script => rustybuzz::Script::from_iso15924_tag(Tag::from_bytes_lossy(
+            script.short_name().as_bytes(),
+        )),
+    }
+}
+
+// 漢字と仮名は混ぜてシェーピングしても字形が変わらないので、同じ用字系として扱う
+fn same_script_class(a: Option<rustybuzz::Script>, b: Option<rustybuzz::Script>) -> bool {
+    let japanese = |script: Option<rustybuzz::Script>| {
+        script.is_some_and(|script| {
+            [script::HAN, script::HIRAGANA, script::KATAKANA].contains(&script)
+        })
+    };
+    a == b || (japanese(a) && japanese(b))
+}
+
+pub(crate) fn font_data_to_face(font_data: &'_ FontData) -> Option<Face<'_>> {
+    Face::from_slice(&font_data.binary, font_data.index)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use font_collector::FontCollector;
+
+    use super::*;
+
+    const FONT_DATA: &[u8] = include_bytes!("../../fonts/BIZUDMincho-Regular.ttf");
+    const EMOJI_FONT_DATA: &[u8] = include_bytes!("../../fonts/NotoEmoji-Regular.ttf");
+
+    #[test]
+    fn nominal_vertical_glyph() {
+        let collector = FontCollector::default();
+        let font_binaries = vec![
+            collector.convert_font(FONT_DATA.to_vec(), None).unwrap(),
+            collector
+                .convert_font(EMOJI_FONT_DATA.to_vec(), None)
+                .unwrap(),
+        ];
+        let shaper = GlyphShaper::new(Arc::new(font_binaries), None);
+
+        let cases = vec![
+            // 縦書きでも同じグリフが使われる文字
+            ('a', false),
+            ('あ', false),
+            ('🐖', false),
+            // 縦書きでは別のグリフが使われる文字
+            ('。', true),
+            ('「', true),
+            ('ー', true),
+        ];
+        for (c, expected) in cases {
+            let horizontal = shaper.nominal_glyph(c, Direction::Horizontal);
+            let vertical = shaper.nominal_glyph(c, Direction::Vertical);
+            assert!(horizontal.is_some());
+            assert_eq!(horizontal != vertical, expected);
+        }
+    }
+
+    fn shaper() -> GlyphShaper {
+        let collector = FontCollector::default();
+        let font = collector
+            .convert_font(EMOJI_FONT_DATA.to_vec(), None)
+            .unwrap();
+        GlyphShaper::new(Arc::new(vec![font]), None)
+    }
+
+    #[test]
+    fn shape_matches_nominal_glyph() {
+        let shaper = shaper();
+        let glyphs = shaper.shape("🐖🍖", Direction::Horizontal);
+        assert_eq!(glyphs.len(), 2);
+        assert_eq!(glyphs[0].chars, 0..1);
+        assert_eq!(glyphs[1].chars, 1..2);
+        assert_eq!(
+            Some(glyphs[0].key),
+            shaper.nominal_glyph('🐖', Direction::Horizontal)
+        );
+        assert!(glyphs[0].advance > 0.0);
+    }
+
+    #[test]
+    fn shape_with_styles() {
+        let collector = FontCollector::default();
+        let regular = collector
+            .convert_font(EMOJI_FONT_DATA.to_vec(), None)
+            .unwrap();
+        let mut font = regular.clone();
+        font.styles.push((FontStyle::BOLD, regular));
+        let styled_shaper = GlyphShaper::new(Arc::new(vec![font]), None);
+
+        let glyphs = styled_shaper.shape_with_styles(
+            "🐖🐖",
+            Direction::Horizontal,
+            &[FontStyle::REGULAR, FontStyle::BOLD],
+        );
+        assert_eq!(glyphs.len(), 2);
+        // 同じグリフでも太字は別のキーとして扱う
+        assert_eq!(glyphs[0].key.glyph_id, glyphs[1].key.glyph_id);
+        assert_eq!(glyphs[0].key.style, FontStyle::REGULAR);
+        assert_eq!(glyphs[1].key.style, FontStyle::BOLD);
+        assert_eq!(
+            styled_shaper.available_style('🐖', FontStyle::BOLD),
+            FontStyle::BOLD
+        );
+        // 斜体のフォントが無いので、近い太字になる
+        assert_eq!(
+            styled_shaper.available_style('🐖', FontStyle::BOLD.with_italic(true)),
+            FontStyle::BOLD
+        );
+
+        // スタイルのフォントが無ければ通常のグリフのまま
+        let shaper = shaper();
+        let glyphs = shaper.shape_with_styles("🐖", Direction::Horizontal, &[FontStyle::BOLD]);
+        assert_eq!(
+            Some(glyphs[0].key),
+            shaper.nominal_glyph('🐖', Direction::Horizontal)
+        );
+    }
+
+    #[test]
+    fn missing_char_is_notdef() {
+        let shaper = shaper();
+        assert!(shaper.has_glyph('🐖'));
+        assert!(!shaper.has_glyph('あ'));
+        assert_eq!(
+            shaper.nominal_glyph('あ', Direction::Horizontal),
+            Some(GlyphKey {
+                font_index: 0,
+                glyph_id: NOTDEF_GLYPH_ID,
+                style: FontStyle::REGULAR,
+            })
+        );
+    }
+
+    #[test]
+    fn variation_selector_joins_previous_char() {
+        let shaper = shaper();
+        // 異体字セレクタは前の文字と同じクラスタになる
+        let glyphs = shaper.shape("🐖\u{FE0E}🍖", Direction::Horizontal);
+        assert_eq!(glyphs.first().map(|glyph| glyph.chars.clone()), Some(0..2));
+        assert_eq!(glyphs.last().map(|glyph| glyph.chars.clone()), Some(2..3));
+    }
+
+    fn mincho_shaper() -> GlyphShaper {
+        let collector = FontCollector::default();
+        let font = collector.convert_font(FONT_DATA.to_vec(), None).unwrap();
+        GlyphShaper::new(Arc::new(vec![font]), None)
+    }
+
+    #[test]
+    fn ideographic_variation_sequence() {
+        let shaper = mincho_shaper();
+        let face = Face::from_slice(FONT_DATA, 0).unwrap();
+        // 葛 の IVS (VS17 と VS18) はそれぞれフォントの cmap の字形になり、異体字セレクタと同じクラスタになる
+        let mut glyph_ids = Vec::new();
+        for selector in ['\u{E0100}', '\u{E0101}'] {
+            let glyphs = shaper.shape(&format!("葛{selector}"), Direction::Horizontal);
+            assert_eq!(glyphs.len(), 1);
+            assert_eq!(glyphs[0].chars, 0..2);
+            let expected = face
+                .glyph_variation_index('葛', selector)
+                .expect("葛 の IVS がフォントに無い");
+            assert_eq!(glyphs[0].key.glyph_id, expected.0);
+            glyph_ids.push(expected.0);
+        }
+        assert_ne!(glyph_ids[0], glyph_ids[1]);
+    }
+
+    #[test]
+    fn combining_mark_composes_with_previous_char() {
+        let shaper = mincho_shaper();
+        // 結合文字の濁点は前の仮名と合わせて一つのグリフになる
+        let glyphs = shaper.shape("か\u{3099}き", Direction::Horizontal);
+        assert_eq!(glyphs.len(), 2);
+        assert_eq!(glyphs[0].chars, 0..2);
+        assert_eq!(
+            Some(glyphs[0].key),
+            shaper.nominal_glyph('が', Direction::Horizontal)
+        );
+        assert_eq!(glyphs[1].chars, 2..3);
+    }
+
+    #[test]
+    fn script_of_char() {
+        assert_eq!(char_script('a'), Some(script::LATIN));
+        assert_eq!(char_script('漢'), Some(script::HAN));
+        assert_eq!(char_script('あ'), Some(script::HIRAGANA));
+        assert_eq!(char_script('ア'), Some(script::KATAKANA));
+        assert_eq!(char_script('Ж'), Some(script::CYRILLIC));
+        // 記号や結合文字は前後の文字に従う
+        assert_eq!(char_script('1'), None);
+        assert_eq!(char_script('、'), None);
+        assert_eq!(char_script('\u{3099}'), None);
+        assert_eq!(char_script('\u{E0100}'), None);
+    }
+}
@@ -95,6 +95,16 @@ impl SvgBuffers {
     }
 }
 
+/// SVG の幅と高さを返す
+pub fn svg_size(svg: &str) -> Result<[f32; 2], FontRasterizerError> {
+    let tree = usvg::Tree::from_str(svg, &usvg::Options::default()).map_err(|err| {
+        log::error!("svg parse error: {:?}", err);
+        FontRasterizerError::SvgParseError
+    })?;
+    let size = tree.size();
+    Ok([size.width(), size.height()])
+}
+
 #[allow(non_upper_case_globals)]
 pub fn svg_to_vector_vertex(svg: &str) -> Result<VectorVertex, FontRasterizerError> {
     let tree = usvg::Tree::from_str(svg, &usvg::Options::default()).map_err(|err| {
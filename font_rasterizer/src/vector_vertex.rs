@@ -324,12 +324,30 @@ impl VertexBuilderOptions {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VectorVertex {
     pub(crate) vertex: Vec<Vertex>,
     pub(crate) index: Vec<u32>,
 }
 impl VectorVertex {
+    /// 色付きのグリフの層の色を頂点に持たせる。色はインスタンスの色と同じ色空間の RGB とする
+    pub(crate) fn with_layer_color(self, color: [f32; 3]) -> Self {
+        let [r, g, b] = color.map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u32);
+        let layer_color = LAYER_COLOR_FLAG | (r << 24) | (g << 16) | (b << 8);
+        let vertex = self
+            .vertex
+            .into_iter()
+            .map(|vertex| Vertex {
+                vertex_type: (vertex.vertex_type & VERTEX_TYPE_MASK) | layer_color,
+                ..vertex
+            })
+            .collect();
+        Self {
+            vertex,
+            index: self.index,
+        }
+    }
+
     pub fn vertex_size(&self) -> u64 {
         (self.vertex.len() * std::mem::size_of::<Vertex>()) as u64
     }
@@ -339,6 +357,11 @@ impl VectorVertex {
     }
 }
 
+// vertex_type の 0-3 ビットは頂点の種別、4 ビット目は層の色を持つか、8-31 ビットは層の色の RGB とする。
+// 層の色を持たない頂点はインスタンスの色で描く
+const VERTEX_TYPE_MASK: u32 = 0x0F;
+const LAYER_COLOR_FLAG: u32 = 1 << 4;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub(crate) struct Vertex {
@@ -155,9 +155,11 @@ pub async fn render_vector_vertex_to_png_async(
     let vector_instances_refs = [&vector_instances];
     rasterizer.render(
         &mut encoder,
+        &device,
         Buffers {
             glyph_buffers: None,
             vector_buffers: Some((&vector_vertex_buffer, &vector_instances_refs)),
+            image_buffers: None,
         },
         &render_view,
     );
@@ -0,0 +1,424 @@
+use std::{cell::RefCell, ops::Range};
+
+use tree_sitter::{InputEdit, Node, Point, Tree};
+
+use crate::{
+    HighlightContext, KindAndRange, SubParsers, collect_highlights, root_parser,
+    settings::HighlightSettings, walk_tree,
+};
+
+/// 前回の構文木を保持し、変更のあった部分だけハイライトを計算し直す
+pub struct Highlighter {
+    language: String,
+    settings: HighlightSettings,
+    parser: tree_sitter::Parser,
+    sub_parsers: SubParsers,
+    text: String,
+    tree: Option<Tree>,
+    // 現在のテキスト全体のハイライト
+    highlights: Vec<(String, Range<usize>)>,
+}
+
+/// [`Highlighter::update`] の結果。範囲はいずれも文字単位
+///
+/// 前回のハイライトのうち `removed` と重なるものは捨て、それより後ろのものは文字数の増減だけ位置をずらす。
+/// その上で `changed_ranges` と重なるものを `highlights` で置き換える。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HighlightUpdate {
+    /// 変更前のテキストで編集された範囲
+    pub removed: Range<usize>,
+    /// 変更後のテキストで編集された範囲
+    pub inserted: Range<usize>,
+    /// ハイライトを計算し直した範囲(変更後のテキストでの位置)
+    pub changed_ranges: Vec<Range<usize>>,
+    /// changed_ranges の中のハイライト
+    pub highlights: Vec<(String, Range<usize>)>,
+}
+
+impl Highlighter {
+    /// language で指定した言語のハイライタを作る。対応していない言語の場合は None を返す
+    pub fn new(language: &str, settings: HighlightSettings) -> Option<Self> {
+        Some(Self {
+            language: language.to_string(),
+            settings,
+            parser: root_parser(language)?,
+            sub_parsers: SubParsers::default(),
+            text: String::new(),
+            tree: None,
+            highlights: Vec::new(),
+        })
+    }
+
+    /// 現在のテキスト全体のハイライト。範囲の開始位置の順に並んでいる
+    pub fn highlights(&self) -> &[(String, Range<usize>)] {
+        &self.highlights
+    }
+
+    /// ハイライトしているテキスト
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// 編集された行を置き換えてテキストを更新し、変更のあった範囲のハイライトを返す。
+    ///
+    /// `changed_rows` には text_buffer の ChangeEvent で追加・移動・削除された文字の、
+    /// 変更前と変更後の行をすべて含める。`line_count` は変更後の行数で、`line` は変更後の行の内容を返す。
+    /// 文字のない末尾の空行の増減は ChangeEvent に現れないので、行数が合わなければ末尾まで置き換える
+    pub fn update(
+        &mut self,
+        changed_rows: Range<usize>,
+        line_count: usize,
+        line: impl Fn(usize) -> String,
+    ) -> HighlightUpdate {
+        let Some(edit) = self.replace_rows(changed_rows, line_count, line) else {
+            return HighlightUpdate::default();
+        };
+        let text = self.text.as_str();
+        let (tree, changed_bytes) = match self.tree.take() {
+            Some(mut old_tree) => {
+                old_tree.edit(&edit.input_edit);
+                let Some(new_tree) = self.parser.parse(text, Some(&old_tree)) else {
+                    return HighlightUpdate::default();
+                };
+                // ブロックの構造が変わった範囲に加えて、インラインの変更を拾うために編集範囲も含める
+                let mut ranges: Vec<Range<usize>> = old_tree
+                    .changed_ranges(&new_tree)
+                    .map(|range| range.start_byte..range.end_byte)
+                    .collect();
+                ranges.push(edit.input_edit.start_byte..edit.input_edit.new_end_byte);
+                (new_tree, ranges)
+            }
+            None => {
+                let Some(tree) = self.parser.parse(text, None) else {
+                    return HighlightUpdate::default();
+                };
+                let whole = tree.root_node().byte_range();
+                (tree, vec![whole])
+            }
+        };
+
+        let blocks = changed_blocks(&tree, &changed_bytes);
+        let events = RefCell::new(Vec::new());
+        for block in blocks.iter() {
+            let mut context = HighlightContext::new(text).with_language_suggestion(&self.language);
+            for ancestor in ancestors(block) {
+                context = context.with_kind(&KindAndRange::new(&context, &ancestor));
+            }
+            walk_tree(context, *block, &mut self.sub_parsers, &|event| {
+                events.borrow_mut().push(event);
+            });
+        }
+
+        let changed_ranges: Vec<Range<usize>> = blocks
+            .iter()
+            .map(|block| {
+                crate::byte_to_char_position(text, block.start_byte())
+                    ..crate::byte_to_char_position(text, block.end_byte())
+            })
+            .collect();
+        // ブロックからはみ出したハイライトは範囲内に切り詰める
+        let highlights: Vec<(String, Range<usize>)> =
+            collect_highlights(events.into_inner(), &self.settings)
+                .into_iter()
+                .filter_map(|(category, range)| {
+                    changed_ranges
+                        .iter()
+                        .find(|changed| changed.start < range.end && range.start < changed.end)
+                        .map(|changed| {
+                            (
+                                category,
+                                range.start.max(changed.start)..range.end.min(changed.end),
+                            )
+                        })
+                })
+                .collect();
+        self.tree = Some(tree);
+        let update = HighlightUpdate {
+            removed: edit.removed,
+            inserted: edit.inserted,
+            changed_ranges,
+            highlights,
+        };
+        merge_highlights(&mut self.highlights, &update);
+        update
+    }
+
+    // 編集された行を置き換え、構文木に適用する編集を返す。テキストが変わらなければ None を返す
+    fn replace_rows(
+        &mut self,
+        changed_rows: Range<usize>,
+        line_count: usize,
+        line: impl Fn(usize) -> String,
+    ) -> Option<RowEdit> {
+        let old_line_count = self.text.matches('\n').count() + 1;
+        // 編集された行より後ろの行は、変更の前後で同じ位置に同じ文字が並んでいる
+        let mut old_end = changed_rows.end.min(old_line_count);
+        let mut new_end = changed_rows.end.min(line_count);
+        if old_line_count - old_end != line_count - new_end {
+            old_end = old_line_count;
+            new_end = line_count;
+        }
+        let start = changed_rows.start.min(old_end).min(new_end);
+
+        let mut start_byte = row_start_byte(&self.text, start);
+        let old_end_byte = row_start_byte(&self.text, old_end);
+        let mut replacement: String = (start..new_end).map(|row| line(row) + "\n").collect();
+        if old_end == old_line_count {
+            // 最後の行の後ろには改行が無いので、行の間の改行を付け替える
+            match replacement.pop() {
+                None if 0 < start && start < old_line_count => start_byte -= 1,
+                Some(_) if start == old_line_count => replacement.insert(0, '\n'),
+                _ => {}
+            }
+        }
+        if self.text[start_byte..old_end_byte] == replacement {
+            return None;
+        }
+
+        let start_char = crate::byte_to_char_position(&self.text, start_byte);
+        let removed = start_char..start_char + self.text[start_byte..old_end_byte].chars().count();
+        let inserted = start_char..start_char + replacement.chars().count();
+        let new_end_byte = start_byte + replacement.len();
+        let start_position = point(&self.text, start_byte);
+        let old_end_position = point(&self.text, old_end_byte);
+        self.text
+            .replace_range(start_byte..old_end_byte, &replacement);
+        Some(RowEdit {
+            input_edit: InputEdit {
+                start_byte,
+                old_end_byte,
+                new_end_byte,
+                start_position,
+                old_end_position,
+                new_end_position: point(&self.text, new_end_byte),
+            },
+            removed,
+            inserted,
+        })
+    }
+}
+
+// 行の置き換えを構文木の編集と文字単位の範囲で表したもの
+struct RowEdit {
+    input_edit: InputEdit,
+    removed: Range<usize>,
+    inserted: Range<usize>,
+}
+
+// row 行目の先頭のバイト位置。行数と同じ値を渡すとテキストの末尾を返す
+fn row_start_byte(text: &str, row: usize) -> usize {
+    match row.checked_sub(1) {
+        None => 0,
+        Some(newlines) => text
+            .match_indices('\n')
+            .nth(newlines)
+            .map_or(text.len(), |(i, _)| i + 1),
+    }
+}
+
+/// 前回のハイライトに update を反映する
+fn merge_highlights(highlights: &mut Vec<(String, Range<usize>)>, update: &HighlightUpdate) {
+    highlights.retain(|(_, range)| {
+        range.end <= update.removed.start || update.removed.end <= range.start
+    });
+    for (_, range) in highlights.iter_mut() {
+        if range.start >= update.removed.end {
+            let start = range.start - update.removed.len() + update.inserted.len();
+            *range = start..start + range.len();
+        }
+    }
+    highlights.retain(|(_, range)| {
+        !update
+            .changed_ranges
+            .iter()
+            .any(|changed| changed.start < range.end && range.start < changed.end)
+    });
+    highlights.extend(update.highlights.iter().cloned());
+    highlights.sort_by_key(|(category, range)| (range.start, range.end, category.clone()));
+}
+
+fn point(text: &str, byte: usize) -> Point {
+    let before = &text[..byte];
+    match before.rfind('\n') {
+        Some(newline) => Point::new(before.matches('\n').count(), byte - newline - 1),
+        None => Point::new(0, byte),
+    }
+}
+
+/// 変更範囲を含むブロック(ルートか Markdown の section の直下のノード)を列挙する
+fn changed_blocks<'a>(tree: &'a Tree, ranges: &[Range<usize>]) -> Vec<Node<'a>> {
+    let root = tree.root_node();
+    let mut blocks: Vec<Node> = ranges
+        .iter()
+        .filter_map(|range| root.descendant_for_byte_range(range.start, range.end))
+        .map(|mut node| {
+            while let Some(parent) = node.parent() {
+                if parent.parent().is_none() || parent.kind() == "section" {
+                    break;
+                }
+                node = parent;
+            }
+            node
+        })
+        .collect();
+    blocks.sort_by_key(|node| (node.start_byte(), std::cmp::Reverse(node.end_byte())));
+    // ノード同士は入れ子か重ならないかのどちらかなので、他のブロックに含まれるものを除く
+    let mut result: Vec<Node> = Vec::new();
+    for block in blocks {
+        if result
+            .last()
+            .is_some_and(|last| block.end_byte() <= last.end_byte())
+        {
+            continue;
+        }
+        result.push(block);
+    }
+    result
+}
+
+/// ルートから親までの祖先ノード
+fn ancestors<'a>(node: &Node<'a>) -> Vec<Node<'a>> {
+    let mut result = Vec::new();
+    let mut current = node.parent();
+    while let Some(parent) = current {
+        current = parent.parent();
+        result.push(parent);
+    }
+    result.reverse();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full(language: &str, text: &str) -> Vec<(String, Range<usize>)> {
+        let mut highlights = crate::highlight(language, text, &HighlightSettings::default());
+        highlights.sort_by_key(|(category, range)| (range.start, range.end, category.clone()));
+        highlights
+    }
+
+    // ChangeEvent と同じく、文字の変わった行だけを編集された行として渡す。
+    // 文字のない末尾の空行は ChangeEvent に現れないので、有無の違いは編集とみなさない
+    fn update_text(highlighter: &mut Highlighter, text: &str) -> HighlightUpdate {
+        let old: Vec<String> = highlighter.text().split('\n').map(String::from).collect();
+        let new: Vec<&str> = text.split('\n').collect();
+        let changed: Vec<usize> = (0..old.len().max(new.len()))
+            .filter(|&row| {
+                old.get(row).map_or("", |line| line.as_str()) != new.get(row).copied().unwrap_or("")
+            })
+            .collect();
+        let changed_rows = match (changed.first(), changed.last()) {
+            (Some(first), Some(last)) => *first..last + 1,
+            _ => 0..0,
+        };
+        highlighter.update(changed_rows, new.len(), |row| new[row].to_string())
+    }
+
+    #[test]
+    fn test_replace_rows() {
+        let mut highlighter = Highlighter::new("markdown", HighlightSettings::default()).unwrap();
+        let cases = [
+            // 先頭から入力する
+            ("ab", 0..1),
+            // 行の途中に改行を入れる
+            ("a\nb", 0..2),
+            // 末尾に空行を足す。ChangeEvent には現れない
+            ("a\nb\n\n", 0..0),
+            // 空行の後ろに入力する
+            ("a\nb\n\nc", 3..4),
+            // 最後の行を消す
+            ("a\nb\n", 3..4),
+            // 複数の行をまとめて消す
+            ("b\n", 0..2),
+            ("", 0..2),
+        ];
+        for (text, changed_rows) in cases {
+            let lines: Vec<&str> = text.split('\n').collect();
+            highlighter.update(changed_rows, lines.len(), |row| lines[row].to_string());
+            assert_eq!(highlighter.text(), text);
+        }
+
+        // 文字単位の範囲と構文木に適用する編集
+        let mut highlighter = Highlighter::new("markdown", HighlightSettings::default()).unwrap();
+        highlighter.update(0..2, 2, |row| ["abc", "def"][row].to_string());
+        let edit = highlighter
+            .replace_rows(1..2, 2, |row| ["abc", "dXef"][row].to_string())
+            .unwrap();
+        assert_eq!(edit.input_edit.start_byte, 4);
+        assert_eq!(edit.input_edit.old_end_byte, 7);
+        assert_eq!(edit.input_edit.new_end_byte, 8);
+        assert_eq!(edit.input_edit.start_position, Point::new(1, 0));
+        assert_eq!(edit.removed, 4..7);
+        assert_eq!(edit.inserted, 4..8);
+
+        // 変更が無ければ何もしない
+        assert!(
+            highlighter
+                .replace_rows(0..2, 2, |row| ["abc", "dXef"][row].to_string())
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let texts = [
+            "# title\n\nhello world\n\n- item\n",
+            "# title\n\nhello **world**\n\n- item\n",
+            "# title\n\nhello **world**\n\n- item\n- second\n",
+            "# title\n\nhello\n\n**world**\n\n- item\n- second\n",
+            "# title\n\n```rust\nfn main() {}\n```\n\n- item\n- second\n",
+            "## title\n\n```rust\nfn main() {}\n```\n",
+        ];
+        let mut highlighter = Highlighter::new("markdown", HighlightSettings::default()).unwrap();
+        let mut highlights = Vec::new();
+        for text in texts {
+            let update = update_text(&mut highlighter, text);
+            merge_highlights(&mut highlights, &update);
+            assert_eq!(highlights, full("markdown", text), "{}", text);
+            // ハイライタの保持する全体のハイライトも一致する
+            assert_eq!(highlighter.highlights(), full("markdown", text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_incremental_update_language() {
+        let texts = [
+            "fn main() {}\n",
+            "fn main() {}\n\nfn sub() {}\n",
+            "// comment\nfn main() {}\n\nfn sub() {}\n",
+            "/* fn main() {}\n\nfn sub() {}\n",
+            "fn main() {}\n\nfn sub() {}\n",
+        ];
+        let mut highlighter = Highlighter::new("rust", HighlightSettings::default()).unwrap();
+        let mut highlights = Vec::new();
+        for text in texts {
+            let update = update_text(&mut highlighter, text);
+            merge_highlights(&mut highlights, &update);
+            assert_eq!(highlights, full("rust", text), "{}", text);
+            assert_eq!(highlighter.highlights(), full("rust", text), "{}", text);
+        }
+        assert!(Highlighter::new("unknown", HighlightSettings::default()).is_none());
+    }
+
+    #[test]
+    fn test_update_only_changed_block() {
+        let mut highlighter = Highlighter::new("markdown", HighlightSettings::default()).unwrap();
+        update_text(&mut highlighter, "# title\n\nfirst\n\nsecond\n");
+
+        let update = highlighter.update(4..5, 6, |row| {
+            ["# title", "", "first", "", "*second*", ""][row].to_string()
+        });
+        assert_eq!(update.changed_ranges, vec![16..25]);
+        assert!(
+            update
+                .highlights
+                .iter()
+                .any(|(category, range)| category == "markdown.emphasis" && *range == (16..24))
+        );
+
+        // 変更が無ければ何もしない
+        let update = highlighter.update(0..0, 6, |_| unreachable!());
+        assert_eq!(update, HighlightUpdate::default());
+    }
+}
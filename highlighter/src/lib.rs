@@ -1,15 +1,30 @@
-use std::{ops::Range, sync::mpsc};
+use std::{collections::HashMap, ops::Range, path::Path, sync::mpsc};
 
 use crate::settings::HighlightSettings;
 
+mod incremental;
+mod preview;
+mod query;
 pub mod settings;
 
+pub use incremental::{HighlightUpdate, Highlighter};
+pub use preview::{PreviewSpan, PreviewStyle, markdown_preview};
+
 #[derive(Debug, Clone)]
 struct CallbackArguments {
     pub language: String,
     pub kind_stack: KindStack,
 }
 
+// walk が走査中に通知するイベント
+#[derive(Debug, Clone)]
+enum WalkEvent {
+    // 構文木のノードを訪れた
+    Node(CallbackArguments),
+    // 一つの構文木を highlights.scm でハイライトした結果。範囲は文字位置で、重なりは無い
+    Captures(Vec<(String, Range<usize>)>),
+}
+
 #[derive(Debug, Clone, Default)]
 struct KindStack {
     kinds: Vec<KindAndRange>,
@@ -47,32 +62,160 @@ fn byte_to_char_position(text: &str, byte_pos: usize) -> usize {
         .count()
 }
 
-fn markdown_highlight_callback(target_string: &str, callback: impl Fn(CallbackArguments)) {
-    let mut parser = md_parser();
+fn highlight_callback(language: &str, target_string: &str, callback: impl Fn(WalkEvent)) {
+    let Some(mut parser) = root_parser(language) else {
+        return;
+    };
     let tree = parser.parse(target_string, None).unwrap();
-    let cursor = tree.root_node().walk();
-    let context = HighlightContext::new(target_string);
-    walk(context, &mut cursor.clone(), &callback);
+    let context = HighlightContext::new(target_string).with_language_suggestion(language);
+    walk_tree(
+        context,
+        tree.root_node(),
+        &mut SubParsers::default(),
+        &callback,
+    );
 }
 
 pub fn markdown_highlight(
     target_string: &str,
     settings: &HighlightSettings,
+) -> Vec<(String, Range<usize>)> {
+    highlight(MARKDOWN, target_string, settings)
+}
+
+/// テキスト全体を language で指定した言語としてハイライトする。
+/// 対応していない言語の場合は空の結果を返す
+pub fn highlight(
+    language: &str,
+    target_string: &str,
+    settings: &HighlightSettings,
 ) -> Vec<(String, Range<usize>)> {
     let (tx, rx) = mpsc::channel();
-    markdown_highlight_callback(target_string, |args| {
-        tx.send(args).unwrap();
+    highlight_callback(language, target_string, |event| {
+        tx.send(event).unwrap();
     });
     // rx 側でイテレーションを終了させるためにtxをドロップする
     drop(tx);
-    rx.iter()
-        .filter_map(|arg| {
-            println!("arg: {:?}", arg.kind_stack.path);
-            settings.args_to_definition(&arg)
-        })
+    collect_highlights(rx.iter(), settings)
+}
+
+/// ハイライトしないことを示すカテゴリ名。設定ファイルで highlights.scm の結果を打ち消すのにも使う
+pub const NONE_CATEGORY: &str = "none";
+
+// highlights.scm の結果の上に、上書きとして設定ファイルの定義を重ねる
+fn collect_highlights(
+    events: impl IntoIterator<Item = WalkEvent>,
+    settings: &HighlightSettings,
+) -> Vec<(String, Range<usize>)> {
+    let mut captures = Vec::new();
+    let mut definitions = Vec::new();
+    for event in events {
+        match event {
+            WalkEvent::Node(args) => definitions.extend(settings.args_to_definition(&args)),
+            WalkEvent::Captures(highlights) => captures.extend(highlights),
+        }
+    }
+    paint(captures.into_iter().chain(definitions))
+        .into_iter()
+        .filter(|(category, _)| category != NONE_CATEGORY)
+        .collect()
+}
+
+/// 範囲を順に塗り重ねて、重なりの無い範囲にする。後の範囲ほど優先される
+fn paint(
+    highlights: impl IntoIterator<Item = (String, Range<usize>)>,
+) -> Vec<(String, Range<usize>)> {
+    let highlights: Vec<_> = highlights
+        .into_iter()
+        .filter(|(_, range)| !range.is_empty())
+        .collect();
+    let Some(start) = highlights.iter().map(|(_, range)| range.start).min() else {
+        return Vec::new();
+    };
+    let end = highlights
+        .iter()
+        .map(|(_, range)| range.end)
+        .max()
+        .unwrap_or(start);
+    let mut canvas: Vec<Option<usize>> = vec![None; end - start];
+    for (index, (_, range)) in highlights.iter().enumerate() {
+        canvas[range.start - start..range.end - start].fill(Some(index));
+    }
+
+    // 隣り合っていても、別の範囲から塗られた部分はつなげない
+    let mut result: Vec<(usize, Range<usize>)> = Vec::new();
+    for (offset, index) in canvas.into_iter().enumerate() {
+        let Some(index) = index else {
+            continue;
+        };
+        let position = start + offset;
+        match result.last_mut() {
+            Some((last, range)) if range.end == position && *last == index => range.end += 1,
+            _ => result.push((index, position..position + 1)),
+        }
+    }
+    result
+        .into_iter()
+        .map(|(index, range)| (highlights[index].0.clone(), range))
         .collect()
 }
 
+const MARKDOWN: &str = "markdown";
+
+/// ハイライトできる言語
+pub const SUPPORTED_LANGUAGES: &[&str] = &[
+    MARKDOWN,
+    "rust",
+    "java",
+    "go",
+    "json",
+    "bash",
+    "toml",
+    "python",
+    "javascript",
+    "typescript",
+    "yaml",
+    "html",
+    "c",
+    "cpp",
+    "sql",
+];
+
+/// 言語名やその別名(コードブロックの info string に書かれるもの)をハイライトに使う言語に解決する
+pub fn resolve_language(name: &str) -> Option<&'static str> {
+    let language = match name.to_ascii_lowercase().as_str() {
+        "md" | "markdown" => MARKDOWN,
+        "rs" | "rust" => "rust",
+        "java" => "java",
+        "go" | "golang" => "go",
+        "json" => "json",
+        "sh" | "bash" | "zsh" | "shell" => "bash",
+        "toml" => "toml",
+        "py" | "python" | "python3" => "python",
+        "js" | "mjs" | "cjs" | "jsx" | "javascript" => "javascript",
+        "ts" | "mts" | "cts" | "typescript" => "typescript",
+        "yml" | "yaml" => "yaml",
+        "htm" | "html" => "html",
+        "c" | "h" => "c",
+        "cpp" | "c++" | "cc" | "cxx" | "hpp" | "hh" | "hxx" => "cpp",
+        "sql" | "postgresql" | "postgres" | "mysql" | "sqlite" => "sql",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// ファイルの拡張子からハイライトに使う言語を推測する
+pub fn language_from_extension(extension: &str) -> Option<&'static str> {
+    resolve_language(extension)
+}
+
+/// ファイルのパスからハイライトに使う言語を推測する
+pub fn language_from_path(path: &Path) -> Option<&'static str> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(language_from_extension)
+}
+
 #[derive(Clone, Debug)]
 struct KindAndRange {
     kind: String,
@@ -184,10 +327,91 @@ impl<'a> Iterator for TreeCursorIterator<'a> {
     }
 }
 
+/// Markdown の中で使うパーサー。ノードごとに作り直さないよう使い回す
+#[derive(Default)]
+struct SubParsers {
+    inline: Option<tree_sitter::Parser>,
+    code: HashMap<String, tree_sitter::Parser>,
+}
+
+impl SubParsers {
+    fn inline_parser(&mut self) -> &mut tree_sitter::Parser {
+        self.inline.get_or_insert_with(md_inline_parser)
+    }
+
+    fn code_parser(&mut self, language: &str) -> Option<&mut tree_sitter::Parser> {
+        if !self.code.contains_key(language) {
+            self.code
+                .insert(language.to_string(), code_parser(language)?);
+        }
+        self.code.get_mut(language)
+    }
+}
+
+// テキスト全体を解析するパーサー
+fn root_parser(language: &str) -> Option<tree_sitter::Parser> {
+    if language == MARKDOWN {
+        Some(md_parser())
+    } else {
+        code_parser(language)
+    }
+}
+
+// コードブロックの中で使うパーサー
+fn code_parser(language: &str) -> Option<tree_sitter::Parser> {
+    let parser = match language {
+        "rust" => rust_parser(),
+        "java" => java_parser(),
+        "go" => go_parser(),
+        "json" => json_parser(),
+        "bash" => bash_parser(),
+        "toml" => toml_parser(),
+        "python" => python_parser(),
+        "javascript" => javascript_parser(),
+        "typescript" => typescript_parser(),
+        "yaml" => yaml_parser(),
+        "html" => html_parser(),
+        "c" => c_parser(),
+        "cpp" => cpp_parser(),
+        "sql" => sql_parser(),
+        _ => return None,
+    };
+    Some(parser)
+}
+
+// 一つの構文木(またはその一部)を highlights.scm でハイライトしてから走査する
+fn walk_tree<'a>(
+    context: HighlightContext<'a>,
+    node: tree_sitter::Node,
+    parsers: &mut SubParsers,
+    callback: &impl Fn(WalkEvent),
+) {
+    let language = if context.in_inline {
+        query::MARKDOWN_INLINE
+    } else {
+        context.language_suggestion.as_str()
+    };
+    let offset = context.target_string_byte_offset;
+    let captures =
+        query::query_highlights(language, node, &context.target_string.as_bytes()[offset..])
+            .into_iter()
+            .map(|(category, range)| {
+                (
+                    category,
+                    byte_to_char_position(context.target_string, offset + range.start)
+                        ..byte_to_char_position(context.target_string, offset + range.end),
+                )
+            })
+            .collect();
+    callback(WalkEvent::Captures(captures));
+    walk(context, &mut node.walk(), parsers, callback);
+}
+
 fn walk<'a>(
     context: HighlightContext<'a>,
     cursor: &mut tree_sitter::TreeCursor,
-    callback: &impl Fn(CallbackArguments),
+    parsers: &mut SubParsers,
+    callback: &impl Fn(WalkEvent),
 ) {
     let mut context = context.clone();
 
@@ -198,44 +422,36 @@ fn walk<'a>(
             let mut current_stack = context.kind_stack.clone();
             current_stack.push(KindAndRange::new(&context, &current_node));
 
-            callback(CallbackArguments {
+            callback(WalkEvent::Node(CallbackArguments {
                 language,
                 kind_stack: current_stack.clone(),
-            });
+            }));
         }
 
         let mut require_children = true;
         match current_node.kind() {
             "inline" if !context.in_inline => {
-                let mut parser = md_inline_parser();
-                let tree = parser
+                let tree = parsers
+                    .inline_parser()
                     .parse(
                         &context.target_string
                             [cursor.node().start_byte()..cursor.node().end_byte()],
                         None,
                     )
                     .unwrap();
-                let mut inner_cursor = tree.root_node().walk();
-                walk(
+                walk_tree(
                     context
                         .with_kind(&KindAndRange::new(&context, &current_node))
                         .with_byte_offset(cursor.node().start_byte()),
-                    &mut inner_cursor,
+                    tree.root_node(),
+                    parsers,
                     callback,
                 );
                 require_children = false;
             }
             "code_fence_content" => {
-                let mut parser = match context.language_suggestion.as_str() {
-                    "rust" => rust_parser(),
-                    "java" => java_parser(),
-                    "go" => go_parser(),
-                    "json" => json_parser(),
-                    "bash" => bash_parser(),
-                    "toml" => toml_parser(),
-                    _ => {
-                        return;
-                    }
+                let Some(parser) = parsers.code_parser(&context.language_suggestion) else {
+                    return;
                 };
                 let tree = parser
                     .parse(
@@ -244,22 +460,22 @@ fn walk<'a>(
                         None,
                     )
                     .unwrap();
-                let mut inner_cursor = tree.root_node().walk();
-                walk(
+                walk_tree(
                     context
                         .with_kind(&KindAndRange::new(&context, &current_node))
                         .with_byte_offset(cursor.node().start_byte()),
-                    &mut inner_cursor,
+                    tree.root_node(),
+                    parsers,
                     callback,
                 );
                 require_children = false;
             }
             "info_string" => {
                 let language_node = current_node.child(0).unwrap();
-                let lang = &context.target_string
-                    [language_node.start_byte()..language_node.end_byte()]
-                    .to_string();
-                context = context.with_language_suggestion(lang);
+                let lang =
+                    &context.target_string[language_node.start_byte()..language_node.end_byte()];
+                // 別名で書かれていても対応する言語でハイライトする
+                context = context.with_language_suggestion(resolve_language(lang).unwrap_or(lang));
                 require_children = false;
             }
             _ => {}
@@ -269,6 +485,7 @@ fn walk<'a>(
             walk(
                 context.with_kind(&KindAndRange::new(&context, &current_node)),
                 cursor,
+                parsers,
                 callback,
             );
             cursor.goto_parent();
@@ -344,6 +561,70 @@ fn toml_parser() -> tree_sitter::Parser {
     parser
 }
 
+fn python_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_python::LANGUAGE.into())
+        .unwrap();
+    parser
+}
+
+fn javascript_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_javascript::LANGUAGE.into())
+        .unwrap();
+    parser
+}
+
+fn typescript_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into())
+        .unwrap();
+    parser
+}
+
+fn yaml_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_yaml::LANGUAGE.into())
+        .unwrap();
+    parser
+}
+
+fn html_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_html::LANGUAGE.into())
+        .unwrap();
+    parser
+}
+
+fn c_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_c::LANGUAGE.into())
+        .unwrap();
+    parser
+}
+
+fn cpp_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_cpp::LANGUAGE.into())
+        .unwrap();
+    parser
+}
+
+fn sql_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_sequel::LANGUAGE.into())
+        .unwrap();
+    parser
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Mutex;
@@ -404,35 +685,39 @@ public class HelloWorld {
 goodbye!
 "#;
 
-        markdown_highlight_callback(
-            target_string,
-            |CallbackArguments {
-                 language,
-                 kind_stack,
-             }| {
-                let indent = "  ".repeat(kind_stack.kinds.len());
-                println!("{}-----", indent);
-                println!("{}lang: \"{}\"", indent, language);
-                println!("{}Kind stack: {}", indent, kind_stack.path);
-                println!("{}Range: {:?}", indent, kind_stack.range(0));
-                if language == "rust" && kind_stack.ends_with("function_item.identifier") {
-                    println!(
-                        "{}Matched text: {}",
-                        indent,
-                        target_string.chars().collect::<Vec<_>>()[kind_stack.range(0)]
-                            .iter()
-                            .collect::<String>()
-                    );
-                }
-            },
-        );
+        highlight_callback(MARKDOWN, target_string, |event| {
+            let WalkEvent::Node(CallbackArguments {
+                language,
+                kind_stack,
+            }) = event
+            else {
+                return;
+            };
+            let indent = "  ".repeat(kind_stack.kinds.len());
+            println!("{}-----", indent);
+            println!("{}lang: \"{}\"", indent, language);
+            println!("{}Kind stack: {}", indent, kind_stack.path);
+            println!("{}Range: {:?}", indent, kind_stack.range(0));
+            if language == "rust" && kind_stack.ends_with("function_item.identifier") {
+                println!(
+                    "{}Matched text: {}",
+                    indent,
+                    target_string.chars().collect::<Vec<_>>()[kind_stack.range(0)]
+                        .iter()
+                        .collect::<String>()
+                );
+            }
+        });
     }
 
     #[test]
     fn test_utf8() {
         let target_string = "やさしい🐖**健康料理**365日";
         let has_strong = Mutex::new(false);
-        markdown_highlight_callback(target_string, |CallbackArguments { kind_stack, .. }| {
+        highlight_callback(MARKDOWN, target_string, |event| {
+            let WalkEvent::Node(CallbackArguments { kind_stack, .. }) = event else {
+                return;
+            };
             if kind_stack.ends_with("strong_emphasis") {
                 *has_strong.lock().unwrap() = true;
                 assert_eq!(
@@ -571,4 +856,125 @@ fn save_json_name(now: &str) {
             macro_texts
         );
     }
+
+    #[test]
+    fn test_highlight_language() {
+        let settings = HighlightSettings::default();
+        let target_string = "fn main() {\n    println!(\"あいう\");\n}\n";
+
+        let highlighted = highlight("rust", target_string, &settings);
+        let source_chars: Vec<_> = target_string.chars().collect();
+        let texts: Vec<(&str, String)> = highlighted
+            .iter()
+            .map(|(category, range)| {
+                (
+                    category.as_str(),
+                    source_chars[range.clone()].iter().collect::<String>(),
+                )
+            })
+            .collect();
+        assert!(
+            texts.contains(&("function", "main".to_string())),
+            "{:?}",
+            texts
+        );
+        assert!(
+            texts.contains(&("string", "\"あいう\"".to_string())),
+            "{:?}",
+            texts
+        );
+
+        let highlighted = highlight("toml", "[package]\nname = \"kashikishi\"\n", &settings);
+        assert!(highlighted.iter().any(|(category, _)| category == "string"));
+
+        assert!(highlight("unknown", "text", &settings).is_empty());
+    }
+
+    #[test]
+    fn test_language_from_path() {
+        assert_eq!(language_from_path(Path::new("src/main.rs")), Some("rust"));
+        assert_eq!(language_from_path(Path::new("Cargo.toml")), Some("toml"));
+        assert_eq!(language_from_path(Path::new("memo.MD")), Some("markdown"));
+        assert_eq!(language_from_path(Path::new("run.sh")), Some("bash"));
+        assert_eq!(language_from_path(Path::new("app.ts")), Some("typescript"));
+        assert_eq!(language_from_path(Path::new("main.cpp")), Some("cpp"));
+        assert_eq!(language_from_path(Path::new("schema.sql")), Some("sql"));
+        assert_eq!(language_from_path(Path::new("memo.txt")), None);
+        assert_eq!(language_from_path(Path::new("Makefile")), None);
+        assert!(
+            SUPPORTED_LANGUAGES
+                .iter()
+                .all(|language| root_parser(language).is_some())
+        );
+    }
+
+    #[test]
+    fn test_code_fence_alias() {
+        let settings = HighlightSettings::default();
+        let cases = [
+            ("py", "def main():\n    return \"a\"\n", "def"),
+            ("ts", "const x: number = 1;\n", "const"),
+            ("yml", "name: \"kashikishi\" # comment\n", "# comment"),
+            ("zsh", "if true; then echo \"a\"; fi\n", "if"),
+            ("c++", "int main() { return 0; }\n", "return"),
+            (
+                "javascript",
+                "function main() { return null; }\n",
+                "function",
+            ),
+            ("html", "<p class=\"a\">text</p>\n", "class"),
+            ("c", "#include <stdio.h>\n", "#include"),
+            ("sql", "SELECT name FROM memo; -- comment\n", "SELECT"),
+            ("postgresql", "select 1;\n", "select"),
+        ];
+        for (info_string, code, expected) in cases {
+            let target_string = format!("```{info_string}\n{code}```\n");
+            let source_chars: Vec<_> = target_string.chars().collect();
+            let highlighted = markdown_highlight(&target_string, &settings);
+            assert!(
+                highlighted
+                    .iter()
+                    .any(
+                        |(_, range)| source_chars[range.clone()].iter().collect::<String>()
+                            == expected
+                    ),
+                "{info_string}: {highlighted:?}"
+            );
+        }
+        assert_eq!(resolve_language("Python3"), Some("python"));
+        assert_eq!(resolve_language("sh"), Some("bash"));
+        assert_eq!(resolve_language("SQL"), Some("sql"));
+        assert_eq!(resolve_language("sqlite"), Some("sql"));
+        assert_eq!(resolve_language("cobol"), None);
+    }
+
+    #[test]
+    fn test_query_highlights_with_override() {
+        let settings = HighlightSettings::default();
+        let target_string = "# Title\n\n```rust\nlet x = 1;\n```\n";
+        let source_chars: Vec<_> = target_string.chars().collect();
+        let texts: Vec<(String, String)> = highlight(MARKDOWN, target_string, &settings)
+            .into_iter()
+            .map(|(category, range)| (category, source_chars[range].iter().collect()))
+            .collect();
+        let has = |category: &str, text: &str| {
+            texts
+                .iter()
+                .any(|(c, t)| c.as_str() == category && t.as_str() == text)
+        };
+        // 設定ファイルに定義の無いものは highlights.scm の結果になる
+        assert!(has("punctuation.delimiter", "```"), "{:?}", texts);
+        assert!(has("keyword", "let"), "{:?}", texts);
+        // 設定ファイルの定義が優先される
+        assert!(
+            texts
+                .iter()
+                .any(|(c, t)| c == "markdown.title" && t.contains("Title")),
+            "{:?}",
+            texts
+        );
+        // 重なりは無い
+        let highlighted = highlight(MARKDOWN, target_string, &settings);
+        assert!(highlighted.windows(2).all(|w| w[0].1.end <= w[1].1.start));
+    }
 }
@@ -0,0 +1,199 @@
+//! Markdown のライブプレビューのために、記法の文字と装飾する範囲を求める。
+//!
+//! 範囲はいずれも文字単位で、親の範囲の後に子の範囲が続くように並ぶ。
+use std::ops::Range;
+
+use crate::{TreeCursorIterator, md_inline_parser, md_parser};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewStyle {
+    /// 見出しの `#` や強調の `**`、リンクの括弧など、プレビューでは目立たなくする記法の文字
+    Markup,
+    /// 見出しの文字。値は見出しのレベル
+    Heading(usize),
+    Strong,
+    Emphasis,
+    Strikethrough,
+    Code,
+    /// リンクの文字
+    Link,
+    /// 箇条書きの `-` `*` `+` の文字
+    ListBullet,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewSpan {
+    pub style: PreviewStyle,
+    pub range: Range<usize>,
+}
+
+pub fn markdown_preview(text: &str) -> Vec<PreviewSpan> {
+    let Some(tree) = md_parser().parse(text, None) else {
+        return Vec::new();
+    };
+    let positions = CharPositions::new(text);
+    let mut inline_parser = md_inline_parser();
+
+    let mut spans = Vec::new();
+    for node in TreeCursorIterator::new(tree.walk()) {
+        let range = node.byte_range();
+        match node.kind() {
+            "atx_heading" | "setext_heading" => {
+                if let Some(level) = heading_level(node) {
+                    spans.push(positions.span(PreviewStyle::Heading(level), range));
+                }
+            }
+            "atx_h1_marker"
+            | "atx_h2_marker"
+            | "atx_h3_marker"
+            | "atx_h4_marker"
+            | "atx_h5_marker"
+            | "atx_h6_marker"
+            | "setext_h1_underline"
+            | "setext_h2_underline" => {
+                spans.push(positions.span(PreviewStyle::Markup, range));
+            }
+            "list_marker_minus" | "list_marker_plus" | "list_marker_star" => {
+                // マーカーのノードは後ろの空白を含むので、記号の一文字だけにする
+                let marker = text[range.clone()].trim_start();
+                let start = range.end - marker.len();
+                spans.push(positions.span(PreviewStyle::ListBullet, start..start + 1));
+            }
+            "inline" => {
+                let offset = range.start;
+                if let Some(inline_tree) = inline_parser.parse(&text[range], None) {
+                    for inline_node in TreeCursorIterator::new(inline_tree.walk()) {
+                        inline_spans(&positions, offset, inline_node, &mut spans);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
+fn inline_spans(
+    positions: &CharPositions,
+    offset: usize,
+    node: tree_sitter::Node,
+    spans: &mut Vec<PreviewSpan>,
+) {
+    let range = offset + node.start_byte()..offset + node.end_byte();
+    let style = match node.kind() {
+        "strong_emphasis" => PreviewStyle::Strong,
+        "emphasis" => PreviewStyle::Emphasis,
+        "strikethrough" => PreviewStyle::Strikethrough,
+        "code_span" => PreviewStyle::Code,
+        "emphasis_delimiter" | "code_span_delimiter" => PreviewStyle::Markup,
+        // リンクは全体を記法の文字とし、後に続く link_text の範囲で上書きする
+        "inline_link" | "full_reference_link" | "collapsed_reference_link" | "shortcut_link" => {
+            PreviewStyle::Markup
+        }
+        "link_text" => PreviewStyle::Link,
+        "uri_autolink" | "email_autolink" => {
+            // `<` と `>` は記法の文字とし、その間をリンクとする
+            spans.push(positions.span(PreviewStyle::Markup, range.clone()));
+            spans.push(positions.span(PreviewStyle::Link, range.start + 1..range.end - 1));
+            return;
+        }
+        _ => return,
+    };
+    spans.push(positions.span(style, range));
+}
+
+fn heading_level(node: tree_sitter::Node) -> Option<usize> {
+    let mut cursor = node.walk();
+    node.children(&mut cursor)
+        .find_map(|child| match child.kind() {
+            "atx_h1_marker" | "setext_h1_underline" => Some(1),
+            "atx_h2_marker" | "setext_h2_underline" => Some(2),
+            "atx_h3_marker" => Some(3),
+            "atx_h4_marker" => Some(4),
+            "atx_h5_marker" => Some(5),
+            "atx_h6_marker" => Some(6),
+            _ => None,
+        })
+}
+
+// バイト位置から文字位置への変換。ノードごとに先頭から数え直さないよう、文字の開始位置を持っておく
+struct CharPositions {
+    char_starts: Vec<usize>,
+}
+
+impl CharPositions {
+    fn new(text: &str) -> Self {
+        Self {
+            char_starts: text.char_indices().map(|(index, _)| index).collect(),
+        }
+    }
+
+    fn position(&self, byte: usize) -> usize {
+        self.char_starts.partition_point(|start| *start < byte)
+    }
+
+    fn span(&self, style: PreviewStyle, range: Range<usize>) -> PreviewSpan {
+        PreviewSpan {
+            style,
+            range: self.position(range.start)..self.position(range.end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled(text: &str, style: PreviewStyle) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        markdown_preview(text)
+            .into_iter()
+            .filter(|span| span.style == style)
+            .map(|span| chars[span.range].iter().collect::<String>())
+            .collect()
+    }
+
+    #[test]
+    fn preview_heading() {
+        let text = "# 見出し\n\n本文\n\nSub\n---\n";
+        assert_eq!(
+            styled(text, PreviewStyle::Heading(1)),
+            vec!["# 見出し\n".to_string()]
+        );
+        assert_eq!(
+            styled(text, PreviewStyle::Heading(2)),
+            vec!["Sub\n---\n".to_string()]
+        );
+        assert_eq!(
+            styled(text, PreviewStyle::Markup),
+            vec!["#".to_string(), "---".to_string()]
+        );
+    }
+
+    #[test]
+    fn preview_inline() {
+        let text = "これは **太字** と *斜体* と ~~取り消し~~ と `code`\n";
+        assert_eq!(styled(text, PreviewStyle::Strong), vec!["**太字**"]);
+        assert_eq!(styled(text, PreviewStyle::Emphasis), vec!["*斜体*"]);
+        assert_eq!(styled(text, PreviewStyle::Code), vec!["`code`"]);
+        assert!(styled(text, PreviewStyle::Strikethrough).contains(&"~~取り消し~~".to_string()));
+        let markup = styled(text, PreviewStyle::Markup);
+        assert_eq!(markup.iter().filter(|m| *m == "*").count(), 6);
+        assert_eq!(markup.iter().filter(|m| *m == "`").count(), 2);
+    }
+
+    #[test]
+    fn preview_list_and_link() {
+        let text =
+            "- 項目\n  * 入れ子\n1. 番号\n\n[リンク](https://example.com) <https://example.com>\n";
+        assert_eq!(styled(text, PreviewStyle::ListBullet), vec!["-", "*"]);
+        assert_eq!(
+            styled(text, PreviewStyle::Link),
+            vec!["リンク", "https://example.com"]
+        );
+        assert_eq!(
+            styled(text, PreviewStyle::Markup),
+            vec!["[リンク](https://example.com)", "<https://example.com>"]
+        );
+    }
+}
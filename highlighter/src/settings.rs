@@ -1,39 +1,116 @@
-use std::{collections::HashSet, ops::Range};
+use std::{
+    collections::HashSet,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
 use serde::Deserialize;
+use thiserror::Error;
 
 use crate::CallbackArguments;
 
+#[derive(Debug, Error)]
+pub enum HighlightSettingsError {
+    #[error("failed to read highlight settings. path:{path:?}, error:{source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid highlight settings. name:{name}, error:{source}")]
+    Parse {
+        name: String,
+        source: serde_json::Error,
+    },
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct HighlightSettings {
     pub definitions: Vec<HighlightCategoryDefinition>,
 }
 
+const BUNDLED_SETTINGS: [&str; 15] = [
+    include_str!("../asset/markdown.json"),
+    include_str!("../asset/json.json"),
+    include_str!("../asset/rust.json"),
+    include_str!("../asset/java.json"),
+    include_str!("../asset/go.json"),
+    include_str!("../asset/bash.json"),
+    include_str!("../asset/toml.json"),
+    include_str!("../asset/python.json"),
+    include_str!("../asset/javascript.json"),
+    include_str!("../asset/typescript.json"),
+    include_str!("../asset/yaml.json"),
+    include_str!("../asset/html.json"),
+    include_str!("../asset/c.json"),
+    include_str!("../asset/cpp.json"),
+    include_str!("../asset/sql.json"),
+];
+
 impl Default for HighlightSettings {
     fn default() -> Self {
-        Self::load_settings(&[
-            include_str!("../asset/markdown.json"),
-            include_str!("../asset/json.json"),
-            include_str!("../asset/rust.json"),
-            include_str!("../asset/java.json"),
-            include_str!("../asset/go.json"),
-            include_str!("../asset/bash.json"),
-            include_str!("../asset/toml.json"),
-        ])
+        // 同梱の定義が正しいことはテストで確認している
+        Self::load_settings(&BUNDLED_SETTINGS).expect("bundled highlight settings are invalid")
     }
 }
 
 impl HighlightSettings {
-    pub fn load_settings(setting_strings: &[&str]) -> Self {
+    pub fn load_settings(setting_strings: &[&str]) -> Result<Self, HighlightSettingsError> {
         let mut result = HighlightSettings {
             definitions: vec![],
         };
-        for setting_string in setting_strings {
-            let defs: Vec<HighlightCategoryDefinition> =
-                serde_json::from_str(setting_string).unwrap();
-            result.definitions.extend(defs);
+        for (index, setting_string) in setting_strings.iter().enumerate() {
+            result.add_definitions(setting_string, &format!("#{index}"))?;
+        }
+        Ok(result)
+    }
+
+    /// dir にある *.json の定義を読み込み、今の定義に追加する。
+    /// 同じキーの定義は後から追加したものが優先されるので、既定の定義を上書きできる。
+    /// dir が存在しない場合は何もしない
+    pub fn with_user_settings(mut self, dir: &Path) -> Result<Self, HighlightSettingsError> {
+        if !dir.exists() {
+            return Ok(self);
+        }
+        let io_error = |source| HighlightSettingsError::Io {
+            path: dir.to_path_buf(),
+            source,
+        };
+        let mut paths = std::fs::read_dir(dir)
+            .map_err(io_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(io_error)?;
+        paths.retain(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        });
+        // 読み込む順序で優先度が変わるので、ファイル名の順にする
+        paths.sort();
+        for path in paths {
+            let setting_string =
+                std::fs::read_to_string(&path).map_err(|source| HighlightSettingsError::Io {
+                    path: path.clone(),
+                    source,
+                })?;
+            self.add_definitions(&setting_string, &path.to_string_lossy())?;
         }
-        result
+        Ok(self)
+    }
+
+    fn add_definitions(
+        &mut self,
+        setting_string: &str,
+        name: &str,
+    ) -> Result<(), HighlightSettingsError> {
+        let defs: Vec<HighlightCategoryDefinition> =
+            serde_json::from_str(setting_string).map_err(|source| {
+                HighlightSettingsError::Parse {
+                    name: name.to_string(),
+                    source,
+                }
+            })?;
+        self.definitions.extend(defs);
+        Ok(())
     }
 
     pub(crate) fn args_to_definition(
@@ -54,6 +131,7 @@ impl HighlightSettings {
                 }
             }
         }
+        // スコアが同じ場合は後から追加した定義を優先する(max_by_key は最後の最大値を返す)
         if let Some((_, res)) = result.into_iter().max_by_key(|(score, _)| *score) {
             Some(res)
         } else {
@@ -87,7 +165,7 @@ pub struct KeyDefinition {
 
 #[cfg(test)]
 mod tests {
-    use crate::settings::HighlightSettings;
+    use crate::settings::{HighlightSettings, HighlightSettingsError};
 
     #[test]
     fn test_load_definitions() {
@@ -103,11 +181,55 @@ mod tests {
             }
         ]
 "#;
-        let settings = HighlightSettings::load_settings(&[config_string]);
+        let settings = HighlightSettings::load_settings(&[config_string]).unwrap();
         let definitions = settings.definitions;
         assert_eq!(definitions.len(), 1);
         assert_eq!(definitions[0].name, "function");
         assert_eq!(definitions[0].language, "rust");
         assert_eq!(definitions[0].key_definitions.len(), 2);
     }
+
+    #[test]
+    fn test_invalid_definitions() {
+        let result = HighlightSettings::load_settings(&["[]", "[{\"name\": \"function\"}]"]);
+        assert!(matches!(
+            result,
+            Err(HighlightSettingsError::Parse { name, .. }) if name == "#1"
+        ));
+    }
+
+    #[test]
+    fn test_user_settings() {
+        let dir = std::env::temp_dir().join(format!("highlighter-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("rust.json"),
+            r#"[{ "name": "type", "language": "rust", "key_definitions": [{ "key": "fn", "depth": 0 }] }]"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("README.md"), "not a definition").unwrap();
+
+        let settings = HighlightSettings::default()
+            .with_user_settings(&dir)
+            .unwrap();
+        // 既定では keyword になる fn を、利用者の定義で上書きする
+        let highlighted = crate::highlight("rust", "fn main() {}", &settings);
+        assert!(highlighted.contains(&("type".to_string(), 0..2)));
+
+        std::fs::write(dir.join("broken.json"), "[").unwrap();
+        let result = HighlightSettings::default().with_user_settings(&dir);
+        assert!(matches!(
+            result,
+            Err(HighlightSettingsError::Parse { name, .. }) if name.ends_with("broken.json")
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // ディレクトリが無ければ既定の定義のまま
+        assert_eq!(
+            HighlightSettings::default()
+                .with_user_settings(&dir)
+                .unwrap(),
+            HighlightSettings::default()
+        );
+    }
 }
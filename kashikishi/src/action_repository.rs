@@ -1,7 +1,7 @@
 use std::sync::LazyLock;
 
 use serde::{Deserialize, Serialize};
-use stroke_parser::Action;
+use stroke_parser::{Action, ActionArgument};
 
 static MODE_ACTIONS: LazyLock<Vec<ActionDefinition>> =
     LazyLock::new(|| serde_json::from_str(include_str!("../asset/actions/mode.json")).unwrap());
@@ -34,6 +34,9 @@ pub struct ActionDefinition {
     pub namespace: String,
     pub name: String,
     pub description: String,
+    /// コマンドパレットでローマ字から検索するための description の読みがな
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reading: Option<String>,
 }
 
 impl ActionDefinition {
@@ -57,4 +60,33 @@ impl ActionRepository {
             ActionNamespace::Custom(_) => todo!(),
         }
     }
+
+    /// Action の説明を返す。定義が見つからない場合は namespace:name を返す
+    pub(crate) fn describe(&self, action: &Action) -> String {
+        let Action::Command(namespace, name, argument) = action else {
+            return format!("{:?}", action);
+        };
+        let mut description = format!("{}:{}", **namespace, **name);
+        for action_namespace in [
+            ActionNamespace::Mode,
+            ActionNamespace::System,
+            ActionNamespace::Edit,
+            ActionNamespace::World,
+            ActionNamespace::ActionRecorder,
+            ActionNamespace::Kashikishi,
+        ] {
+            if let Some(definition) = self
+                .load_actions(action_namespace)
+                .iter()
+                .find(|d| d.namespace == **namespace && d.name == **name)
+            {
+                description = definition.description.clone();
+                break;
+            }
+        }
+        match argument {
+            ActionArgument::None => description,
+            argument => format!("{}({})", description, argument),
+        }
+    }
 }
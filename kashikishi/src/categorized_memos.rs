@@ -5,6 +5,7 @@ use std::{
 };
 
 use chrono::{DateTime, Local};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use crate::memos::Memos;
@@ -33,93 +34,23 @@ pub(crate) struct CategorizedMemos {
     pub(crate) categorized: BTreeMap<String, Memos>,
 }
 
-const DEFAULT_CATEGORY: &str = "default";
-
 impl CategorizedMemos {
-    fn new() -> Self {
-        CategorizedMemos {
-            current_category: DEFAULT_CATEGORY.to_string(),
-            categorized: BTreeMap::from([(DEFAULT_CATEGORY.to_string(), Memos::default())]),
-        }
-    }
-
-    pub(crate) fn load_memos() -> CategorizedMemos {
+    /// 以前の形式で保存されたメモを読み込む。ワークスペースへの移行にのみ使う
+    pub(crate) fn load_legacy_memos() -> Option<CategorizedMemos> {
         let memos_file = memos_file();
-        if memos_file.exists() {
-            // Read memos from file
-            let memos_json = fs::read_to_string(memos_file).unwrap();
-            serde_json::from_str(&memos_json).unwrap()
-        } else {
-            // ファイルが存在しない時は、親ディレクトリまで作成してからファイルを作る
-            let memos_dir = memos_file.parent().unwrap();
-            fs::create_dir_all(memos_dir).unwrap();
-
-            let memos = CategorizedMemos::new();
-            let memos_json = serde_json::to_string(&memos).unwrap();
-            fs::write(memos_file, memos_json).unwrap();
-            memos
+        let memos_json = fs::read_to_string(&memos_file).ok()?;
+        match serde_json::from_str(&memos_json) {
+            Ok(memos) => Some(memos),
+            Err(e) => {
+                warn!("failed to load legacy memos. path:{memos_file:?}, error:{e}");
+                None
+            }
         }
     }
-
-    pub(crate) fn save_memos(&self) -> Result<(), std::io::Error> {
-        if Self::load_memos() == *self {
-            return Ok(());
-        }
-
-        let memos_file = memos_file();
-        // 上記のファイルを memos.[現在日時].json にリネームして保存する
-        let now = chrono::Local::now();
-        let memos_file_backup =
-            memos_file.with_extension(format!("{}.json", now.format("%Y%m%d%H%M%S")));
-        fs::rename(&memos_file, memos_file_backup)?;
-
-        let memos_json = serde_json::to_string_pretty(self).unwrap();
-        fs::write(memos_file, memos_json)
-    }
-
-    pub(crate) fn update_current_memos(&mut self, memos: Memos) {
-        self.categorized
-            .insert(self.current_category.to_string(), memos);
-    }
-
-    pub(crate) fn get_current_memos(&self) -> Option<&Memos> {
-        self.categorized.get(self.current_category.as_str())
-    }
-
-    pub(crate) fn add_memo(&mut self, category: Option<&str>, content: String) {
-        self.categorized
-            .entry(category.unwrap_or(DEFAULT_CATEGORY).to_string())
-            .or_insert(Memos::empty())
-            .memos
-            .push(content);
-    }
-
-    pub(crate) fn rename_category(&mut self, new_name: &str, old_name: &str) {
-        let memos = self.categorized.remove(old_name).unwrap_or_default();
-        self.categorized.insert(new_name.to_string(), memos);
-    }
-
-    pub(crate) fn remove_category(&mut self, category: &str) {
-        if self.current_category == category {
-            self.current_category = DEFAULT_CATEGORY.to_string();
-        }
-        let category_docs: Option<Memos> = self.categorized.remove(category);
-        if let Some(mut category_docs) = category_docs {
-            self.categorized
-                .entry(DEFAULT_CATEGORY.to_string())
-                .or_insert(Memos::empty())
-                .memos
-                .append(&mut category_docs.memos);
-        }
-    }
-
-    pub(crate) fn categories(&self) -> Vec<String> {
-        self.categorized.keys().cloned().collect()
-    }
 }
 
 fn memos_file() -> PathBuf {
     // いわゆるホームディレクトリのパスを取得する
-    let home_dir = dirs::home_dir().unwrap();
+    let home_dir = dirs::home_dir().unwrap_or_default();
     Path::new(&home_dir).join(".config/kashikishi/categorized_memos.json")
 }
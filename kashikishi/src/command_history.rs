@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// 最近使ったものとして優先的に並べるコマンドの数
+const RECENT_COMMANDS: usize = 5;
+/// 履歴として保持するコマンドの最大数
+const MAX_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct CommandHistoryEntry {
+    /// namespace:name 形式のコマンド
+    command: String,
+    count: u32,
+    /// 最後に使われた順番。大きいほど新しい
+    last_used: u64,
+}
+
+/// コマンドパレットから実行したコマンドの履歴。
+///
+/// $HOME/.config/kashikishi/command_history.json に保存され、
+/// パレットの並び順を「最近使ったもの → よく使うもの → その他」にするために使う。
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub(crate) struct CommandHistory {
+    entries: Vec<CommandHistoryEntry>,
+    sequence: u64,
+}
+
+impl CommandHistory {
+    pub(crate) fn load() -> Self {
+        let history_file = history_file();
+        if !history_file.exists() {
+            return Self::default();
+        }
+        // 履歴は失われても困らないので読み込めない場合は空の履歴から始める
+        match std::fs::read_to_string(&history_file)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+        {
+            Ok(history) => history,
+            Err(e) => {
+                warn!("failed to load command history. path:{history_file:?}, error:{e}");
+                Self::default()
+            }
+        }
+    }
+
+    pub(crate) fn save(&self) {
+        let history_file = history_file();
+        let result = serde_json::to_string(self)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&history_file, json).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!("failed to save command history. path:{history_file:?}, error:{e}");
+        }
+    }
+
+    pub(crate) fn record(&mut self, command: &str) {
+        self.sequence += 1;
+        match self.entries.iter_mut().find(|e| e.command == command) {
+            Some(entry) => {
+                entry.count += 1;
+                entry.last_used = self.sequence;
+            }
+            None => self.entries.push(CommandHistoryEntry {
+                command: command.to_string(),
+                count: 1,
+                last_used: self.sequence,
+            }),
+        }
+        if self.entries.len() > MAX_ENTRIES {
+            // 使用回数が少なく、古いものから捨てる
+            self.entries
+                .sort_by(|a, b| b.count.cmp(&a.count).then(b.last_used.cmp(&a.last_used)));
+            self.entries.truncate(MAX_ENTRIES);
+        }
+    }
+
+    /// 並び順の優先度。値が小さいほど先頭に並べる。履歴にないコマンドは None
+    pub(crate) fn rank(&self, command: &str) -> Option<usize> {
+        self.ranked_commands().iter().position(|c| *c == command)
+    }
+
+    fn ranked_commands(&self) -> Vec<&str> {
+        let mut recent = self.entries.iter().collect::<Vec<_>>();
+        recent.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+        let (recent, rest) = recent.split_at(recent.len().min(RECENT_COMMANDS));
+
+        let mut frequent = rest.to_vec();
+        frequent.sort_by(|a, b| b.count.cmp(&a.count).then(b.last_used.cmp(&a.last_used)));
+
+        recent
+            .iter()
+            .chain(frequent.iter())
+            .map(|e| e.command.as_str())
+            .collect()
+    }
+}
+
+fn history_file() -> PathBuf {
+    // いわゆるホームディレクトリのパスを取得する
+    let home_dir = dirs::home_dir().unwrap_or_default();
+    Path::new(&home_dir).join(".config/kashikishi/command_history.json")
+}
@@ -0,0 +1,366 @@
+//! 異常終了したときにメモを復元するためのジャーナル。
+//!
+//! 最後に保存した後の編集内容を、メモごとの全文として `.journal` に追記していく。
+//! 追記の途中で終了して最後の行が壊れていても、それより前の行は読み込める。
+//!
+//! 起動中は `.session` を置いてロックしておき、正常に終了したときや Session を破棄したときに取り除く。
+//! 起動時に `.session` が残っていて、どのプロセスもロックしていなければ前回は異常終了したとみなし、
+//! その時点の `.journal` を `.journal.recover` に退避して復元の対象とする。
+//! 他のプロセスがロックしていれば、そのプロセスが起動中なので復元の対象にしない。
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::workspace::{WorkspaceError, write_atomically};
+
+const JOURNAL_FILE: &str = ".journal";
+const RECOVER_FILE: &str = ".journal.recover";
+const SESSION_FILE: &str = ".session";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct JournalEntry {
+    path: PathBuf,
+    text: String,
+}
+
+/// 追記専用のジャーナルファイル
+pub(crate) struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub(crate) fn new(root: &Path) -> Self {
+        Self {
+            path: root.join(JOURNAL_FILE),
+        }
+    }
+
+    pub(crate) fn append(&self, path: &Path, text: &str) -> Result<(), WorkspaceError> {
+        let entry = JournalEntry {
+            path: path.to_path_buf(),
+            text: text.to_string(),
+        };
+        // serde_json は改行をエスケープするので、一つのエントリは必ず一行になる
+        let mut line = serde_json::to_string(&entry).unwrap_or_default();
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|source| WorkspaceError::Io {
+                path: self.path.clone(),
+                source,
+            })
+    }
+
+    /// すべてのメモを保存し終えたときに呼び出し、ジャーナルを空にする
+    pub(crate) fn clear(&self) {
+        if let Err(e) = fs::remove_file(&self.path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("failed to clear journal. path:{:?}, error:{e}", self.path);
+        }
+    }
+}
+
+fn read_entries(path: &Path) -> Vec<JournalEntry> {
+    let Ok(journal) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    journal
+        .lines()
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                // 書き込みの途中で終了した行は読み飛ばす
+                warn!("skip broken journal entry. path:{path:?}, error:{e}");
+                None
+            }
+        })
+        .collect()
+}
+
+// ワークスペースごとの開始中のセッション
+#[derive(Default)]
+struct ActiveSession {
+    // モードの切り替えで新旧のワールドのセッションが重なるので数える
+    count: usize,
+    // ロックしている `.session`。他のプロセスがロックしている場合は None
+    marker: Option<File>,
+}
+
+static ACTIVE_SESSIONS: Mutex<BTreeMap<PathBuf, ActiveSession>> = Mutex::new(BTreeMap::new());
+
+// `.session` を開いてロックし、異常終了した前回のセッションが残っていたかどうかと合わせて返す
+fn lock_session_file(session_file: &Path) -> (Option<File>, bool) {
+    let existed = session_file.exists();
+    let file = match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(session_file)
+    {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("failed to create session file. path:{session_file:?}, error:{e}");
+            return (None, existed);
+        }
+    };
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            info!("session is owned by another process. path:{session_file:?}");
+            return (None, false);
+        }
+        Err(TryLockError::Error(e)) => {
+            // ロックできないファイルシステムでは、マーカーが残っているかどうかだけで判断する
+            warn!("failed to lock session file. path:{session_file:?}, error:{e}");
+        }
+    }
+    if let Err(e) = file
+        .set_len(0)
+        .and_then(|_| (&file).write_all(std::process::id().to_string().as_bytes()))
+    {
+        warn!("failed to write session file. path:{session_file:?}, error:{e}");
+    }
+    (Some(file), existed)
+}
+
+/// 起動中であることを示すマーカー。異常終了の検出に使う
+pub(crate) struct Session {
+    root: PathBuf,
+    finished: bool,
+}
+
+impl Session {
+    /// セッションを開始する。前回のセッションが異常終了していれば、
+    /// 復元できる内容を `.journal.recover` に退避する
+    pub(crate) fn start(root: &Path) -> Self {
+        let session_file = root.join(SESSION_FILE);
+        let journal_file = root.join(JOURNAL_FILE);
+        let mut active_sessions = ACTIVE_SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+        let active = active_sessions.entry(root.to_path_buf()).or_default();
+        // このプロセスで開始中のセッションがあれば、モードを切り替えただけなので異常終了ではない
+        let mut unclean = false;
+        if active.count == 0 {
+            (active.marker, unclean) = lock_session_file(&session_file);
+        }
+        active.count += 1;
+        if unclean && !read_entries(&journal_file).is_empty() {
+            let recover_file = root.join(RECOVER_FILE);
+            // 前回の復元を選ばないまま再び異常終了した場合は、古い内容の後ろに追記する
+            let result = fs::read_to_string(&journal_file)
+                .and_then(|journal| {
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&recover_file)
+                        // 途中で途切れた行に続けて書かないよう、改行を挟む
+                        .and_then(|mut file| write!(file, "\n{}", journal))
+                })
+                .and_then(|_| fs::remove_file(&journal_file));
+            if let Err(e) = result {
+                warn!("failed to keep journal for recovery. error:{e}");
+            }
+        }
+        Self {
+            root: root.to_path_buf(),
+            finished: false,
+        }
+    }
+
+    /// 正常に終了したことを記録する。同じワークスペースのセッションがすべて終了したらマーカーを取り除く
+    pub(crate) fn finish(&mut self) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        let mut active_sessions = ACTIVE_SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(active) = active_sessions.get_mut(&self.root) else {
+            return;
+        };
+        active.count -= 1;
+        if active.count == 0
+            && let Some(ActiveSession {
+                marker: Some(_marker),
+                ..
+            }) = active_sessions.remove(&self.root)
+        {
+            // 他のプロセスに取られないよう、ロックしたまま取り除く
+            let _ = fs::remove_file(self.root.join(SESSION_FILE));
+        }
+    }
+
+    /// 異常終了した前回のセッションから復元できるメモの内容。メモごとに最新の内容のみを返す
+    pub(crate) fn recoverable(&self) -> BTreeMap<PathBuf, String> {
+        read_entries(&self.root.join(RECOVER_FILE))
+            .into_iter()
+            .map(|entry| (entry.path, entry.text))
+            .collect()
+    }
+
+    /// 復元できる内容をファイルに書き戻す。書き戻したメモのパスを返す
+    pub(crate) fn recover(&self) -> Result<Vec<PathBuf>, WorkspaceError> {
+        let recoverable = self.recoverable();
+        for (path, text) in recoverable.iter() {
+            write_atomically(path, text)?;
+        }
+        self.discard_recoverable();
+        Ok(recoverable.into_keys().collect())
+    }
+
+    pub(crate) fn discard_recoverable(&self) {
+        let _ = fs::remove_file(self.root.join(RECOVER_FILE));
+    }
+
+    /// 異常終了を再現するため、マーカーを残したままロックを解いてセッションを終える
+    #[cfg(test)]
+    fn abandon(mut self) {
+        let mut active_sessions = ACTIVE_SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(active) = active_sessions.get_mut(&self.root) {
+            active.count -= 1;
+            if active.count == 0 {
+                active_sessions.remove(&self.root);
+            }
+        }
+        self.finished = true;
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workspace::tests::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_journal_append_and_clear() {
+        let dir = TempDir::new();
+        let journal = Journal::new(&dir.0);
+        journal.append(&dir.0.join("a.md"), "first\nline").unwrap();
+        journal.append(&dir.0.join("a.md"), "second").unwrap();
+        assert_eq!(
+            read_entries(&journal.path)
+                .iter()
+                .map(|e| e.text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["first\nline", "second"]
+        );
+        journal.clear();
+        assert!(read_entries(&journal.path).is_empty());
+    }
+
+    #[test]
+    fn test_recover_after_unclean_shutdown() {
+        let dir = TempDir::new();
+        let memo = dir.0.join("default/a.md");
+        fs::create_dir_all(memo.parent().unwrap()).unwrap();
+        fs::write(&memo, "saved").unwrap();
+
+        // 編集中に異常終了し、最後の追記が途中で途切れた状態を作る
+        let session = Session::start(&dir.0);
+        let journal = Journal::new(&dir.0);
+        journal.append(&memo, "edited").unwrap();
+        journal.append(&memo, "edited more").unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.0.join(JOURNAL_FILE))
+            .unwrap();
+        file.write_all(br#"{"path":"default/a.md","te"#).unwrap();
+        session.abandon();
+        assert!(dir.0.join(SESSION_FILE).exists());
+
+        let session = Session::start(&dir.0);
+        assert!(read_entries(&journal.path).is_empty());
+        assert_eq!(
+            session.recoverable(),
+            BTreeMap::from([(memo.clone(), "edited more".to_string())])
+        );
+        assert_eq!(session.recover().unwrap(), vec![memo.clone()]);
+        assert_eq!(fs::read_to_string(&memo).unwrap(), "edited more");
+        assert!(session.recoverable().is_empty());
+    }
+
+    #[test]
+    fn test_recoverable_when_not_finished() {
+        let dir = TempDir::new();
+        let session = Session::start(&dir.0);
+        Journal::new(&dir.0)
+            .append(&dir.0.join("a.md"), "edited")
+            .unwrap();
+        session.abandon();
+
+        let session = Session::start(&dir.0);
+        assert_eq!(
+            session.recoverable(),
+            BTreeMap::from([(dir.0.join("a.md"), "edited".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_no_recovery_after_clean_shutdown() {
+        let dir = TempDir::new();
+        let mut session = Session::start(&dir.0);
+        Journal::new(&dir.0)
+            .append(&dir.0.join("a.md"), "edited")
+            .unwrap();
+        session.finish();
+        assert!(!dir.0.join(SESSION_FILE).exists());
+
+        let session = Session::start(&dir.0);
+        assert!(session.recoverable().is_empty());
+    }
+
+    #[test]
+    fn test_overlapping_sessions() {
+        let dir = TempDir::new();
+        let other = TempDir::new();
+        // 別のワークスペースのセッションは数に含めない
+        let other_session = Session::start(&other.0);
+        let session = Session::start(&dir.0);
+        Journal::new(&dir.0)
+            .append(&dir.0.join("a.md"), "edited")
+            .unwrap();
+
+        // モードの切り替えで新しいセッションが先に始まる
+        let new_session = Session::start(&dir.0);
+        assert!(new_session.recoverable().is_empty());
+        drop(session);
+        assert!(dir.0.join(SESSION_FILE).exists());
+        drop(new_session);
+        assert!(!dir.0.join(SESSION_FILE).exists());
+        assert!(other.0.join(SESSION_FILE).exists());
+        drop(other_session);
+    }
+
+    #[test]
+    fn test_no_recovery_while_another_process_running() {
+        let dir = TempDir::new();
+        let memo = dir.0.join("a.md");
+        Journal::new(&dir.0).append(&memo, "edited").unwrap();
+        // 他のプロセスが起動中で、`.session` をロックしている状態を作る
+        let other_process = File::create(dir.0.join(SESSION_FILE)).unwrap();
+        other_process.lock().unwrap();
+
+        let session = Session::start(&dir.0);
+        assert!(session.recoverable().is_empty());
+        assert_eq!(read_entries(&dir.0.join(JOURNAL_FILE)).len(), 1);
+        // 他のプロセスのマーカーは取り除かない
+        drop(session);
+        assert!(dir.0.join(SESSION_FILE).exists());
+    }
+}
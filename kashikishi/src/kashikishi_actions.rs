@@ -1,14 +1,21 @@
+use std::path::{Path, PathBuf};
+
 use chrono::Days;
-use stroke_parser::Action;
+use stroke_parser::{Action, ActionStore};
 use ui_support::ui::{FileChooser, SelectBox, SelectOption, TextInput};
 use ui_support::ui_context::UiContext;
 
 use crate::{
     action_repository::{ActionNamespace, ActionRepository},
-    categorized_memos::CategorizedMemos,
+    command_history::CommandHistory,
 };
 
-pub(crate) fn command_palette_select(context: &UiContext, narrow: Option<String>) -> SelectBox {
+pub(crate) fn command_palette_select(
+    context: &UiContext,
+    narrow: Option<String>,
+    store: &ActionStore,
+    history: &CommandHistory,
+) -> SelectBox {
     let mut options = Vec::new();
 
     let action_repository = ActionRepository::default();
@@ -22,13 +29,32 @@ pub(crate) fn command_palette_select(context: &UiContext, narrow: Option<String>
         ActionNamespace::Kashikishi,
     ] {
         for action_definition in action_repository.load_actions(namespace) {
-            options.push(SelectOption::new(
+            let command = format!("{}:{}", action_definition.namespace, action_definition.name);
+            let action = action_definition.to_action();
+            let key_hint = store.stroke_strings(&action).join(", ");
+
+            // 実行したコマンドを履歴に残すため、選択時に記録用のアクションも送る
+            let mut option = SelectOption::new_with_trailing_actions(
                 action_definition.description.clone(),
-                action_definition.to_action(),
-            ));
+                action,
+                vec![Action::new_command_with_argument(
+                    "kashikishi",
+                    "record-command-history",
+                    &command,
+                )],
+            )
+            .with_keywords(action_definition.reading.iter().cloned().collect());
+            if !key_hint.is_empty() {
+                option = option.with_key_hint(key_hint);
+            }
+            options.push((history.rank(&command), option));
         }
     }
-    SelectBox::new(context, "アクションの選択".to_string(), options, narrow)
+    // 履歴にあるものを先頭に並べる。履歴にないものは定義順のまま
+    options.sort_by_key(|(rank, _)| rank.unwrap_or(usize::MAX));
+    let options = options.into_iter().map(|(_, option)| option).collect();
+
+    SelectBox::new(context, "アクションの選択".to_string(), options, narrow).with_fuzzy_match()
 }
 
 pub(crate) fn insert_date_select(context: &UiContext) -> SelectBox {
@@ -67,14 +93,11 @@ pub(crate) fn insert_date_select(context: &UiContext) -> SelectBox {
     SelectBox::new(context, "挿入したい日付を選択".to_string(), options, None)
 }
 
-pub(crate) fn move_category_ui(
-    context: &UiContext,
-    categorized_memos: &CategorizedMemos,
-) -> SelectBox {
+pub(crate) fn move_category_ui(context: &UiContext, categories: &[String]) -> SelectBox {
     let mut options = vec![];
 
     let mut has_archive = false;
-    for category in categorized_memos.categories() {
+    for category in categories {
         if category == "archive" {
             has_archive = true;
         }
@@ -83,7 +106,7 @@ pub(crate) fn move_category_ui(
             vec![Action::new_command_with_argument(
                 "kashikishi",
                 "move-category",
-                &category,
+                category,
             )],
         ));
     }
@@ -106,12 +129,12 @@ pub(crate) fn move_category_ui(
     )
 }
 
-pub(crate) fn move_memo_ui(context: &UiContext, categorized_memos: &CategorizedMemos) -> SelectBox {
+pub(crate) fn move_memo_ui(context: &UiContext, categories: &[String]) -> SelectBox {
     let mut options = Vec::new();
-    for category in categorized_memos.categories() {
+    for category in categories {
         options.push(SelectOption::new(
             category.clone(),
-            Action::new_command_with_argument("kashikishi", "move-memo", &category),
+            Action::new_command_with_argument("kashikishi", "move-memo", category),
         ));
     }
     SelectBox::new_without_action_name(
@@ -131,15 +154,12 @@ pub(crate) fn add_category_ui(context: &UiContext) -> TextInput {
     )
 }
 
-pub(crate) fn rename_category_select_ui(
-    context: &UiContext,
-    categorized_memos: &CategorizedMemos,
-) -> SelectBox {
+pub(crate) fn rename_category_select_ui(context: &UiContext, categories: &[String]) -> SelectBox {
     let mut options = Vec::new();
-    for category in categorized_memos.categories() {
+    for category in categories {
         options.push(SelectOption::new(
             category.clone(),
-            Action::new_command_with_argument("kashikishi", "rename-category-ui", &category),
+            Action::new_command_with_argument("kashikishi", "rename-category-ui", category),
         ));
     }
     SelectBox::new_without_action_name(
@@ -159,15 +179,12 @@ pub(crate) fn rename_category_ui(context: &UiContext, category_name: &str) -> Te
     )
 }
 
-pub(crate) fn remove_category_ui(
-    context: &UiContext,
-    categorized_memos: &CategorizedMemos,
-) -> SelectBox {
+pub(crate) fn remove_category_ui(context: &UiContext, categories: &[String]) -> SelectBox {
     let mut options = Vec::new();
-    for category in categorized_memos.categories() {
+    for category in categories {
         options.push(SelectOption::new(
             category.clone(),
-            Action::new_command_with_argument("kashikishi", "remove-category", &category),
+            Action::new_command_with_argument("kashikishi", "remove-category", category),
         ));
     }
     SelectBox::new_without_action_name(
@@ -186,3 +203,83 @@ pub(crate) fn open_file_ui(context: &UiContext) -> FileChooser {
         Action::new_command("kashikishi", "open-file"),
     )
 }
+
+pub(crate) fn select_memo_ui(context: &UiContext, memo_names: &[(String, bool)]) -> SelectBox {
+    let options = memo_names
+        .iter()
+        .enumerate()
+        .map(|(index, (name, dirty))| {
+            // 未保存の変更があるメモには印を付ける
+            let text = if *dirty {
+                format!("* {}", name)
+            } else {
+                name.clone()
+            };
+            SelectOption::new(
+                text,
+                Action::new_command_with_argument("kashikishi", "select-memo", &index.to_string()),
+            )
+        })
+        .collect();
+    SelectBox::new_without_action_name(context, "メモを選択".to_string(), options, None)
+}
+
+pub(crate) fn save_as_ui(context: &UiContext, path: &Path) -> TextInput {
+    TextInput::new(
+        context,
+        "保存先のパスを入力".to_string(),
+        Some(path.to_string_lossy().to_string()),
+        Action::new_command("kashikishi", "save-as"),
+    )
+}
+
+pub(crate) fn rename_memo_ui(context: &UiContext, file_name: &str) -> TextInput {
+    TextInput::new(
+        context,
+        "変更後のファイル名を入力".to_string(),
+        Some(file_name.to_string()),
+        Action::new_command("kashikishi", "rename-memo"),
+    )
+}
+
+pub(crate) fn external_change_ui(context: &UiContext, path: &Path) -> SelectBox {
+    let path = path.to_string_lossy().to_string();
+    let options = vec![
+        SelectOption::new(
+            "ファイルを読み込み直す(編集中の内容は失われます)".to_string(),
+            Action::new_command_with_argument("kashikishi", "reload-memo", &path),
+        ),
+        SelectOption::new_multiple("編集中の内容を残す".to_string(), vec![]),
+    ];
+    SelectBox::new_without_action_name(
+        context,
+        format!("{} が他のアプリケーションで変更されました", path),
+        options,
+        None,
+    )
+}
+
+pub(crate) fn recover_journal_ui(context: &UiContext, paths: &[PathBuf]) -> SelectBox {
+    let file_names = paths
+        .iter()
+        .filter_map(|path| path.file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let options = vec![
+        SelectOption::new(
+            format!("保存されていなかった編集内容を復元する({})", file_names),
+            Action::new_command("kashikishi", "recover-journal"),
+        ),
+        SelectOption::new(
+            "復元せずに破棄する".to_string(),
+            Action::new_command("kashikishi", "discard-journal"),
+        ),
+    ];
+    SelectBox::new_without_action_name(
+        context,
+        "前回は正常に終了しませんでした".to_string(),
+        options,
+        None,
+    )
+}
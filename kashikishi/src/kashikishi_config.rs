@@ -1,3 +1,5 @@
+use highlighter::settings::HighlightSettings;
+use log::warn;
 use serde::{Deserialize, Serialize};
 use ui_support::editor_settings::EditorSettings;
 
@@ -49,3 +51,16 @@ impl KashikishiConfig {
         std::path::Path::new(&home_dir).join(".config/kashikishi/config.json")
     }
 }
+
+/// $HOME/.config/kashikishi/highlight にある JSON のハイライト定義を組み込みの定義に重ねて読み込む。
+/// 読み込めなかった場合は警告を出して組み込みの定義だけを使う
+pub(crate) fn load_highlight_settings() -> HighlightSettings {
+    let home_dir = dirs::home_dir().unwrap_or_default();
+    let dir = std::path::Path::new(&home_dir).join(".config/kashikishi/highlight");
+    HighlightSettings::default()
+        .with_user_settings(&dir)
+        .unwrap_or_else(|e| {
+            warn!("failed to load highlight settings. error:{e}");
+            HighlightSettings::default()
+        })
+}
@@ -3,11 +3,16 @@
 mod action_repository;
 #[allow(dead_code)]
 mod categorized_memos;
+mod command_history;
+mod journal;
 mod kashikishi_actions;
 mod kashikishi_config;
 mod local_datetime_format;
 mod memos;
+mod presentation;
+mod presentation_export;
 mod rokid_max_ext;
+mod workspace;
 mod world;
 
 use std::{
@@ -19,7 +24,7 @@ use std::{
 
 use arboard::Clipboard;
 use clap::Parser;
-use font_collector::{FontCollector, FontRepository};
+use font_collector::{FontCollector, FontRepository, font_index::SCRIPT_SAMPLE_CHARS};
 use rokid_max_ext::RokidMaxAction;
 use stroke_parser::{
     Action, ActionArgument, ActionStore, CommandName, CommandNamespace,
@@ -30,27 +35,29 @@ use winit::{event::WindowEvent, icon::RgbaIcon};
 use world::{CategorizedMemosWorld, HelpWorld, ModalWorld, NullWorld, StartWorld};
 
 use font_rasterizer::{context::WindowSize, rasterizer_pipeline::Quarity, time::set_clock_mode};
-use log::info;
 use ui_support::{
     Flags, InputResult, RenderData, SimpleStateCallback, SimpleStateSupport,
     action::{ActionProcessor, ActionProcessorStore},
     action_recorder::{ActionRecorder, InMemoryActionRecordRepository},
     camera::{CameraAdjustment, CameraOperation},
     layout_engine::{Model, ModelOperation, World},
-    register_default_border, register_default_caret, run_support,
-    ui::{ImeInput, caret_char, ime_chars},
+    register_default_border, register_default_caret, register_default_decoration, run_support,
+    ui::{ImeInput, StrokeHintItem, StrokeHintOverlay, caret_char, ime_chars},
     ui_context::UiContext,
 };
 
 use crate::{
+    action_repository::ActionRepository, command_history::CommandHistory,
     kashikishi_actions::command_palette_select, kashikishi_config::KashikishiConfig,
-    world::MarkdownPresentationWorld,
+    presentation_export::ExportFormat, world::MarkdownPresentationWorld,
 };
 
 const ICON_IMAGE: &[u8] = include_bytes!("../kashikishi-icon.ico");
 
 const FONT_DATA: &[u8] = include_bytes!("../../fonts/BIZUDMincho-Regular.ttf");
 const EMOJI_FONT_DATA: &[u8] = include_bytes!("../../fonts/NotoEmoji-Regular.ttf");
+// 複数キーのストロークの入力途中にヒントを表示するまでの時間
+const STROKE_HINT_DELAY_MILLIS: u32 = 600;
 const LOG_FILE_NAME: &str = "kashikishi.log";
 const LOG_GENERATIONS: usize = 5;
 
@@ -65,9 +72,52 @@ pub fn main() {
         eprintln!("failed to initialize file logger: {err}");
     }
     let args = Args::parse();
+    if let Some(markdown_path) = args.export_presentation.clone() {
+        pollster::block_on(export(args, markdown_path));
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.list_glyph_cache || args.prune_glyph_cache.is_some() {
+        glyph_cache_command(&args);
+        return;
+    }
     pollster::block_on(run(args));
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn glyph_cache_command(args: &Args) {
+    const MIB: u64 = 1024 * 1024;
+    if let Some(limit_mb) = args.prune_glyph_cache {
+        let removed = font_rasterizer::prune_glyph_cache(limit_mb * MIB);
+        for cache in &removed {
+            println!("removed {}", cache.path.display());
+        }
+        println!("removed {} glyph cache files", removed.len());
+    }
+    if args.list_glyph_cache {
+        let caches = font_rasterizer::list_glyph_caches();
+        for cache in &caches {
+            let last_used = cache
+                .last_used
+                .elapsed()
+                .map(|elapsed| format!("{}h ago", elapsed.as_secs() / 3600))
+                .unwrap_or_else(|_| "-".to_string());
+            println!(
+                "{:>10} bytes  {:>10}  {}",
+                cache.size,
+                last_used,
+                cache.path.display()
+            );
+        }
+        let total_size: u64 = caches.iter().map(|cache| cache.size).sum();
+        println!(
+            "{} glyph cache files, total {} MiB",
+            caches.len(),
+            total_size / MIB
+        );
+    }
+}
+
 fn init_rotating_file_logger() -> io::Result<()> {
     let log_dir = resolve_log_dir();
     fs::create_dir_all(&log_dir)?;
@@ -143,9 +193,48 @@ pub struct Args {
     /// clear glyph cache before startup
     #[arg(long, default_value = "false")]
     pub clear_glyph_cache: bool,
+
+    /// upper limit of the total size of glyph cache files in MiB
+    #[arg(long, default_value_t = font_rasterizer::DEFAULT_GLYPH_CACHE_MAX_TOTAL_SIZE / 1024 / 1024)]
+    pub glyph_cache_limit_mb: u64,
+
+    /// list glyph cache files and exit
+    #[arg(long, default_value = "false")]
+    pub list_glyph_cache: bool,
+
+    /// remove least recently used glyph cache files until the total size fits in the given MiB and exit
+    #[arg(long)]
+    pub prune_glyph_cache: Option<u64>,
+
+    /// export the markdown presentation without opening a window
+    #[arg(long)]
+    pub export_presentation: Option<PathBuf>,
+
+    /// output of the exported presentation. a directory for png, a file for pdf
+    #[arg(long, default_value = "presentation")]
+    pub export_output: PathBuf,
+
+    /// format of the exported presentation
+    #[arg(long, value_enum, default_value_t = ExportFormat::Png)]
+    pub export_format: ExportFormat,
+}
+
+fn load_action_store() -> ActionStore {
+    let mut store: ActionStore = Default::default();
+    let key_setting = include_str!("../asset/key-settings.txt");
+    let keybinds = parse_setting(key_setting);
+    keybinds
+        .iter()
+        .for_each(|k| store.register_keybind(k.clone()));
+    store
+}
+
+struct SystemCommandPalette {
+    // キー操作の表示に使う
+    store: Rc<Mutex<ActionStore>>,
+    history: Rc<Mutex<CommandHistory>>,
 }
 
-struct SystemCommandPalette;
 impl ActionProcessor for SystemCommandPalette {
     fn namespace(&self) -> CommandNamespace {
         "system".into()
@@ -165,7 +254,11 @@ impl ActionProcessor for SystemCommandPalette {
             ActionArgument::String(value) => Some(value.to_owned()),
             _ => None,
         };
-        let modal = command_palette_select(context, narrow);
+        let modal = {
+            let history = self.history.lock().unwrap();
+            let store = self.store.lock().unwrap();
+            command_palette_select(context, narrow, &store, &history)
+        };
         context.register_string(modal.to_string());
         world.add_modal(Box::new(modal));
         world.re_layout();
@@ -174,12 +267,97 @@ impl ActionProcessor for SystemCommandPalette {
     }
 }
 
+struct KashikishiRecordCommandHistory {
+    history: Rc<Mutex<CommandHistory>>,
+}
+
+impl ActionProcessor for KashikishiRecordCommandHistory {
+    fn namespace(&self) -> CommandNamespace {
+        "kashikishi".into()
+    }
+
+    fn name(&self) -> CommandName {
+        "record-command-history".into()
+    }
+
+    fn process(
+        &self,
+        arg: &ActionArgument,
+        _context: &UiContext,
+        _world: &mut dyn World,
+    ) -> InputResult {
+        if let ActionArgument::String(command) = arg {
+            let mut history = self.history.lock().unwrap();
+            history.record(command);
+            history.save();
+        }
+        InputResult::InputConsumed
+    }
+}
+
+fn load_font_repository(args: &Args, config: &KashikishiConfig) -> FontRepository {
+    let mut font_collector = FontCollector::default();
+    if !args.use_embedded_font {
+        font_collector.add_system_fonts();
+    }
+    let mut font_repository = FontRepository::new(font_collector);
+    if !args.use_embedded_font {
+        args.font_names.iter().for_each(|name| {
+            font_repository.add_fallback_font_from_system(name);
+        });
+    }
+
+    // 埋め込みフォントを追加
+    font_repository.add_fallback_font_from_binary(FONT_DATA.to_vec(), None);
+    font_repository.add_fallback_font_from_binary(EMOJI_FONT_DATA.to_vec(), None);
+    // 埋め込みフォントに無い用字系は、表示できるフォントをシステムのフォントから探して加える
+    if !args.use_embedded_font {
+        font_repository.add_fallback_fonts_for_chars(SCRIPT_SAMPLE_CHARS.chars());
+    }
+
+    // コンフィグで指定されたフォントを優先的に設定する
+    if let Some(font_name) = &config.font {
+        font_repository.set_primary_font(font_name);
+    }
+    if let Some(ascii_override_font) = &config.ascii_override_font {
+        font_repository.set_ascii_override_font(ascii_override_font);
+    }
+    font_repository
+}
+
+async fn export(args: Args, markdown_path: PathBuf) {
+    let config = KashikishiConfig::load();
+    let font_repository = load_font_repository(&args, &config);
+    match presentation_export::export_presentation(
+        &markdown_path,
+        &args.export_output,
+        args.export_format,
+        font_repository,
+        config.editor_settings,
+    )
+    .await
+    {
+        Ok(num_of_slides) => println!(
+            "exported {} slides to {}",
+            num_of_slides,
+            args.export_output.display()
+        ),
+        Err(e) => {
+            log::error!("failed to export the presentation. error:{e}");
+            eprintln!("failed to export the presentation. error:{e}");
+        }
+    }
+}
+
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run(args: Args) {
-    // clear glyph cache if requested
+    // clear glyph cache if requested and set the size limit of glyph cache
     #[cfg(not(target_arch = "wasm32"))]
-    if args.clear_glyph_cache {
-        font_rasterizer::clear_glyph_cache();
+    {
+        if args.clear_glyph_cache {
+            font_rasterizer::clear_glyph_cache();
+        }
+        font_rasterizer::set_glyph_cache_max_total_size(args.glyph_cache_limit_mb * 1024 * 1024);
     }
 
     // load config
@@ -192,31 +370,7 @@ pub async fn run(args: Args) {
         .map(|icon| icon.into());
 
     // setup font
-    let font_repository = {
-        let mut font_collector = FontCollector::default();
-        if !args.use_embedded_font {
-            font_collector.add_system_fonts();
-        }
-        let mut font_repository = FontRepository::new(font_collector);
-        if !args.use_embedded_font {
-            args.font_names.iter().for_each(|name| {
-                font_repository.add_fallback_font_from_system(name);
-            });
-        }
-
-        // 埋め込みフォントを追加
-        font_repository.add_fallback_font_from_binary(FONT_DATA.to_vec(), None);
-        font_repository.add_fallback_font_from_binary(EMOJI_FONT_DATA.to_vec(), None);
-
-        // コンフィグで指定されたフォントを優先的に設定する
-        if let Some(font_name) = &config.font {
-            font_repository.set_primary_font(font_name);
-        }
-        if let Some(ascii_override_font) = &config.ascii_override_font {
-            font_repository.set_ascii_override_font(ascii_override_font);
-        }
-        font_repository
-    };
+    let font_repository = load_font_repository(&args, &config);
 
     set_clock_mode(font_rasterizer::time::ClockMode::StepByStep);
     let window_size = WindowSize::new(800, 600);
@@ -252,31 +406,33 @@ pub async fn run(args: Args) {
 }
 
 struct KashikishiCallback {
-    store: ActionStore,
+    store: Rc<Mutex<ActionStore>>,
     world: Box<dyn ModalWorld>,
     ime: ImeInput,
     action_processor_store: ActionProcessorStore,
     rokid_max_action: Rc<Mutex<RokidMaxAction>>,
     action_recorder: Rc<Mutex<ActionRecorder>>,
+    stroke_hint: StrokeHintOverlay,
     config: KashikishiConfig,
 }
 
 impl KashikishiCallback {
     fn new(window_size: WindowSize, config: KashikishiConfig) -> Self {
-        let mut store: ActionStore = Default::default();
-        let key_setting = include_str!("../asset/key-settings.txt");
-        info!("{}", key_setting);
-        let keybinds = parse_setting(key_setting);
-        keybinds
-            .iter()
-            .for_each(|k| store.register_keybind(k.clone()));
+        let store = Rc::new(Mutex::new(load_action_store()));
         let ime = ImeInput::with_settings(config.editor_settings.clone());
 
         let mut action_processor_store = ActionProcessorStore::default();
         action_processor_store.add_default_system_processors();
         action_processor_store.add_default_edit_processors();
         action_processor_store.add_default_world_processors();
-        action_processor_store.add_processor(Box::new(SystemCommandPalette));
+        let command_history = Rc::new(Mutex::new(CommandHistory::load()));
+        action_processor_store.add_processor(Box::new(SystemCommandPalette {
+            store: store.clone(),
+            history: command_history.clone(),
+        }));
+        action_processor_store.add_processor(Box::new(KashikishiRecordCommandHistory {
+            history: command_history,
+        }));
 
         let action_recorder =
             ActionRecorder::new(Box::new(InMemoryActionRecordRepository::default()));
@@ -294,10 +450,28 @@ impl KashikishiCallback {
             action_processor_store,
             rokid_max_action,
             action_recorder,
+            stroke_hint: StrokeHintOverlay::new(STROKE_HINT_DELAY_MILLIS),
             config,
         }
     }
 
+    // ActionStore のストロークの状態をヒントの表示に反映する
+    fn update_stroke_hint(&mut self) {
+        let store = self.store.lock().unwrap();
+        let pending = store.pending_stroke_string().map(|stroke| {
+            let action_repository = ActionRepository::default();
+            let items = store
+                .pending_stroke_candidates()
+                .into_iter()
+                .map(|(keys, action)| {
+                    StrokeHintItem::new(keys, action_repository.describe(&action))
+                })
+                .collect();
+            (stroke, items)
+        });
+        self.stroke_hint.set_pending(pending, self.world.get_mut());
+    }
+
     fn execute_world_action(
         &mut self,
         command_name: &str,
@@ -326,6 +500,8 @@ impl KashikishiCallback {
 
 impl SimpleStateCallback for KashikishiCallback {
     fn init(&mut self, context: &UiContext) {
+        context.set_highlight_settings(kashikishi_config::load_highlight_settings());
+
         // 初期状態で表示するワールドを設定する
         self.world = Box::new(StartWorld::new(context));
 
@@ -340,6 +516,7 @@ impl SimpleStateCallback for KashikishiCallback {
 
         register_default_caret(context);
         register_default_border(context);
+        register_default_decoration(context);
 
         // カメラを初期化する
         context.register_post_action(Action::new_command("world", "fit-by-direction"));
@@ -352,7 +529,9 @@ impl SimpleStateCallback for KashikishiCallback {
     fn update(&mut self, context: &UiContext) {
         self.action_recorder.lock().unwrap().replay(context);
 
+        self.stroke_hint.update(context, self.world.get_mut());
         self.world.get_mut().update(context);
+        self.world.update_overlay(context);
         self.ime.update(context);
 
         let _ = self.rokid_max_action.lock().map(|rokid_max_action| {
@@ -365,7 +544,14 @@ impl SimpleStateCallback for KashikishiCallback {
     }
 
     fn input(&mut self, context: &UiContext, event: &WindowEvent) -> InputResult {
-        if let Some(action) = self.store.winit_window_event_to_action(event) {
+        let action = self
+            .store
+            .lock()
+            .unwrap()
+            .winit_window_event_to_action(event);
+        // ストロークが確定した場合は、アクションを処理する前にヒントを取り除いておく
+        self.update_stroke_hint();
+        if let Some(action) = action {
             self.action(context, action)
         } else {
             InputResult::Noop
@@ -417,7 +603,14 @@ impl SimpleStateCallback for KashikishiCallback {
                 "mode" => {
                     let world: Option<Box<dyn ModalWorld>> = match &*name.to_string() {
                         "start" => Some(Box::new(StartWorld::new(context))),
-                        "category" => Some(Box::new(CategorizedMemosWorld::new(context))),
+                        "category" => match CategorizedMemosWorld::new(context) {
+                            Ok(world) => Some(Box::new(world)),
+                            // ワークスペースを開けなければ、今のモードのままにする
+                            Err(e) => {
+                                log::warn!("failed to open categorized memos. error:{e}");
+                                None
+                            }
+                        },
                         "presentation" => Some(Box::new(MarkdownPresentationWorld::new(context))),
                         "help" => Some(Box::new(HelpWorld::new(context))),
                         _ => None,
@@ -491,6 +684,7 @@ impl SimpleStateCallback for KashikishiCallback {
         let (mut glyph_instances_for_modal, vector_instances_for_modal) = world.modal_instances();
 
         let mut ime_instances = self.ime.get_instances();
+        world_instances.append(&mut self.world.overlay_instances());
 
         if glyph_instances_for_modal.is_empty() {
             world_instances.append(&mut ime_instances);
@@ -502,6 +696,7 @@ impl SimpleStateCallback for KashikishiCallback {
             camera: self.world.get().camera(),
             glyph_instances: world_instances,
             vector_instances: world.vector_instances(),
+            image_instances: world.image_instances(),
             glyph_instances_for_modal,
             vector_instances_for_modal,
         }
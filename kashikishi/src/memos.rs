@@ -1,33 +1,10 @@
 use serde::{Deserialize, Serialize};
-use ui_support::layout_engine::World;
 
 #[derive(PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct Memos {
     pub(crate) memos: Vec<String>,
 }
 
-impl Default for Memos {
-    fn default() -> Self {
-        Memos {
-            memos: vec!["".to_string()],
-        }
-    }
-}
-
-impl Memos {
-    pub(crate) fn empty() -> Self {
-        Memos { memos: vec![] }
-    }
-}
-
-impl From<&dyn World> for Memos {
-    fn from(world: &dyn World) -> Self {
-        Memos {
-            memos: world.strings(),
-        }
-    }
-}
-
 /*
 fn memos_file() -> PathBuf {
     // いわゆるホームディレクトリのパスを取得する
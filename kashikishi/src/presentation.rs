@@ -0,0 +1,261 @@
+//! Markdown のプレゼンテーションのスライドと発表用の補助機能。
+//!
+//! スライド中の HTML コメントと `Note:` で始まる行から後ろはスライドに表示せず、
+//! 発表者向けのノートとして扱う。`<!-- transition: poppy -->` のように書いたコメントは
+//! そのスライドを表示するときの切り替え効果の指定になる。
+//!
+//! スライドは見出しと `---` の区切り線で分割し、区切り線の後のスライドには直前の見出しを引き継ぐ。
+//! 先頭の front matter に `title` があれば、`author` と合わせて表紙のスライドにする。
+use std::time::{Duration, Instant};
+
+use markdown_heading_splitter::{SplitOptions, split_sections};
+use text_buffer::action::EditorOperation;
+use ui_support::{
+    layout_engine::{Model, ModelBorder, ModelOperation},
+    ui::TextEdit,
+    ui_context::{CharEasingsPreset, HighlightMode, TextContext},
+};
+
+const NOTE_PREFIXES: [&str; 2] = ["Note:", "Notes:"];
+const TRANSITION_PREFIX: &str = "transition:";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Slide {
+    /// スライドに表示する文章
+    pub(crate) text: String,
+    /// 発表者向けのノート。スライドには表示しない
+    pub(crate) notes: String,
+    /// スライドを表示するときの切り替え効果
+    pub(crate) transition: Option<CharEasingsPreset>,
+}
+
+impl Slide {
+    /// スライドを表示する TextEdit を作る
+    pub(crate) fn text_edit(&self, text_context: TextContext) -> TextEdit {
+        let mut textedit = TextEdit::new(text_context);
+        textedit.editor_operation(&EditorOperation::InsertString(self.text.clone()));
+        textedit.editor_operation(&EditorOperation::BufferHead);
+        textedit.model_operation(&ModelOperation::SetHighlightMode(HighlightMode::Markdown));
+        textedit.model_operation(&ModelOperation::SetModelBorder(ModelBorder::Rounded));
+        textedit
+    }
+}
+
+/// Markdown を見出しと区切り線ごとのスライドに分割する
+pub(crate) fn slides(markdown: &str) -> Vec<Slide> {
+    let options = SplitOptions::default()
+        .with_horizontal_rule(true)
+        .with_front_matter(true)
+        .with_continuation(true);
+    let document = split_sections(markdown, &options);
+
+    let title_slide = document.front_matter.and_then(|front_matter| {
+        match (front_matter.title, front_matter.author) {
+            (Some(title), Some(author)) => Some(format!("# {title}\n\n{author}")),
+            (Some(title), None) => Some(format!("# {title}")),
+            _ => None,
+        }
+    });
+    let sections = document
+        .sections
+        .into_iter()
+        .map(|section| match section.heading {
+            Some(heading) => format!(
+                "{} {}\n\n{}",
+                "#".repeat(heading.level()),
+                heading.title(),
+                section.body
+            ),
+            None => section.body,
+        });
+    title_slide
+        .into_iter()
+        .chain(sections)
+        .map(|source| parse_slide(&source))
+        .collect()
+}
+
+fn parse_slide(source: &str) -> Slide {
+    let mut notes = Vec::new();
+    let mut transition = None;
+
+    let mut text = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("<!--") {
+        text.push_str(&rest[..start]);
+        let comment_and_after = &rest[start + "<!--".len()..];
+        let (comment, mut after) = match comment_and_after.find("-->") {
+            Some(end) => (
+                &comment_and_after[..end],
+                &comment_and_after[end + "-->".len()..],
+            ),
+            None => (comment_and_after, ""),
+        };
+        // 行全体がコメントの場合は空行を残さない
+        if text.is_empty() || text.ends_with('\n') {
+            after = after.strip_prefix('\n').unwrap_or(after);
+        }
+        rest = after;
+
+        let comment = comment.trim();
+        match comment.strip_prefix(TRANSITION_PREFIX) {
+            Some(name) => transition = transition_preset(name.trim()),
+            None if !comment.is_empty() => notes.push(comment.to_string()),
+            None => {}
+        }
+    }
+    text.push_str(rest);
+
+    // コードブロックの外にある `Note:` の行から後ろはノートとする
+    let mut in_code_block = false;
+    let mut lines = text.split('\n');
+    let mut slide_lines = Vec::new();
+    for line in lines.by_ref() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code_block = !in_code_block;
+        }
+        if !in_code_block
+            && let Some(note) = NOTE_PREFIXES
+                .iter()
+                .find_map(|prefix| trimmed.strip_prefix(prefix))
+        {
+            let note = [note.trim()]
+                .into_iter()
+                .chain(lines.by_ref())
+                .collect::<Vec<_>>()
+                .join("\n");
+            notes.push(note.trim().to_string());
+            break;
+        }
+        slide_lines.push(line);
+    }
+    let mut text = slide_lines.join("\n");
+    if notes.iter().any(|note| !note.is_empty()) {
+        text.truncate(text.trim_end().len());
+    }
+
+    Slide {
+        text,
+        notes: notes
+            .into_iter()
+            .filter(|note| !note.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        transition,
+    }
+}
+
+fn transition_preset(name: &str) -> Option<CharEasingsPreset> {
+    let preset = match name {
+        "default" => CharEasingsPreset::Default,
+        "zero-motion" => CharEasingsPreset::ZeroMotion,
+        "poppy" => CharEasingsPreset::Poppy,
+        "cool" => CharEasingsPreset::Cool,
+        "energetic" => CharEasingsPreset::Energetic,
+        "gentle" => CharEasingsPreset::Gentle,
+        "minimal" => CharEasingsPreset::Minimal,
+        _ => return None,
+    };
+    Some(preset)
+}
+
+/// 発表の経過時間と残り時間を測るタイマー
+pub(crate) struct PresentationTimer {
+    started_at: Instant,
+    planned: Option<Duration>,
+}
+
+impl PresentationTimer {
+    pub(crate) fn start(planned: Option<Duration>) -> Self {
+        Self {
+            started_at: Instant::now(),
+            planned,
+        }
+    }
+
+    pub(crate) fn status(&self) -> String {
+        timer_status(self.started_at.elapsed(), self.planned)
+    }
+}
+
+fn timer_status(elapsed: Duration, planned: Option<Duration>) -> String {
+    match planned {
+        Some(planned) if elapsed <= planned => format!(
+            "経過 {} / 残り {}",
+            minutes_and_seconds(elapsed),
+            minutes_and_seconds(planned - elapsed)
+        ),
+        Some(planned) => format!(
+            "経過 {} / 超過 {}",
+            minutes_and_seconds(elapsed),
+            minutes_and_seconds(elapsed - planned)
+        ),
+        None => format!("経過 {}", minutes_and_seconds(elapsed)),
+    }
+}
+
+fn minutes_and_seconds(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speaker_notes() {
+        let slide =
+            parse_slide("# Title\n\nbody\n<!-- 挨拶をする -->\nmore\n\nNote: ゆっくり話す\n続き\n");
+        assert_eq!(slide.text, "# Title\n\nbody\nmore");
+        assert_eq!(slide.notes, "挨拶をする\n\nゆっくり話す\n続き");
+        assert_eq!(slide.transition, None);
+
+        // コードブロックの中の Note: はスライドの一部
+        let slide = parse_slide("# Code\n\n```\nNote: code\n```\n");
+        assert_eq!(slide.text, "# Code\n\n```\nNote: code\n```\n");
+        assert_eq!(slide.notes, "");
+    }
+
+    #[test]
+    fn test_transition() {
+        let slide = parse_slide("# Title\n<!-- transition: poppy -->\nbody");
+        assert_eq!(slide.text, "# Title\nbody");
+        assert_eq!(slide.notes, "");
+        assert_eq!(slide.transition, Some(CharEasingsPreset::Poppy));
+
+        let slide = parse_slide("# Title\n<!-- transition: unknown -->\nbody");
+        assert_eq!(slide.transition, None);
+    }
+
+    #[test]
+    fn test_slides() {
+        let result = slides("# A\n\na\n\n## B\n\nb\n<!-- note -->\n");
+        let texts: Vec<_> = result.iter().map(|slide| slide.text.as_str()).collect();
+        assert_eq!(texts, vec!["# A\n\na", "## B\n\nb"]);
+        assert_eq!(result[1].notes, "note");
+
+        // 表紙と、区切り線の後で見出しを引き継いだスライド
+        let result = slides("---\ntitle: Deck\nauthor: me\n---\n# A\n\na\n\n---\n\nb\n");
+        let texts: Vec<_> = result.iter().map(|slide| slide.text.as_str()).collect();
+        assert_eq!(texts, vec!["# Deck\n\nme", "# A\n\na", "# A\n\nb"]);
+    }
+
+    #[test]
+    fn test_timer_status() {
+        let minutes = |m: u64| Duration::from_secs(m * 60);
+        assert_eq!(
+            timer_status(Duration::from_secs(312), None),
+            "経過 05:12".to_string()
+        );
+        assert_eq!(
+            timer_status(Duration::from_secs(312), Some(minutes(20))),
+            "経過 05:12 / 残り 14:48".to_string()
+        );
+        assert_eq!(
+            timer_status(minutes(21), Some(minutes(20))),
+            "経過 21:00 / 超過 01:00".to_string()
+        );
+    }
+}
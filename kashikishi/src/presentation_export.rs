@@ -0,0 +1,304 @@
+//! Markdown のプレゼンテーションを、ウインドウを開かずに連番の PNG か複数ページの PDF に書き出す。
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use font_collector::FontRepository;
+use font_rasterizer::{context::WindowSize, rasterizer_pipeline::Quarity};
+use image::{DynamicImage, RgbaImage, codecs::jpeg::JpegEncoder};
+use stroke_parser::Action;
+use thiserror::Error;
+use ui_support::{
+    Flags, InputResult, RenderData, SimpleStateCallback, SimpleStateSupport,
+    camera::CameraAdjustment,
+    editor_settings::{EditorSettings, EditorTextContextProfile},
+    generate_images,
+    layout_engine::{DefaultWorld, World},
+    register_default_border, register_default_decoration,
+    ui_context::{CharEasingsPreset, UiContext},
+};
+use winit::event::WindowEvent;
+
+use crate::presentation::{Slide, slides};
+
+// カメラの移動が落ち着いてから書き出すため、一枚のスライドに使うフレーム数
+const FRAMES_PER_SLIDE: u32 = 3;
+const FRAME_GAIN: Duration = Duration::from_secs(1);
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// 出力先のディレクトリに slide-001.png から連番で書き出す
+    #[default]
+    Png,
+    /// 出力先のファイルにスライドを一ページずつ並べた PDF を書き出す
+    Pdf,
+}
+
+#[derive(Error, Debug)]
+pub(crate) enum ExportError {
+    #[error("io error. path:{path:?}, source:{source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("image error. source:{0}")]
+    Image(#[from] image::ImageError),
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> ExportError {
+    let path = path.to_path_buf();
+    move |source| ExportError::Io { path, source }
+}
+
+pub(crate) async fn export_presentation(
+    markdown_path: &Path,
+    output: &Path,
+    format: ExportFormat,
+    font_repository: FontRepository,
+    editor_settings: EditorSettings,
+) -> Result<usize, ExportError> {
+    let markdown = fs::read_to_string(markdown_path).map_err(io_error(markdown_path))?;
+    let slides = slides(&markdown);
+    let num_of_frame = slides.len() as u32 * FRAMES_PER_SLIDE;
+
+    let window_size = WindowSize::new(1280, 720);
+    let support = SimpleStateSupport {
+        window_icon: None,
+        window_title: "Kashikishi".to_string(),
+        window_size,
+        color_theme: editor_settings.color_theme(),
+        callback: Box::new(ExportCallback::new(window_size, slides, editor_settings)),
+        quarity: Quarity::High,
+        flags: Flags::DEFAULT,
+        font_repository,
+        performance_mode: false,
+        background_image: None,
+        shader_art: None,
+    };
+
+    let images = Arc::new(Mutex::new(Vec::new()));
+    let captured_images = images.clone();
+    generate_images(support, num_of_frame, FRAME_GAIN, move |image, frame| {
+        if frame % FRAMES_PER_SLIDE == FRAMES_PER_SLIDE - 1 {
+            captured_images.lock().unwrap().push(image);
+        }
+    })
+    .await;
+    let images = std::mem::take(&mut *images.lock().unwrap());
+
+    match format {
+        ExportFormat::Png => {
+            fs::create_dir_all(output).map_err(io_error(output))?;
+            for (index, image) in images.iter().enumerate() {
+                image.save(output.join(format!("slide-{:03}.png", index + 1)))?;
+            }
+        }
+        ExportFormat::Pdf => {
+            let pdf = pdf_from_images(&images)?;
+            fs::File::create(output)
+                .and_then(|mut file| file.write_all(&pdf))
+                .map_err(io_error(output))?;
+        }
+    }
+    Ok(images.len())
+}
+
+struct ExportCallback {
+    world: DefaultWorld,
+    slides: Vec<Slide>,
+    editor_settings: EditorSettings,
+    frame: u32,
+}
+
+impl ExportCallback {
+    fn new(window_size: WindowSize, slides: Vec<Slide>, editor_settings: EditorSettings) -> Self {
+        Self {
+            world: DefaultWorld::new(window_size),
+            slides,
+            editor_settings,
+            frame: 0,
+        }
+    }
+}
+
+impl SimpleStateCallback for ExportCallback {
+    fn init(&mut self, context: &UiContext) {
+        register_default_border(context);
+        register_default_decoration(context);
+        // 書き出す画像には動きの途中やキャレットが写らないようにする
+        for slide in self.slides.iter() {
+            context.register_string(slide.text.clone());
+            let text_context = context
+                .text_context(EditorTextContextProfile::Document)
+                .with_char_easings_preset(CharEasingsPreset::ZeroMotion)
+                .with_hyde_caret(true);
+            self.world.add(Box::new(slide.text_edit(text_context)));
+        }
+        self.world.re_layout();
+    }
+
+    fn resize(&mut self, window_size: WindowSize) {
+        self.world.change_window_size(window_size);
+    }
+
+    fn update(&mut self, context: &UiContext) {
+        if self.frame % FRAMES_PER_SLIDE == 0 {
+            self.world.look_at(
+                (self.frame / FRAMES_PER_SLIDE) as usize,
+                CameraAdjustment::FitBoth,
+            );
+        }
+        self.frame += 1;
+        self.world.update(context);
+    }
+
+    fn input(&mut self, _context: &UiContext, _event: &WindowEvent) -> InputResult {
+        InputResult::Noop
+    }
+
+    fn action(&mut self, _context: &UiContext, _action: Action) -> InputResult {
+        InputResult::Noop
+    }
+
+    fn render(&mut self) -> RenderData<'_> {
+        RenderData {
+            camera: self.world.camera(),
+            glyph_instances: self.world.glyph_instances(),
+            vector_instances: self.world.vector_instances(),
+            image_instances: self.world.image_instances(),
+            glyph_instances_for_modal: vec![],
+            vector_instances_for_modal: vec![],
+        }
+    }
+
+    fn initial_editor_settings(&self) -> EditorSettings {
+        self.editor_settings.clone()
+    }
+
+    fn shutdown(&mut self, _context: &UiContext) {}
+}
+
+fn pdf_from_images(images: &[RgbaImage]) -> Result<Vec<u8>, image::ImageError> {
+    let pages = images
+        .iter()
+        .map(|image| {
+            let rgb = DynamicImage::ImageRgba8(image.clone()).to_rgb8();
+            let mut jpeg = Vec::new();
+            JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY).encode_image(&rgb)?;
+            Ok((rgb.width(), rgb.height(), jpeg))
+        })
+        .collect::<Result<Vec<_>, image::ImageError>>()?;
+    Ok(pdf_from_jpegs(&pages))
+}
+
+/// JPEG の画像を一枚ずつページ全体に貼った PDF を作る。ページの大きさは画像のピクセル数をそのまま使う
+fn pdf_from_jpegs(pages: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+
+    // オブジェクトの番号は 1 が Catalog、2 が Pages で、以降はページごとに Page、Contents、Image の順に並べる
+    let page_object = |index: usize| 3 + index * 3;
+    let kids = (0..pages.len())
+        .map(|index| format!("{} 0 R", page_object(index)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    write_object(&mut pdf, &mut offsets, b"<< /Type /Catalog /Pages 2 0 R >>");
+    write_object(
+        &mut pdf,
+        &mut offsets,
+        format!("<< /Type /Pages /Kids [{kids}] /Count {} >>", pages.len()).as_bytes(),
+    );
+    for (index, (width, height, jpeg)) in pages.iter().enumerate() {
+        let page = page_object(index);
+        write_object(
+            &mut pdf,
+            &mut offsets,
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {width} {height}] /Resources << /XObject << /Slide {} 0 R >> >> /Contents {} 0 R >>",
+                page + 2,
+                page + 1
+            )
+            .as_bytes(),
+        );
+        write_object(
+            &mut pdf,
+            &mut offsets,
+            &stream(
+                "",
+                format!("q {width} 0 0 {height} 0 0 cm /Slide Do Q").as_bytes(),
+            ),
+        );
+        write_object(
+            &mut pdf,
+            &mut offsets,
+            &stream(
+                &format!(
+                    "/Type /XObject /Subtype /Image /Width {width} /Height {height} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode "
+                ),
+                jpeg,
+            ),
+        );
+    }
+
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+    for offset in offsets.iter() {
+        pdf.extend(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            offsets.len() + 1
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+fn write_object(pdf: &mut Vec<u8>, offsets: &mut Vec<usize>, body: &[u8]) {
+    offsets.push(pdf.len());
+    pdf.extend(format!("{} 0 obj\n", offsets.len()).as_bytes());
+    pdf.extend(body);
+    pdf.extend(b"\nendobj\n");
+}
+
+fn stream(dictionary: &str, data: &[u8]) -> Vec<u8> {
+    let mut result = format!("<< {dictionary}/Length {} >>\nstream\n", data.len()).into_bytes();
+    result.extend(data);
+    result.extend(b"\nendstream");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdf_from_jpegs() {
+        let pdf = pdf_from_jpegs(&[(4, 3, b"first".to_vec()), (4, 3, b"second".to_vec())]);
+        let text = String::from_utf8(pdf).unwrap();
+        assert!(text.starts_with("%PDF-1.4\n"));
+        assert!(text.contains("/Kids [3 0 R 6 0 R] /Count 2"));
+        assert!(text.ends_with("%%EOF\n"));
+
+        // xref の位置と各オブジェクトの位置が正しい
+        let startxref = text.rfind("startxref\n").unwrap() + "startxref\n".len();
+        let xref: usize = text[startxref..].lines().next().unwrap().parse().unwrap();
+        assert!(text[xref..].starts_with("xref\n0 9\n"));
+        let offsets: Vec<usize> = text[xref..]
+            .lines()
+            .skip(3)
+            .take(8)
+            .map(|line| line[..10].parse().unwrap())
+            .collect();
+        for (index, offset) in offsets.into_iter().enumerate() {
+            assert!(text[offset..].starts_with(&format!("{} 0 obj\n", index + 1)));
+        }
+    }
+}
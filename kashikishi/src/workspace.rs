@@ -0,0 +1,640 @@
+//! ファイルシステム上のディレクトリをメモ帳として扱うワークスペース。
+//!
+//! ワークスペースのルート直下のディレクトリがカテゴリ、その中の `.md` / `.txt` ファイルが
+//! 一つ一つのメモに対応する。メモの並び順はカテゴリのディレクトリ内の `.order` に保存する。
+//!
+//! ```text
+//! $HOME/.config/kashikishi/workspace/
+//!   .current_category
+//!   default/
+//!     .order
+//!     memo-20240101-120000.md
+//!   archive/
+//!     idea.txt
+//!   .trash/
+//! ```
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::Local;
+use thiserror::Error;
+
+use crate::categorized_memos::CategorizedMemos;
+
+pub(crate) const DEFAULT_CATEGORY: &str = "default";
+const ORDER_FILE: &str = ".order";
+const CURRENT_CATEGORY_FILE: &str = ".current_category";
+const TRASH_DIR: &str = ".trash";
+const EXTENSIONS: [&str; 2] = ["md", "txt"];
+
+#[derive(Error, Debug)]
+pub(crate) enum WorkspaceError {
+    #[error("io error. path:{path:?}, source:{source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("already exists. path:{0:?}")]
+    AlreadyExists(PathBuf),
+    #[error("invalid name. name:{0}")]
+    InvalidName(String),
+    #[error("file is changed by another process. path:{0:?}")]
+    ExternalChange(PathBuf),
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> WorkspaceError {
+    let path = path.to_path_buf();
+    move |source| WorkspaceError::Io { path, source }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// カテゴリ名やファイル名としてディレクトリの外を指せないようにする
+fn validate_name(name: &str) -> Result<(), WorkspaceError> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) || name.trim() != name
+    {
+        return Err(WorkspaceError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+pub(crate) fn is_document_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| EXTENSIONS.contains(&ext))
+}
+
+/// 一時ファイルに書き込んでから置き換えることで、書き込みの途中で終了しても
+/// 元のファイルが壊れないようにする
+pub(crate) fn write_atomically(path: &Path, text: &str) -> Result<(), WorkspaceError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error(parent))?;
+    }
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name));
+    fs::write(&temp_path, text).map_err(io_error(&temp_path))?;
+    fs::rename(&temp_path, path).map_err(io_error(path))
+}
+
+/// ファイルと対応付けられた一つのメモ。
+///
+/// 最後に読み込み・保存した時点の内容と更新日時を覚えておき、
+/// 未保存の変更と、他のプロセスによるファイルの変更を検出する。
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Document {
+    path: PathBuf,
+    saved_text: String,
+    // ファイルの更新日時。まだ一度も保存していない場合は None
+    modified: Option<SystemTime>,
+}
+
+impl Document {
+    pub(crate) fn load(path: &Path) -> Result<Self, WorkspaceError> {
+        let text = fs::read_to_string(path).map_err(io_error(path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            saved_text: text,
+            modified: modified_time(path),
+        })
+    }
+
+    /// dir にまだ存在しない名前で新しいメモを作る。ファイルは保存するまで作られない
+    pub(crate) fn new_in(dir: &Path) -> Self {
+        let base = format!("memo-{}", Local::now().format("%Y%m%d-%H%M%S"));
+        let mut path = dir.join(format!("{}.md", base));
+        let mut count = 1;
+        while path.exists() {
+            count += 1;
+            path = dir.join(format!("{}-{}.md", base, count));
+        }
+        Self {
+            path,
+            saved_text: String::new(),
+            modified: None,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn text(&self) -> &str {
+        &self.saved_text
+    }
+
+    pub(crate) fn is_saved_once(&self) -> bool {
+        self.modified.is_some()
+    }
+
+    /// text が最後に保存した内容から変わっているか
+    pub(crate) fn is_dirty(&self, text: &str) -> bool {
+        if self.is_saved_once() {
+            self.saved_text != text
+        } else {
+            // 未保存の空のメモは保存する必要がない
+            !text.is_empty()
+        }
+    }
+
+    /// 最後に読み込み・保存した後に、他のプロセスがファイルを変更したか
+    pub(crate) fn has_external_change(&self) -> bool {
+        match self.modified {
+            Some(modified) => modified_time(&self.path) != Some(modified),
+            None => false,
+        }
+    }
+
+    pub(crate) fn save(&mut self, text: &str) -> Result<(), WorkspaceError> {
+        if self.has_external_change() {
+            return Err(WorkspaceError::ExternalChange(self.path.clone()));
+        }
+        self.write(text)
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), WorkspaceError> {
+        write_atomically(&self.path, text)?;
+        self.saved_text = text.to_string();
+        self.modified = modified_time(&self.path);
+        Ok(())
+    }
+
+    /// 別のパスに保存し、以降はそのパスのメモとして扱う。元のファイルはそのまま残す
+    pub(crate) fn save_as(&mut self, path: &Path, text: &str) -> Result<(), WorkspaceError> {
+        if path.exists() {
+            return Err(WorkspaceError::AlreadyExists(path.to_path_buf()));
+        }
+        self.path = path.to_path_buf();
+        self.write(text)
+    }
+
+    /// 同じディレクトリ内でファイル名を変更する
+    pub(crate) fn rename(&mut self, file_name: &str) -> Result<(), WorkspaceError> {
+        validate_name(file_name)?;
+        let new_path = self.path.with_file_name(file_name);
+        if new_path.exists() {
+            return Err(WorkspaceError::AlreadyExists(new_path));
+        }
+        if self.is_saved_once() {
+            fs::rename(&self.path, &new_path).map_err(io_error(&self.path))?;
+            self.modified = modified_time(&new_path);
+        }
+        self.path = new_path;
+        Ok(())
+    }
+
+    /// ファイルの内容を読み込み直す
+    pub(crate) fn reload(&mut self) -> Result<(), WorkspaceError> {
+        *self = Self::load(&self.path)?;
+        Ok(())
+    }
+
+    /// 他のプロセスによる変更を確認済みとし、現在のファイルの更新日時を覚え直す
+    pub(crate) fn accept_external_change(&mut self) {
+        self.modified = modified_time(&self.path);
+    }
+}
+
+/// カテゴリをディレクトリ、メモをファイルとして扱うワークスペース
+pub(crate) struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub(crate) fn default_root() -> PathBuf {
+        // いわゆるホームディレクトリのパスを取得する
+        let home_dir = dirs::home_dir().unwrap_or_default();
+        Path::new(&home_dir).join(".config/kashikishi/workspace")
+    }
+
+    pub(crate) fn open(root: &Path) -> Result<Self, WorkspaceError> {
+        let default_dir = root.join(DEFAULT_CATEGORY);
+        fs::create_dir_all(&default_dir).map_err(io_error(&default_dir))?;
+        Ok(Self {
+            root: root.to_path_buf(),
+        })
+    }
+
+    /// 既定のワークスペースを開く。ワークスペースが無く、以前の categorized_memos.json がある場合は
+    /// その内容をファイルに書き出して移行する。
+    pub(crate) fn open_default() -> Result<Self, WorkspaceError> {
+        let root = Self::default_root();
+        let exists = root.exists();
+        let workspace = Self::open(&root)?;
+        if !exists && let Some(memos) = CategorizedMemos::load_legacy_memos() {
+            workspace.import_categorized_memos(&memos)?;
+        }
+        Ok(workspace)
+    }
+
+    pub(crate) fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub(crate) fn category_dir(&self, category: &str) -> PathBuf {
+        self.root.join(category)
+    }
+
+    pub(crate) fn categories(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(&self.root) else {
+            return vec![DEFAULT_CATEGORY.to_string()];
+        };
+        let mut categories = entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| !name.starts_with('.'))
+            .collect::<Vec<_>>();
+        categories.sort();
+        categories
+    }
+
+    pub(crate) fn current_category(&self) -> String {
+        fs::read_to_string(self.root.join(CURRENT_CATEGORY_FILE))
+            .ok()
+            .map(|category| category.trim().to_string())
+            .filter(|category| self.category_dir(category).is_dir())
+            .unwrap_or_else(|| DEFAULT_CATEGORY.to_string())
+    }
+
+    pub(crate) fn set_current_category(&self, category: &str) -> Result<(), WorkspaceError> {
+        let path = self.root.join(CURRENT_CATEGORY_FILE);
+        fs::write(&path, category).map_err(io_error(&path))
+    }
+
+    /// カテゴリ内のメモを .order の順に読み込む。.order に無いファイルは名前順で後ろに並べる
+    pub(crate) fn load_documents(&self, category: &str) -> Result<Vec<Document>, WorkspaceError> {
+        let dir = self.category_dir(category);
+        let mut paths = fs::read_dir(&dir)
+            .map_err(io_error(&dir))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| is_document_file(path))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let order = fs::read_to_string(dir.join(ORDER_FILE)).unwrap_or_default();
+        let mut ordered = Vec::new();
+        for name in order.lines() {
+            if let Some(index) = paths
+                .iter()
+                .position(|p| p.file_name() == Some(name.as_ref()))
+            {
+                ordered.push(paths.remove(index));
+            }
+        }
+        ordered.extend(paths);
+
+        ordered.iter().map(|path| Document::load(path)).collect()
+    }
+
+    /// カテゴリ内のメモの並び順を保存する。カテゴリの外のファイルは無視する
+    pub(crate) fn save_order(
+        &self,
+        category: &str,
+        documents: &[Document],
+    ) -> Result<(), WorkspaceError> {
+        let dir = self.category_dir(category);
+        let order = documents
+            .iter()
+            .filter(|doc| doc.path().parent() == Some(dir.as_path()))
+            .map(|doc| doc.file_name())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let path = dir.join(ORDER_FILE);
+        fs::write(&path, order).map_err(io_error(&path))
+    }
+
+    pub(crate) fn new_document(&self, category: &str) -> Document {
+        Document::new_in(&self.category_dir(category))
+    }
+
+    pub(crate) fn add_category(&self, category: &str) -> Result<(), WorkspaceError> {
+        validate_name(category)?;
+        let dir = self.category_dir(category);
+        if dir.exists() {
+            return Err(WorkspaceError::AlreadyExists(dir));
+        }
+        fs::create_dir_all(&dir).map_err(io_error(&dir))
+    }
+
+    pub(crate) fn rename_category(
+        &self,
+        new_name: &str,
+        old_name: &str,
+    ) -> Result<(), WorkspaceError> {
+        validate_name(new_name)?;
+        let old_dir = self.category_dir(old_name);
+        let new_dir = self.category_dir(new_name);
+        if new_dir.exists() {
+            return Err(WorkspaceError::AlreadyExists(new_dir));
+        }
+        let is_current = self.current_category() == old_name;
+        fs::rename(&old_dir, &new_dir).map_err(io_error(&old_dir))?;
+        if is_current {
+            self.set_current_category(new_name)?;
+        }
+        Ok(())
+    }
+
+    /// カテゴリを削除する。中のメモは default カテゴリに移動する
+    pub(crate) fn remove_category(&self, category: &str) -> Result<(), WorkspaceError> {
+        if category == DEFAULT_CATEGORY {
+            return Err(WorkspaceError::InvalidName(category.to_string()));
+        }
+        for mut document in self.load_documents(category)? {
+            self.move_document(&mut document, DEFAULT_CATEGORY)?;
+        }
+        let dir = self.category_dir(category);
+        fs::remove_dir_all(&dir).map_err(io_error(&dir))
+    }
+
+    /// メモを別のカテゴリに移動する。同名のファイルがある場合は別の名前にする
+    pub(crate) fn move_document(
+        &self,
+        document: &mut Document,
+        category: &str,
+    ) -> Result<(), WorkspaceError> {
+        let dir = self.category_dir(category);
+        fs::create_dir_all(&dir).map_err(io_error(&dir))?;
+        let mut new_path = dir.join(document.file_name());
+        if new_path.exists() {
+            new_path = Document::new_in(&dir).path;
+        }
+        if document.is_saved_once() {
+            fs::rename(document.path(), &new_path).map_err(io_error(document.path()))?;
+            document.modified = modified_time(&new_path);
+        }
+        document.path = new_path;
+        Ok(())
+    }
+
+    /// メモを削除する。誤操作に備えて実際には .trash に移動する
+    pub(crate) fn trash(&self, document: &Document) -> Result<(), WorkspaceError> {
+        if !document.is_saved_once() || !document.path().exists() {
+            return Ok(());
+        }
+        let trash_dir = self.root.join(TRASH_DIR);
+        fs::create_dir_all(&trash_dir).map_err(io_error(&trash_dir))?;
+        let trash_path = trash_dir.join(format!(
+            "{}-{}",
+            Local::now().format("%Y%m%d%H%M%S"),
+            document.file_name()
+        ));
+        fs::rename(document.path(), &trash_path).map_err(io_error(document.path()))
+    }
+
+    /// categorized_memos.json のメモをワークスペースのファイルとして書き出す
+    pub(crate) fn import_categorized_memos(
+        &self,
+        memos: &CategorizedMemos,
+    ) -> Result<(), WorkspaceError> {
+        for (category, memos) in memos.categorized.iter() {
+            let dir = self.category_dir(category);
+            fs::create_dir_all(&dir).map_err(io_error(&dir))?;
+            let mut documents = Vec::new();
+            for (index, text) in memos.memos.iter().enumerate() {
+                let mut document = Document {
+                    path: dir.join(format!("memo-{:03}.md", index + 1)),
+                    saved_text: String::new(),
+                    modified: None,
+                };
+                document.write(text)?;
+                documents.push(document);
+            }
+            self.save_order(category, &documents)?;
+        }
+        if memos.categorized.contains_key(&memos.current_category) {
+            self.set_current_category(&memos.current_category)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::memos::Memos;
+
+    use super::*;
+
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "kashikishi-workspace-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_document_dirty_and_save() {
+        let dir = TempDir::new();
+        let workspace = Workspace::open(&dir.0).unwrap();
+        let mut document = workspace.new_document(DEFAULT_CATEGORY);
+        assert!(!document.is_dirty(""));
+        assert!(document.is_dirty("hello"));
+
+        document.save("hello").unwrap();
+        assert!(!document.is_dirty("hello"));
+        assert!(document.is_dirty("hello world"));
+
+        let documents = workspace.load_documents(DEFAULT_CATEGORY).unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].text(), "hello");
+    }
+
+    #[test]
+    fn test_interrupted_save() {
+        let dir = TempDir::new();
+        let workspace = Workspace::open(&dir.0).unwrap();
+        let mut document = workspace.new_document(DEFAULT_CATEGORY);
+        document.save("saved").unwrap();
+
+        // 一時ファイルへの書き込みの途中で終了した状態を作る
+        let temp_path = document
+            .path()
+            .with_file_name(format!(".{}.tmp", document.file_name()));
+        fs::write(&temp_path, "sav").unwrap();
+
+        let documents = workspace.load_documents(DEFAULT_CATEGORY).unwrap();
+        assert_eq!(
+            documents.iter().map(Document::text).collect::<Vec<_>>(),
+            vec!["saved"]
+        );
+
+        document.save("saved again").unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(fs::read_to_string(document.path()).unwrap(), "saved again");
+    }
+
+    #[test]
+    fn test_external_change() {
+        let dir = TempDir::new();
+        let workspace = Workspace::open(&dir.0).unwrap();
+        let mut document = workspace.new_document(DEFAULT_CATEGORY);
+        document.save("hello").unwrap();
+        assert!(!document.has_external_change());
+
+        // 更新日時の分解能が粗いファイルシステムでも変更を検出できるよう、日時を明示的にずらす
+        fs::write(document.path(), "changed").unwrap();
+        let file = fs::File::options()
+            .write(true)
+            .open(document.path())
+            .unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+        assert!(document.has_external_change());
+        assert!(matches!(
+            document.save("mine"),
+            Err(WorkspaceError::ExternalChange(_))
+        ));
+
+        document.reload().unwrap();
+        assert_eq!(document.text(), "changed");
+        assert!(!document.has_external_change());
+    }
+
+    #[test]
+    fn test_order_and_rename() {
+        let dir = TempDir::new();
+        let workspace = Workspace::open(&dir.0).unwrap();
+        let category_dir = workspace.category_dir(DEFAULT_CATEGORY);
+        fs::write(category_dir.join("a.md"), "a").unwrap();
+        fs::write(category_dir.join("b.txt"), "b").unwrap();
+        fs::write(category_dir.join("ignored.json"), "{}").unwrap();
+
+        let mut documents = workspace.load_documents(DEFAULT_CATEGORY).unwrap();
+        assert_eq!(
+            documents.iter().map(Document::text).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+
+        documents.swap(0, 1);
+        workspace.save_order(DEFAULT_CATEGORY, &documents).unwrap();
+        documents[0].rename("c.md").unwrap();
+        workspace.save_order(DEFAULT_CATEGORY, &documents).unwrap();
+        assert!(matches!(
+            documents[1].rename("c.md"),
+            Err(WorkspaceError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            documents[1].rename("../c.md"),
+            Err(WorkspaceError::InvalidName(_))
+        ));
+
+        let documents = workspace.load_documents(DEFAULT_CATEGORY).unwrap();
+        assert_eq!(
+            documents
+                .iter()
+                .map(Document::file_name)
+                .collect::<Vec<_>>(),
+            vec!["c.md", "a.md"]
+        );
+    }
+
+    #[test]
+    fn test_categories() {
+        let dir = TempDir::new();
+        let workspace = Workspace::open(&dir.0).unwrap();
+        workspace.add_category("idea").unwrap();
+        assert!(workspace.add_category("idea").is_err());
+        assert!(workspace.add_category(".hidden").is_err());
+
+        let mut document = workspace.new_document("idea");
+        document.save("idea memo").unwrap();
+        workspace.set_current_category("idea").unwrap();
+
+        workspace.rename_category("plan", "idea").unwrap();
+        assert_eq!(workspace.categories(), vec!["default", "plan"]);
+        assert_eq!(workspace.current_category(), "plan");
+
+        workspace.remove_category("plan").unwrap();
+        assert_eq!(workspace.categories(), vec!["default"]);
+        assert_eq!(workspace.current_category(), DEFAULT_CATEGORY);
+        let documents = workspace.load_documents(DEFAULT_CATEGORY).unwrap();
+        assert_eq!(documents[0].text(), "idea memo");
+    }
+
+    #[test]
+    fn test_trash() {
+        let dir = TempDir::new();
+        let workspace = Workspace::open(&dir.0).unwrap();
+        let mut document = workspace.new_document(DEFAULT_CATEGORY);
+        document.save("bye").unwrap();
+        workspace.trash(&document).unwrap();
+        assert!(!document.path().exists());
+        assert!(
+            workspace
+                .load_documents(DEFAULT_CATEGORY)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(fs::read_dir(dir.0.join(TRASH_DIR)).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_import_categorized_memos() {
+        let dir = TempDir::new();
+        let workspace = Workspace::open(&dir.0).unwrap();
+        let memos = CategorizedMemos {
+            current_category: "work".to_string(),
+            categorized: BTreeMap::from([
+                (
+                    DEFAULT_CATEGORY.to_string(),
+                    Memos {
+                        memos: vec!["first".to_string(), "second".to_string()],
+                    },
+                ),
+                (
+                    "work".to_string(),
+                    Memos {
+                        memos: vec!["todo".to_string()],
+                    },
+                ),
+            ]),
+        };
+        workspace.import_categorized_memos(&memos).unwrap();
+
+        assert_eq!(workspace.categories(), vec!["default", "work"]);
+        assert_eq!(workspace.current_category(), "work");
+        let documents = workspace.load_documents(DEFAULT_CATEGORY).unwrap();
+        assert_eq!(
+            documents.iter().map(Document::text).collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+    }
+}
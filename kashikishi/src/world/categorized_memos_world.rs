@@ -1,54 +1,444 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
-use font_rasterizer::glyph_vertex_buffer::Direction;
+use font_rasterizer::{
+    context::WindowSize, glyph_instances::GlyphInstances, glyph_vertex_buffer::Direction,
+    image_instances::ImageInstances, time::now_millis, vector_instances::VectorInstances,
+};
+use log::warn;
 use stroke_parser::{Action, ActionArgument};
 use text_buffer::action::EditorOperation;
 use ui_support::{
     InputResult,
-    camera::CameraAdjustment,
-    layout_engine::{DefaultWorld, Model, ModelOperation, World},
+    camera::{Camera, CameraAdjustment, CameraOperation},
+    layout_engine::{
+        DebugWorldSnapshot, DefaultWorld, Model, ModelOperation, RemovedModelType, World,
+        WorldLayout,
+    },
     ui::TextEdit,
-    ui_context::UiContext,
+    ui_context::{CharEasingsPreset, HighlightMode, UiContext},
 };
 
 use crate::{
-    categorized_memos::CategorizedMemos,
+    journal::{Journal, Session},
     kashikishi_actions::{
-        add_category_ui, insert_date_select, move_category_ui, move_memo_ui, open_file_ui,
-        remove_category_ui, rename_category_select_ui, rename_category_ui,
+        add_category_ui, external_change_ui, insert_date_select, move_category_ui, move_memo_ui,
+        open_file_ui, recover_journal_ui, remove_category_ui, rename_category_select_ui,
+        rename_category_ui, rename_memo_ui, save_as_ui, select_memo_ui,
     },
-    memos::Memos,
+    workspace::{Document, Workspace, WorkspaceError},
 };
 
 use super::ModalWorld;
 
-pub(crate) struct CategorizedMemosWorld {
+/// 他のプロセスによるファイルの変更と、編集内容を確認する間隔
+const CHECK_INTERVAL_MILLIS: u32 = 1000;
+/// 最後の編集からこの時間が経過したら自動保存する
+const AUTOSAVE_DELAY_MILLIS: u32 = 3000;
+/// 編集が続いていても、未保存の変更がこの時間残っていれば自動保存する
+const AUTOSAVE_MAX_INTERVAL_MILLIS: u32 = 30000;
+
+/// DefaultWorld のモデルとメモのファイルを対応付けて管理する World。
+///
+/// モデルの追加・削除・入れ替えは World の操作として外からも行われるので、
+/// それらの操作に合わせて Document の並びも更新する。
+struct DocumentWorld {
     world: DefaultWorld,
-    memos: CategorizedMemos,
+    documents: Vec<Document>,
+    // World::remove_current で取り除かれたメモ。保存時にゴミ箱に移動する
+    removed: Vec<Document>,
+    // 新しいメモを作るディレクトリ
+    dir: PathBuf,
+    last_checked: u32,
+    journal: Journal,
+    // ジャーナルに最後に記録した内容
+    journaled: HashMap<PathBuf, String>,
+    last_edited: Option<u32>,
+    dirty_since: Option<u32>,
+}
+
+impl DocumentWorld {
+    fn new(window_size: WindowSize, dir: PathBuf, journal: Journal) -> Self {
+        Self {
+            world: DefaultWorld::new(window_size),
+            documents: Vec::new(),
+            removed: Vec::new(),
+            dir,
+            last_checked: now_millis(),
+            journal,
+            journaled: HashMap::new(),
+            last_edited: None,
+            dirty_since: None,
+        }
+    }
+
+    /// フォーカスしているメモのモデルを取り除き、そのメモを返す。
+    /// モーダルを表示している場合や、メモが無い場合は何もせずに None を返す
+    fn remove_current_document(&mut self) -> Option<Document> {
+        let focus = self.world.focus_index();
+        if self.world.has_modal() || focus >= self.documents.len() {
+            return None;
+        }
+        self.world.remove_current();
+        Some(self.documents.remove(focus))
+    }
+
+    fn add_document(&mut self, model: Box<dyn Model>, document: Document) {
+        self.world.add(model);
+        self.documents.push(document);
+    }
+
+    fn add_document_next(&mut self, model: Box<dyn Model>, document: Document) {
+        let index = (self.world.focus_index() + 1).min(self.documents.len());
+        self.world.add_next(model);
+        self.documents.insert(index, document);
+    }
+
+    fn current_document(&self) -> Option<&Document> {
+        self.documents.get(self.world.focus_index())
+    }
+
+    fn current_document_mut(&mut self) -> Option<&mut Document> {
+        self.documents.get_mut(self.world.focus_index())
+    }
+
+    fn documents_with_texts(&mut self) -> impl Iterator<Item = (&mut Document, String)> {
+        self.documents.iter_mut().zip(self.world.strings())
+    }
+
+    // モデルの内容をまとめて置き換える。アンドゥで元に戻せるように編集操作で行う
+    fn replace_text(&mut self, index: usize, text: &str) {
+        for op in [
+            EditorOperation::BufferLast,
+            EditorOperation::Mark,
+            EditorOperation::BufferHead,
+            EditorOperation::Cut(|_| {}),
+            EditorOperation::InsertString(text.to_string()),
+            EditorOperation::BufferHead,
+        ] {
+            self.world.editor_operation_at(index, &op);
+        }
+    }
+
+    // 読み込み直したメモの内容を返す
+    fn reload(&mut self, context: &UiContext, index: usize) -> Option<String> {
+        let document = self.documents.get_mut(index)?;
+        if let Err(e) = document.reload() {
+            warn!("failed to reload memo. error:{e}");
+            return None;
+        }
+        let text = document.text().to_string();
+        context.register_string(text.clone());
+        self.replace_text(index, &text);
+        Some(text)
+    }
+
+    // 変更されたファイルを検出し、未編集のメモはそのまま読み込み直す。
+    // 編集中のメモは内容を失わないよう、どうするかを選んでもらう
+    fn check_external_changes(&mut self, context: &UiContext, texts: &mut [String]) {
+        for index in 0..self.documents.len() {
+            let document = &mut self.documents[index];
+            if !document.has_external_change() {
+                continue;
+            }
+            if !document.is_dirty(&texts[index]) && document.path().exists() {
+                if let Some(text) = self.reload(context, index) {
+                    texts[index] = text;
+                }
+            } else {
+                // 何度も確認しないよう、変更を確認済みとしておく
+                document.accept_external_change();
+                context.register_action(Action::new_command_with_argument(
+                    "kashikishi",
+                    "external-change-ui",
+                    &document.path().to_string_lossy(),
+                ));
+            }
+        }
+    }
+
+    // 前回の確認から変わったメモをジャーナルに追記し、編集が落ち着いたら自動保存を要求する
+    fn record_edits(&mut self, context: &UiContext, now: u32, texts: &[String]) {
+        let mut edited = false;
+        let mut has_dirty = false;
+        for (document, text) in self.documents.iter().zip(texts) {
+            if !document.is_dirty(text) {
+                continue;
+            }
+            has_dirty = true;
+            if self.journaled.get(document.path()) == Some(text) {
+                continue;
+            }
+            if let Err(e) = self.journal.append(document.path(), text) {
+                warn!("failed to append journal. error:{e}");
+            }
+            self.journaled
+                .insert(document.path().to_path_buf(), text.clone());
+            edited = true;
+        }
+        if edited {
+            self.last_edited = Some(now);
+            self.dirty_since.get_or_insert(now);
+        }
+        if !has_dirty {
+            self.last_edited = None;
+            self.dirty_since = None;
+            return;
+        }
+
+        let settled = self
+            .last_edited
+            .is_some_and(|t| now.saturating_sub(t) >= AUTOSAVE_DELAY_MILLIS);
+        let too_long = self
+            .dirty_since
+            .is_some_and(|t| now.saturating_sub(t) >= AUTOSAVE_MAX_INTERVAL_MILLIS);
+        if settled || too_long {
+            self.last_edited = None;
+            self.dirty_since = None;
+            context.register_action(Action::new_command("kashikishi", "save"));
+        }
+    }
+
+    // すべてのメモが保存済みであれば、ジャーナルはもう必要ない
+    fn clear_journal_if_saved(&mut self) {
+        let all_saved = self
+            .documents
+            .iter()
+            .zip(self.world.strings())
+            .all(|(document, text)| !document.is_dirty(&text));
+        if all_saved {
+            self.journal.clear();
+            self.journaled.clear();
+        }
+    }
+}
+
+impl World for DocumentWorld {
+    fn add(&mut self, model: Box<dyn Model>) {
+        let document = Document::new_in(&self.dir);
+        self.add_document(model, document);
+    }
+
+    fn add_next(&mut self, model: Box<dyn Model>) {
+        let document = Document::new_in(&self.dir);
+        self.add_document_next(model, document);
+    }
+
+    fn add_modal(&mut self, model: Box<dyn Model>) {
+        self.world.add_modal(model);
+    }
+
+    fn remove_current(&mut self) -> RemovedModelType {
+        if self.world.has_modal() {
+            return self.world.remove_current();
+        }
+        if let Some(document) = self.remove_current_document() {
+            self.removed.push(document);
+        }
+        RemovedModelType::Normal
+    }
+
+    fn re_layout(&mut self) {
+        self.world.re_layout();
+    }
+
+    fn update(&mut self, context: &UiContext) {
+        self.world.update(context);
+        let now = now_millis();
+        if now.saturating_sub(self.last_checked) >= CHECK_INTERVAL_MILLIS {
+            self.last_checked = now;
+            // すべてのモデルの内容を集めるのは重いので、一回の確認で一度だけにする
+            let mut texts = self.world.strings();
+            self.check_external_changes(context, &mut texts);
+            self.record_edits(context, now, &texts);
+        }
+    }
+
+    fn model_length(&self) -> usize {
+        self.world.model_length()
+    }
+
+    fn look_at(&mut self, model_num: usize, adjustment: CameraAdjustment) {
+        self.world.look_at(model_num, adjustment);
+    }
+
+    fn look_modal(&mut self, adjustment: CameraAdjustment) {
+        self.world.look_modal(adjustment);
+    }
+
+    fn look_current(&mut self, adjustment: CameraAdjustment) {
+        self.world.look_current(adjustment);
+    }
+
+    fn look_next(&mut self, adjustment: CameraAdjustment) {
+        self.world.look_next(adjustment);
+    }
+
+    fn look_prev(&mut self, adjustment: CameraAdjustment) {
+        self.world.look_prev(adjustment);
+    }
+
+    fn swap_next(&mut self) {
+        let before = self.world.focus_index();
+        self.world.swap_next();
+        let after = self.world.focus_index();
+        if before != after {
+            self.documents.swap(before, after);
+        }
+    }
+
+    fn swap_prev(&mut self) {
+        let before = self.world.focus_index();
+        self.world.swap_prev();
+        let after = self.world.focus_index();
+        if before != after {
+            self.documents.swap(before, after);
+        }
+    }
+
+    fn camera(&self) -> &Camera {
+        self.world.camera()
+    }
+
+    fn camera_operation(&mut self, camera_operation: CameraOperation) {
+        self.world.camera_operation(camera_operation);
+    }
+
+    fn change_window_size(&mut self, window_size: WindowSize) {
+        self.world.change_window_size(window_size);
+    }
+
+    fn change_layout(&mut self, layout: WorldLayout) {
+        self.world.change_layout(layout);
+    }
+
+    fn change_char_easings_preset(&mut self, preset: CharEasingsPreset) {
+        self.world.change_char_easings_preset(preset);
+    }
+
+    fn layout(&self) -> &WorldLayout {
+        self.world.layout()
+    }
+
+    fn glyph_instances(&self) -> Vec<&GlyphInstances> {
+        self.world.glyph_instances()
+    }
+
+    fn vector_instances(&self) -> Vec<&VectorInstances<String>> {
+        self.world.vector_instances()
+    }
+
+    fn image_instances(&self) -> Vec<&ImageInstances> {
+        self.world.image_instances()
+    }
+
+    fn modal_instances(&self) -> (Vec<&GlyphInstances>, Vec<&VectorInstances<String>>) {
+        self.world.modal_instances()
+    }
+
+    fn editor_operation(&mut self, op: &EditorOperation) {
+        self.world.editor_operation(op);
+    }
+
+    fn model_operation(&mut self, op: &ModelOperation) {
+        self.world.model_operation(op);
+    }
+
+    fn current_string(&self) -> String {
+        self.world.current_string()
+    }
+
+    fn strings(&self) -> Vec<String> {
+        self.world.strings()
+    }
+
+    fn chars(&self) -> HashSet<char> {
+        self.world.chars()
+    }
+
+    fn debug_snapshot(&self) -> DebugWorldSnapshot {
+        self.world.debug_snapshot()
+    }
+
+    fn move_to_position(&mut self, x_ratio: f32, y_ratio: f32) {
+        self.world.move_to_position(x_ratio, y_ratio);
+    }
+}
+
+pub(crate) struct CategorizedMemosWorld {
+    world: DocumentWorld,
+    workspace: Workspace,
+    session: Session,
+    current_category: String,
 }
 
 impl CategorizedMemosWorld {
-    pub(crate) fn new(context: &UiContext) -> Self {
+    pub(crate) fn new(context: &UiContext) -> Result<Self, WorkspaceError> {
+        // ワークスペースが開けない場合は一時ディレクトリで編集を続けられるようにする
+        let workspace = match Workspace::open_default() {
+            Ok(workspace) => workspace,
+            Err(e) => {
+                warn!("failed to open workspace. error:{e}");
+                Workspace::open(&std::env::temp_dir().join("kashikishi/workspace"))?
+            }
+        };
+        let current_category = workspace.current_category();
+        let session = Session::start(workspace.root());
+        // 前回異常終了していた場合は、ジャーナルから復元するかを選んでもらう
+        if !session.recoverable().is_empty() {
+            context.register_action(Action::new_command("kashikishi", "recover-journal-ui"));
+        }
         let mut result = Self {
-            world: DefaultWorld::new(context.window_size()),
-            memos: CategorizedMemos::load_memos(),
+            world: DocumentWorld::new(
+                context.window_size(),
+                workspace.category_dir(&current_category),
+                Journal::new(workspace.root()),
+            ),
+            workspace,
+            session,
+            current_category,
         };
         result.reset_world(context);
-        result
+        Ok(result)
+    }
+
+    fn new_textedit(context: &UiContext, document: &Document) -> Box<dyn Model> {
+        let mut textedit = TextEdit::from_context(context);
+        // メモ以外のファイルは拡張子に合わせてハイライトする
+        let highlight_mode =
+            HighlightMode::from_path(document.path()).unwrap_or(HighlightMode::Markdown);
+        textedit.model_operation(&ModelOperation::SetHighlightMode(highlight_mode));
+        // 画像のリンクはメモのあるディレクトリからの相対パスとして読む
+        textedit.model_operation(&ModelOperation::SetBaseDir(
+            document.path().parent().map(|dir| dir.to_path_buf()),
+        ));
+        textedit.editor_operation(&EditorOperation::InsertString(document.text().to_string()));
+        textedit.editor_operation(&EditorOperation::BufferHead);
+        Box::new(textedit)
     }
 
     // ワールドを今のカテゴリでリセットする
     fn reset_world(&mut self, context: &UiContext) {
-        let mut world = DefaultWorld::new(context.window_size());
-        for memo in self.memos.get_current_memos().unwrap().memos.iter() {
-            let mut textedit = TextEdit::from_context(context);
-            textedit.model_operation(&ModelOperation::SetHighlightMode(
-                ui_support::ui_context::HighlightMode::Markdown,
-            ));
-            textedit.editor_operation(&EditorOperation::InsertString(memo.to_string()));
-            textedit.editor_operation(&EditorOperation::BufferHead);
-            let model = Box::new(textedit);
-            world.add(model);
+        let mut world = DocumentWorld::new(
+            context.window_size(),
+            self.workspace.category_dir(&self.current_category),
+            Journal::new(self.workspace.root()),
+        );
+        let mut documents = self
+            .workspace
+            .load_documents(&self.current_category)
+            .unwrap_or_else(|e| {
+                warn!("failed to load memos. error:{e}");
+                Vec::new()
+            });
+        if documents.is_empty() {
+            documents.push(self.workspace.new_document(&self.current_category));
+        }
+        for document in documents {
+            let model = Self::new_textedit(context, &document);
+            world.add_document(model, document);
         }
         let look_at = 0;
         let adjustment = match context.global_direction() {
@@ -60,15 +450,61 @@ impl CategorizedMemosWorld {
         self.world = world;
     }
 
-    fn sync(&mut self) {
-        self.memos
-            .update_current_memos(Memos::from(&self.world as &dyn World));
+    fn change_category(&mut self, context: &UiContext, category: String) {
+        self.save();
+        self.current_category = category;
+        self.reset_world(context);
     }
 
+    // 未保存の変更があるメモを保存し、取り除かれたメモをゴミ箱に移す
     fn save(&mut self) {
-        self.memos
-            .update_current_memos(Memos::from(&self.world as &dyn World));
-        self.memos.save_memos().unwrap();
+        for (document, text) in self.world.documents_with_texts() {
+            if document.is_dirty(&text)
+                && let Err(e) = document.save(&text)
+            {
+                warn!("failed to save memo. error:{e}");
+            }
+        }
+        for document in self.world.removed.drain(..) {
+            if let Err(e) = self.workspace.trash(&document) {
+                warn!("failed to remove memo. error:{e}");
+            }
+        }
+        let result = self
+            .workspace
+            .save_order(&self.current_category, &self.world.documents)
+            .and_then(|_| self.workspace.set_current_category(&self.current_category));
+        if let Err(e) = result {
+            warn!("failed to save workspace. error:{e}");
+        }
+        self.world.clear_journal_if_saved();
+    }
+
+    fn current_text_and_document(&mut self) -> Option<(String, &mut Document)> {
+        let text = self.world.current_string();
+        self.world.current_document_mut().map(|doc| (text, doc))
+    }
+
+    fn open_file(&mut self, context: &UiContext, chars: &mut HashSet<char>, path: &Path) {
+        // 既に開いているファイルであればそのメモに移動する
+        if let Some(index) = self.world.documents.iter().position(|d| d.path() == path) {
+            self.world.look_at(index, CameraAdjustment::NoCare);
+            return;
+        }
+        match Document::load(path) {
+            Ok(document) => {
+                chars.extend(document.text().chars());
+                let model = Self::new_textedit(context, &document);
+                self.world.add_document_next(model, document);
+                self.world.re_layout();
+                self.world.look_next(CameraAdjustment::NoCare);
+            }
+            Err(e) => warn!("failed to open file. error:{e}"),
+        }
+    }
+
+    fn categories(&self) -> Vec<String> {
+        self.workspace.categories()
     }
 }
 
@@ -114,22 +550,101 @@ impl ModalWorld for CategorizedMemosWorld {
         match name.as_str() {
             "save" => self.save(),
             "add-memo" => {
-                let mut textedit = TextEdit::from_context(context);
-                textedit.model_operation(&ModelOperation::SetHighlightMode(
-                    ui_support::ui_context::HighlightMode::Markdown,
-                ));
-                let model = Box::new(textedit);
-                self.world.add(model);
+                let document = self.workspace.new_document(&self.current_category);
+                self.world
+                    .add_document(Self::new_textedit(context, &document), document);
                 self.world.re_layout();
                 self.world
                     .look_at(self.world.model_length() - 1, CameraAdjustment::NoCare);
-                self.sync();
             }
             "remove-memo" => {
-                self.world.remove_current();
+                // 明示的に削除したメモはすぐにゴミ箱に移す
+                if let Some(document) = self.world.remove_current_document()
+                    && let Err(e) = self.workspace.trash(&document)
+                {
+                    warn!("failed to remove memo. error:{e}");
+                }
                 self.world.re_layout();
                 self.world.look_prev(CameraAdjustment::NoCare);
-                self.sync();
+            }
+            "select-memo-ui" => {
+                let memo_names = self
+                    .world
+                    .documents_with_texts()
+                    .map(|(document, text)| (document.file_name(), document.is_dirty(&text)))
+                    .collect::<Vec<_>>();
+                self.add_modal(
+                    context,
+                    &mut chars,
+                    Box::new(select_memo_ui(context, &memo_names)),
+                );
+            }
+            "select-memo" => {
+                if let ActionArgument::String(index) = argument
+                    && let Ok(index) = index.parse::<usize>()
+                {
+                    self.world.look_at(index, CameraAdjustment::NoCare);
+                }
+            }
+            "save-as-ui" => {
+                if let Some(document) = self.world.current_document() {
+                    let path = document.path().to_path_buf();
+                    self.add_modal(context, &mut chars, Box::new(save_as_ui(context, &path)));
+                }
+            }
+            "save-as" => {
+                if let ActionArgument::String2(path, _) = argument
+                    && let Some((text, document)) = self.current_text_and_document()
+                {
+                    // 相対パスは今のメモのディレクトリを基準にする
+                    let path = match document.path().parent() {
+                        Some(dir) => dir.join(path),
+                        None => PathBuf::from(path),
+                    };
+                    if let Err(e) = document.save_as(&path, &text) {
+                        warn!("failed to save memo. error:{e}");
+                    }
+                }
+            }
+            "rename-memo-ui" => {
+                if let Some(document) = self.world.current_document() {
+                    let file_name = document.file_name();
+                    self.add_modal(
+                        context,
+                        &mut chars,
+                        Box::new(rename_memo_ui(context, &file_name)),
+                    );
+                }
+            }
+            "rename-memo" => {
+                if let ActionArgument::String2(file_name, _) = argument
+                    && let Some(document) = self.world.current_document_mut()
+                    && let Err(e) = document.rename(&file_name)
+                {
+                    warn!("failed to rename memo. error:{e}");
+                }
+            }
+            "reload-memo" => {
+                let index = match argument {
+                    ActionArgument::String(path) => self
+                        .world
+                        .documents
+                        .iter()
+                        .position(|d| d.path() == Path::new(&path)),
+                    _ => Some(self.world.world.focus_index()),
+                };
+                if let Some(index) = index {
+                    self.world.reload(context, index);
+                }
+            }
+            "external-change-ui" => {
+                if let ActionArgument::String(path) = argument {
+                    self.add_modal(
+                        context,
+                        &mut chars,
+                        Box::new(external_change_ui(context, Path::new(&path))),
+                    );
+                }
             }
             "insert-date" => {
                 self.add_modal(context, &mut chars, Box::new(insert_date_select(context)))
@@ -137,36 +652,49 @@ impl ModalWorld for CategorizedMemosWorld {
             "move-category-ui" => self.add_modal(
                 context,
                 &mut chars,
-                Box::new(move_category_ui(context, &self.memos)),
+                Box::new(move_category_ui(context, &self.categories())),
             ),
-            "move-category" => match argument {
-                ActionArgument::String(category) => 'outer: {
-                    if self.memos.current_category == category {
-                        break 'outer;
+            "move-category" => {
+                if let ActionArgument::String(category) = argument
+                    && self.current_category != category
+                {
+                    if !self.categories().contains(&category)
+                        && let Err(e) = self.workspace.add_category(&category)
+                    {
+                        warn!("failed to add category. error:{e}");
+                    } else {
+                        self.change_category(context, category);
+                        chars.extend(self.world_chars());
                     }
-                    self.sync();
-                    self.memos.current_category = category;
-                    self.reset_world(context);
-                    chars.extend(self.world_chars());
                 }
-                _ => { /* noop */ }
-            },
+            }
             "move-memo-ui" => self.add_modal(
                 context,
                 &mut chars,
-                Box::new(move_memo_ui(context, &self.memos)),
+                Box::new(move_memo_ui(context, &self.categories())),
             ),
-            "move-memo" => 'outer: {
-                match argument {
-                    ActionArgument::String(category) => {
-                        if self.memos.current_category == category {
-                            break 'outer;
+            "move-memo" => {
+                if let ActionArgument::String(category) = argument
+                    && self.current_category != category
+                    && let Some((text, mut document)) = self
+                        .current_text_and_document()
+                        .map(|(text, document)| (text, document.clone()))
+                {
+                    let result = if document.is_dirty(&text) {
+                        document.save(&text)
+                    } else {
+                        Ok(())
+                    }
+                    .and_then(|_| self.workspace.move_document(&mut document, &category));
+                    match result {
+                        Ok(_) => {
+                            // 移動したメモはゴミ箱に移さずに取り除く
+                            self.world.remove_current_document();
+                            self.world.re_layout();
+                            self.world.look_prev(CameraAdjustment::NoCare);
                         }
-                        self.memos
-                            .add_memo(Some(&category), self.world.current_string());
-                        context.register_action(Action::new_command("world", "remove-current"));
+                        Err(e) => warn!("failed to move memo. error:{e}"),
                     }
-                    _ => { /* noop */ }
                 }
             }
             "add-category-ui" => {
@@ -174,16 +702,17 @@ impl ModalWorld for CategorizedMemosWorld {
             }
             "add-category" => {
                 if let ActionArgument::String2(category, _) = argument
-                    && !self.memos.categories().contains(&category)
+                    && !self.categories().contains(&category)
+                    && let Err(e) = self.workspace.add_category(&category)
                 {
-                    self.memos.add_memo(Some(&category), String::new());
+                    warn!("failed to add category. error:{e}");
                 }
             }
             "rename-category-select-ui" => {
                 self.add_modal(
                     context,
                     &mut chars,
-                    Box::new(rename_category_select_ui(context, &self.memos)),
+                    Box::new(rename_category_select_ui(context, &self.categories())),
                 );
             }
             "rename-category-ui" => {
@@ -197,27 +726,74 @@ impl ModalWorld for CategorizedMemosWorld {
             }
             "rename-category" => {
                 if let ActionArgument::String2(new_name, old_name) = argument {
-                    self.memos.rename_category(&new_name, &old_name);
+                    let is_current = self.current_category == old_name;
+                    if is_current {
+                        self.save();
+                    }
+                    match self.workspace.rename_category(&new_name, &old_name) {
+                        Ok(_) if is_current => {
+                            self.current_category = new_name;
+                            self.reset_world(context);
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("failed to rename category. error:{e}"),
+                    }
                 }
             }
             "remove-category-ui" => {
                 self.add_modal(
                     context,
                     &mut chars,
-                    Box::new(remove_category_ui(context, &self.memos)),
+                    Box::new(remove_category_ui(context, &self.categories())),
                 );
             }
             "remove-category" => {
                 if let ActionArgument::String(category) = argument {
-                    self.memos.remove_category(&category);
-                    if self.memos.current_category == category {
+                    let is_current = self.current_category == category;
+                    if is_current {
+                        self.save();
+                    }
+                    match self.workspace.remove_category(&category) {
+                        Ok(_) if is_current => {
+                            self.current_category = self.workspace.current_category();
+                            self.reset_world(context);
+                            chars.extend(self.world_chars());
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("failed to remove category. error:{e}"),
+                    }
+                }
+            }
+            "recover-journal-ui" => {
+                let paths = self.session.recoverable().into_keys().collect::<Vec<_>>();
+                if !paths.is_empty() {
+                    self.add_modal(
+                        context,
+                        &mut chars,
+                        Box::new(recover_journal_ui(context, &paths)),
+                    );
+                }
+            }
+            "recover-journal" => {
+                // 復元した内容を読み込めるよう、編集中の内容を保存してから書き戻す
+                self.save();
+                match self.session.recover() {
+                    Ok(_) => {
                         self.reset_world(context);
+                        chars.extend(self.world_chars());
                     }
+                    Err(e) => warn!("failed to recover memos. error:{e}"),
                 }
             }
+            "discard-journal" => self.session.discard_recoverable(),
             "open-file-ui" => {
                 self.add_modal(context, &mut chars, Box::new(open_file_ui(context)));
             }
+            "open-file" => {
+                if let ActionArgument::String(path) = argument {
+                    self.open_file(context, &mut chars, Path::new(&path));
+                }
+            }
             _ => { /* noop */ }
         }
         (InputResult::InputConsumed, chars)
@@ -225,5 +801,6 @@ impl ModalWorld for CategorizedMemosWorld {
 
     fn graceful_exit(&mut self) {
         self.save();
+        self.session.finish();
     }
 }
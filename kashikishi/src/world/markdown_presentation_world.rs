@@ -1,22 +1,34 @@
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use markdown_heading_splitter::split_headings;
-use stroke_parser::Action;
+use font_rasterizer::glyph_instances::GlyphInstances;
+use stroke_parser::{Action, ActionArgument};
+use text_buffer::{action::EditorOperation, buffer::CellPosition};
 use ui_support::{
     camera::CameraAdjustment,
-    layout_engine::{DefaultWorld, Model, World},
-    ui::FileChooser,
+    editor_settings::EditorTextContextProfile,
+    layout_engine::{DefaultWorld, Model, ModelBorder, ModelOperation, World},
+    ui::{Card, FileChooser, TextEdit, TextInput},
     ui_context::UiContext,
 };
 
-use crate::world::ModalWorld;
+use crate::{
+    presentation::{PresentationTimer, Slide, slides},
+    world::ModalWorld,
+};
 
 pub(crate) struct MarkdownPresentationWorld {
     world: DefaultWorld,
     markdown_path: Option<PathBuf>,
+    slides: Vec<Slide>,
+    // 切り替え効果を最後に表示したスライドの位置
+    shown_slide: Option<usize>,
+    timer: Option<PresentationTimer>,
+    timer_card: Card,
+    timer_text: String,
 }
 
 impl MarkdownPresentationWorld {
@@ -42,6 +54,11 @@ impl MarkdownPresentationWorld {
         Self {
             world,
             markdown_path: None,
+            slides: Vec::new(),
+            shown_slide: None,
+            timer: None,
+            timer_card: Card::with_settings(context.editor_settings()),
+            timer_text: String::new(),
         }
     }
 
@@ -56,36 +73,93 @@ impl MarkdownPresentationWorld {
                 )
             });
 
-            let markdowns = split_headings(&markdown_content);
-
-            for (heading, content) in markdowns {
-                let mut textedit = ui_support::ui::TextEdit::from_context(context);
-                textedit.editor_operation(&text_buffer::action::EditorOperation::InsertString(
-                    format!(
-                        "{} {}\n\n{}",
-                        "#".repeat(heading.level()),
-                        heading.title(),
-                        content
-                    ),
-                ));
-                textedit.editor_operation(&text_buffer::action::EditorOperation::BufferHead);
-                textedit.model_operation(
-                    &ui_support::layout_engine::ModelOperation::SetHighlightMode(
-                        ui_support::ui_context::HighlightMode::Markdown,
-                    ),
-                );
-                textedit.model_operation(
-                    &ui_support::layout_engine::ModelOperation::SetModelBorder(
-                        ui_support::layout_engine::ModelBorder::Rounded,
-                    ),
-                );
-                let model = Box::new(textedit);
-                self.world.add(model);
+            self.slides = slides(&markdown_content);
+            for slide in self.slides.iter() {
+                let mut text_context = context.text_context(EditorTextContextProfile::Document);
+                if let Some(transition) = slide.transition {
+                    text_context = text_context.with_char_easings_preset(transition);
+                }
+                self.world.add(Box::new(slide.text_edit(text_context)));
             }
+            self.shown_slide = Some(self.world.focus_index());
             self.world.re_layout();
             self.world.look_modal(CameraAdjustment::FitBoth);
         }
     }
+
+    // 切り替え効果の指定があるスライドは、文字を置き換えて表示し直すことで効果を見せる
+    fn play_transition(&mut self, index: usize) {
+        if self
+            .slides
+            .get(index)
+            .and_then(|slide| slide.transition)
+            .is_none()
+        {
+            return;
+        }
+        let Some(text) = self.world.strings().get(index).cloned() else {
+            return;
+        };
+        let last_row = text.split('\n').count() - 1;
+        let last_col = text
+            .split('\n')
+            .next_back()
+            .map(|line| line.chars().count())
+            .unwrap_or_default();
+        for op in [
+            EditorOperation::Replace(
+                CellPosition::new(0, 0)..CellPosition::new(last_row, last_col),
+                text,
+                CellPosition::new(0, 0),
+            ),
+            EditorOperation::BufferHead,
+        ] {
+            self.world.editor_operation_at(index, &op);
+        }
+    }
+
+    // 今のスライドのノートをモーダルで表示する。表示中であれば閉じる
+    fn toggle_speaker_notes(&mut self, context: &UiContext) -> HashSet<char> {
+        if self.world.has_modal() {
+            self.world.remove_current();
+            return HashSet::new();
+        }
+        let focus = self.world.focus_index();
+        let Some(slide) = self.slides.get(focus) else {
+            return HashSet::new();
+        };
+        let notes = if slide.notes.is_empty() {
+            "ノートはありません"
+        } else {
+            &slide.notes
+        };
+        let text = format!(
+            "スライド {} / {} のノート\n\n{}",
+            focus + 1,
+            self.slides.len(),
+            notes
+        );
+        let chars = text.chars().collect();
+
+        let mut textedit = TextEdit::from_context(context);
+        textedit.editor_operation(&EditorOperation::InsertString(text));
+        textedit.editor_operation(&EditorOperation::BufferHead);
+        textedit.model_operation(&ModelOperation::SetModelBorder(ModelBorder::Rounded));
+        self.world.add_modal(Box::new(textedit));
+        self.world.re_layout();
+        self.world.look_modal(CameraAdjustment::FitBoth);
+        chars
+    }
+
+    fn start_timer(&mut self, minutes: &str) {
+        let planned = minutes
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .map(|minutes| Duration::from_secs(minutes * 60));
+        self.timer = Some(PresentationTimer::start(planned));
+        self.timer_text.clear();
+    }
 }
 
 impl ModalWorld for MarkdownPresentationWorld {
@@ -113,6 +187,33 @@ impl ModalWorld for MarkdownPresentationWorld {
         self.world.look_modal(CameraAdjustment::FitBoth);
     }
 
+    fn update_overlay(&mut self, context: &UiContext) {
+        let focus = self.world.focus_index();
+        if !self.slides.is_empty() && self.shown_slide != Some(focus) {
+            self.shown_slide = Some(focus);
+            self.play_transition(focus);
+        }
+
+        let Some(timer) = &self.timer else {
+            return;
+        };
+        let timer_text = format!("{} / {}  {}", focus + 1, self.slides.len(), timer.status());
+        if timer_text != self.timer_text {
+            context.register_string(timer_text.clone());
+            self.timer_card.set_text(context, timer_text.clone());
+            self.timer_text = timer_text;
+        }
+        self.timer_card.update(context);
+    }
+
+    fn overlay_instances(&self) -> Vec<&GlyphInstances> {
+        if self.timer.is_some() {
+            self.timer_card.get_instances()
+        } else {
+            Vec::new()
+        }
+    }
+
     fn apply_action(
         &mut self,
         context: &ui_support::ui_context::UiContext,
@@ -120,12 +221,50 @@ impl ModalWorld for MarkdownPresentationWorld {
     ) -> (ui_support::InputResult, std::collections::HashSet<char>) {
         match action {
             stroke_parser::Action::Command(namespace, name, argument)
-                if namespace == "kashikishi".into() && name == "open-markdown".into() =>
+                if namespace == "kashikishi".into() =>
             {
-                if let stroke_parser::ActionArgument::String(path) = argument {
-                    self.open_markdown(context, PathBuf::from(path));
+                match &*name.to_string() {
+                    "open-markdown" => {
+                        if let ActionArgument::String(path) = argument {
+                            self.open_markdown(context, PathBuf::from(path));
+                        }
+                        (ui_support::InputResult::InputConsumed, self.world_chars())
+                    }
+                    "toggle-speaker-notes" => {
+                        let chars = self.toggle_speaker_notes(context);
+                        (ui_support::InputResult::InputConsumed, chars)
+                    }
+                    "start-presentation-timer-ui" => {
+                        let message = "発表時間(分)を入力してください";
+                        let modal = TextInput::new(
+                            context,
+                            message.to_string(),
+                            None,
+                            Action::new_command("kashikishi", "start-presentation-timer"),
+                        );
+                        self.world.add_modal(Box::new(modal));
+                        self.world.re_layout();
+                        self.world.look_modal(CameraAdjustment::FitBoth);
+                        (
+                            ui_support::InputResult::InputConsumed,
+                            message.chars().collect(),
+                        )
+                    }
+                    "start-presentation-timer" => {
+                        match argument {
+                            ActionArgument::String(minutes)
+                            | ActionArgument::String2(minutes, _) => self.start_timer(&minutes),
+                            _ => self.start_timer(""),
+                        }
+                        (ui_support::InputResult::InputConsumed, HashSet::new())
+                    }
+                    "stop-presentation-timer" => {
+                        self.timer = None;
+                        self.timer_text.clear();
+                        (ui_support::InputResult::InputConsumed, HashSet::new())
+                    }
+                    _ => (ui_support::InputResult::Noop, HashSet::new()),
                 }
-                (ui_support::InputResult::InputConsumed, self.world_chars())
             }
             _ => (ui_support::InputResult::Noop, HashSet::new()),
         }
@@ -12,6 +12,7 @@ pub(crate) use start_world::StartWorld;
 
 use std::collections::HashSet;
 
+use font_rasterizer::glyph_instances::GlyphInstances;
 use ui_support::{
     InputResult,
     layout_engine::{Model, World},
@@ -29,5 +30,13 @@ pub(crate) trait ModalWorld {
     fn graceful_exit(&mut self) {
         // noop
     }
+    // 毎フレーム呼び出され、ワールドに重ねて表示するものを更新する
+    fn update_overlay(&mut self, _context: &UiContext) {
+        // noop
+    }
+    // ワールドに重ねて表示するもの
+    fn overlay_instances(&self) -> Vec<&GlyphInstances> {
+        Vec::new()
+    }
     fn add_modal(&mut self, context: &UiContext, chars: &mut HashSet<char>, model: Box<dyn Model>);
 }
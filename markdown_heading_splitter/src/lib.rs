@@ -1,3 +1,12 @@
+mod outline;
+mod split;
+
+pub use outline::{
+    OutlineItem, Rewrite, demote_section, move_section_down, move_section_up, outline,
+    promote_section,
+};
+pub use split::{FrontMatter, Section, SplitDocument, SplitOptions, split_sections};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Heading {
     H1(String),
@@ -47,10 +56,14 @@ impl Heading {
 struct HeadingNode {
     start_byte: usize,
     end_byte: usize,
+    // 見出しの先頭の行(0 始まり)
+    start_row: usize,
+    // 見出しが占める行数。setext 形式の見出しは下線の行を含めて 2 行になる
+    row_count: usize,
     heading: Heading,
 }
 
-pub fn split_headings(markdown: &str) -> Vec<(Heading, String)> {
+fn heading_nodes(markdown: &str) -> Vec<HeadingNode> {
     let mut parser = tree_sitter::Parser::new();
     parser
         .set_language(&tree_sitter_md::LANGUAGE.into())
@@ -63,24 +76,15 @@ pub fn split_headings(markdown: &str) -> Vec<(Heading, String)> {
     let mut nodes = Vec::new();
     collect_heading_nodes(markdown, tree.root_node(), &mut nodes);
     nodes.sort_by_key(|node| node.start_byte);
+    nodes
+}
 
-    let mut sections = Vec::with_capacity(nodes.len());
-    for (i, node) in nodes.iter().enumerate() {
-        let section_end = nodes
-            .get(i + 1)
-            .map(|next| next.start_byte)
-            .unwrap_or(markdown.len());
-
-        let body = markdown
-            .get(node.end_byte..section_end)
-            .unwrap_or("")
-            .trim()
-            .to_string();
-
-        sections.push((node.heading.clone(), body));
-    }
-
-    sections
+pub fn split_headings(markdown: &str) -> Vec<(Heading, String)> {
+    split_sections(markdown, &SplitOptions::default())
+        .sections
+        .into_iter()
+        .filter_map(|section| section.heading.map(|heading| (heading, section.body)))
+        .collect()
 }
 
 pub fn sprint_headings(markdown: &str) -> Vec<(Heading, String)> {
@@ -96,6 +100,8 @@ fn collect_heading_nodes(markdown: &str, node: tree_sitter::Node<'_>, out: &mut
         out.push(HeadingNode {
             start_byte: node.start_byte(),
             end_byte: node.end_byte(),
+            start_row: node.start_position().row,
+            row_count: if kind == "setext_heading" { 2 } else { 1 },
             heading: Heading::new(level, title),
         });
     }
@@ -0,0 +1,287 @@
+//! 見出しの構造(アウトライン)の取得と、セクション単位での書き換え。
+//!
+//! 行の位置はすべて 0 始まりで、テキストを `\n` で区切った行に対応する。
+use std::ops::Range;
+
+use crate::{Heading, HeadingNode, heading_nodes};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineItem {
+    pub heading: Heading,
+    /// 見出しの行
+    pub row: usize,
+    /// 見出しが占める行数。setext 形式の見出しは下線の行を含めて 2 行になる
+    pub heading_rows: usize,
+    /// 下位の見出しを含むセクションの行の範囲。テキスト末尾の改行の後の空行は含まない
+    pub section: Range<usize>,
+}
+
+/// 行単位の書き換え。`rows` の行を `lines` で置き換える
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    pub rows: Range<usize>,
+    pub lines: Vec<String>,
+    /// 書き換えた後に操作したセクションの見出しがある行
+    pub caret_row: usize,
+}
+
+pub fn outline(markdown: &str) -> Vec<OutlineItem> {
+    let nodes = heading_nodes(markdown);
+    let total_rows = lines(markdown).len();
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let level = node.heading.level();
+            let end = nodes[i + 1..]
+                .iter()
+                .find(|next| next.heading.level() <= level)
+                .map(|next| next.start_row)
+                .unwrap_or(total_rows);
+            item(node, end)
+        })
+        .collect()
+}
+
+fn item(node: &HeadingNode, section_end: usize) -> OutlineItem {
+    OutlineItem {
+        heading: node.heading.clone(),
+        row: node.start_row,
+        heading_rows: node.row_count,
+        section: node.start_row..section_end,
+    }
+}
+
+// 末尾の改行の後の空行は、セクションの入れ替えで途中に移動しないよう行として扱わない
+fn lines(markdown: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = markdown.split('\n').collect();
+    if markdown.ends_with('\n') {
+        lines.pop();
+    }
+    lines
+}
+
+// row を含む最も内側のセクション
+fn section_index(items: &[OutlineItem], row: usize) -> Option<usize> {
+    items.iter().rposition(|item| item.section.contains(&row))
+}
+
+/// row を含むセクションの見出しを、下位の見出しも含めて一つ上のレベルにする
+pub fn promote_section(markdown: &str, row: usize) -> Option<Rewrite> {
+    change_level(markdown, row, -1)
+}
+
+/// row を含むセクションの見出しを、下位の見出しも含めて一つ下のレベルにする
+pub fn demote_section(markdown: &str, row: usize) -> Option<Rewrite> {
+    change_level(markdown, row, 1)
+}
+
+fn change_level(markdown: &str, row: usize, delta: isize) -> Option<Rewrite> {
+    let items = outline(markdown);
+    let index = section_index(&items, row)?;
+    let section = items[index].section.clone();
+    let targets: Vec<&OutlineItem> = items[index..]
+        .iter()
+        .take_while(|item| section.contains(&item.row))
+        .collect();
+    // H1 より上や H6 より下になる見出しがあれば何もしない
+    if targets
+        .iter()
+        .any(|item| !(1..=6).contains(&(item.heading.level() as isize + delta)))
+    {
+        return None;
+    }
+
+    let source = lines(markdown);
+    let last = targets.last()?;
+    let rows = section.start..last.row + last.heading_rows;
+    let mut new_lines = Vec::new();
+    let mut current = rows.start;
+    for item in targets.iter() {
+        new_lines.extend(
+            source[current..item.row]
+                .iter()
+                .map(|line| line.to_string()),
+        );
+        let level = (item.heading.level() as isize + delta) as usize;
+        new_lines.push(heading_line(source[item.row], item, level));
+        current = item.row + item.heading_rows;
+    }
+    Some(Rewrite {
+        rows,
+        lines: new_lines,
+        caret_row: section.start,
+    })
+}
+
+fn heading_line(line: &str, item: &OutlineItem, level: usize) -> String {
+    let hashes = "#".repeat(level);
+    if item.heading_rows == 1 {
+        // ブロック引用などの中の見出しは、`#` より前を残す
+        if let Some(start) = line.find('#') {
+            let rest = line[start..].trim_start_matches('#');
+            return format!("{}{}{}", &line[..start], hashes, rest);
+        }
+    }
+    // setext 形式の見出しは ATX 形式に書き換える
+    format!("{} {}", hashes, item.heading.title())
+}
+
+/// row を含むセクションを、同じレベルの一つ前のセクションと入れ替える
+pub fn move_section_up(markdown: &str, row: usize) -> Option<Rewrite> {
+    let items = outline(markdown);
+    let index = section_index(&items, row)?;
+    let current = &items[index];
+    let previous = items[..index]
+        .iter()
+        .rev()
+        .find(|item| item.heading.level() <= current.heading.level())?;
+    if previous.heading.level() != current.heading.level() {
+        return None;
+    }
+    let (rows, lines) = swap(
+        &lines(markdown),
+        previous.section.clone(),
+        current.section.clone(),
+    );
+    Some(Rewrite {
+        caret_row: rows.start,
+        rows,
+        lines,
+    })
+}
+
+/// row を含むセクションを、同じレベルの一つ後のセクションと入れ替える
+pub fn move_section_down(markdown: &str, row: usize) -> Option<Rewrite> {
+    let items = outline(markdown);
+    let index = section_index(&items, row)?;
+    let current = &items[index];
+    let next = items[index + 1..]
+        .iter()
+        .find(|item| item.row == current.section.end)?;
+    if next.heading.level() != current.heading.level() {
+        return None;
+    }
+    let source = lines(markdown);
+    let (rows, lines) = swap(&source, current.section.clone(), next.section.clone());
+    let (next_body, _) = split_gap(&source, next.section.clone());
+    let (_, current_gap) = split_gap(&source, current.section.clone());
+    Some(Rewrite {
+        caret_row: rows.start + next_body.len() + current_gap.len(),
+        rows,
+        lines,
+    })
+}
+
+// 隣り合う二つのセクションを入れ替える。
+// セクション末尾の空行は区切りとして元の位置に残す
+fn swap(source: &[&str], first: Range<usize>, second: Range<usize>) -> (Range<usize>, Vec<String>) {
+    let (first_body, first_gap) = split_gap(source, first.clone());
+    let (second_body, second_gap) = split_gap(source, second.clone());
+    let lines = [second_body, first_gap, first_body, second_gap]
+        .concat()
+        .into_iter()
+        .map(|line| line.to_string())
+        .collect();
+    (first.start..second.end, lines)
+}
+
+fn split_gap<'a>(source: &[&'a str], rows: Range<usize>) -> (Vec<&'a str>, Vec<&'a str>) {
+    let section = &source[rows];
+    let gap = section
+        .iter()
+        .rev()
+        .take_while(|line| line.trim().is_empty())
+        .count();
+    let (body, gap) = section.split_at(section.len() - gap);
+    (body.to_vec(), gap.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(markdown: &str, rewrite: &Rewrite) -> String {
+        let mut lines: Vec<String> = markdown.split('\n').map(|line| line.to_string()).collect();
+        lines.splice(rewrite.rows.clone(), rewrite.lines.iter().cloned());
+        lines.join("\n")
+    }
+
+    const MARKDOWN: &str = "# A\na\n\n## A-1\n\n# B\nb\n\n## B-1\nb-1\n";
+
+    #[test]
+    fn outline_sections() {
+        let items = outline(MARKDOWN);
+        let summary: Vec<_> = items
+            .iter()
+            .map(|item| (item.heading.title(), item.row, item.section.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("A", 0, 0..5),
+                ("A-1", 3, 3..5),
+                ("B", 5, 5..10),
+                ("B-1", 8, 8..10),
+            ]
+        );
+        // setext 形式の見出しは 2 行を占める
+        assert_eq!(outline("Title\n=====\ntext")[0].heading_rows, 2);
+    }
+
+    #[test]
+    fn promote_and_demote() {
+        let rewrite = demote_section(MARKDOWN, 1).unwrap();
+        assert_eq!(rewrite.rows, 0..4);
+        assert_eq!(
+            apply(MARKDOWN, &rewrite),
+            "## A\na\n\n### A-1\n\n# B\nb\n\n## B-1\nb-1\n"
+        );
+        assert_eq!(rewrite.caret_row, 0);
+
+        let rewrite = promote_section(MARKDOWN, 9).unwrap();
+        assert_eq!(
+            apply(MARKDOWN, &rewrite),
+            "# A\na\n\n## A-1\n\n# B\nb\n\n# B-1\nb-1\n"
+        );
+
+        // H1 より上にはできない
+        assert_eq!(promote_section(MARKDOWN, 0), None);
+        // 見出しより前の行はどのセクションにも含まれない
+        assert_eq!(demote_section("text\n# A\n", 0), None);
+        // setext 形式の見出しは ATX 形式に書き換える
+        let markdown = "Title\n=====\ntext";
+        assert_eq!(
+            apply(markdown, &demote_section(markdown, 2).unwrap()),
+            "## Title\ntext"
+        );
+        // ブロック引用の中の見出しは引用のまま
+        let markdown = "> # Quote\n";
+        assert_eq!(
+            apply(markdown, &demote_section(markdown, 0).unwrap()),
+            "> ## Quote\n"
+        );
+    }
+
+    #[test]
+    fn move_sections() {
+        let rewrite = move_section_up(MARKDOWN, 6).unwrap();
+        assert_eq!(
+            apply(MARKDOWN, &rewrite),
+            "# B\nb\n\n## B-1\nb-1\n\n# A\na\n\n## A-1\n"
+        );
+        assert_eq!(rewrite.caret_row, 0);
+
+        let rewrite = move_section_down(MARKDOWN, 0).unwrap();
+        assert_eq!(
+            apply(MARKDOWN, &rewrite),
+            "# B\nb\n\n## B-1\nb-1\n\n# A\na\n\n## A-1\n"
+        );
+        assert_eq!(rewrite.caret_row, 6);
+
+        // 親の見出しをまたいでは移動しない
+        assert_eq!(move_section_up(MARKDOWN, 8), None);
+        assert_eq!(move_section_down(MARKDOWN, 3), None);
+        assert_eq!(move_section_up(MARKDOWN, 0), None);
+    }
+}
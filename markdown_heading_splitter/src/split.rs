@@ -0,0 +1,352 @@
+//! 分割の方法を指定して Markdown をセクションに分割する。
+//!
+//! プレゼンテーションのスライドのように、見出しのほかに `---` の区切り線でも分割したり、
+//! 文書の先頭の YAML の front matter から文書の情報を読み取ったりできる。
+use crate::{Heading, heading_nodes};
+
+/// 分割の方法の指定。`SplitOptions::default()` は `split_headings` と同じく、
+/// すべてのレベルの見出しで分割する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitOptions {
+    horizontal_rule: bool,
+    max_heading_level: usize,
+    front_matter: bool,
+    continuation: bool,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        Self {
+            horizontal_rule: false,
+            max_heading_level: 6,
+            front_matter: false,
+            continuation: false,
+        }
+    }
+}
+
+impl SplitOptions {
+    /// `---` などの区切り線でも分割する。
+    /// 区切り線で分割する場合は、最初の見出しや区切り線より前の文章もセクションになる
+    pub fn with_horizontal_rule(mut self, horizontal_rule: bool) -> Self {
+        self.horizontal_rule = horizontal_rule;
+        self
+    }
+
+    /// 分割に使う見出しのレベルの上限。より深い見出しは本文に含める
+    pub fn with_max_heading_level(mut self, max_heading_level: usize) -> Self {
+        self.max_heading_level = max_heading_level;
+        self
+    }
+
+    /// 文書の先頭の `---` で囲まれた YAML を front matter として読み取り、本文から取り除く
+    pub fn with_front_matter(mut self, front_matter: bool) -> Self {
+        self.front_matter = front_matter;
+        self
+    }
+
+    /// 区切り線で分割した後のセクションに、直前の見出しを引き継ぐ
+    pub fn with_continuation(mut self, continuation: bool) -> Self {
+        self.continuation = continuation;
+        self
+    }
+}
+
+/// 文書の先頭の front matter から読み取った情報
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub theme: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// セクションの見出し。区切り線で始まるセクションは見出しを持たない
+    pub heading: Option<Heading>,
+    /// 前後の空白をトリムした本文
+    pub body: String,
+    /// 区切り線の後で、直前の見出しを引き継いだセクションであるか
+    pub continued: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SplitDocument {
+    pub front_matter: Option<FrontMatter>,
+    pub sections: Vec<Section>,
+}
+
+// セクションの区切り。見出しか区切り線
+struct Boundary {
+    start_byte: usize,
+    end_byte: usize,
+    heading: Option<Heading>,
+}
+
+pub fn split_sections(markdown: &str, options: &SplitOptions) -> SplitDocument {
+    let (front_matter, markdown) = if options.front_matter {
+        match parse_front_matter(markdown) {
+            Some((front_matter, rest)) => (Some(front_matter), rest),
+            None => (None, markdown),
+        }
+    } else {
+        (None, markdown)
+    };
+
+    let mut boundaries: Vec<Boundary> = heading_nodes(markdown)
+        .into_iter()
+        .filter(|node| node.heading.level() <= options.max_heading_level)
+        .map(|node| Boundary {
+            start_byte: node.start_byte,
+            end_byte: node.end_byte,
+            heading: Some(node.heading),
+        })
+        .collect();
+    if options.horizontal_rule {
+        boundaries.extend(
+            thematic_breaks(markdown)
+                .into_iter()
+                .map(|(start_byte, end_byte)| Boundary {
+                    start_byte,
+                    end_byte,
+                    heading: None,
+                }),
+        );
+        boundaries.sort_by_key(|boundary| boundary.start_byte);
+    }
+
+    let mut sections = Vec::with_capacity(boundaries.len() + 1);
+    if options.horizontal_rule {
+        let preamble = markdown
+            .get(..boundaries.first().map_or(markdown.len(), |b| b.start_byte))
+            .unwrap_or("")
+            .trim();
+        if !preamble.is_empty() {
+            sections.push(Section {
+                heading: None,
+                body: preamble.to_string(),
+                continued: false,
+            });
+        }
+    }
+
+    let mut current_heading: Option<Heading> = None;
+    for (i, boundary) in boundaries.iter().enumerate() {
+        let section_end = boundaries
+            .get(i + 1)
+            .map(|next| next.start_byte)
+            .unwrap_or(markdown.len());
+        let body = markdown
+            .get(boundary.end_byte..section_end)
+            .unwrap_or("")
+            .trim()
+            .to_string();
+
+        let section = match &boundary.heading {
+            Some(heading) => {
+                current_heading = Some(heading.clone());
+                Section {
+                    heading: Some(heading.clone()),
+                    body,
+                    continued: false,
+                }
+            }
+            // 区切り線が続くなどして空になったセクションは作らない
+            None if body.is_empty() => continue,
+            None if options.continuation && current_heading.is_some() => Section {
+                heading: current_heading.clone(),
+                body,
+                continued: true,
+            },
+            None => Section {
+                heading: None,
+                body,
+                continued: false,
+            },
+        };
+        sections.push(section);
+    }
+
+    SplitDocument {
+        front_matter,
+        sections,
+    }
+}
+
+fn thematic_breaks(markdown: &str) -> Vec<(usize, usize)> {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_md::LANGUAGE.into())
+        .expect("failed to set markdown language");
+
+    let Some(tree) = parser.parse(markdown, None) else {
+        return Vec::new();
+    };
+
+    let mut breaks = Vec::new();
+    collect_thematic_breaks(tree.root_node(), &mut breaks);
+    breaks
+}
+
+fn collect_thematic_breaks(node: tree_sitter::Node<'_>, out: &mut Vec<(usize, usize)>) {
+    if node.kind() == "thematic_break" {
+        out.push((node.start_byte(), node.end_byte()));
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_thematic_breaks(child, out);
+    }
+}
+
+// 先頭の `---` の行から、次の `---` か `...` の行までを front matter とする。
+// YAML は `key: value` の形の行だけを読み、値を囲む引用符は取り除く
+fn parse_front_matter(markdown: &str) -> Option<(FrontMatter, &str)> {
+    let mut lines = markdown.split_inclusive('\n');
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+
+    let mut front_matter = FrontMatter::default();
+    let mut consumed = markdown.find('\n')? + 1;
+    for line in lines {
+        consumed += line.len();
+        let line = line.trim_end();
+        if line == "---" || line == "..." {
+            return Some((front_matter, &markdown[consumed..]));
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = unquote(value.trim()).to_string();
+        match key.trim() {
+            "title" => front_matter.title = Some(value),
+            "author" => front_matter.author = Some(value),
+            "theme" => front_matter.theme = Some(value),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(unquoted) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return unquoted;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(document: &SplitDocument) -> Vec<(Option<String>, &str, bool)> {
+        document
+            .sections
+            .iter()
+            .map(|section| {
+                (
+                    section
+                        .heading
+                        .as_ref()
+                        .map(|heading| heading.title().to_string()),
+                    section.body.as_str(),
+                    section.continued,
+                )
+            })
+            .collect()
+    }
+
+    const MARKDOWN: &str = "# A\na\n\n## A-1\na-1\n\n---\n\nafter\n\n# B\nb\n";
+
+    #[test]
+    fn default_options_split_every_heading() {
+        let document = split_sections(MARKDOWN, &SplitOptions::default());
+        assert_eq!(
+            summary(&document),
+            vec![
+                (Some("A".to_string()), "a", false),
+                (Some("A-1".to_string()), "a-1\n\n---\n\nafter", false),
+                (Some("B".to_string()), "b", false),
+            ]
+        );
+        assert_eq!(document.front_matter, None);
+    }
+
+    #[test]
+    fn split_horizontal_rule() {
+        let options = SplitOptions::default().with_horizontal_rule(true);
+        let document = split_sections("intro\n\n---\n\n# A\na\n\n---\n\n---\n\nb\n", &options);
+        assert_eq!(
+            summary(&document),
+            vec![
+                (None, "intro", false),
+                (Some("A".to_string()), "a", false),
+                (None, "b", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_max_heading_level() {
+        let options = SplitOptions::default().with_max_heading_level(1);
+        let document = split_sections(MARKDOWN, &options);
+        assert_eq!(
+            summary(&document),
+            vec![
+                (
+                    Some("A".to_string()),
+                    "a\n\n## A-1\na-1\n\n---\n\nafter",
+                    false
+                ),
+                (Some("B".to_string()), "b", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_front_matter() {
+        let markdown = "---\ntitle: \"Deck\"\nauthor: me\ntheme: dark\nother: x\n---\n# A\na\n";
+        let options = SplitOptions::default().with_front_matter(true);
+        let document = split_sections(markdown, &options);
+        assert_eq!(
+            document.front_matter,
+            Some(FrontMatter {
+                title: Some("Deck".to_string()),
+                author: Some("me".to_string()),
+                theme: Some("dark".to_string()),
+            })
+        );
+        assert_eq!(
+            summary(&document),
+            vec![(Some("A".to_string()), "a", false)]
+        );
+
+        // 閉じる行がなければ front matter として扱わない
+        let document = split_sections("---\ntitle: x\n", &options);
+        assert_eq!(document.front_matter, None);
+    }
+
+    #[test]
+    fn split_continuation() {
+        let options = SplitOptions::default()
+            .with_horizontal_rule(true)
+            .with_max_heading_level(1)
+            .with_continuation(true);
+        let document = split_sections(MARKDOWN, &options);
+        assert_eq!(
+            summary(&document),
+            vec![
+                (Some("A".to_string()), "a\n\n## A-1\na-1", false),
+                (Some("A".to_string()), "after", true),
+                (Some("B".to_string()), "b", false),
+            ]
+        );
+    }
+}
@@ -191,6 +191,7 @@ impl SimpleStateCallback for SingleCharCallback {
             camera: &self.camera,
             glyph_instances: self.glyphs.iter().collect(),
             vector_instances: vec![],
+            image_instances: vec![],
             glyph_instances_for_modal: vec![],
             vector_instances_for_modal: vec![],
         }
@@ -118,6 +118,7 @@ impl SimpleStateCallback for Callback {
             camera: self.world.camera(),
             glyph_instances: world_instances,
             vector_instances: self.world.vector_instances(),
+            image_instances: self.world.image_instances(),
             glyph_instances_for_modal: vec![],
             vector_instances_for_modal: vec![],
         }
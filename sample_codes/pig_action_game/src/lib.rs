@@ -610,6 +610,7 @@ impl SimpleStateCallback for PigActionGame {
             camera: &self.camera,
             glyph_instances,
             vector_instances: vec![],
+            image_instances: vec![],
             glyph_instances_for_modal: vec![],
             vector_instances_for_modal: vec![],
         }
@@ -250,6 +250,7 @@ impl SimpleStateCallback for SingleCharCallback {
             camera: self.world.camera(),
             glyph_instances: world_instances,
             vector_instances: self.world.vector_instances(),
+            image_instances: self.world.image_instances(),
             glyph_instances_for_modal,
             vector_instances_for_modal,
         }
@@ -0,0 +1,161 @@
+use std::sync::{Arc, Mutex};
+
+use font_rasterizer::context::WindowSize;
+use stroke_parser::Action;
+use text_buffer::action::EditorOperation;
+use ui_support::{
+    InputResult, RenderData, SimpleStateCallback,
+    action::ActionProcessorStore,
+    action_recorder::ActionRecorder,
+    camera::CameraAdjustment,
+    layout_engine::{DebugWorldSnapshot, DefaultWorld, ModelOperation, World},
+    register_default_border, register_default_caret, register_default_decoration,
+    ui::{TextEdit, caret_char},
+    ui_context::{CharEasingsPreset, HighlightMode, UiContext},
+};
+use winit::event::WindowEvent;
+
+use crate::scenario::Scenario;
+
+/// シナリオ実行後の World の状態
+#[derive(Debug, Clone)]
+pub(crate) struct WorldState {
+    pub(crate) snapshot: DebugWorldSnapshot,
+    pub(crate) strings: Vec<String>,
+    pub(crate) replay_finished: bool,
+}
+
+/// TextEdit を一つ持つ World に対してシナリオを再生するコールバック。
+///
+/// ウインドウからの入力は受け付けず、ActionRecorder が再生する Action のみで状態が変化する。
+pub(crate) struct ScenarioCallback {
+    world: DefaultWorld,
+    action_processor_store: ActionProcessorStore,
+    recorder: ActionRecorder,
+    highlight_mode: HighlightMode,
+    state: Arc<Mutex<Option<WorldState>>>,
+}
+
+impl ScenarioCallback {
+    pub(crate) fn new(
+        window_size: WindowSize,
+        scenario: Scenario,
+        highlight_mode: HighlightMode,
+        state: Arc<Mutex<Option<WorldState>>>,
+    ) -> Self {
+        let mut action_processor_store = ActionProcessorStore::default();
+        action_processor_store.add_default_system_processors();
+        action_processor_store.add_default_edit_processors();
+        action_processor_store.add_default_world_processors();
+
+        Self {
+            world: DefaultWorld::new(window_size),
+            action_processor_store,
+            recorder: ActionRecorder::new(Box::new(scenario)),
+            highlight_mode,
+            state,
+        }
+    }
+
+    fn capture(&self) {
+        let world_state = WorldState {
+            snapshot: self.world.debug_snapshot(),
+            strings: self.world.strings(),
+            replay_finished: !self.recorder.in_replay(),
+        };
+        *self.state.lock().unwrap() = Some(world_state);
+    }
+}
+
+impl SimpleStateCallback for ScenarioCallback {
+    fn init(&mut self, context: &UiContext) {
+        register_default_caret(context);
+        register_default_border(context);
+        register_default_decoration(context);
+        context.register_string(
+            [
+                caret_char(text_buffer::caret::CaretType::Primary),
+                caret_char(text_buffer::caret::CaretType::Mark),
+            ]
+            .iter()
+            .collect(),
+        );
+
+        let mut text_edit = TextEdit::from_context(context);
+        text_edit.model_operation(&ModelOperation::SetHighlightMode(
+            self.highlight_mode.clone(),
+        ));
+        self.world.add(Box::new(text_edit));
+        self.world
+            .change_char_easings_preset(CharEasingsPreset::ZeroMotion);
+        self.world
+            .look_current(CameraAdjustment::FitBothAndCentering);
+
+        // 再生開始時刻はこの時点の時計を基準にする
+        self.recorder.start_replay();
+    }
+
+    fn resize(&mut self, window_size: WindowSize) {
+        self.world.change_window_size(window_size);
+    }
+
+    fn update(&mut self, context: &UiContext) {
+        self.recorder.replay(context);
+        self.world.update(context);
+        self.world
+            .look_current(CameraAdjustment::FitBothAndCentering);
+        self.capture();
+    }
+
+    fn input(&mut self, _context: &UiContext, _event: &WindowEvent) -> InputResult {
+        InputResult::Noop
+    }
+
+    fn action(&mut self, context: &UiContext, action: Action) -> InputResult {
+        let result = self
+            .action_processor_store
+            .process(&action, context, &mut self.world);
+        if result != InputResult::Noop {
+            return result;
+        }
+
+        match action {
+            Action::Keytype(c) => {
+                context.register_string(c.to_string());
+                self.world.editor_operation(&EditorOperation::InsertChar(c));
+                InputResult::InputConsumed
+            }
+            Action::ImeInput(value) => {
+                context.register_string(value.clone());
+                self.world
+                    .model_operation(&ModelOperation::SetPreedit(None));
+                self.world
+                    .editor_operation(&EditorOperation::InsertString(value));
+                InputResult::InputConsumed
+            }
+            Action::ImePreedit(value, position) => {
+                context.register_string(value.clone());
+                self.world
+                    .model_operation(&ModelOperation::SetPreedit(Some((value, position))));
+                InputResult::InputConsumed
+            }
+            Action::ImeEnable | Action::ImeDisable | Action::Command(..) => InputResult::Noop,
+        }
+    }
+
+    fn render(&'_ mut self) -> RenderData<'_> {
+        let (glyph_instances_for_modal, vector_instances_for_modal) = self.world.modal_instances();
+        RenderData {
+            camera: self.world.camera(),
+            glyph_instances: self.world.glyph_instances(),
+            vector_instances: self.world.vector_instances(),
+            image_instances: self.world.image_instances(),
+            glyph_instances_for_modal,
+            vector_instances_for_modal,
+        }
+    }
+
+    fn shutdown(&mut self, _context: &UiContext) {
+        self.capture();
+    }
+}
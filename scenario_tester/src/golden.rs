@@ -0,0 +1,221 @@
+use std::path::Path;
+
+use image::{ImageBuffer, Rgba};
+
+use crate::ScenarioError;
+
+/// ゴールデンイメージとの比較時に許容する誤差。
+///
+/// GPU やドライバの差でアンチエイリアスの結果が僅かに変わるため、
+/// 完全一致ではなくチャンネル単位の差分と、差分のあるピクセルの割合で判定する。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// 1 チャンネルあたりに許容する差分(0-255)
+    pub channel: u8,
+    /// channel を超える差分を持つピクセルの許容割合(0.0-1.0)
+    pub pixel_ratio: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 8,
+            pixel_ratio: 0.001,
+        }
+    }
+}
+
+impl Tolerance {
+    pub fn exact() -> Self {
+        Self {
+            channel: 0,
+            pixel_ratio: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageDiff {
+    pub different_pixels: u64,
+    pub total_pixels: u64,
+    pub max_channel_diff: u8,
+}
+
+impl ImageDiff {
+    pub fn ratio(&self) -> f32 {
+        if self.total_pixels == 0 {
+            0.0
+        } else {
+            self.different_pixels as f32 / self.total_pixels as f32
+        }
+    }
+
+    pub fn within(&self, tolerance: Tolerance) -> bool {
+        self.ratio() <= tolerance.pixel_ratio
+    }
+}
+
+/// 二つの画像の差分を計算する。サイズが異なる場合は None を返す。
+pub fn diff_images(
+    actual: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    expected: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    tolerance: Tolerance,
+) -> Option<ImageDiff> {
+    if actual.dimensions() != expected.dimensions() {
+        return None;
+    }
+    let mut different_pixels = 0;
+    let mut max_channel_diff = 0;
+    for (a, e) in actual.pixels().zip(expected.pixels()) {
+        let diff =
+            a.0.iter()
+                .zip(e.0.iter())
+                .map(|(a, e)| a.abs_diff(*e))
+                .max()
+                .unwrap_or(0);
+        max_channel_diff = max_channel_diff.max(diff);
+        if diff > tolerance.channel {
+            different_pixels += 1;
+        }
+    }
+    Some(ImageDiff {
+        different_pixels,
+        total_pixels: actual.width() as u64 * actual.height() as u64,
+        max_channel_diff,
+    })
+}
+
+/// 環境変数 UPDATE_GOLDEN が設定されている場合は golden を更新する。
+const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+/// ゴールデンイメージと比較する。
+///
+/// `UPDATE_GOLDEN=1` が指定されている場合は actual をゴールデンとして書き出して成功扱いにする。
+/// ゴールデンイメージが存在しない場合は、基準無しで成功しないようにエラーを返す。
+/// 不一致の場合や golden が無い場合は比較用に `*.actual.png` を golden の隣に書き出す。
+pub fn assert_golden(
+    actual: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    golden_path: impl AsRef<Path>,
+    tolerance: Tolerance,
+) -> Result<(), ScenarioError> {
+    let golden_path = golden_path.as_ref();
+    let update = std::env::var(UPDATE_GOLDEN_ENV).is_ok_and(|v| v != "0");
+    if update {
+        if let Some(parent) = golden_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|source| ScenarioError::Io {
+                path: parent.to_path_buf(),
+                source,
+            })?;
+        }
+        actual
+            .save(golden_path)
+            .map_err(|source| ScenarioError::Image {
+                path: golden_path.to_path_buf(),
+                source,
+            })?;
+        log::info!("golden image is written. path: {}", golden_path.display());
+        return Ok(());
+    }
+
+    if !golden_path.exists() {
+        save_actual(actual, golden_path);
+        return Err(ScenarioError::GoldenMissing(golden_path.to_path_buf()));
+    }
+
+    let expected = image::open(golden_path)
+        .map_err(|source| ScenarioError::Image {
+            path: golden_path.to_path_buf(),
+            source,
+        })?
+        .to_rgba8();
+
+    let result = match diff_images(actual, &expected, tolerance) {
+        Some(diff) if diff.within(tolerance) => return Ok(()),
+        Some(diff) => ScenarioError::GoldenMismatch {
+            path: golden_path.to_path_buf(),
+            ratio: diff.ratio(),
+            max_channel_diff: diff.max_channel_diff,
+        },
+        None => ScenarioError::GoldenSizeMismatch {
+            path: golden_path.to_path_buf(),
+            actual: actual.dimensions(),
+            expected: expected.dimensions(),
+        },
+    };
+
+    save_actual(actual, golden_path);
+    Err(result)
+}
+
+fn save_actual(actual: &ImageBuffer<Rgba<u8>, Vec<u8>>, golden_path: &Path) {
+    let actual_path = golden_path.with_extension("actual.png");
+    if let Err(err) = actual.save(&actual_path) {
+        log::warn!(
+            "failed to save actual image. path: {}, err: {}",
+            actual_path.display(),
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: u32, height: u32, color: [u8; 4]) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        ImageBuffer::from_pixel(width, height, Rgba(color))
+    }
+
+    #[test]
+    fn test_diff_images_same() {
+        let a = filled(4, 4, [10, 20, 30, 255]);
+        let diff = diff_images(&a, &a.clone(), Tolerance::exact()).unwrap();
+        assert_eq!(diff.different_pixels, 0);
+        assert_eq!(diff.max_channel_diff, 0);
+        assert!(diff.within(Tolerance::exact()));
+    }
+
+    #[test]
+    fn test_diff_images_tolerance() {
+        let a = filled(10, 10, [100, 100, 100, 255]);
+        let mut b = a.clone();
+        // 1 ピクセルだけ大きくずらし、他は許容範囲内の差分にする
+        for pixel in b.pixels_mut() {
+            pixel.0[0] = 104;
+        }
+        b.put_pixel(0, 0, Rgba([200, 100, 100, 255]));
+
+        let diff = diff_images(&a, &b, Tolerance::default()).unwrap();
+        assert_eq!(diff.different_pixels, 1);
+        assert_eq!(diff.max_channel_diff, 100);
+        assert!(!diff.within(Tolerance::default()));
+        assert!(diff.within(Tolerance {
+            channel: 8,
+            pixel_ratio: 0.01,
+        }));
+    }
+
+    #[test]
+    fn test_assert_golden_missing() {
+        let dir = std::env::temp_dir().join(format!("golden_test_{}", std::process::id()));
+        let golden_path = dir.join("missing.png");
+        let actual = filled(2, 2, [0, 0, 0, 255]);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let result = assert_golden(&actual, &golden_path, Tolerance::default());
+        let actual_written = golden_path.with_extension("actual.png").exists();
+        let golden_written = golden_path.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(result, Err(ScenarioError::GoldenMissing(_))));
+        assert!(actual_written);
+        assert!(!golden_written);
+    }
+
+    #[test]
+    fn test_diff_images_size_mismatch() {
+        let a = filled(2, 2, [0, 0, 0, 255]);
+        let b = filled(3, 2, [0, 0, 0, 255]);
+        assert!(diff_images(&a, &b, Tolerance::default()).is_none());
+    }
+}
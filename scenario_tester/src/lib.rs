@@ -0,0 +1,258 @@
+//! ActionRecorder で記録したシナリオを headless に再生し、
+//! 編集結果やレイアウトの回帰をウインドウなしで検出するためのテストハーネス。
+//!
+//! ```ignore
+//! let scenario = Scenario::load("scenarios/basic_input.jsonl")?;
+//! let result = ScenarioRunner::new(scenario).run()?;
+//! assert_eq!(result.current_string(), "ab\nc");
+//! assert_golden(result.image(), "golden/basic_input.png", Tolerance::default())?;
+//! ```
+mod callback;
+mod golden;
+mod scenario;
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use font_collector::FontRepository;
+use font_rasterizer::{color_theme::ColorTheme, context::WindowSize, rasterizer_pipeline::Quarity};
+use image::{ImageBuffer, Rgba};
+use thiserror::Error;
+use ui_support::{
+    Flags, SimpleStateSupport, generate_image_iter, layout_engine::DebugWorldSnapshot,
+    ui_context::HighlightMode,
+};
+use web_time::Duration;
+
+pub use golden::{ImageDiff, Tolerance, assert_golden, diff_images};
+pub use scenario::Scenario;
+
+use crate::callback::{ScenarioCallback, WorldState};
+
+const FONT_DATA: &[u8] = include_bytes!("../../fonts/BIZUDMincho-Regular.ttf");
+const EMOJI_FONT_DATA: &[u8] = include_bytes!("../../fonts/NotoEmoji-Regular.ttf");
+
+#[derive(Error, Debug)]
+pub enum ScenarioError {
+    #[error("io error. path:{path:?}, source:{source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse scenario. path:{path:?}, line:{line}, source:{source}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        source: std::io::Error,
+    },
+    #[error("image error. path:{path:?}, source:{source}")]
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error(
+        "golden image mismatch. path:{path:?}, ratio:{ratio}, max_channel_diff:{max_channel_diff}"
+    )]
+    GoldenMismatch {
+        path: PathBuf,
+        ratio: f32,
+        max_channel_diff: u8,
+    },
+    #[error("golden image is missing. run with UPDATE_GOLDEN=1 to create it. path:{0:?}")]
+    GoldenMissing(PathBuf),
+    #[error("golden image size mismatch. path:{path:?}, actual:{actual:?}, expected:{expected:?}")]
+    GoldenSizeMismatch {
+        path: PathBuf,
+        actual: (u32, u32),
+        expected: (u32, u32),
+    },
+    #[error("scenario did not finish within {0} frames")]
+    NotFinished(u32),
+}
+
+pub struct ScenarioResult {
+    snapshot: DebugWorldSnapshot,
+    strings: Vec<String>,
+    image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+}
+
+impl ScenarioResult {
+    pub fn snapshot(&self) -> &DebugWorldSnapshot {
+        &self.snapshot
+    }
+
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
+
+    /// フォーカスが当たっているモデルの文字列
+    pub fn current_string(&self) -> &str {
+        self.strings
+            .get(self.snapshot.focus)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    pub fn image(&self) -> &ImageBuffer<Rgba<u8>, Vec<u8>> {
+        &self.image
+    }
+}
+
+/// グローバルな時計(font_rasterizer::time)を共有するため、シナリオの同時実行を防ぐ
+static RUN_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct ScenarioRunner {
+    scenario: Scenario,
+    window_size: WindowSize,
+    color_theme: ColorTheme,
+    highlight_mode: HighlightMode,
+    frame_gain: Duration,
+    settle_frames: u32,
+    software_renderer: bool,
+    font_repository: Option<FontRepository>,
+}
+
+impl ScenarioRunner {
+    pub fn new(scenario: Scenario) -> Self {
+        Self {
+            scenario,
+            window_size: WindowSize::new(640, 480),
+            color_theme: ColorTheme::SolarizedDark,
+            highlight_mode: HighlightMode::None,
+            frame_gain: Duration::from_millis(16),
+            settle_frames: 10,
+            software_renderer: true,
+            font_repository: None,
+        }
+    }
+
+    pub fn with_window_size(mut self, window_size: WindowSize) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    pub fn with_color_theme(mut self, color_theme: ColorTheme) -> Self {
+        self.color_theme = color_theme;
+        self
+    }
+
+    pub fn with_highlight_mode(mut self, highlight_mode: HighlightMode) -> Self {
+        self.highlight_mode = highlight_mode;
+        self
+    }
+
+    pub fn with_frame_gain(mut self, frame_gain: Duration) -> Self {
+        self.frame_gain = frame_gain;
+        self
+    }
+
+    /// 再生完了後、アニメーションの収束を待つフレーム数
+    pub fn with_settle_frames(mut self, settle_frames: u32) -> Self {
+        self.settle_frames = settle_frames;
+        self
+    }
+
+    /// ソフトウェアレンダラを使うか(デフォルト true)。
+    /// ゴールデンイメージを環境に依存させないため、通常は true のままにする。
+    pub fn with_software_renderer(mut self, software_renderer: bool) -> Self {
+        self.software_renderer = software_renderer;
+        self
+    }
+
+    /// 未指定の場合は同梱のフォントのみを使う
+    pub fn with_font_repository(mut self, font_repository: FontRepository) -> Self {
+        self.font_repository = Some(font_repository);
+        self
+    }
+
+    fn num_of_frame(&self) -> u32 {
+        let frame_gain = self.frame_gain.as_millis().max(1);
+        let replay_frames = self.scenario.duration().as_millis().div_ceil(frame_gain) as u32;
+        // 最初のフレームは初期化に使われるので余分に 1 フレーム足す
+        replay_frames + self.settle_frames + 1
+    }
+
+    /// シナリオを最後まで再生し、最終フレームの状態を返す。
+    ///
+    /// 時計を共有する都合上、内部でロックを取って同期的に実行する。
+    pub fn run(self) -> Result<ScenarioResult, ScenarioError> {
+        let _lock = RUN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let num_of_frame = self.num_of_frame();
+        let state = Arc::new(Mutex::new(None));
+        let callback = ScenarioCallback::new(
+            self.window_size,
+            self.scenario,
+            self.highlight_mode,
+            state.clone(),
+        );
+        let font_repository = self.font_repository.unwrap_or_else(|| {
+            let mut font_repository = FontRepository::default();
+            font_repository.add_fallback_font_from_binary(FONT_DATA.to_vec(), None);
+            font_repository.add_fallback_font_from_binary(EMOJI_FONT_DATA.to_vec(), None);
+            font_repository
+        });
+        let flags = if self.software_renderer {
+            Flags::SOFTWARE_RENDERER
+        } else {
+            Flags::empty()
+        };
+        let support = SimpleStateSupport {
+            window_icon: None,
+            window_title: "scenario_tester".to_string(),
+            window_size: self.window_size,
+            callback: Box::new(callback),
+            quarity: Quarity::Fixed(self.window_size.width, self.window_size.height),
+            color_theme: self.color_theme,
+            flags,
+            font_repository,
+            performance_mode: false,
+            background_image: None,
+            shader_art: None,
+        };
+
+        let image = pollster::block_on(generate_image_iter(support, num_of_frame, self.frame_gain))
+            .last()
+            .map(|(image, _)| image)
+            .expect("num_of_frame is always greater than 0");
+
+        let Some(WorldState {
+            snapshot,
+            strings,
+            replay_finished,
+        }) = state.lock().unwrap().take()
+        else {
+            return Err(ScenarioError::NotFinished(num_of_frame));
+        };
+        if !replay_finished {
+            return Err(ScenarioError::NotFinished(num_of_frame));
+        }
+        Ok(ScenarioResult {
+            snapshot,
+            strings,
+            image,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "GPU もしくはソフトウェアレンダラ(lavapipe, WARP 等)が必要"]
+    fn test_basic_input_scenario() {
+        let scenario = Scenario::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/scenarios/basic_input.jsonl"
+        ))
+        .unwrap();
+        let result = ScenarioRunner::new(scenario).run().unwrap();
+
+        assert_eq!(result.current_string(), "ab\nc");
+        assert_eq!(result.snapshot().models.len(), 1);
+        // 描画結果はフォントやレンダラで変わるので、ゴールデン画像を用意するまでは比べない
+    }
+}
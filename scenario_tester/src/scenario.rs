@@ -0,0 +1,118 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use serde_jsonlines::BufReadExt;
+use stroke_parser::{Action, ActionArgument};
+use ui_support::action_recorder::ActionRecordRepository;
+use web_time::Duration;
+
+use crate::ScenarioError;
+
+/// ActionRecorder で記録した `.jsonl` 形式の操作列。
+///
+/// `action_recorder:wait` を含めてそのまま保持し、再生時の待ち時間として利用する。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scenario {
+    actions: Vec<Action>,
+}
+
+impl Scenario {
+    pub fn new(actions: Vec<Action>) -> Self {
+        Self { actions }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|source| ScenarioError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let actions = BufReader::new(file)
+            .json_lines::<Action>()
+            .enumerate()
+            .map(|(line, action)| {
+                action.map_err(|source| ScenarioError::Parse {
+                    path: path.to_path_buf(),
+                    line: line + 1,
+                    source,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { actions })
+    }
+
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// シナリオに含まれる wait の合計時間
+    pub fn duration(&self) -> Duration {
+        self.actions
+            .iter()
+            .fold(Duration::ZERO, |acc, action| match action {
+                Action::Command(namespace, name, ActionArgument::Integer(millis))
+                    if namespace.as_str() == "action_recorder" && name.as_str() == "wait" =>
+                {
+                    acc + Duration::from_millis((*millis).max(0) as u64)
+                }
+                _ => acc,
+            })
+    }
+}
+
+impl ActionRecordRepository for Scenario {
+    fn save(&mut self, actions: &[Action]) {
+        self.actions = actions.to_vec();
+    }
+
+    fn load(&self) -> Vec<Action> {
+        self.actions.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait(millis: i32) -> Action {
+        Action::Command(
+            "action_recorder".into(),
+            "wait".into(),
+            ActionArgument::Integer(millis),
+        )
+    }
+
+    #[test]
+    fn test_duration() {
+        let scenario = Scenario::new(vec![
+            wait(100),
+            Action::Keytype('a'),
+            wait(250),
+            Action::new_command("edit", "return"),
+        ]);
+        assert_eq!(scenario.duration(), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_load_bundled_scenario() {
+        let scenario = Scenario::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/scenarios/basic_input.jsonl"
+        ))
+        .unwrap();
+        assert!(scenario.actions().contains(&Action::Keytype('a')));
+        assert!(scenario.duration() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_load_reports_line_number() {
+        let dir = std::env::temp_dir().join("scenario_tester_test_load_reports_line_number");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("broken.jsonl");
+        std::fs::write(&path, "{\"Keytype\":\"a\"}\nnot json\n").unwrap();
+
+        match Scenario::load(&path) {
+            Err(ScenarioError::Parse { line, .. }) => assert_eq!(line, 2),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+}
@@ -151,6 +151,83 @@ mod tests {
         );
     }
 
+    #[test]
+    fn stroke_string_roundtrip() {
+        let keybinds = parse_setting(
+            r"
+            C-X C-S system:save
+            A-S-Comma edit:buffer-head
+            C-A-S-MoveLeft world:move-left
+            Return edit:return
+            ",
+        );
+        let strokes = keybinds
+            .iter()
+            .map(KeyBind::stroke_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            strokes,
+            vec!["C-X C-S", "A-S-Comma", "C-A-S-MoveLeft", "Return"]
+        );
+    }
+
+    #[test]
+    fn stroke_strings_of_action_store() {
+        let mut store = crate::ActionStore::default();
+        parse_setting(
+            r"
+            Backspace edit:backspace
+            C-H edit:backspace
+            Return edit:return
+            ",
+        )
+        .into_iter()
+        .for_each(|keybind| store.register_keybind(keybind));
+        assert_eq!(
+            store.stroke_strings(&Action::new_command("edit", "backspace")),
+            vec!["Backspace", "C-H"]
+        );
+        assert!(
+            store
+                .stroke_strings(&Action::new_command("edit", "delete"))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn pending_stroke_candidates_of_action_store() {
+        let mut store = crate::ActionStore::default();
+        parse_setting(
+            r"
+            C-X C-S system:save
+            C-X C-T C-D system:change-theme(dark)
+            C-X system:never
+            C-F edit:forward
+            ",
+        )
+        .into_iter()
+        .for_each(|keybind| store.register_keybind(keybind));
+
+        assert_eq!(store.pending_stroke_string(), None);
+        assert!(store.pending_stroke_candidates().is_empty());
+
+        store.current_stroke = Stroke::new(vec![InputWithModifier::new_key(
+            keys::KeyCode::X,
+            keys::ModifiersState::Ctrl,
+        )]);
+        assert_eq!(store.pending_stroke_string(), Some("C-X".to_string()));
+        assert_eq!(
+            store.pending_stroke_candidates(),
+            vec![
+                ("C-S".to_string(), Action::new_command("system", "save")),
+                (
+                    "C-T C-D".to_string(),
+                    Action::new_command_with_argument("system", "change-theme", "dark")
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn parse_input_with_modifier_none() {
         assert_eq!(parse_input_with_modifier(""), None);
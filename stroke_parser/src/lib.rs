@@ -47,6 +47,28 @@ impl InputWithModifier {
     }
 }
 
+// キー設定ファイルと同じ書式(例: C-A-S-Return)で表示する
+impl Display for InputWithModifier {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let modifier = match self.modifires {
+            keys::ModifiersState::CtrlAltShift => "C-A-S-",
+            keys::ModifiersState::CtrlAlt => "C-A-",
+            keys::ModifiersState::CtrlShift => "C-S-",
+            keys::ModifiersState::AltShift => "A-S-",
+            keys::ModifiersState::Ctrl => "C-",
+            keys::ModifiersState::Alt => "A-",
+            keys::ModifiersState::Shift => "S-",
+            keys::ModifiersState::NONE => "",
+        };
+        let input = match self.input {
+            Input::Keyboard(key) => serde_json::to_string(&key),
+            Input::Mouse(mouse) => serde_json::to_string(&mouse),
+        }
+        .map_err(|_| std::fmt::Error)?;
+        write!(f, "{}{}", modifier, input.trim_matches('"'))
+    }
+}
+
 #[derive(Debug, PartialOrd, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Action {
     Command(CommandNamespace, CommandName, ActionArgument),
@@ -178,11 +200,33 @@ impl Stroke {
         self.keys.starts_with(&stroke.keys)
     }
 
+    // prefix を取り除いた残りのストローク
+    fn strip_prefix(&self, prefix: &Stroke) -> Option<Stroke> {
+        self.keys
+            .strip_prefix(prefix.keys.as_slice())
+            .map(|keys| Stroke::new(keys.to_vec()))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
     fn clear(&mut self) {
         self.keys.clear()
     }
 }
 
+impl Display for Stroke {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| key.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", keys.join(" "))
+    }
+}
+
 #[derive(Debug, PartialOrd, PartialEq, Clone, Serialize, Deserialize)]
 pub struct KeyBind {
     stroke: Stroke,
@@ -193,6 +237,15 @@ impl KeyBind {
     pub(crate) fn new(stroke: Stroke, action: Action) -> KeyBind {
         KeyBind { stroke, action }
     }
+
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+
+    /// キー設定ファイルと同じ書式のストローク(例: C-X C-F)
+    pub fn stroke_string(&self) -> String {
+        self.stroke.to_string()
+    }
 }
 
 pub struct ActionStore {
@@ -334,6 +387,41 @@ impl ActionStore {
         self.keybinds.push(keybind);
     }
 
+    /// 複数キーのストロークの入力途中であれば、入力済みのストロークを返す
+    pub fn pending_stroke_string(&self) -> Option<String> {
+        if self.current_stroke.is_empty() {
+            None
+        } else {
+            Some(self.current_stroke.to_string())
+        }
+    }
+
+    /// 入力途中のストロークに続けて入力できるキーと、その結果実行される Action を登録順に返す
+    pub fn pending_stroke_candidates(&self) -> Vec<(String, Action)> {
+        if self.current_stroke.is_empty() {
+            return Vec::new();
+        }
+        self.keybinds
+            .iter()
+            .filter_map(|keybind| {
+                keybind
+                    .stroke
+                    .strip_prefix(&self.current_stroke)
+                    .filter(|rest| !rest.is_empty())
+                    .map(|rest| (rest.to_string(), keybind.action.clone()))
+            })
+            .collect()
+    }
+
+    /// action に割り当てられているストロークを登録順に返す
+    pub fn stroke_strings(&self, action: &Action) -> Vec<String> {
+        self.keybinds
+            .iter()
+            .filter(|keybind| keybind.action == *action)
+            .map(KeyBind::stroke_string)
+            .collect()
+    }
+
     fn get_action(&self) -> Option<Action> {
         self.keybinds
             .iter()
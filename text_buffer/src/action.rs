@@ -1,4 +1,4 @@
-use std::sync::mpsc::Sender;
+use std::{ops::Range, sync::mpsc::Sender};
 
 use crate::buffer::*;
 use crate::caret::*;
@@ -40,6 +40,10 @@ pub enum EditorOperation {
     Highlight(String),
     MoveToNext(String),
     MoveToPrevious(String),
+
+    // 範囲の文字列を置き換え、キャレットを置き換えた後の指定の位置に移動する。
+    // 位置がバッファの外であれば範囲の先頭に移動する。一回の Undo で元に戻る
+    Replace(Range<CellPosition>, String, CellPosition),
 }
 
 impl EditorOperation {
@@ -61,6 +65,7 @@ impl EditorOperation {
                 | EditorOperation::Copy(_)
                 | EditorOperation::Cut(_)
                 | EditorOperation::UnMark
+                | EditorOperation::Replace(..)
         )
     }
 
@@ -321,6 +326,36 @@ impl BufferApplyer {
                     func(text);
                 }
             }
+            EditorOperation::Replace(range, str_value, caret_position) => {
+                if !buffer.contains_position(&range.start)
+                    || !buffer.contains_position(&range.end)
+                    || range.start > range.end
+                {
+                    return reverse_actions;
+                }
+                let str_value = str_value.replace("\r\n", "\n").replace('\r', "\n");
+                let origin_caret = *current_caret;
+                current_caret.move_to(range.end, sender);
+                let removed = buffer.copy_string(
+                    &Caret::new_without_event(range.start, CaretType::Primary),
+                    current_caret,
+                );
+                while current_caret.position != range.start {
+                    let _removed_char = buffer.backspace(current_caret);
+                }
+                buffer.insert_string(current_caret, str_value.clone());
+                reverse_actions.push(ReverseAction::MoveTo(*current_caret));
+                str_value.chars().for_each(|_| {
+                    reverse_actions.push(ReverseAction::Backspace);
+                });
+                reverse_actions.push(ReverseAction::InsertString(removed));
+                reverse_actions.push(ReverseAction::MoveTo(origin_caret));
+                if buffer.contains_position(caret_position) {
+                    current_caret.move_to(*caret_position, sender);
+                } else {
+                    current_caret.move_to(range.start, sender);
+                }
+            }
             EditorOperation::Highlight(keyword) => {
                 buffer.highlight(keyword);
             }
@@ -435,6 +470,92 @@ mod tests {
         assert_eq!(caret, Caret::new([0, 0].into(), &tx));
     }
 
+    #[test]
+    fn test_replace() {
+        let (tx, _rx) = channel::<ChangeEvent>();
+        let mut sut = Buffer::new(tx.clone());
+        let mut caret = Caret::new([0, 0].into(), &tx);
+        BufferApplyer::apply_action(
+            &mut sut,
+            &mut caret,
+            &mut None,
+            &EditorOperation::InsertString("ABCD\nEFGH\nIJKL".to_string()),
+            &tx,
+        );
+        let result = BufferApplyer::apply_action(
+            &mut sut,
+            &mut caret,
+            &mut None,
+            &EditorOperation::Replace(
+                [0, 2].into()..[1, 3].into(),
+                "あ\nい\nう".to_string(),
+                [0, 2].into(),
+            ),
+            &tx,
+        );
+        assert_eq!(sut.to_buffer_string(), "ABあ\nい\nうH\nIJKL".to_string());
+        assert_eq!(caret.position, [0, 2].into());
+
+        BufferApplyer::apply_reserve_actions(&mut sut, &mut caret, &mut None, &result, &tx);
+        assert_eq!(sut.to_buffer_string(), "ABCD\nEFGH\nIJKL".to_string());
+        assert_eq!(caret.position, [2, 4].into());
+
+        // 範囲外の指定は無視する
+        let result = BufferApplyer::apply_action(
+            &mut sut,
+            &mut caret,
+            &mut None,
+            &EditorOperation::Replace([0, 0].into()..[3, 0].into(), String::new(), [0, 0].into()),
+            &tx,
+        );
+        assert!(result.is_empty());
+        assert_eq!(sut.to_buffer_string(), "ABCD\nEFGH\nIJKL".to_string());
+    }
+
+    #[test]
+    fn test_replace_with_caret_target() {
+        let (tx, _rx) = channel::<ChangeEvent>();
+        let mut sut = Buffer::new(tx.clone());
+        let mut caret = Caret::new([0, 0].into(), &tx);
+        BufferApplyer::apply_action(
+            &mut sut,
+            &mut caret,
+            &mut None,
+            &EditorOperation::InsertString("# A\na\n# B\nb".to_string()),
+            &tx,
+        );
+        caret.move_to([0, 0].into(), &tx);
+        // セクションを入れ替え、キャレットを移動先の見出しに置く
+        let result = BufferApplyer::apply_action(
+            &mut sut,
+            &mut caret,
+            &mut None,
+            &EditorOperation::Replace(
+                [0, 0].into()..[3, 1].into(),
+                "# B\nb\n# A\na".to_string(),
+                [2, 0].into(),
+            ),
+            &tx,
+        );
+        assert_eq!(sut.to_buffer_string(), "# B\nb\n# A\na".to_string());
+        assert_eq!(caret.position, [2, 0].into());
+
+        // 一回の取り消しで文字列もキャレットも元に戻る
+        BufferApplyer::apply_reserve_actions(&mut sut, &mut caret, &mut None, &result, &tx);
+        assert_eq!(sut.to_buffer_string(), "# A\na\n# B\nb".to_string());
+        assert_eq!(caret.position, [0, 0].into());
+
+        // バッファの外の位置は範囲の先頭に移動する
+        BufferApplyer::apply_action(
+            &mut sut,
+            &mut caret,
+            &mut None,
+            &EditorOperation::Replace([1, 0].into()..[1, 1].into(), "x".to_string(), [9, 0].into()),
+            &tx,
+        );
+        assert_eq!(caret.position, [1, 0].into());
+    }
+
     #[test]
     fn test_copy() {
         let (tx, _rx) = channel::<ChangeEvent>();
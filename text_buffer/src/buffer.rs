@@ -209,6 +209,12 @@ impl Buffer {
         }
     }
 
+    pub(crate) fn contains_position(&self, position: &CellPosition) -> bool {
+        self.lines
+            .get(position.row)
+            .is_some_and(|line| position.col <= line.chars.len())
+    }
+
     fn is_buffer_head(&self, caret: &Caret) -> bool {
         caret.position.row == 0
     }
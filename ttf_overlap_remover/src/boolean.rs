@@ -0,0 +1,289 @@
+//! 2 つのパスのブール演算
+//!
+//! オーバーラップ除去と同じく、全セグメントを交差点で分割し、
+//! 各エッジの左右が演算結果の内側かどうかで境界エッジを選んでパスを再構成する。
+//! 結果のエッジは内側が進行方向の右になるように向きを揃えるので、
+//! non-zero と even-odd のどちらの fill rule で塗っても同じ見た目になる。
+
+use tiny_skia_path::{Path, PathBuilder, Point};
+
+use crate::{
+    assemble_loops, dedup_segments, path_segment::PathSegment, path_to_subpaths, side_points,
+    split_all_segments, tolerance_scale, total_winding,
+};
+
+/// ブール演算の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BooleanOp {
+    /// a または b の内側
+    Union,
+    /// a と b の両方の内側
+    Intersection,
+    /// a の内側で b の外側
+    Difference,
+    /// a と b のどちらか一方だけの内側
+    Xor,
+}
+
+impl BooleanOp {
+    fn apply(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            BooleanOp::Union => in_a || in_b,
+            BooleanOp::Intersection => in_a && in_b,
+            BooleanOp::Difference => in_a && !in_b,
+            BooleanOp::Xor => in_a != in_b,
+        }
+    }
+}
+
+/// 入力のパスの内側を判定する規則
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillRule {
+    /// ワインディングナンバーが 0 でなければ内側
+    #[default]
+    NonZero,
+    /// ワインディングナンバーが奇数なら内側
+    EvenOdd,
+}
+
+impl FillRule {
+    fn is_inside(self, winding: i32) -> bool {
+        match self {
+            FillRule::NonZero => winding != 0,
+            FillRule::EvenOdd => winding % 2 != 0,
+        }
+    }
+}
+
+/// 2 つのパスのブール演算の結果を返す。
+///
+/// a と b の内側は fill_rule で判定する。結果が空になる場合は None を返す。
+pub fn boolean_op(a: &Path, b: &Path, op: BooleanOp, fill_rule: FillRule) -> Option<Path> {
+    let subpaths_a = path_to_subpaths(a);
+    let subpaths_b = path_to_subpaths(b);
+
+    // 全セグメントペア間の交差点を検出して分割する
+    let all_segments: Vec<PathSegment> = subpaths_a
+        .iter()
+        .chain(subpaths_b.iter())
+        .flatten()
+        .cloned()
+        .collect();
+    let all_segments = split_all_segments(all_segments);
+
+    let scale = tolerance_scale(&[subpaths_a.as_slice(), subpaths_b.as_slice()].concat());
+    let inside = |point: Point| {
+        op.apply(
+            fill_rule.is_inside(total_winding(point, &subpaths_a, scale)),
+            fill_rule.is_inside(total_winding(point, &subpaths_b, scale)),
+        )
+    };
+
+    // 片側だけが内側のエッジを残し、内側が右になるように向きを揃える
+    let boundary_segments: Vec<PathSegment> = all_segments
+        .iter()
+        .filter_map(|seg| {
+            let (left, right) = side_points(seg, scale)?;
+            match (inside(left), inside(right)) {
+                (false, true) => Some(seg.clone()),
+                (true, false) => Some(seg.reverse()),
+                _ => None,
+            }
+        })
+        .collect();
+
+    // a と b の重なったエッジが同じ向きで残るので 1 つにまとめる
+    let boundary_segments = dedup_segments(boundary_segments);
+
+    let mut pb = PathBuilder::new();
+    for loop_segments in assemble_loops(&boundary_segments) {
+        push_segments(&mut pb, &loop_segments);
+    }
+    pb.finish()
+}
+
+/// セグメント列を閉じたサブパスとして追加する
+fn push_segments(pb: &mut PathBuilder, segments: &[PathSegment]) {
+    let Some(first) = segments.first() else {
+        return;
+    };
+    let (from, _) = first.endpoints();
+    pb.move_to(from.x, from.y);
+    for seg in segments {
+        match seg {
+            PathSegment::Line(line) => pb.line_to(line.to.x, line.to.y),
+            PathSegment::Quadratic(quad) => {
+                pb.quad_to(quad.control.x, quad.control.y, quad.to.x, quad.to.y)
+            }
+            PathSegment::Cubic(cubic) => pb.cubic_to(
+                cubic.control1.x,
+                cubic.control1.y,
+                cubic.control2.x,
+                cubic.control2.y,
+                cubic.to.x,
+                cubic.to.y,
+            ),
+        }
+    }
+    pb.close();
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_skia_path::{Path, PathBuilder, Rect};
+
+    use crate::path_to_subpaths;
+
+    use super::{BooleanOp, FillRule, boolean_op};
+
+    fn rect(left: f32, top: f32, right: f32, bottom: f32) -> Path {
+        PathBuilder::from_rect(Rect::from_ltrb(left, top, right, bottom).unwrap())
+    }
+
+    fn circle(cx: f32, cy: f32, r: f32) -> Path {
+        PathBuilder::from_circle(cx, cy, r).unwrap()
+    }
+
+    fn concat(paths: &[Path]) -> Path {
+        let mut pb = PathBuilder::new();
+        for path in paths {
+            pb.push_path(path);
+        }
+        pb.finish().unwrap()
+    }
+
+    /// 曲線をフラット化して求めた符号付き面積の絶対値の合計。
+    /// 結果のサブパスは向きが揃っているので、穴は負の面積として差し引かれる
+    fn area(path: &Path) -> f32 {
+        let mut sum = 0.0f64;
+        for subpath in path_to_subpaths(path) {
+            for seg in &subpath {
+                let points = seg.flatten(0.01);
+                for pair in points.windows(2) {
+                    sum += (pair[0].x as f64) * (pair[1].y as f64)
+                        - (pair[1].x as f64) * (pair[0].y as f64);
+                }
+            }
+        }
+        (sum / 2.0).abs() as f32
+    }
+
+    fn assert_area(path: Option<Path>, expected: f32) {
+        let path = path.expect("結果が空");
+        let actual = area(&path);
+        assert!(
+            (actual - expected).abs() < expected * 0.01,
+            "expected area {expected}, actual {actual}"
+        );
+    }
+
+    #[test]
+    fn test_overlapping_rects() {
+        let a = rect(0.0, 0.0, 10.0, 10.0);
+        let b = rect(5.0, 5.0, 15.0, 15.0);
+        assert_area(
+            boolean_op(&a, &b, BooleanOp::Union, FillRule::NonZero),
+            175.0,
+        );
+        assert_area(
+            boolean_op(&a, &b, BooleanOp::Intersection, FillRule::NonZero),
+            25.0,
+        );
+        assert_area(
+            boolean_op(&a, &b, BooleanOp::Difference, FillRule::NonZero),
+            75.0,
+        );
+        assert_area(boolean_op(&a, &b, BooleanOp::Xor, FillRule::NonZero), 150.0);
+    }
+
+    #[test]
+    fn test_empty_result() {
+        let a = rect(0.0, 0.0, 10.0, 10.0);
+        let b = rect(20.0, 0.0, 30.0, 10.0);
+        assert!(boolean_op(&a, &b, BooleanOp::Intersection, FillRule::NonZero).is_none());
+        // b に覆われた a の差は空
+        let cover = rect(-5.0, -5.0, 15.0, 15.0);
+        assert!(boolean_op(&a, &cover, BooleanOp::Difference, FillRule::NonZero).is_none());
+        // 同じパスの xor は空
+        assert!(boolean_op(&a, &a, BooleanOp::Xor, FillRule::NonZero).is_none());
+    }
+
+    #[test]
+    fn test_disjoint_union() {
+        let a = rect(0.0, 0.0, 10.0, 10.0);
+        let b = rect(20.0, 0.0, 30.0, 10.0);
+        let result = boolean_op(&a, &b, BooleanOp::Union, FillRule::NonZero).unwrap();
+        assert_eq!(path_to_subpaths(&result).len(), 2);
+        assert_area(Some(result), 200.0);
+    }
+
+    #[test]
+    fn test_difference_makes_hole() {
+        let a = rect(0.0, 0.0, 20.0, 20.0);
+        let b = rect(5.0, 5.0, 15.0, 15.0);
+        let result = boolean_op(&a, &b, BooleanOp::Difference, FillRule::NonZero).unwrap();
+        assert_eq!(path_to_subpaths(&result).len(), 2);
+        assert_area(Some(result), 300.0);
+    }
+
+    #[test]
+    fn test_fill_rule() {
+        // 同じ向きの入れ子の矩形は non-zero では塗りつぶし、even-odd では枠になる
+        let a = concat(&[rect(0.0, 0.0, 20.0, 20.0), rect(5.0, 5.0, 15.0, 15.0)]);
+        let b = rect(0.0, 0.0, 20.0, 10.0);
+        assert_area(
+            boolean_op(&a, &b, BooleanOp::Intersection, FillRule::NonZero),
+            200.0,
+        );
+        assert_area(
+            boolean_op(&a, &b, BooleanOp::Intersection, FillRule::EvenOdd),
+            150.0,
+        );
+    }
+
+    #[test]
+    fn test_scale_invariance() {
+        // 小さな図形でも大きな図形でも、面積は倍率の 2 乗で変わるだけ
+        for scale in [0.1, 100.0] {
+            let a = rect(0.0, 0.0, 10.0 * scale, 10.0 * scale);
+            let b = rect(5.0 * scale, 5.0 * scale, 15.0 * scale, 15.0 * scale);
+            let c = circle(0.0, 0.0, 10.0 * scale);
+            let area_scale = scale * scale;
+            assert_area(
+                boolean_op(&a, &b, BooleanOp::Union, FillRule::NonZero),
+                175.0 * area_scale,
+            );
+            assert_area(
+                boolean_op(&a, &b, BooleanOp::Intersection, FillRule::NonZero),
+                25.0 * area_scale,
+            );
+            assert_area(
+                boolean_op(&a, &b, BooleanOp::Xor, FillRule::NonZero),
+                150.0 * area_scale,
+            );
+            assert_area(
+                boolean_op(&c, &a, BooleanOp::Intersection, FillRule::NonZero),
+                std::f32::consts::PI * 100.0 / 4.0 * area_scale,
+            );
+        }
+    }
+
+    #[test]
+    fn test_curves() {
+        let a = circle(0.0, 0.0, 10.0);
+        let b = rect(0.0, -20.0, 20.0, 20.0);
+        let half = std::f32::consts::PI * 100.0 / 2.0;
+        assert_area(
+            boolean_op(&a, &b, BooleanOp::Intersection, FillRule::NonZero),
+            half,
+        );
+        assert_area(
+            boolean_op(&a, &b, BooleanOp::Difference, FillRule::NonZero),
+            half,
+        );
+        assert_area(
+            boolean_op(&a, &b, BooleanOp::Union, FillRule::NonZero),
+            half + 800.0,
+        );
+    }
+}
@@ -9,14 +9,20 @@
 //! 3. 交差点でセグメントを分割し、グラフ構造を構築
 //! 4. ワインディングナンバーを用いて各エッジの内外を判定
 //! 5. 外側境界のエッジのみ残してパスを再構成
+//!
+//! 同じ仕組みで、2 つのパスの和・積・差・排他的論理和を求める [`boolean_op`] も提供する。
 
 use std::iter::chain;
 
+pub use boolean::{BooleanOp, FillRule, boolean_op};
 pub use outline_builder::OverlapRemoveOutlineBuilder;
 
+mod boolean;
 mod cross_point;
 mod outline_builder;
 mod path_segment;
+#[cfg(test)]
+mod robustness_tests;
 mod winding;
 
 use path_segment::PathSegment;
@@ -140,11 +146,27 @@ pub(crate) fn remove_path_overlap(paths: Vec<Path>) -> Vec<Path> {
     let boundary_segments = dedup_segments(boundary_segments);
 
     // セグメントからループを再構成
-    let loops = build_loops(&boundary_segments);
+    let all_loops = assemble_loops(&boundary_segments);
+
+    // Path に変換
+    let result: Vec<Path> = all_loops
+        .iter()
+        .filter_map(|loop_seg| segments_to_path(loop_seg))
+        .collect();
+
+    if result.is_empty() {
+        return paths;
+    }
+    result
+}
+
+/// 境界セグメントからループを再構成する。
+/// ループにならなかったセグメントはチェーンとして追加する（不完全なグラフへの対策）
+fn assemble_loops(boundary_segments: &[PathSegment]) -> Vec<Vec<PathSegment>> {
+    let loops = build_loops(boundary_segments);
 
-    // 未使用セグメントをチェーンとして追加（不完全なグラフへの対策）
     let used_count: usize = loops.iter().map(|l| l.len()).sum();
-    let all_loops = if used_count < boundary_segments.len() {
+    if used_count < boundary_segments.len() {
         let mut all = loops;
         let used_segs: std::collections::HashSet<usize> = {
             let mut set = std::collections::HashSet::new();
@@ -172,18 +194,7 @@ pub(crate) fn remove_path_overlap(paths: Vec<Path>) -> Vec<Path> {
         all
     } else {
         loops
-    };
-
-    // Path に変換
-    let result: Vec<Path> = all_loops
-        .iter()
-        .filter_map(|loop_seg| segments_to_path(loop_seg))
-        .collect();
-
-    if result.is_empty() {
-        return paths;
     }
-    result
 }
 
 /// セグメント列を Path に変換
@@ -493,6 +504,27 @@ fn split_segment_at_points(
 const EPSILON: f32 = 1e-5;
 const GREEDY_EPSILON: f32 = 0.5;
 
+// 内外の判定に使う点のずらし幅と曲線の平坦化の誤差は、グリフの座標に合わせた値を基準にする。
+// この大きさより小さな図形では、図形の大きさに比例して小さくする
+const REFERENCE_SIZE: f32 = 100.0;
+
+/// 図形の大きさに応じた、内外の判定の許容量の倍率。REFERENCE_SIZE 以上の図形では 1
+fn tolerance_scale(subpaths: &[Vec<PathSegment>]) -> f32 {
+    let (left, top, right, bottom) = subpaths.iter().flatten().fold(
+        (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+        |(left, top, right, bottom), seg| {
+            let (l, t, r, b) = seg.bounding_rect();
+            (left.min(l), top.min(t), right.max(r), bottom.max(b))
+        },
+    );
+    let size = (right - left).max(bottom - top);
+    if size.is_finite() {
+        (size / REFERENCE_SIZE).clamp(0.0, 1.0)
+    } else {
+        1.0
+    }
+}
+
 /// 各エッジの左右のワインディングナンバーを計算し、境界エッジのみ残す。
 ///
 /// 境界エッジとは: エッジの左側が外側(winding==0)で右側が内側(winding!=0)のエッジ。
@@ -501,30 +533,16 @@ fn filter_boundary_segments(
     segments: &[PathSegment],
     original_subpaths: &[Vec<PathSegment>],
 ) -> Vec<PathSegment> {
+    let scale = tolerance_scale(original_subpaths);
     segments
         .iter()
         .filter(|seg| {
-            let mid = seg.evaluate(0.5);
-            let tangent = compute_tangent(seg);
-            let len = (tangent.x * tangent.x + tangent.y * tangent.y).sqrt();
-            if len < 1e-10 {
+            let Some((left, right)) = side_points(seg, scale) else {
                 return false; // 退化セグメントは除去
-            }
-            let tx = tangent.x / len;
-            let ty = tangent.y / len;
-
-            // セグメントの長さに応じたオフセット量
-            let (from, to) = seg.endpoints();
-            let seg_len = from.distance(to);
-            let offset = (seg_len * 0.01).clamp(0.1, 2.0);
-
-            // 左法線 (進行方向から見て左 = 反時計回り90度回転)
-            let left = Point::from_xy(mid.x - ty * offset, mid.y + tx * offset);
-            // 右法線
-            let right = Point::from_xy(mid.x + ty * offset, mid.y - tx * offset);
+            };
 
-            let w_left = total_winding(left, original_subpaths);
-            let w_right = total_winding(right, original_subpaths);
+            let w_left = total_winding(left, original_subpaths, scale);
+            let w_right = total_winding(right, original_subpaths, scale);
 
             // 片方が外側(0)で他方が内側(非0): 境界エッジ
             // outer boundary: w_left=0, w_right≠0
@@ -535,6 +553,31 @@ fn filter_boundary_segments(
         .collect()
 }
 
+/// セグメントの中点から左右に少しずらした点を返す。退化セグメントの場合は None。
+/// scale は tolerance_scale で求めた倍率
+fn side_points(seg: &PathSegment, scale: f32) -> Option<(Point, Point)> {
+    let mid = seg.evaluate(0.5);
+    let tangent = compute_tangent(seg);
+    let len = (tangent.x * tangent.x + tangent.y * tangent.y).sqrt();
+    if len < 1e-10 {
+        return None;
+    }
+    let tx = tangent.x / len;
+    let ty = tangent.y / len;
+
+    // セグメントの長さに応じたオフセット量。
+    // 短いセグメントでも曲線の平坦化の誤差より大きくずらせるように下限を設ける
+    let (from, to) = seg.endpoints();
+    let seg_len = from.distance(to);
+    let offset = (seg_len * 0.01).clamp(0.1 * scale, 2.0 * scale);
+
+    // 左法線 (進行方向から見て左 = 反時計回り90度回転)
+    let left = Point::from_xy(mid.x - ty * offset, mid.y + tx * offset);
+    // 右法線
+    let right = Point::from_xy(mid.x + ty * offset, mid.y - tx * offset);
+    Some((left, right))
+}
+
 /// セグメントの中点付近の接線ベクトルを計算
 fn compute_tangent(seg: &PathSegment) -> Point {
     let p0 = seg.evaluate(0.49);
@@ -542,11 +585,11 @@ fn compute_tangent(seg: &PathSegment) -> Point {
     Point::from_xy(p1.x - p0.x, p1.y - p0.y)
 }
 
-/// 全サブパスに対するワインディングナンバーの合計
-fn total_winding(point: Point, subpaths: &[Vec<PathSegment>]) -> i32 {
+/// 全サブパスに対するワインディングナンバーの合計。scale は tolerance_scale で求めた倍率
+fn total_winding(point: Point, subpaths: &[Vec<PathSegment>], scale: f32) -> i32 {
     let mut total = 0i32;
     for subpath in subpaths {
-        total += winding::winding_number(point, subpath);
+        total += winding::winding_number(point, subpath, 0.5 * scale);
     }
     total
 }
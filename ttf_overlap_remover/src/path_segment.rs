@@ -151,7 +151,7 @@ impl SegmentTrait for Quadratic {
 
     fn flatten(&self, tolerance: f32) -> Vec<Point> {
         let mut points = vec![self.from];
-        flatten_quadratic(self.from, self.control, self.to, tolerance, &mut points);
+        flatten_quadratic(self.from, self.control, self.to, tolerance, 0, &mut points);
         points
     }
 
@@ -262,6 +262,7 @@ impl SegmentTrait for Cubic {
             self.control2,
             self.to,
             tolerance,
+            0,
             &mut points,
         );
         points
@@ -326,13 +327,23 @@ impl SegmentTrait for Cubic {
     }
 }
 
+// フラット化で曲線を分割する回数の上限。許容誤差が 0 に近くても再帰が止まるようにする
+const MAX_FLATTEN_DEPTH: u32 = 16;
+
 /// 2次ベジェ曲線をフラット化
-fn flatten_quadratic(p0: Point, p1: Point, p2: Point, tolerance: f32, points: &mut Vec<Point>) {
+fn flatten_quadratic(
+    p0: Point,
+    p1: Point,
+    p2: Point,
+    tolerance: f32,
+    depth: u32,
+    points: &mut Vec<Point>,
+) {
     // De Casteljau の誤差推定
     let dx = p0.x - 2.0 * p1.x + p2.x;
     let dy = p0.y - 2.0 * p1.y + p2.y;
     let err = dx * dx + dy * dy;
-    if err <= tolerance * tolerance {
+    if err <= tolerance * tolerance || depth >= MAX_FLATTEN_DEPTH {
         points.push(p2);
         return;
     }
@@ -340,8 +351,8 @@ fn flatten_quadratic(p0: Point, p1: Point, p2: Point, tolerance: f32, points: &m
     let p01 = mid(p0, p1);
     let p12 = mid(p1, p2);
     let p012 = mid(p01, p12);
-    flatten_quadratic(p0, p01, p012, tolerance, points);
-    flatten_quadratic(p012, p12, p2, tolerance, points);
+    flatten_quadratic(p0, p01, p012, tolerance, depth + 1, points);
+    flatten_quadratic(p012, p12, p2, tolerance, depth + 1, points);
 }
 
 /// 3次ベジェ曲線をフラット化
@@ -351,6 +362,7 @@ fn flatten_cubic(
     p2: Point,
     p3: Point,
     tolerance: f32,
+    depth: u32,
     points: &mut Vec<Point>,
 ) {
     // 制御点からの最大偏差を計算
@@ -359,7 +371,7 @@ fn flatten_cubic(
     let d2x = 3.0 * p2.x - p0.x - 2.0 * p3.x;
     let d2y = 3.0 * p2.y - p0.y - 2.0 * p3.y;
     let err = (d1x * d1x + d1y * d1y).max(d2x * d2x + d2y * d2y);
-    if err <= tolerance * tolerance {
+    if err <= tolerance * tolerance || depth >= MAX_FLATTEN_DEPTH {
         points.push(p3);
         return;
     }
@@ -369,8 +381,8 @@ fn flatten_cubic(
     let p012 = mid(p01, p12);
     let p123 = mid(p12, p23);
     let p0123 = mid(p012, p123);
-    flatten_cubic(p0, p01, p012, p0123, tolerance, points);
-    flatten_cubic(p0123, p123, p23, p3, tolerance, points);
+    flatten_cubic(p0, p01, p012, p0123, tolerance, depth + 1, points);
+    flatten_cubic(p0123, p123, p23, p3, tolerance, depth + 1, points);
 }
 
 fn mid(a: Point, b: Point) -> Point {
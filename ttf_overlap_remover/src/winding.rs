@@ -10,8 +10,8 @@ use tiny_skia_path::Point;
 use crate::path_segment::PathSegment;
 
 /// サブパスに対するワインディングナンバーを計算する
-/// 直線セグメントは直接計算、曲線セグメントはフラット化して近似する
-pub(crate) fn winding_number(point: Point, subpath: &[PathSegment]) -> i32 {
+/// 直線セグメントは直接計算、曲線セグメントは tolerance の誤差でフラット化して近似する
+pub(crate) fn winding_number(point: Point, subpath: &[PathSegment], tolerance: f32) -> i32 {
     let mut winding = 0i32;
 
     for segment in subpath {
@@ -20,7 +20,7 @@ pub(crate) fn winding_number(point: Point, subpath: &[PathSegment]) -> i32 {
                 winding += crossing_number_line(point, line.from, line.to);
             }
             _ => {
-                let points = segment.flatten(0.5);
+                let points = segment.flatten(tolerance);
                 for pair in points.windows(2) {
                     winding += crossing_number_line(point, pair[0], pair[1]);
                 }
@@ -89,14 +89,14 @@ mod tests {
     #[test]
     fn test_point_inside() {
         let path = square_path();
-        let w = winding_number(Point::from_xy(5.0, 5.0), &path);
+        let w = winding_number(Point::from_xy(5.0, 5.0), &path, 0.5);
         assert_ne!(w, 0, "中心の点は内側");
     }
 
     #[test]
     fn test_point_outside() {
         let path = square_path();
-        let w = winding_number(Point::from_xy(15.0, 5.0), &path);
+        let w = winding_number(Point::from_xy(15.0, 5.0), &path, 0.5);
         assert_eq!(w, 0, "外側の点は winding = 0");
     }
 
@@ -104,7 +104,7 @@ mod tests {
     fn test_reversed_path() {
         // 逆向きのパスでは winding の符号が反転する
         let path: Vec<PathSegment> = square_path().iter().rev().map(|s| s.reverse()).collect();
-        let w = winding_number(Point::from_xy(5.0, 5.0), &path);
+        let w = winding_number(Point::from_xy(5.0, 5.0), &path, 0.5);
         assert_ne!(w, 0, "逆向きでも内側は non-zero");
     }
 }
@@ -215,6 +215,7 @@ impl SimpleStateCallback for SingleCharCallback {
             camera: &self.camera,
             glyph_instances: vec![],
             vector_instances: self.vectors.iter().collect(),
+            image_instances: vec![],
             glyph_instances_for_modal: vec![],
             vector_instances_for_modal: vec![],
         }
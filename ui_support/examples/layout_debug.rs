@@ -21,7 +21,7 @@ use ui_support::{
     layout_engine::{
         DebugModelNode, DebugWorldSnapshot, DefaultWorld, Model, ModelBorder, ModelOperation, World,
     },
-    register_default_border, register_default_caret,
+    register_default_border, register_default_caret, register_default_decoration,
     ui::{SelectBox, SelectOption, SingleSvg, StackLayout, TextEdit},
     ui_context::{CharEasingsPreset, UiContext},
 };
@@ -178,6 +178,7 @@ impl SimpleStateCallback for LayoutDebugCallback {
     fn init(&mut self, context: &UiContext) {
         register_default_caret(context);
         register_default_border(context);
+        register_default_decoration(context);
         build_case(&self.case_name)(context, &mut self.world);
         register_world_chars(context, &self.world);
         self.world
@@ -211,6 +212,7 @@ impl SimpleStateCallback for LayoutDebugCallback {
             camera: self.world.camera(),
             glyph_instances: self.world.glyph_instances(),
             vector_instances: self.world.vector_instances(),
+            image_instances: self.world.image_instances(),
             glyph_instances_for_modal: vec![],
             vector_instances_for_modal: vec![],
         }
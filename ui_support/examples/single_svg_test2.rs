@@ -138,6 +138,7 @@ impl SimpleStateCallback for SingleCharCallback {
             camera: self.world.camera(),
             glyph_instances: vec![],
             vector_instances: self.world.vector_instances(),
+            image_instances: self.world.image_instances(),
             glyph_instances_for_modal: vec![],
             vector_instances_for_modal: vec![],
         }
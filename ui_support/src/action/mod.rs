@@ -1,8 +1,12 @@
 mod edit;
+#[cfg(not(target_arch = "wasm32"))]
+mod outline;
 mod system;
 mod world;
 
 pub use edit::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use outline::*;
 pub use system::*;
 pub use world::*;
 
@@ -76,6 +80,15 @@ impl ActionProcessorStore {
         self.add_processor(Box::new(EditCut));
         self.add_processor(Box::new(EditHighlightUi));
         self.add_processor(Box::new(EditHighlight));
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.add_processor(Box::new(EditOutlineUi));
+            self.add_processor(Box::new(EditOutlineJump));
+            self.add_processor(Box::new(EditPromoteSection));
+            self.add_processor(Box::new(EditDemoteSection));
+            self.add_processor(Box::new(EditMoveSectionUp));
+            self.add_processor(Box::new(EditMoveSectionDown));
+        }
     }
 
     pub fn add_default_world_processors(&mut self) {
@@ -114,6 +127,7 @@ impl ActionProcessorStore {
         self.add_processor(Box::new(WorldMovePhysicalPrevious));
         self.add_processor(Box::new(WorldMovePhysicalNext));
         self.add_processor(Box::new(WorldToggleHighlightMode));
+        self.add_processor(Box::new(WorldToggleMarkdownPreview));
         self.add_processor(Box::new(WorldChangeCharEasingsPresetUi));
         self.add_processor(Box::new(WorldChangeCharEasingsPreset));
         self.add_processor(Box::new(WorldCameraMoveUp));
@@ -0,0 +1,125 @@
+use stroke_parser::{Action, ActionArgument, CommandName, CommandNamespace};
+use text_buffer::{
+    action::EditorOperation,
+    buffer::CellPosition,
+    caret::{Caret, CaretType},
+};
+
+use crate::{
+    UiContext,
+    camera::CameraAdjustment,
+    layout_engine::{Model, ModelOperation, SectionOperation, World},
+    ui::{SelectBox, SelectOption},
+};
+
+use super::{ActionProcessor, InputResult};
+
+// Markdown の見出しを一覧し、選択した見出しへキャレットを移動する
+pub struct EditOutlineUi;
+impl ActionProcessor for EditOutlineUi {
+    fn namespace(&self) -> CommandNamespace {
+        "edit".into()
+    }
+
+    fn name(&self) -> CommandName {
+        "outline-ui".into()
+    }
+
+    fn process(
+        &self,
+        _arg: &ActionArgument,
+        context: &UiContext,
+        world: &mut dyn World,
+    ) -> InputResult {
+        let outline = markdown_heading_splitter::outline(&world.current_string());
+        let options: Vec<SelectOption> = outline
+            .into_iter()
+            .map(|item| {
+                // 見出しのレベルを全角空白の字下げで表す
+                let label = format!(
+                    "{}{}",
+                    "　".repeat(item.heading.level().saturating_sub(1)),
+                    item.heading.title()
+                );
+                SelectOption::new(
+                    label,
+                    Action::new_command_with_argument(
+                        "edit",
+                        "outline-jump",
+                        &item.row.to_string(),
+                    ),
+                )
+            })
+            .collect();
+        if options.is_empty() {
+            return InputResult::InputConsumed;
+        }
+        let model =
+            SelectBox::new_without_action_name(context, "見出しの選択".to_string(), options, None);
+        context.register_string(model.to_string());
+        world.add_modal(Box::new(model));
+        world.re_layout();
+        world.look_modal(CameraAdjustment::FitBoth);
+        InputResult::InputConsumed
+    }
+}
+
+pub struct EditOutlineJump;
+impl ActionProcessor for EditOutlineJump {
+    fn namespace(&self) -> CommandNamespace {
+        "edit".into()
+    }
+
+    fn name(&self) -> CommandName {
+        "outline-jump".into()
+    }
+
+    fn process(
+        &self,
+        arg: &ActionArgument,
+        _context: &UiContext,
+        world: &mut dyn World,
+    ) -> InputResult {
+        if let ActionArgument::String(row) = arg
+            && let Ok(row) = row.parse::<usize>()
+        {
+            world.editor_operation(&EditorOperation::MoveTo(Caret::new_without_event(
+                CellPosition::new(row, 0),
+                CaretType::Primary,
+            )));
+        }
+        InputResult::InputConsumed
+    }
+}
+
+macro_rules! section_processor {
+    ( $proc_name:ident, $name:expr, $section_operation:ident ) => {
+        pub struct $proc_name;
+        impl ActionProcessor for $proc_name {
+            fn namespace(&self) -> CommandNamespace {
+                "edit".into()
+            }
+
+            fn name(&self) -> CommandName {
+                $name.into()
+            }
+
+            fn process(
+                &self,
+                _arg: &ActionArgument,
+                _context: &UiContext,
+                world: &mut dyn World,
+            ) -> InputResult {
+                world.model_operation(&ModelOperation::EditSection(
+                    SectionOperation::$section_operation,
+                ));
+                InputResult::InputConsumed
+            }
+        }
+    };
+}
+
+section_processor!(EditPromoteSection, "promote-section", Promote);
+section_processor!(EditDemoteSection, "demote-section", Demote);
+section_processor!(EditMoveSectionUp, "move-section-up", MoveUp);
+section_processor!(EditMoveSectionDown, "move-section-down", MoveDown);
@@ -344,6 +344,15 @@ fn toggle_highlight_mode(_arg: &ActionArgument, _context: &UiContext, world: &mu
     world.model_operation(&ModelOperation::ToggleHighlightMode);
 }
 
+world_processor!(
+    WorldToggleMarkdownPreview,
+    "toggle-markdown-preview",
+    toggle_markdown_preview
+);
+fn toggle_markdown_preview(_arg: &ActionArgument, _context: &UiContext, world: &mut dyn World) {
+    world.model_operation(&ModelOperation::ToggleMarkdownPreview);
+}
+
 world_processor!(
     WorldChangeCharEasingsPresetUi,
     "change-char-easings-preset-ui",
@@ -178,6 +178,11 @@ impl ActionRecorder {
     pub fn set_replay_mode(&mut self, replay_mode: ReplayMode) {
         self.replay_mode = replay_mode;
     }
+
+    // 再生待ちの Action が残っているかを返す
+    pub fn in_replay(&self) -> bool {
+        self.mode == RecorderMode::Replay
+    }
 }
 
 static NAMES: LazyLock<Vec<CommandName>> = LazyLock::new(|| {
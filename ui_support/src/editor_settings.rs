@@ -1,10 +1,17 @@
+use std::collections::BTreeMap;
+
+use font_collector::FontStyle;
 use font_rasterizer::{
-    color_theme::ColorTheme, glyph_vertex_buffer::Direction, rasterizer_renderrer::OutlineFillRule,
+    char_width_calcurator::AmbiguousWidth,
+    color_theme::{ColorTheme, ThemedColor},
+    glyph_vertex_buffer::Direction,
+    rasterizer_renderrer::OutlineFillRule,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::ui_context::{
-    CharEasings, CharEasingsPreset, GpuEasingConfig, HighlightMode, TextContext,
+use crate::{
+    ui::{CharAttribute, Decoration},
+    ui_context::{CharEasings, CharEasingsPreset, GpuEasingConfig, HighlightMode, TextContext},
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -69,6 +76,34 @@ impl From<OutlineFillRule> for EditorOutlineFillRule {
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EditorAmbiguousWidth {
+    #[default]
+    Auto,
+    Narrow,
+    Wide,
+}
+
+impl From<EditorAmbiguousWidth> for AmbiguousWidth {
+    fn from(value: EditorAmbiguousWidth) -> Self {
+        match value {
+            EditorAmbiguousWidth::Auto => AmbiguousWidth::Auto,
+            EditorAmbiguousWidth::Narrow => AmbiguousWidth::Narrow,
+            EditorAmbiguousWidth::Wide => AmbiguousWidth::Wide,
+        }
+    }
+}
+
+impl From<AmbiguousWidth> for EditorAmbiguousWidth {
+    fn from(value: AmbiguousWidth) -> Self {
+        match value {
+            AmbiguousWidth::Auto => EditorAmbiguousWidth::Auto,
+            AmbiguousWidth::Narrow => EditorAmbiguousWidth::Narrow,
+            AmbiguousWidth::Wide => EditorAmbiguousWidth::Wide,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum EditorColorTheme {
     SolarizedLight,
     #[default]
@@ -123,6 +158,7 @@ pub enum EditorHighlightMode {
     #[default]
     None,
     Markdown,
+    MarkdownPreview,
     Language(String),
 }
 
@@ -131,6 +167,7 @@ impl From<EditorHighlightMode> for HighlightMode {
         match value {
             EditorHighlightMode::None => HighlightMode::None,
             EditorHighlightMode::Markdown => HighlightMode::Markdown,
+            EditorHighlightMode::MarkdownPreview => HighlightMode::MarkdownPreview,
             EditorHighlightMode::Language(language) => HighlightMode::Language(language),
         }
     }
@@ -141,11 +178,236 @@ impl From<HighlightMode> for EditorHighlightMode {
         match value {
             HighlightMode::None => EditorHighlightMode::None,
             HighlightMode::Markdown => EditorHighlightMode::Markdown,
+            HighlightMode::MarkdownPreview => EditorHighlightMode::MarkdownPreview,
             HighlightMode::Language(language) => EditorHighlightMode::Language(language),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EditorThemedColor {
+    Text,
+    TextComment,
+    TextEmphasized,
+    Background,
+    BackgroundHighlights,
+    Yellow,
+    Orange,
+    Red,
+    Magenta,
+    Violet,
+    Blue,
+    Cyan,
+    Green,
+}
+
+impl From<EditorThemedColor> for ThemedColor {
+    fn from(value: EditorThemedColor) -> Self {
+        match value {
+            EditorThemedColor::Text => ThemedColor::Text,
+            EditorThemedColor::TextComment => ThemedColor::TextComment,
+            EditorThemedColor::TextEmphasized => ThemedColor::TextEmphasized,
+            EditorThemedColor::Background => ThemedColor::Background,
+            EditorThemedColor::BackgroundHighlights => ThemedColor::BackgroundHighlights,
+            EditorThemedColor::Yellow => ThemedColor::Yellow,
+            EditorThemedColor::Orange => ThemedColor::Orange,
+            EditorThemedColor::Red => ThemedColor::Red,
+            EditorThemedColor::Magenta => ThemedColor::Magenta,
+            EditorThemedColor::Violet => ThemedColor::Violet,
+            EditorThemedColor::Blue => ThemedColor::Blue,
+            EditorThemedColor::Cyan => ThemedColor::Cyan,
+            EditorThemedColor::Green => ThemedColor::Green,
+        }
+    }
+}
+
+impl From<ThemedColor> for EditorThemedColor {
+    fn from(value: ThemedColor) -> Self {
+        match value {
+            ThemedColor::Text => EditorThemedColor::Text,
+            ThemedColor::TextComment => EditorThemedColor::TextComment,
+            ThemedColor::TextEmphasized => EditorThemedColor::TextEmphasized,
+            ThemedColor::Background => EditorThemedColor::Background,
+            ThemedColor::BackgroundHighlights => EditorThemedColor::BackgroundHighlights,
+            ThemedColor::Yellow => EditorThemedColor::Yellow,
+            ThemedColor::Orange => EditorThemedColor::Orange,
+            ThemedColor::Red => EditorThemedColor::Red,
+            ThemedColor::Magenta => EditorThemedColor::Magenta,
+            ThemedColor::Violet => EditorThemedColor::Violet,
+            ThemedColor::Blue => EditorThemedColor::Blue,
+            ThemedColor::Cyan => EditorThemedColor::Cyan,
+            ThemedColor::Green => EditorThemedColor::Green,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EditorDecoration {
+    #[default]
+    None,
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+}
+
+impl From<EditorDecoration> for Decoration {
+    fn from(value: EditorDecoration) -> Self {
+        match value {
+            EditorDecoration::None => Decoration::None,
+            EditorDecoration::Bold => Decoration::Bold,
+            EditorDecoration::Italic => Decoration::Italic,
+            EditorDecoration::Underline => Decoration::Underline,
+            EditorDecoration::Strikethrough => Decoration::Strikethrough,
+        }
+    }
+}
+
+/// フォントの太さ、幅、斜体。太さは 100 から 900、幅は 100 を標準とした百分率で指定する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorFontStyle {
+    pub weight: u16,
+    pub width: u16,
+    pub italic: bool,
+}
+
+impl Default for EditorFontStyle {
+    fn default() -> Self {
+        FontStyle::REGULAR.into()
+    }
+}
+
+impl From<EditorFontStyle> for FontStyle {
+    fn from(value: EditorFontStyle) -> Self {
+        FontStyle {
+            weight: value.weight,
+            width: value.width,
+            italic: value.italic,
+        }
+    }
+}
+
+impl From<FontStyle> for EditorFontStyle {
+    fn from(value: FontStyle) -> Self {
+        EditorFontStyle {
+            weight: value.weight,
+            width: value.width,
+            italic: value.italic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EditorCharAttribute {
+    pub color: EditorThemedColor,
+    #[serde(default)]
+    pub decoration: EditorDecoration,
+    /// フォントのスタイル。指定が無ければ decoration の太字や斜体に合わせる
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub font_style: Option<EditorFontStyle>,
+}
+
+impl From<EditorCharAttribute> for CharAttribute {
+    fn from(value: EditorCharAttribute) -> Self {
+        let attribute = CharAttribute::new(value.color.into(), value.decoration.into());
+        match value.font_style {
+            Some(font_style) => attribute.with_font_style(font_style.into()),
+            None => attribute,
+        }
+    }
+}
+
+/// ハイライトのカテゴリ名から文字の色と装飾への対応。
+/// 設定が無いカテゴリは組み込みの対応を使う
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorHighlightStyles {
+    /// すべてのテーマで使う対応
+    pub categories: BTreeMap<String, EditorCharAttribute>,
+    /// テーマごとの対応。categories よりも優先する
+    pub themes: BTreeMap<EditorColorTheme, BTreeMap<String, EditorCharAttribute>>,
+}
+
+impl EditorHighlightStyles {
+    /// カテゴリ名に対応する文字の属性を返す。
+    /// highlights.scm のキャプチャ名は `function.method.call` のように細分化されていることがあるので、
+    /// 見つからなければ末尾を `.` で区切って短くした名前で探す
+    pub fn attribute(&self, color_theme: ColorTheme, category: &str) -> CharAttribute {
+        let theme_styles = self.themes.get(&color_theme.into());
+        let mut category = category;
+        loop {
+            let style = theme_styles
+                .and_then(|styles| styles.get(category))
+                .or_else(|| self.categories.get(category));
+            if let Some(style) = style {
+                return (*style).into();
+            }
+            if let Some(color) = default_highlight_color(category) {
+                return CharAttribute::new(color, Decoration::None);
+            }
+            match category.rsplit_once('.') {
+                Some((parent, _)) => category = parent,
+                None => return CharAttribute::default(),
+            }
+        }
+    }
+}
+
+// 組み込みのカテゴリ名と色の対応
+fn default_highlight_color(category: &str) -> Option<ThemedColor> {
+    let color = match category {
+        "markdown.emphasis" => ThemedColor::Cyan,
+        "markdown.list" => ThemedColor::Cyan,
+        "markdown.literal" => ThemedColor::Cyan,
+        "markdown.reference" => ThemedColor::Magenta,
+        "markdown.strong" => ThemedColor::Green,
+        "markdown.title" => ThemedColor::Green,
+        "markdown.uri" => ThemedColor::Magenta,
+        "markdown.checked" => ThemedColor::Green,
+        "markdown.unchecked" => ThemedColor::Yellow,
+        "markdown.block_quote" => ThemedColor::TextComment,
+        // Markdown の highlights.scm のキャプチャ名
+        "text.emphasis" => ThemedColor::Cyan,
+        "text.literal" => ThemedColor::Cyan,
+        "text.reference" => ThemedColor::Magenta,
+        "text.strong" => ThemedColor::Green,
+        "text.title" => ThemedColor::Green,
+        "text.uri" => ThemedColor::Magenta,
+        "comment" => ThemedColor::TextComment,
+        "constant" => ThemedColor::Blue,
+        "constant.builtin" => ThemedColor::Blue,
+        "boolean" => ThemedColor::Blue,
+        "escape" => ThemedColor::TextComment,
+        "string.escape" => ThemedColor::TextComment,
+        "string.special.key" => ThemedColor::Yellow,
+        "number" => ThemedColor::Green,
+        "string" => ThemedColor::Green,
+        "attribute" => ThemedColor::Yellow,
+        "constructor" => ThemedColor::Green,
+        "function" => ThemedColor::Cyan,
+        "function.builtin" => ThemedColor::Cyan,
+        "function.macro" => ThemedColor::Cyan,
+        "function.method" => ThemedColor::Cyan,
+        "identifier" => ThemedColor::Cyan,
+        "keyword" => ThemedColor::Blue,
+        "label" => ThemedColor::TextComment,
+        "operator" => ThemedColor::TextEmphasized,
+        "property" => ThemedColor::Yellow,
+        "punctuation.bracket" => ThemedColor::Cyan,
+        "punctuation.delimiter" => ThemedColor::TextComment,
+        "punctuation.special" => ThemedColor::TextComment,
+        "tag" => ThemedColor::Blue,
+        "type" => ThemedColor::Green,
+        "type.builtin" => ThemedColor::Green,
+        "variable" => ThemedColor::Yellow,
+        "variable.builtin" => ThemedColor::Yellow,
+        "variable.parameter" => ThemedColor::Yellow,
+        _ => return None,
+    };
+    Some(color)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct EditorTextContextSettings {
@@ -322,8 +584,10 @@ pub struct EditorSettings {
     pub color_theme: EditorColorTheme,
     pub global_direction: EditorDirection,
     pub outline_fill_rule: EditorOutlineFillRule,
+    pub ambiguous_width: EditorAmbiguousWidth,
     pub base_text_context: EditorTextContextSettings,
     pub profiles: EditorSettingsProfiles,
+    pub highlight_styles: EditorHighlightStyles,
 }
 
 impl EditorSettings {
@@ -339,6 +603,10 @@ impl EditorSettings {
         self.outline_fill_rule.into()
     }
 
+    pub fn ambiguous_width(&self) -> AmbiguousWidth {
+        self.ambiguous_width.into()
+    }
+
     pub fn set_color_theme(&mut self, color_theme: ColorTheme) {
         self.color_theme = color_theme.into();
     }
@@ -351,6 +619,10 @@ impl EditorSettings {
         self.outline_fill_rule = outline_fill_rule.into();
     }
 
+    pub fn set_ambiguous_width(&mut self, ambiguous_width: AmbiguousWidth) {
+        self.ambiguous_width = ambiguous_width.into();
+    }
+
     pub fn text_context(&self, profile: EditorTextContextProfile) -> TextContext {
         let mut context = self
             .base_text_context
@@ -379,3 +651,64 @@ impl EditorSettings {
         context
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_styles() {
+        let mut styles = EditorHighlightStyles::default();
+        let keyword =
+            |styles: &EditorHighlightStyles, theme: ColorTheme| styles.attribute(theme, "keyword");
+        assert_eq!(
+            keyword(&styles, ColorTheme::SolarizedDark),
+            CharAttribute::new(ThemedColor::Blue, Decoration::None)
+        );
+        // 細分化されたキャプチャ名は親の名前で探す
+        assert_eq!(
+            styles.attribute(ColorTheme::SolarizedDark, "function.method.call"),
+            CharAttribute::new(ThemedColor::Cyan, Decoration::None)
+        );
+        assert_eq!(
+            styles.attribute(ColorTheme::SolarizedDark, "unknown"),
+            CharAttribute::default()
+        );
+
+        styles.categories.insert(
+            "keyword".to_string(),
+            EditorCharAttribute {
+                color: EditorThemedColor::Red,
+                decoration: EditorDecoration::Bold,
+                font_style: None,
+            },
+        );
+        styles.themes.insert(
+            EditorColorTheme::SolarizedLight,
+            BTreeMap::from([(
+                "keyword".to_string(),
+                EditorCharAttribute {
+                    color: EditorThemedColor::Violet,
+                    decoration: EditorDecoration::None,
+                    font_style: Some(EditorFontStyle {
+                        weight: 300,
+                        ..Default::default()
+                    }),
+                },
+            )]),
+        );
+        assert_eq!(
+            keyword(&styles, ColorTheme::SolarizedDark),
+            CharAttribute::new(ThemedColor::Red, Decoration::Bold)
+        );
+        assert_eq!(
+            keyword(&styles, ColorTheme::SolarizedLight),
+            CharAttribute::new(ThemedColor::Violet, Decoration::None)
+                .with_font_style(FontStyle::REGULAR.with_weight(300))
+        );
+
+        let json = serde_json::to_string(&styles).unwrap();
+        let restored: EditorHighlightStyles = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, styles);
+    }
+}
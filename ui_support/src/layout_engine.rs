@@ -2,7 +2,7 @@ mod model;
 pub use model::{
     DebugModelDetails, DebugModelNode, DebugSelectBoxSnapshot, DebugSingleSvgSnapshot,
     DebugStackLayoutSnapshot, DebugTextEditSnapshot, DebugTextInputSnapshot, Model,
-    ModelAttributes, ModelBorder, ModelOperation, ModelOperationResult,
+    ModelAttributes, ModelBorder, ModelOperation, ModelOperationResult, SectionOperation,
 };
 mod default_world;
 pub use default_world::{DebugWorldSnapshot, DefaultWorld, WorldLayout};
@@ -11,7 +11,7 @@ use text_buffer::action::EditorOperation;
 
 use font_rasterizer::{
     context::WindowSize, glyph_instances::GlyphInstances, glyph_vertex_buffer::Direction,
-    vector_instances::VectorInstances,
+    image_instances::ImageInstances, vector_instances::VectorInstances,
 };
 
 use crate::ui_context::UiContext;
@@ -80,6 +80,24 @@ impl DefaultWorld {
         }
     }
 
+    /// モーダルではない、今フォーカスが当たっているモデルの位置を返す
+    pub fn focus_index(&self) -> usize {
+        self.focus
+    }
+
+    /// モーダルなモデルを表示しているかどうか
+    pub fn has_modal(&self) -> bool {
+        !self.modal_models.is_empty()
+    }
+
+    /// フォーカスやカメラを動かさずに、指定した位置のモデルを編集する
+    pub fn editor_operation_at(&mut self, model_index: usize, op: &EditorOperation) {
+        if let Some(model) = self.models.get_mut(model_index) {
+            model.editor_operation(op);
+            self.world_updated = true;
+        }
+    }
+
     fn get_current_mut(&mut self) -> Option<&mut Box<dyn Model>> {
         self.modal_models
             .last_mut()
@@ -107,6 +125,11 @@ impl DefaultWorld {
         models.iter().flat_map(|m| m.vector_instances()).collect()
     }
 
+    #[inline]
+    fn to_image_instances(models: &[Box<dyn Model>]) -> Vec<&ImageInstances> {
+        models.iter().flat_map(|m| m.image_instances()).collect()
+    }
+
     #[inline]
     fn glyph_instances_inner(&self) -> Vec<&GlyphInstances> {
         let models: Vec<&GlyphInstances> =
@@ -123,6 +146,14 @@ impl DefaultWorld {
             Self::to_vector_instances(&self.removed_models);
         chain(models, removed_models).collect()
     }
+
+    #[inline]
+    fn image_instances_inner(&self) -> Vec<&ImageInstances> {
+        let models: Vec<&ImageInstances> =
+            Self::to_image_instances(&self.models[self.get_surrounding_model_range()]);
+        let removed_models: Vec<&ImageInstances> = Self::to_image_instances(&self.removed_models);
+        chain(models, removed_models).collect()
+    }
 }
 
 impl World for DefaultWorld {
@@ -181,6 +212,10 @@ impl World for DefaultWorld {
         self.vector_instances_inner()
     }
 
+    fn image_instances(&self) -> Vec<&ImageInstances> {
+        self.image_instances_inner()
+    }
+
     fn modal_instances(&self) -> (Vec<&GlyphInstances>, Vec<&VectorInstances<String>>) {
         (
             Self::to_glyph_instances(&self.modal_models),
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 use glam::{Mat4, Quat, Vec2, Vec3};
 use phisical_layouter::CharWidthResolver;
@@ -7,7 +7,7 @@ use text_buffer::action::EditorOperation;
 
 use font_rasterizer::{
     glyph_instances::GlyphInstances, glyph_vertex_buffer::Direction,
-    vector_instances::VectorInstances,
+    image_instances::ImageInstances, vector_instances::VectorInstances,
 };
 
 use crate::{
@@ -123,6 +123,7 @@ pub struct DebugSelectBoxSnapshot {
     pub narrowed_option_count: usize,
     pub show_action_name: bool,
     pub cancellable: bool,
+    pub fuzzy_match: bool,
     pub max_line: usize,
 }
 
@@ -184,6 +185,10 @@ pub trait Model {
     fn bound(&self) -> (f32, f32);
     fn glyph_instances(&self) -> Vec<&GlyphInstances>;
     fn vector_instances(&self) -> Vec<&VectorInstances<String>>;
+    // テクスチャを貼って描く画像のインスタンスを返す
+    fn image_instances(&self) -> Vec<&ImageInstances> {
+        Vec::new()
+    }
     fn update(&mut self, context: &UiContext);
     fn editor_operation(&mut self, op: &EditorOperation);
     fn model_operation(&mut self, op: &ModelOperation) -> ModelOperationResult;
@@ -253,10 +258,29 @@ pub enum ModelOperation {
     MovePhysicalPrevious(Arc<dyn CharWidthResolver>),
     MovePhysicalNext(Arc<dyn CharWidthResolver>),
     ToggleHighlightMode,
+    // Markdown のハイライトとライブプレビューを切り替える
+    ToggleMarkdownPreview,
     SetHighlightMode(HighlightMode),
     // IME のプレエディット（未確定文字列）をモデルへ設定/解除する
     // None で解除、Some((value, selection)) で設定
     SetPreedit(Option<(String, Option<(usize, usize)>)>),
+    // キャレットのある Markdown のセクションを編集する
+    EditSection(SectionOperation),
+    // 画像などの相対パスの基準にするディレクトリを設定する
+    SetBaseDir(Option<PathBuf>),
+}
+
+// Markdown のセクション単位の編集
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionOperation {
+    // 見出しのレベルを一つ上げる
+    Promote,
+    // 見出しのレベルを一つ下げる
+    Demote,
+    // 同じレベルの前のセクションと入れ替える
+    MoveUp,
+    // 同じレベルの次のセクションと入れ替える
+    MoveDown,
 }
 
 #[derive(PartialEq)]
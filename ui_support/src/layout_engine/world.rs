@@ -3,7 +3,8 @@ use std::collections::HashSet;
 use text_buffer::action::EditorOperation;
 
 use font_rasterizer::{
-    context::WindowSize, glyph_instances::GlyphInstances, vector_instances::VectorInstances,
+    context::WindowSize, glyph_instances::GlyphInstances, image_instances::ImageInstances,
+    vector_instances::VectorInstances,
 };
 
 use crate::{
@@ -65,6 +66,8 @@ pub trait World {
     fn glyph_instances(&self) -> Vec<&GlyphInstances>;
     // vector_instances を返す
     fn vector_instances(&self) -> Vec<&VectorInstances<String>>;
+    // image_instances を返す
+    fn image_instances(&self) -> Vec<&ImageInstances>;
     // モーダル時のインスタンスを返す
     fn modal_instances(&self) -> (Vec<&GlyphInstances>, Vec<&VectorInstances<String>>);
 
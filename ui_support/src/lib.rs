@@ -13,7 +13,7 @@ pub mod ui_context;
 
 use glam::Mat4;
 pub use render_state::RenderTargetResponse;
-use text_instances::BorderType;
+use text_instances::{BorderType, CharOverlay};
 use ui::caret_char;
 
 use std::sync::Arc;
@@ -27,6 +27,7 @@ use font_rasterizer::{
     context::WindowSize,
     glyph_instances::GlyphInstances,
     glyph_vertex_buffer::Direction,
+    image_instances::ImageInstances,
     rasterizer_pipeline::Quarity,
     rasterizer_renderrer::OutlineFillRule,
     time::{ClockMode, increment_fixed_clock, set_clock_mode},
@@ -148,6 +149,7 @@ impl ApplicationHandler for App {
                         font_repository,
                         performance_mode,
                         flags.contains(Flags::TRANCEPARENT),
+                        flags.contains(Flags::SOFTWARE_RENDERER),
                         background_image,
                         shader_art,
                     ));
@@ -185,6 +187,7 @@ impl ApplicationHandler for App {
                             font_repository,
                             performance_mode,
                             flags.contains(Flags::TRANCEPARENT),
+                            flags.contains(Flags::SOFTWARE_RENDERER),
                             background_image,
                             shader_art,
                         )
@@ -478,6 +481,9 @@ bitflags! {
         const NO_TITLEBAR  = 0b_0000_1000;
         // focus が無い時に省エネモードにするかは選択可能にする
         const SLEEP_WHEN_FOCUS_LOST = 0b_0001_0000;
+        // GPU を使わずにソフトウェアレンダラ(wgpu の fallback adapter)で描画する。
+        // 環境差による描画結果のぶれを抑えたいテスト用途を想定している。
+        const SOFTWARE_RENDERER = 0b_0010_0000;
         const DEFAULT      = Self::EXIT_ON_ESC.bits() | Self::FULL_SCREEN.bits() | Self::SLEEP_WHEN_FOCUS_LOST.bits();
     }
 }
@@ -608,6 +614,7 @@ pub struct RenderData<'a> {
     pub camera: &'a Camera,
     pub glyph_instances: Vec<&'a GlyphInstances>,
     pub vector_instances: Vec<&'a VectorInstances<String>>,
+    pub image_instances: Vec<&'a ImageInstances>,
     pub glyph_instances_for_modal: Vec<&'a GlyphInstances>,
     pub vector_instances_for_modal: Vec<&'a VectorInstances<String>>,
 }
@@ -633,6 +640,7 @@ pub async fn generate_images<F>(
         support.font_repository,
         support.performance_mode,
         support.flags.contains(Flags::TRANCEPARENT),
+        support.flags.contains(Flags::SOFTWARE_RENDERER),
         support.background_image,
         support.shader_art,
     )
@@ -684,6 +692,7 @@ pub async fn generate_image_iter(
         support.font_repository,
         support.performance_mode,
         support.flags.contains(Flags::TRANCEPARENT),
+        support.flags.contains(Flags::SOFTWARE_RENDERER),
         support.background_image,
         support.shader_art,
     )
@@ -751,6 +760,18 @@ pub fn register_default_border(context: &UiContext) {
     );
 }
 
+/// Markdown のライブプレビューで文字に重ねる下線、取り消し線、箇条書きの黒丸を登録する
+#[inline]
+pub fn register_default_decoration(context: &UiContext) {
+    let line = include_str!("../asset/overlay_line.svg");
+    context.register_svg(CharOverlay::Underline.to_key(), line.to_string());
+    context.register_svg(CharOverlay::Strikethrough.to_key(), line.to_string());
+    context.register_svg(
+        CharOverlay::Bullet.to_key(),
+        include_str!("../asset/overlay_bullet.svg").to_string(),
+    );
+}
+
 #[inline]
 pub(crate) fn to_ndc_position(model: &dyn Model, camera: &Camera) -> (f32, f32) {
     let glam::Vec3 { x, y, z } = model.position();
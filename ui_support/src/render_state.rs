@@ -1,13 +1,20 @@
-use std::sync::{Arc, mpsc::Receiver};
+use std::{
+    collections::HashSet,
+    sync::{Arc, mpsc::Receiver},
+};
 
 use font_collector::FontRepository;
+#[cfg(not(target_arch = "wasm32"))]
+use font_rasterizer::charset;
 use font_rasterizer::{
     char_width_calcurator::CharWidthCalculator,
     color_theme::ColorTheme,
     context::{StateContext, WindowSize},
     glyph_instances::GlyphInstances,
     glyph_vertex_buffer::{Direction, GlyphVertexBuffer},
+    image_instances::ImageInstances,
     rasterizer_pipeline::{Buffers, Quarity, RasterizerPipeline},
+    shaping::{GlyphKey, GlyphShaper},
     svg::SvgVertexBuffer,
     vector_instances::VectorInstances,
     vector_vertex_buffer::VectorVertexBuffer,
@@ -262,6 +269,7 @@ pub(crate) struct RenderState {
 
     pub(crate) ui_string_receiver: Receiver<String>,
     pub(crate) ui_svg_receiver: Receiver<(String, String)>,
+    pub(crate) ui_glyph_receiver: Receiver<Vec<GlyphKey>>,
     pub(crate) action_queue_receiver: Receiver<Action>,
     pub(crate) post_action_queue_receiver: Receiver<Action>,
 }
@@ -276,6 +284,7 @@ impl RenderState {
         font_repository: FontRepository,
         performance_mode: bool,
         transparent_background: bool,
+        software_renderer: bool,
         background_image: Option<DynamicImage>,
         shader_art: Option<String>,
     ) -> Self {
@@ -304,7 +313,7 @@ impl RenderState {
                     wgpu::PowerPreference::default()
                 },
                 compatible_surface: surface.as_ref(),
-                force_fallback_adapter: false,
+                force_fallback_adapter: software_renderer,
                 apply_limit_buckets: false,
             })
             .await
@@ -439,17 +448,29 @@ impl RenderState {
 
         let font_binaries = font_repository.get_fonts();
         let font_binaries = Arc::new(font_binaries);
-        let char_width_calcurator = Arc::new(CharWidthCalculator::new(font_binaries.clone()));
-        let ascii_override_font = font_repository.get_ascii_override_font();
-        let glyph_vertex_buffer = GlyphVertexBuffer::new(
-            font_binaries,
-            ascii_override_font,
-            char_width_calcurator.clone(),
+        let char_width_calcurator = Arc::new(
+            CharWidthCalculator::new(font_binaries.clone())
+                .with_ambiguous_width(editor_settings.ambiguous_width()),
         );
+        let ascii_override_font = font_repository.get_ascii_override_font();
+        let glyph_shaper = Arc::new(GlyphShaper::new(font_binaries, ascii_override_font));
+        let glyph_vertex_buffer =
+            GlyphVertexBuffer::new(glyph_shaper.clone(), char_width_calcurator.clone());
+        // ウィンドウに描く場合は描画が止まらないように、グリフの変換を別のスレッドで行う。
+        // 画像に描く場合は変換の終わったグリフで描く必要があるので、これまで通り描く前に変換する
+        #[cfg(not(target_arch = "wasm32"))]
+        let glyph_vertex_buffer = if matches!(render_target, RenderTarget::Window { .. }) {
+            let glyph_vertex_buffer = glyph_vertex_buffer.with_background_conversion();
+            glyph_vertex_buffer.warm_up_cache(charset::japanese_common().collect());
+            glyph_vertex_buffer
+        } else {
+            glyph_vertex_buffer
+        };
         let svg_vertex_buffer = SvgVertexBuffer::default();
 
         let (ui_string_sender, ui_string_receiver) = std::sync::mpsc::channel();
         let (ui_svg_sender, ui_svg_receiver) = std::sync::mpsc::channel();
+        let (ui_glyph_sender, ui_glyph_receiver) = std::sync::mpsc::channel();
         let (action_queue_sender, action_queue_receiver) = std::sync::mpsc::channel();
         let (post_action_queue_sender, post_action_queue_receiver) = std::sync::mpsc::channel();
 
@@ -460,6 +481,7 @@ impl RenderState {
             device,
             queue,
             char_width_calcurator,
+            glyph_shaper,
             color_theme,
             window_size,
             editor_settings.global_direction(),
@@ -469,6 +491,7 @@ impl RenderState {
         let senders = Senders::new(
             ui_string_sender,
             ui_svg_sender,
+            ui_glyph_sender,
             action_queue_sender,
             post_action_queue_sender,
         );
@@ -495,6 +518,7 @@ impl RenderState {
 
             ui_string_receiver,
             ui_svg_receiver,
+            ui_glyph_receiver,
             action_queue_receiver,
             post_action_queue_receiver,
         };
@@ -705,13 +729,29 @@ impl RenderState {
         &mut self,
     ) -> Result<RenderTargetResponse, SurfaceAcquireError> {
         record_start_of_phase("render 0: append glyph");
+        let mut chars = HashSet::new();
         while let Ok(s) = self.ui_string_receiver.try_recv() {
+            chars.extend(s.chars());
+        }
+        if !chars.is_empty() {
+            self.add_fallback_fonts_for_missing_chars(&chars);
             let _ = self.glyph_vertex_buffer.append_chars(
                 self.context.device(),
                 self.context.queue(),
-                s.chars().collect(),
+                chars,
+            );
+        }
+        while let Ok(keys) = self.ui_glyph_receiver.try_recv() {
+            let _ = self.glyph_vertex_buffer.append_glyphs(
+                self.context.device(),
+                self.context.queue(),
+                keys.into_iter().collect(),
             );
         }
+        #[cfg(not(target_arch = "wasm32"))]
+        let _ = self
+            .glyph_vertex_buffer
+            .receive_converted_glyphs(self.context.device(), self.context.queue());
 
         record_start_of_phase("render 0: append svg");
         while let Ok((key, svg)) = self.ui_svg_receiver.try_recv() {
@@ -742,6 +782,7 @@ impl RenderState {
             camera,
             glyph_instances,
             vector_instances,
+            image_instances,
             glyph_instances_for_modal,
             vector_instances_for_modal,
         } = self.simple_state_callback.render();
@@ -763,9 +804,15 @@ impl RenderState {
                         &vector_instances,
                     ))
                 };
+            let image_buffers: Option<&[&ImageInstances]> = if image_instances.is_empty() {
+                None
+            } else {
+                Some(&image_instances)
+            };
             Buffers {
                 glyph_buffers,
                 vector_buffers,
+                image_buffers,
             }
         };
         let modal_buffers = {
@@ -787,6 +834,7 @@ impl RenderState {
             Buffers {
                 glyph_buffers,
                 vector_buffers,
+                image_buffers: None,
             }
         };
 
@@ -858,24 +906,71 @@ impl RenderState {
         self.refresh_font_buffers();
     }
 
-    fn refresh_font_buffers(&mut self) {
+    // どのフォントにも無い文字は、表示できるフォントをシステムのフォントから探してフォールバックに加える。
+    // フォールバックのフォントは末尾に加わるので、登録済みのグリフのフォントの番号は変わらない
+    fn add_fallback_fonts_for_missing_chars(&mut self, chars: &HashSet<char>) {
+        let glyph_shaper = self.context.glyph_shaper().clone();
+        let missing_chars: Vec<char> = chars
+            .iter()
+            .copied()
+            .filter(|c| !c.is_control() && !glyph_shaper.has_glyph(*c))
+            .collect();
+        if missing_chars.is_empty() {
+            return;
+        }
+        if self
+            .context
+            .state_context_mut()
+            .font_repository
+            .add_fallback_fonts_for_chars(missing_chars)
+        {
+            // 登録済みのグリフは変換し直さず、シェーパと文字の幅だけを差し替える
+            let (glyph_shaper, char_width_calcurator) = self.new_glyph_shaper();
+            let _ = self.glyph_vertex_buffer.append_fonts(
+                self.context.device(),
+                self.context.queue(),
+                glyph_shaper.clone(),
+                char_width_calcurator.clone(),
+            );
+            self.context.state_context_mut().char_width_calcurator = char_width_calcurator;
+            self.context.state_context_mut().glyph_shaper = glyph_shaper;
+        }
+    }
+
+    fn new_glyph_shaper(&self) -> (Arc<GlyphShaper>, Arc<CharWidthCalculator>) {
         let font_binaries = self.context.font_repository().get_fonts();
         let font_binaries = Arc::new(font_binaries);
         let ascii_override_font = self.context.font_repository().get_ascii_override_font();
-        let char_width_calcurator = Arc::new(CharWidthCalculator::new(font_binaries.clone()));
+        // 文字の幅はフォントごとに違うので作り直す
+        let char_width_calcurator = Arc::new(
+            CharWidthCalculator::new(font_binaries.clone())
+                .with_ambiguous_width(self.context.editor_settings().ambiguous_width()),
+        );
+        let glyph_shaper = Arc::new(GlyphShaper::new(font_binaries, ascii_override_font));
+        (glyph_shaper, char_width_calcurator)
+    }
+
+    fn refresh_font_buffers(&mut self) {
+        let (glyph_shaper, char_width_calcurator) = self.new_glyph_shaper();
 
+        // シェーピングで求めたグリフはフォントが変わると番号が変わるので、TextEdit がシェーピングし直して登録する
         let registerd_chars = self.glyph_vertex_buffer.registerd_chars();
-        self.glyph_vertex_buffer = GlyphVertexBuffer::new(
-            font_binaries,
-            ascii_override_font,
-            char_width_calcurator.clone(),
-        );
+        let glyph_vertex_buffer =
+            GlyphVertexBuffer::new(glyph_shaper.clone(), char_width_calcurator.clone());
+        #[cfg(not(target_arch = "wasm32"))]
+        let glyph_vertex_buffer = if self.glyph_vertex_buffer.is_background_conversion() {
+            glyph_vertex_buffer.with_background_conversion()
+        } else {
+            glyph_vertex_buffer
+        };
+        self.glyph_vertex_buffer = glyph_vertex_buffer;
         let _ = self.glyph_vertex_buffer.append_chars(
             self.context.device(),
             self.context.queue(),
             registerd_chars,
         );
         self.context.state_context_mut().char_width_calcurator = char_width_calcurator;
+        self.context.state_context_mut().glyph_shaper = glyph_shaper;
     }
 
     pub(crate) fn change_quarity(&mut self, quarity: Quarity) {
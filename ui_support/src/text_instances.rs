@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use text_buffer::{
     buffer::{BufferChar, CellPosition},
@@ -9,6 +9,7 @@ use wgpu::{Device, Queue};
 use font_rasterizer::{
     glyph_instances::GlyphInstances,
     glyph_vertex_buffer::Direction,
+    shaping::GlyphKey,
     vector_instances::{InstanceAttributes, InstanceKey, VectorInstances},
 };
 
@@ -51,20 +52,37 @@ impl TextInstancesKey {
     }
 }
 
+/// シェーピングの結果、文字を単独で表示するときの代わりに描くもの
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum GlyphOverride {
+    /// 合字などのグリフを描く
+    Glyph(GlyphKey),
+    /// 前の文字のグリフに含まれるので描かない
+    Hidden,
+}
+
+// バッファの文字のインスタンスは、その位置の GlyphOverride ごとに分けて持つ。
+// プレエディットと削除中の文字は常に文字を単独で表示するときのグリフで描く
 #[derive(Default)]
 pub(crate) struct TextInstances {
-    glyph_instances: BTreeMap<char, GlyphInstances>,
+    glyph_instances: BTreeMap<(char, Option<GlyphOverride>), GlyphInstances>,
+    glyph_overrides: BTreeMap<CellPosition, GlyphOverride>,
     direction: Direction,
 }
 
 impl TextInstances {
+    fn glyph_key(&self, key: &TextInstancesKey) -> (char, Option<GlyphOverride>) {
+        (key.c, self.glyph_overrides.get(&key.position).copied())
+    }
+
     pub(crate) fn add(
         &mut self,
         key: TextInstancesKey,
         instance: InstanceAttributes,
         device: &Device,
     ) {
-        self.get_or_insert_glyph_instances_mut(&key, device)
+        let glyph_key = self.glyph_key(&key);
+        self.get_or_insert_glyph_instances_mut(glyph_key, device)
             .insert(key.to_instance_key(), instance)
     }
 
@@ -74,24 +92,73 @@ impl TextInstances {
         instance: InstanceAttributes,
         device: &Device,
     ) {
-        self.get_or_insert_glyph_instances_mut(&key, device)
+        self.get_or_insert_glyph_instances_mut((key.c, None), device)
             .insert(key.to_preedit_instance_key(), instance)
     }
 
     fn get_or_insert_glyph_instances_mut(
         &mut self,
-        key: &TextInstancesKey,
+        (c, glyph_override): (char, Option<GlyphOverride>),
         device: &Device,
     ) -> &mut GlyphInstances {
-        self.glyph_instances.entry(key.c).or_insert_with(|| {
-            let mut instances = GlyphInstances::new(key.c, device);
-            instances.set_direction(&self.direction);
-            instances
-        })
+        self.glyph_instances
+            .entry((c, glyph_override))
+            .or_insert_with(|| {
+                let mut instances = match glyph_override {
+                    Some(GlyphOverride::Glyph(glyph)) => {
+                        GlyphInstances::new_glyph(c, glyph, device)
+                    }
+                    Some(GlyphOverride::Hidden) | None => GlyphInstances::new(c, device),
+                };
+                instances.set_direction(&self.direction);
+                instances
+            })
+    }
+
+    /// シェーピングの結果を差し替え、変わった位置の文字のインスタンスを描くグリフごとに移し替える。
+    /// char_at はその位置にある現在のバッファの文字を返す
+    pub(crate) fn set_glyph_overrides(
+        &mut self,
+        glyph_overrides: BTreeMap<CellPosition, GlyphOverride>,
+        char_at: impl Fn(&CellPosition) -> Option<char>,
+        device: &Device,
+    ) {
+        let changed_positions: BTreeSet<CellPosition> = self
+            .glyph_overrides
+            .iter()
+            .chain(glyph_overrides.iter())
+            .filter(|(position, _)| {
+                self.glyph_overrides.get(position) != glyph_overrides.get(position)
+            })
+            .map(|(position, _)| *position)
+            .collect();
+
+        let mut moved = Vec::new();
+        for position in changed_positions.iter() {
+            let Some(c) = char_at(position) else {
+                continue;
+            };
+            let key = TextInstancesKey {
+                c,
+                position: *position,
+            };
+            let glyph_key = self.glyph_key(&key);
+            if let Some(instances) = self.glyph_instances.get_mut(&glyph_key)
+                && let Some(instance) = instances.remove(&key.to_instance_key())
+            {
+                moved.push((key, instance));
+            }
+        }
+
+        self.glyph_overrides = glyph_overrides;
+        for (key, instance) in moved {
+            self.add(key, instance, device);
+        }
     }
 
     pub(crate) fn get_mut(&mut self, key: &TextInstancesKey) -> Option<&mut InstanceAttributes> {
-        if let Some(instances) = self.glyph_instances.get_mut(&key.c) {
+        let glyph_key = self.glyph_key(key);
+        if let Some(instances) = self.glyph_instances.get_mut(&glyph_key) {
             instances.get_mut(&key.to_instance_key())
         } else {
             None
@@ -102,7 +169,7 @@ impl TextInstances {
         &mut self,
         key: &TextInstancesKey,
     ) -> Option<&mut InstanceAttributes> {
-        if let Some(instances) = self.glyph_instances.get_mut(&key.c) {
+        if let Some(instances) = self.glyph_instances.get_mut(&(key.c, None)) {
             instances.get_mut(&key.to_preedit_instance_key())
         } else {
             None
@@ -110,7 +177,8 @@ impl TextInstances {
     }
 
     pub(crate) fn remove(&mut self, key: &TextInstancesKey) -> Option<InstanceAttributes> {
-        if let Some(instances) = self.glyph_instances.get_mut(&key.c) {
+        let glyph_key = self.glyph_key(key);
+        if let Some(instances) = self.glyph_instances.get_mut(&glyph_key) {
             instances.remove(&key.to_instance_key())
         } else {
             None
@@ -118,18 +186,18 @@ impl TextInstances {
     }
 
     pub(crate) fn remove_preedit(&mut self, key: &TextInstancesKey) -> Option<InstanceAttributes> {
-        if let Some(instances) = self.glyph_instances.get_mut(&key.c) {
+        if let Some(instances) = self.glyph_instances.get_mut(&(key.c, None)) {
             instances.remove(&key.to_preedit_instance_key())
         } else {
             None
         }
     }
 
-    pub(crate) fn pre_remove(&mut self, key: &TextInstancesKey) {
-        if let Some(instances) = self.glyph_instances.get_mut(&key.c)
-            && let Some(instance) = instances.remove(&key.to_instance_key())
-        {
-            instances.insert(key.to_pre_remove_instance_key(), instance);
+    // 削除中の文字は合字を崩して、文字を単独で表示するときのグリフで描く
+    pub(crate) fn pre_remove(&mut self, key: &TextInstancesKey, device: &Device) {
+        if let Some(instance) = self.remove(key) {
+            self.get_or_insert_glyph_instances_mut((key.c, None), device)
+                .insert(key.to_pre_remove_instance_key(), instance);
         }
     }
 
@@ -137,7 +205,7 @@ impl TextInstances {
         &mut self,
         key: &TextInstancesKey,
     ) -> Option<&mut InstanceAttributes> {
-        if let Some(instances) = self.glyph_instances.get_mut(&key.c) {
+        if let Some(instances) = self.glyph_instances.get_mut(&(key.c, None)) {
             instances.get_mut(&key.to_pre_remove_instance_key())
         } else {
             None
@@ -148,7 +216,7 @@ impl TextInstances {
         &mut self,
         key: &TextInstancesKey,
     ) -> Option<InstanceAttributes> {
-        if let Some(instances) = self.glyph_instances.get_mut(&key.c) {
+        if let Some(instances) = self.glyph_instances.get_mut(&(key.c, None)) {
             instances.remove(&key.to_pre_remove_instance_key())
         } else {
             None
@@ -169,7 +237,11 @@ impl TextInstances {
     }
 
     pub(crate) fn to_instances(&self) -> Vec<&GlyphInstances> {
-        self.glyph_instances.values().collect()
+        self.glyph_instances
+            .iter()
+            .filter(|((_, glyph_override), _)| glyph_override != &Some(GlyphOverride::Hidden))
+            .map(|(_, instances)| instances)
+            .collect()
     }
 }
 
@@ -346,3 +418,74 @@ impl BorderInstances {
         self.vector_instances.values().collect()
     }
 }
+
+/// 文字に重ねて描く図形
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub(crate) enum CharOverlay {
+    #[default]
+    None,
+    Underline,
+    Strikethrough,
+    // 箇条書きの記号の代わりに描く黒丸
+    Bullet,
+}
+
+impl CharOverlay {
+    pub(crate) fn to_key(self) -> String {
+        match self {
+            CharOverlay::None => "overlay_none".to_string(),
+            CharOverlay::Underline => "overlay_underline".to_string(),
+            CharOverlay::Strikethrough => "overlay_strikethrough".to_string(),
+            CharOverlay::Bullet => "overlay_bullet".to_string(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct OverlayInstances {
+    vector_instances: BTreeMap<CharOverlay, VectorInstances<String>>,
+}
+
+impl OverlayInstances {
+    pub(crate) fn add(
+        &mut self,
+        overlay: CharOverlay,
+        position: CellPosition,
+        instance: InstanceAttributes,
+        device: &Device,
+    ) {
+        let instances = self
+            .vector_instances
+            .entry(overlay)
+            .or_insert_with(|| VectorInstances::new(overlay.to_key(), device));
+        instances.insert(InstanceKey::Position(position.row, position.col), instance);
+    }
+
+    pub(crate) fn get_mut(
+        &mut self,
+        overlay: CharOverlay,
+        position: CellPosition,
+    ) -> Option<&mut InstanceAttributes> {
+        self.vector_instances
+            .get_mut(&overlay)
+            .and_then(|instances| {
+                instances.get_mut(&InstanceKey::Position(position.row, position.col))
+            })
+    }
+
+    pub(crate) fn remove(&mut self, overlay: CharOverlay, position: CellPosition) {
+        if let Some(instances) = self.vector_instances.get_mut(&overlay) {
+            instances.remove(&InstanceKey::Position(position.row, position.col));
+        }
+    }
+
+    pub(crate) fn update(&mut self, device: &Device, queue: &Queue) {
+        for instances in self.vector_instances.values_mut() {
+            instances.update_buffer(device, queue)
+        }
+    }
+
+    pub(crate) fn to_instances(&self) -> Vec<&VectorInstances<String>> {
+        self.vector_instances.values().collect()
+    }
+}
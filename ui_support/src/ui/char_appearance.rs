@@ -0,0 +1,452 @@
+//! ハイライトの結果から、文字ごとの色や大きさ、傾き、フォントのスタイル、重ねる図形を決める。
+//!
+//! 太字や斜体はフォントのスタイルで表し、フォントにそのスタイルが無いときだけ大きさや傾きで似せる。
+//!
+//! Markdown のライブプレビューでは、キャレットの無い行の記法の文字を小さく目立たなくし、
+//! 見出しを大きく、強調を太字や斜体にして、リンクに下線を引き、箇条書きの記号を黒丸で描く。
+//! 大きく描く行は、隣の文字や行と重ならないように文字の間隔を広げ、行の下に空の行を空ける。
+use std::collections::BTreeMap;
+
+use font_collector::FontStyle;
+use font_rasterizer::color_theme::ThemedColor;
+use highlighter::{PreviewStyle, markdown_preview};
+use phisical_layouter::{CharWidthResolver, PhysicalLayout, PhysicalPosition};
+
+use crate::{
+    text_instances::CharOverlay,
+    ui::{CharAttribute, Decoration},
+};
+
+use super::view_element_state::ViewElementStateUpdateRequest;
+
+// 見出しのレベルごとの文字の倍率
+const HEADING_SCALES: [f32; 6] = [1.5, 1.3, 1.15, 1.05, 1.0, 1.0];
+// 目立たなくする記法の文字の倍率
+const MARKUP_SCALE: f32 = 0.6;
+// フォントに太字が無いときは少し大きくして表す
+const BOLD_SCALE: f32 = 1.1;
+// フォントに斜体が無いときは右に傾けて表す
+const ITALIC_SLANT: f32 = -0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CharAppearance {
+    // ハイライトの色の代わりに使う色
+    color: Option<ThemedColor>,
+    decoration: Decoration,
+    font_style: FontStyle,
+    scale: f32,
+    overlay: CharOverlay,
+}
+
+impl Default for CharAppearance {
+    fn default() -> Self {
+        Self {
+            color: None,
+            decoration: Decoration::None,
+            font_style: FontStyle::REGULAR,
+            scale: 1.0,
+            overlay: CharOverlay::None,
+        }
+    }
+}
+
+impl CharAppearance {
+    pub(crate) fn from_attribute(attribute: CharAttribute) -> Self {
+        Self {
+            decoration: attribute.decoration,
+            font_style: attribute.font_style,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn font_style(&self) -> FontStyle {
+        self.font_style
+    }
+
+    /// available_style はフォントで実際に表示できるスタイル
+    pub(crate) fn update_request(
+        &self,
+        highlight_color: ThemedColor,
+        available_style: FontStyle,
+    ) -> ViewElementStateUpdateRequest {
+        let decoration_scale = if self.font_style.is_bold() && !available_style.is_bold() {
+            BOLD_SCALE
+        } else {
+            1.0
+        };
+        let slant = if self.font_style.italic && !available_style.italic {
+            ITALIC_SLANT
+        } else {
+            0.0
+        };
+        let decoration_overlay = match self.decoration {
+            Decoration::Underline => CharOverlay::Underline,
+            Decoration::Strikethrough => CharOverlay::Strikethrough,
+            Decoration::None | Decoration::Bold | Decoration::Italic => CharOverlay::None,
+        };
+        let overlay = match self.overlay {
+            CharOverlay::None => decoration_overlay,
+            overlay => overlay,
+        };
+        let scale = self.scale * decoration_scale;
+        ViewElementStateUpdateRequest {
+            base_color: Some(self.color.unwrap_or(highlight_color)),
+            style_scale: Some([scale, scale]),
+            slant: Some([slant]),
+            overlay: Some(overlay),
+            font_style: Some(available_style),
+            ..Default::default()
+        }
+    }
+}
+
+/// Markdown のライブプレビューでの文字ごとの見た目。改行を含めた文字位置で引く。
+/// caret_row の行の記法の文字はそのまま表示する
+pub(crate) fn markdown_preview_appearances(
+    text: &str,
+    caret_row: Option<usize>,
+) -> Vec<CharAppearance> {
+    let rows: Vec<usize> = text
+        .chars()
+        .scan(0, |row, c| {
+            let current = *row;
+            if c == '\n' {
+                *row += 1;
+            }
+            Some(current)
+        })
+        .collect();
+    let mut appearances = vec![CharAppearance::default(); rows.len()];
+    let mut markups = vec![false; rows.len()];
+    let mut bullets = vec![false; rows.len()];
+
+    // 範囲は親の後に子が続くので、後の範囲ほど内側の指定になる
+    for span in markdown_preview(text) {
+        for position in span.range.start..span.range.end.min(rows.len()) {
+            let appearance = &mut appearances[position];
+            let on_caret_row = caret_row == Some(rows[position]);
+            match span.style {
+                PreviewStyle::Markup => markups[position] = !on_caret_row,
+                PreviewStyle::Heading(level) => {
+                    appearance.scale *= HEADING_SCALES[level.clamp(1, 6) - 1];
+                }
+                PreviewStyle::Strong => {
+                    appearance.decoration = Decoration::Bold;
+                    appearance.font_style =
+                        appearance.font_style.with_weight(FontStyle::BOLD.weight);
+                    appearance.color = Some(ThemedColor::TextEmphasized);
+                }
+                PreviewStyle::Emphasis => {
+                    appearance.decoration = Decoration::Italic;
+                    appearance.font_style = appearance.font_style.with_italic(true);
+                }
+                PreviewStyle::Strikethrough => appearance.decoration = Decoration::Strikethrough,
+                PreviewStyle::Code => {}
+                PreviewStyle::Link => {
+                    // リンク全体を記法の文字とした後に、リンクの文字だけを戻す
+                    markups[position] = false;
+                    appearance.decoration = Decoration::Underline;
+                    appearance.color = Some(ThemedColor::Blue);
+                }
+                PreviewStyle::ListBullet => bullets[position] = !on_caret_row,
+            }
+        }
+    }
+
+    for ((appearance, markup), bullet) in appearances.iter_mut().zip(markups).zip(bullets) {
+        if bullet {
+            // 記号は隠して、代わりに黒丸を描く
+            appearance.scale = 0.0;
+            appearance.overlay = CharOverlay::Bullet;
+        } else if markup {
+            appearance.color = Some(ThemedColor::TextComment);
+            appearance.decoration = Decoration::None;
+            appearance.font_style = FontStyle::REGULAR;
+            appearance.scale *= MARKUP_SCALE;
+        }
+    }
+    appearances
+}
+
+/// 1 より大きく描く文字のある行ごとの、最も大きい文字の倍率。appearances は改行を含めた文字位置で引く
+pub(crate) fn row_scales(text: &str, appearances: &[CharAppearance]) -> BTreeMap<usize, f32> {
+    let mut scales: BTreeMap<usize, f32> = BTreeMap::new();
+    let mut row = 0;
+    for (c, appearance) in text.chars().zip(appearances) {
+        if c == '\n' {
+            row += 1;
+        } else if appearance.scale > 1.0 {
+            let scale = scales.entry(row).or_insert(1.0);
+            *scale = scale.max(appearance.scale);
+        }
+    }
+    scales
+}
+
+/// scale 倍の文字が上下の行と重ならないように、行の下に空ける行の数
+pub(crate) fn extra_rows(scale: f32) -> usize {
+    (scale - 1.0).max(0.0).ceil() as usize
+}
+
+/// row_scales の行の文字を倍率に合わせた間隔で並べ直して折り返し、
+/// 物理行ごとに extra_rows の分だけ下に行を空けて、それより後ろの文字とキャレットを下にずらす
+pub(crate) fn reserve_scaled_rows(
+    layout: &mut PhysicalLayout,
+    row_scales: &BTreeMap<usize, f32>,
+    max_line_width: usize,
+    width_resolver: &dyn CharWidthResolver,
+) {
+    // 大きく描く行の物理行の範囲
+    let mut ranges: BTreeMap<usize, (usize, usize)> = BTreeMap::new();
+    for (c, position) in layout.chars.iter() {
+        if row_scales.contains_key(&c.position.row) {
+            let (first, last) = ranges
+                .entry(c.position.row)
+                .or_insert((position.row, position.row));
+            *first = (*first).min(position.row);
+            *last = (*last).max(position.row);
+        }
+    }
+    if ranges.is_empty() {
+        return;
+    }
+
+    // 並べ直した文字の位置と、その文字の前にあるキャレットの位置
+    let mut char_moves: BTreeMap<PhysicalPosition, PhysicalPosition> = BTreeMap::new();
+    let mut caret_moves: BTreeMap<PhysicalPosition, PhysicalPosition> = BTreeMap::new();
+    // 行の最後の物理行と、増えた物理行の数
+    let mut shifts: Vec<(usize, isize)> = Vec::new();
+    for (row, (first, last)) in ranges {
+        let scale = row_scales[&row];
+        let line_rows = 1 + extra_rows(scale);
+        // 変換中の文字も含めて元の順に並べる
+        let mut cells: Vec<(PhysicalPosition, usize)> = layout
+            .chars
+            .iter()
+            .filter(|(c, _)| c.position.row == row)
+            .chain(
+                layout
+                    .preedit_chars
+                    .iter()
+                    .filter(|(_, position)| (first..=last).contains(&position.row)),
+            )
+            .map(|(c, position)| (*position, width_resolver.resolve_width(c.c)))
+            .collect();
+        cells.sort();
+
+        let shift: isize = shifts.iter().map(|(_, count)| count).sum();
+        let top = (first as isize + shift) as usize;
+        let mut line = 0;
+        let mut col = 0.0f32;
+        for (position, width) in cells.iter() {
+            let scaled_width = *width as f32 * scale;
+            if col > 0.0 && col + scaled_width > max_line_width as f32 {
+                line += 1;
+                col = 0.0;
+            }
+            let new_row = top + line * line_rows;
+            caret_moves.insert(
+                *position,
+                PhysicalPosition {
+                    row: new_row,
+                    col: col.round() as usize,
+                },
+            );
+            // 文字は広げた幅の中央に置く
+            char_moves.insert(
+                *position,
+                PhysicalPosition {
+                    row: new_row,
+                    col: (col + (scaled_width - *width as f32) / 2.0).round() as usize,
+                },
+            );
+            col += scaled_width;
+        }
+        if let Some((position, width)) = cells.last() {
+            caret_moves.insert(
+                PhysicalPosition {
+                    row: position.row,
+                    col: position.col + width,
+                },
+                PhysicalPosition {
+                    row: top + line * line_rows,
+                    col: col.round() as usize,
+                },
+            );
+        }
+        let count = ((line + 1) * line_rows) as isize - (last - first + 1) as isize;
+        shifts.push((last, count));
+    }
+
+    let shifted = |position: PhysicalPosition| -> PhysicalPosition {
+        let shift: isize = shifts
+            .iter()
+            .filter(|(last, _)| *last < position.row)
+            .map(|(_, count)| count)
+            .sum();
+        PhysicalPosition {
+            row: (position.row as isize + shift) as usize,
+            col: position.col,
+        }
+    };
+    for (_, position) in layout
+        .chars
+        .iter_mut()
+        .chain(layout.preedit_chars.iter_mut())
+    {
+        *position = char_moves
+            .get(position)
+            .copied()
+            .unwrap_or_else(|| shifted(*position));
+    }
+    for position in std::iter::once(&mut layout.main_caret_pos).chain(layout.mark_pos.as_mut()) {
+        *position = caret_moves
+            .get(position)
+            .copied()
+            .unwrap_or_else(|| shifted(*position));
+    }
+}
+
+/// 大きく描く文字のある物理行と、その下に空けた行の数
+pub(crate) fn scaled_physical_rows(
+    layout: &PhysicalLayout,
+    row_scales: &BTreeMap<usize, f32>,
+) -> BTreeMap<usize, usize> {
+    layout
+        .chars
+        .iter()
+        .filter_map(|(c, position)| {
+            row_scales
+                .get(&c.position.row)
+                .map(|scale| (position.row, extra_rows(*scale)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use text_buffer::buffer::{BufferChar, CellPosition};
+
+    use super::*;
+
+    struct WidthResolver;
+
+    impl CharWidthResolver for WidthResolver {
+        fn resolve_width(&self, c: char) -> usize {
+            if c.is_ascii() { 1 } else { 2 }
+        }
+    }
+
+    fn layout_char(
+        row: usize,
+        col: usize,
+        c: char,
+        physical: [usize; 2],
+    ) -> (BufferChar, PhysicalPosition) {
+        (
+            BufferChar {
+                position: CellPosition::new(row, col),
+                c,
+            },
+            PhysicalPosition {
+                row: physical[0],
+                col: physical[1],
+            },
+        )
+    }
+
+    #[test]
+    fn test_update_request() {
+        let appearance =
+            CharAppearance::from_attribute(CharAttribute::new(ThemedColor::Text, Decoration::Bold));
+        // フォントに太字があればそのまま使う
+        let request = appearance.update_request(ThemedColor::Text, FontStyle::BOLD);
+        assert_eq!(request.style_scale, Some([1.0, 1.0]));
+        assert_eq!(request.font_style, Some(FontStyle::BOLD));
+        // フォントに太字が無ければ大きくして似せる
+        let request = appearance.update_request(ThemedColor::Text, FontStyle::REGULAR);
+        assert_eq!(request.style_scale, Some([BOLD_SCALE, BOLD_SCALE]));
+        assert_eq!(request.font_style, Some(FontStyle::REGULAR));
+    }
+
+    #[test]
+    fn test_markdown_preview_appearances() {
+        let text = "# 見出し\n- ***太字*** [リンク](url)\n";
+        let appearances = markdown_preview_appearances(text, None);
+        let at = |target: &str| {
+            let position = text.find(target).unwrap();
+            appearances[text[..position].chars().count()]
+        };
+
+        // 見出しの記法の文字は目立たなくし、見出しの文字は大きくする
+        assert_eq!(at("#").color, Some(ThemedColor::TextComment));
+        assert_eq!(at("#").scale, HEADING_SCALES[0] * MARKUP_SCALE);
+        assert_eq!(at("見").scale, HEADING_SCALES[0]);
+        // 箇条書きの記号は黒丸に置き換える
+        assert_eq!(at("-").scale, 0.0);
+        assert_eq!(at("-").overlay, CharOverlay::Bullet);
+        // 強調は太字、リンクは下線にする
+        assert_eq!(at("太").font_style, FontStyle::BOLD.with_italic(true));
+        assert_eq!(at("**").color, Some(ThemedColor::TextComment));
+        assert_eq!(at("リ").decoration, Decoration::Underline);
+        assert_eq!(at("(url)").color, Some(ThemedColor::TextComment));
+
+        // キャレットのある行の記法の文字はそのまま表示する
+        let appearances = markdown_preview_appearances(text, Some(1));
+        assert_eq!(appearances[0].color, Some(ThemedColor::TextComment));
+        assert_eq!(appearances[6].overlay, CharOverlay::None);
+        assert_eq!(appearances[6].scale, 1.0);
+    }
+
+    #[test]
+    fn test_row_scales() {
+        let text = "# 見出し\n本文\n## 小見出し";
+        let appearances = markdown_preview_appearances(text, None);
+        let scales = row_scales(text, &appearances);
+        assert_eq!(
+            scales,
+            BTreeMap::from([(0, HEADING_SCALES[0]), (2, HEADING_SCALES[1])])
+        );
+        assert_eq!(extra_rows(1.0), 0);
+        assert_eq!(extra_rows(1.05), 1);
+        assert_eq!(extra_rows(2.5), 2);
+    }
+
+    #[test]
+    fn test_reserve_scaled_rows() {
+        // 1 行目を 1.5 倍にする。折り返しの幅は全角 3 文字分
+        let mut layout = PhysicalLayout {
+            chars: vec![
+                layout_char(0, 0, '見', [0, 0]),
+                layout_char(0, 1, '出', [0, 2]),
+                layout_char(0, 2, 'し', [0, 4]),
+                layout_char(1, 0, '本', [1, 0]),
+            ],
+            preedit_chars: Vec::new(),
+            main_caret_pos: PhysicalPosition { row: 0, col: 6 },
+            mark_pos: Some(PhysicalPosition { row: 0, col: 2 }),
+        };
+        let scales = BTreeMap::from([(0, 1.5)]);
+        reserve_scaled_rows(&mut layout, &scales, 6, &WidthResolver);
+
+        let positions: Vec<[usize; 2]> = layout
+            .chars
+            .iter()
+            .map(|(_, position)| [position.row, position.col])
+            .collect();
+        // 広げた幅の中央に文字を置き、収まらない文字は 1 行空けて折り返す
+        assert_eq!(positions, vec![[0, 1], [0, 4], [2, 1], [4, 0]]);
+        // 行末のキャレットは折り返した行の末尾に、文字の前のキャレットは広げた幅の左端に動く
+        assert_eq!(layout.main_caret_pos, PhysicalPosition { row: 2, col: 3 });
+        assert_eq!(layout.mark_pos, Some(PhysicalPosition { row: 0, col: 3 }));
+        assert_eq!(
+            scaled_physical_rows(&layout, &scales),
+            BTreeMap::from([(0, 1), (2, 1)])
+        );
+
+        // 大きく描く行が無ければ何もしない
+        reserve_scaled_rows(&mut layout, &BTreeMap::new(), 6, &WidthResolver);
+        assert_eq!(layout.chars[3].1, PhysicalPosition { row: 4, col: 0 });
+        assert_eq!(layout.main_caret_pos, PhysicalPosition { row: 2, col: 3 });
+    }
+}
@@ -0,0 +1,349 @@
+//! SelectBox の絞り込み用のあいまい検索。
+//!
+//! キーワードの文字が対象文字列に順番通りに含まれていればマッチとし、
+//! 連続して一致する、単語の先頭で一致するといった条件で加点してスコアを付ける。
+//! ローマ字で入力されたキーワードはひらがなに変換したものでも照合するので、
+//! 読みを持つ選択肢であれば "kaigyou" で「改行」を探すことができる。
+
+const SCORE_MATCH: i32 = 1;
+const BONUS_CONSECUTIVE: i32 = 5;
+const BONUS_HEAD: i32 = 10;
+const BONUS_WORD_BOUNDARY: i32 = 8;
+const PENALTY_GAP: i32 = 1;
+const MAX_GAP_PENALTY: i32 = 5;
+
+/// ローマ字をひらがなに変換する。変換できない文字はそのまま残す。
+pub(crate) fn romaji_to_hiragana(romaji: &str) -> String {
+    let chars: Vec<char> = romaji.to_lowercase().chars().collect();
+    let mut result = String::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let next = chars.get(index + 1).copied();
+
+        // 子音の連続は促音にする(例: kitte -> きって)
+        if let Some(next) = next
+            && c == next
+            && is_consonant(c)
+            && c != 'n'
+        {
+            result.push('っ');
+            index += 1;
+            continue;
+        }
+
+        // n の後に母音、y、n 以外が続く場合は「ん」とする
+        if c == 'n' {
+            match next {
+                None => {
+                    result.push('ん');
+                    index += 1;
+                    continue;
+                }
+                Some('n') | Some('\'') => {
+                    result.push('ん');
+                    index += 2;
+                    continue;
+                }
+                Some(next) if !is_vowel(next) && next != 'y' => {
+                    result.push('ん');
+                    index += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        let mut converted = false;
+        for len in (1..=4).rev() {
+            if index + len > chars.len() {
+                continue;
+            }
+            let key: String = chars[index..index + len].iter().collect();
+            if let Some(kana) = lookup_romaji(&key) {
+                result.push_str(kana);
+                index += len;
+                converted = true;
+                break;
+            }
+        }
+        if !converted {
+            result.push(c);
+            index += 1;
+        }
+    }
+    result
+}
+
+/// 比較用に文字列を正規化する。英字は小文字にし、カタカナはひらがなにする。
+pub(crate) fn normalize(text: &str) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(katakana_to_hiragana)
+        .collect()
+}
+
+fn katakana_to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'i' | 'u' | 'e' | 'o')
+}
+
+fn is_consonant(c: char) -> bool {
+    c.is_ascii_lowercase() && !is_vowel(c)
+}
+
+fn is_word_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ':' | '-' | '_' | '(' | '[' | '/' | '・' | '、')
+}
+
+/// keyword が text にあいまいに一致する場合はスコアを返す。大きいほどよく一致している。
+pub(crate) fn fuzzy_score(text: &str, keyword: &str) -> Option<i32> {
+    let text: Vec<char> = normalize(text).chars().collect();
+    let keyword: Vec<char> = normalize(keyword).chars().collect();
+    if keyword.is_empty() {
+        return Some(0);
+    }
+
+    // 先頭文字の出現位置ごとに貪欲に照合し、最もスコアが高いものを採用する
+    text.iter()
+        .enumerate()
+        .filter(|(_, c)| **c == keyword[0])
+        .filter_map(|(start, _)| score_from(&text, &keyword, start))
+        .max()
+}
+
+fn score_from(text: &[char], keyword: &[char], start: usize) -> Option<i32> {
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    let mut position = start;
+    for k in keyword {
+        let found = (position..text.len()).find(|i| text[*i] == *k)?;
+        score += SCORE_MATCH;
+        if found == 0 {
+            score += BONUS_HEAD;
+        } else if is_word_boundary(text[found - 1]) {
+            score += BONUS_WORD_BOUNDARY;
+        }
+        match previous {
+            Some(previous) if previous + 1 == found => score += BONUS_CONSECUTIVE,
+            Some(previous) => {
+                score -= ((found - previous - 1) as i32 * PENALTY_GAP).min(MAX_GAP_PENALTY)
+            }
+            None => {}
+        }
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+/// keyword をそのまま照合した場合と、ローマ字からかなに変換して照合した場合の良い方のスコアを返す。
+pub(crate) fn fuzzy_score_with_kana(text: &str, keyword: &str) -> Option<i32> {
+    let direct = fuzzy_score(text, keyword);
+    if !keyword.is_ascii() {
+        return direct;
+    }
+    // 入力途中の子音("kaig" の "g" など)はかなに変換できないので照合から外す
+    let kana = romaji_to_hiragana(keyword);
+    let kana = kana.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    if kana.is_empty() || kana.is_ascii() {
+        return direct;
+    }
+    direct.max(fuzzy_score(text, kana))
+}
+
+fn lookup_romaji(key: &str) -> Option<&'static str> {
+    let kana = match key {
+        "a" => "あ",
+        "i" => "い",
+        "u" => "う",
+        "e" => "え",
+        "o" => "お",
+        "ka" => "か",
+        "ki" => "き",
+        "ku" => "く",
+        "ke" => "け",
+        "ko" => "こ",
+        "kya" => "きゃ",
+        "kyu" => "きゅ",
+        "kyo" => "きょ",
+        "ga" => "が",
+        "gi" => "ぎ",
+        "gu" => "ぐ",
+        "ge" => "げ",
+        "go" => "ご",
+        "gya" => "ぎゃ",
+        "gyu" => "ぎゅ",
+        "gyo" => "ぎょ",
+        "sa" => "さ",
+        "si" | "shi" => "し",
+        "su" => "す",
+        "se" => "せ",
+        "so" => "そ",
+        "sya" | "sha" => "しゃ",
+        "syu" | "shu" => "しゅ",
+        "sye" | "she" => "しぇ",
+        "syo" | "sho" => "しょ",
+        "za" => "ざ",
+        "zi" | "ji" => "じ",
+        "zu" => "ず",
+        "ze" => "ぜ",
+        "zo" => "ぞ",
+        "zya" | "ja" | "jya" => "じゃ",
+        "zyu" | "ju" | "jyu" => "じゅ",
+        "zye" | "je" | "jye" => "じぇ",
+        "zyo" | "jo" | "jyo" => "じょ",
+        "ta" => "た",
+        "ti" | "chi" => "ち",
+        "tu" | "tsu" => "つ",
+        "te" => "て",
+        "to" => "と",
+        "tya" | "cha" | "cya" => "ちゃ",
+        "tyu" | "chu" | "cyu" => "ちゅ",
+        "tye" | "che" | "cye" => "ちぇ",
+        "tyo" | "cho" | "cyo" => "ちょ",
+        "thi" => "てぃ",
+        "da" => "だ",
+        "di" => "ぢ",
+        "du" => "づ",
+        "de" => "で",
+        "do" => "ど",
+        "dhi" => "でぃ",
+        "na" => "な",
+        "ni" => "に",
+        "nu" => "ぬ",
+        "ne" => "ね",
+        "no" => "の",
+        "nya" => "にゃ",
+        "nyu" => "にゅ",
+        "nyo" => "にょ",
+        "ha" => "は",
+        "hi" => "ひ",
+        "hu" | "fu" => "ふ",
+        "he" => "へ",
+        "ho" => "ほ",
+        "hya" => "ひゃ",
+        "hyu" => "ひゅ",
+        "hyo" => "ひょ",
+        "fa" => "ふぁ",
+        "fi" => "ふぃ",
+        "fe" => "ふぇ",
+        "fo" => "ふぉ",
+        "ba" => "ば",
+        "bi" => "び",
+        "bu" => "ぶ",
+        "be" => "べ",
+        "bo" => "ぼ",
+        "bya" => "びゃ",
+        "byu" => "びゅ",
+        "byo" => "びょ",
+        "pa" => "ぱ",
+        "pi" => "ぴ",
+        "pu" => "ぷ",
+        "pe" => "ぺ",
+        "po" => "ぽ",
+        "pya" => "ぴゃ",
+        "pyu" => "ぴゅ",
+        "pyo" => "ぴょ",
+        "ma" => "ま",
+        "mi" => "み",
+        "mu" => "む",
+        "me" => "め",
+        "mo" => "も",
+        "mya" => "みゃ",
+        "myu" => "みゅ",
+        "myo" => "みょ",
+        "ya" => "や",
+        "yu" => "ゆ",
+        "yo" => "よ",
+        "ra" => "ら",
+        "ri" => "り",
+        "ru" => "る",
+        "re" => "れ",
+        "ro" => "ろ",
+        "rya" => "りゃ",
+        "ryu" => "りゅ",
+        "ryo" => "りょ",
+        "wa" => "わ",
+        "wi" => "うぃ",
+        "we" => "うぇ",
+        "wo" => "を",
+        "va" => "ゔぁ",
+        "vi" => "ゔぃ",
+        "vu" => "ゔ",
+        "ve" => "ゔぇ",
+        "vo" => "ゔぉ",
+        "xa" | "la" => "ぁ",
+        "xi" | "li" => "ぃ",
+        "xu" | "lu" => "ぅ",
+        "xe" | "le" => "ぇ",
+        "xo" | "lo" => "ぉ",
+        "xya" | "lya" => "ゃ",
+        "xyu" | "lyu" => "ゅ",
+        "xyo" | "lyo" => "ょ",
+        "xtu" | "ltu" | "xtsu" | "ltsu" => "っ",
+        "-" => "ー",
+        _ => return None,
+    };
+    Some(kana)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_romaji_to_hiragana() {
+        assert_eq!(romaji_to_hiragana("kaigyou"), "かいぎょう");
+        assert_eq!(romaji_to_hiragana("kitte"), "きって");
+        assert_eq!(romaji_to_hiragana("shinbun"), "しんぶん");
+        assert_eq!(romaji_to_hiragana("konnnichiha"), "こんにちは");
+        assert_eq!(romaji_to_hiragana("kan'i"), "かんい");
+        assert_eq!(romaji_to_hiragana("fonto"), "ふぉんと");
+        assert_eq!(romaji_to_hiragana("ko-do"), "こーど");
+        // 変換できない文字はそのまま残る
+        assert_eq!(romaji_to_hiragana("kaig"), "かいg");
+        assert_eq!(romaji_to_hiragana("C-x"), "cーx");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("バックスペース"), "ばっくすぺーす");
+        assert_eq!(normalize("ASCII文字"), "ascii文字");
+    }
+
+    #[test]
+    fn test_fuzzy_score_match() {
+        assert!(fuzzy_score("edit:return", "ret").is_some());
+        assert!(fuzzy_score("edit:return", "etrn").is_some());
+        assert!(fuzzy_score("edit:return", "nr").is_none());
+        assert!(fuzzy_score("改行", "").is_some());
+    }
+
+    #[test]
+    fn test_fuzzy_score_order() {
+        // 連続して一致するほうがスコアが高い
+        assert!(fuzzy_score("return", "ret") > fuzzy_score("rxexturn", "ret"));
+        // 単語の先頭で一致するほうがスコアが高い
+        assert!(fuzzy_score("edit:copy", "cop") > fuzzy_score("edit:ecopy", "cop"));
+        // 先頭で一致するほうがスコアが高い
+        assert!(fuzzy_score("copy", "co") > fuzzy_score("xcopy", "co"));
+    }
+
+    #[test]
+    fn test_fuzzy_score_with_kana() {
+        assert!(fuzzy_score_with_kana("かいぎょう", "kaigyou").is_some());
+        // 入力途中の子音は無視される
+        assert!(fuzzy_score_with_kana("かいぎょう", "kaig").is_some());
+        assert!(fuzzy_score_with_kana("バックスペース", "bakku").is_some());
+        assert!(fuzzy_score_with_kana("改行", "kaigyou").is_none());
+        // 英字のまま一致する場合はそのまま使う
+        assert!(fuzzy_score_with_kana("edit:return", "return").is_some());
+    }
+}
@@ -0,0 +1,241 @@
+//! Markdown の `![alt](path)` で指定された画像を読み込み、TextEdit に表示できる形にする。
+//!
+//! SVG はそのままベクターとして描く。PNG などのラスター画像はテクスチャを貼った四角形として描く。
+//! 画像の読み込みは時間がかかるので、描画のスレッドとは別のスレッドで行う。
+use std::path::{Path, PathBuf};
+
+use font_rasterizer::svg::svg_size;
+use image::DynamicImage;
+
+// ラスター画像をテクスチャにするときの長辺の画素数の上限
+const RASTER_MAX_PIXELS: u32 = 1024;
+
+/// Markdown の画像のリンク
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ImageLink {
+    /// リンクのある行
+    pub(crate) row: usize,
+    pub(crate) path: String,
+}
+
+/// 行ごとに `![alt](path)` を探す。`path "title"` のようなタイトルや `<path>` の括弧は取り除く。
+/// コードブロックとインラインコードの中は画像として扱わない
+pub(crate) fn image_links(text: &str) -> Vec<ImageLink> {
+    let mut links = Vec::new();
+    let mut fence: Option<(char, usize)> = None;
+    for (row, line) in text.lines().enumerate() {
+        if let Some(marker) = code_fence(line) {
+            fence = match fence {
+                None => Some(marker),
+                // 開いたときと同じ記号で、同じ数以上並んでいれば閉じる
+                Some((c, count)) if marker.0 == c && marker.1 >= count => None,
+                Some(opened) => Some(opened),
+            };
+            continue;
+        }
+        if fence.is_some() {
+            continue;
+        }
+        // バッククォートで区切った奇数番目はインラインコード
+        for part in line.split('`').step_by(2) {
+            links.extend(
+                line_image_paths(part)
+                    .into_iter()
+                    .map(|path| ImageLink { row, path }),
+            );
+        }
+    }
+    links
+}
+
+// コードブロックの区切りの行なら、区切りの記号と並んだ数を返す
+fn code_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let c = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let count = trimmed.chars().take_while(|x| *x == c).count();
+    (count >= 3).then_some((c, count))
+}
+
+fn line_image_paths(line: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("![") {
+        rest = &rest[start + 2..];
+        let Some(alt_end) = rest.find("](") else {
+            break;
+        };
+        let target = &rest[alt_end + 2..];
+        let Some(target_end) = target.find(')') else {
+            break;
+        };
+        let path = target[..target_end]
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches('<')
+            .trim_end_matches('>');
+        if !path.is_empty() {
+            paths.push(path.to_string());
+        }
+        rest = &target[target_end + 1..];
+    }
+    paths
+}
+
+/// リンクに書かれたパスを解決する。相対パスは base_dir からのパスとして扱う
+pub(crate) fn resolve_image_path(base_dir: Option<&Path>, path: &str) -> PathBuf {
+    let path = Path::new(path);
+    match base_dir {
+        Some(base_dir) if path.is_relative() => base_dir.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// 画像を描画に使うときのキー
+pub(crate) fn image_key(path: &Path) -> String {
+    format!("inline_image:{}", path.display())
+}
+
+/// 読み込んだ画像
+#[derive(Debug, Clone)]
+pub(crate) enum DecodedImage {
+    Svg { svg: String, aspect: f32 },
+    Raster(DynamicImage),
+}
+
+impl DecodedImage {
+    /// 幅に対する高さの比
+    pub(crate) fn aspect(&self) -> f32 {
+        match self {
+            DecodedImage::Svg { aspect, .. } => *aspect,
+            DecodedImage::Raster(image) => image.height() as f32 / image.width().max(1) as f32,
+        }
+    }
+}
+
+/// 画像を読み込む。読み込めない画像や対応していない形式は None を返す。
+/// ラスター画像はテクスチャにしたときに大きくなりすぎないよう、ここで縮小しておく
+pub(crate) fn decode_image(path: &Path) -> Option<DecodedImage> {
+    let is_svg = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+    if is_svg {
+        let svg = std::fs::read_to_string(path).ok()?;
+        let [width, height] = svg_size(&svg).ok()?;
+        if width <= 0.0 || height <= 0.0 {
+            return None;
+        }
+        Some(DecodedImage::Svg {
+            svg,
+            aspect: height / width,
+        })
+    } else {
+        let image = image::open(path).ok()?;
+        if image.width() == 0 || image.height() == 0 {
+            return None;
+        }
+        let image = if image.width() > RASTER_MAX_PIXELS || image.height() > RASTER_MAX_PIXELS {
+            image.thumbnail(RASTER_MAX_PIXELS, RASTER_MAX_PIXELS)
+        } else {
+            image
+        };
+        Some(DecodedImage::Raster(image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn test_image_links() {
+        let text = "# 画像\n![図](figure.svg) と ![写真](<photo.png> \"タイトル\")\n![](empty)\n[リンク](link.md)\n";
+        assert_eq!(
+            image_links(text),
+            vec![
+                ImageLink {
+                    row: 1,
+                    path: "figure.svg".to_string()
+                },
+                ImageLink {
+                    row: 1,
+                    path: "photo.png".to_string()
+                },
+                ImageLink {
+                    row: 2,
+                    path: "empty".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_links_skip_code() {
+        let text = "```md\n![コード](in_fence.png)\n~~~\n![まだコード](still.png)\n````\n![図](a.png) `![コード](inline.png)` ![図](b.png)\n    ```\n![インデントが深い区切りは無視](c.png)\n~~~\n```\n![閉じていない](d.png)\n";
+        assert_eq!(
+            image_links(text),
+            vec![
+                ImageLink {
+                    row: 5,
+                    path: "a.png".to_string()
+                },
+                ImageLink {
+                    row: 5,
+                    path: "b.png".to_string()
+                },
+                ImageLink {
+                    row: 7,
+                    path: "c.png".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_image_path() {
+        let base_dir = Path::new("/memo/dir");
+        assert_eq!(
+            resolve_image_path(Some(base_dir), "images/a.png"),
+            PathBuf::from("/memo/dir/images/a.png")
+        );
+        assert_eq!(
+            resolve_image_path(Some(base_dir), "/abs/a.png"),
+            PathBuf::from("/abs/a.png")
+        );
+        assert_eq!(
+            resolve_image_path(None, "images/a.png"),
+            PathBuf::from("images/a.png")
+        );
+    }
+
+    #[test]
+    fn test_decode_image() {
+        let dir = std::env::temp_dir().join(format!("inline_image_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let png = dir.join("wide.png");
+        RgbaImage::from_pixel(2048, 512, Rgba([255, 0, 0, 255]))
+            .save(&png)
+            .unwrap();
+        let Some(DecodedImage::Raster(image)) = decode_image(&png) else {
+            panic!("PNG を読み込めない");
+        };
+        assert_eq!((image.width(), image.height()), (1024, 256));
+
+        let svg = dir.join("tall.svg");
+        std::fs::write(
+            &svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="20"><rect width="10" height="20"/></svg>"#,
+        )
+        .unwrap();
+        assert_eq!(decode_image(&svg).map(|image| image.aspect()), Some(2.0));
+
+        assert!(decode_image(&dir.join("missing.png")).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,17 @@
 mod card;
+#[cfg(not(target_arch = "wasm32"))]
+mod char_appearance;
 mod file_chooser;
+mod fuzzy_match;
 mod ime_input;
+mod inline_image;
 mod select_option;
 mod selectbox;
+mod shaped_text;
 mod single_line;
 mod single_svg;
 mod stack_layout;
+mod stroke_hint;
 mod text_input;
 mod textedit;
 mod view_element_state;
@@ -20,9 +26,11 @@ pub use selectbox::SelectBox;
 pub use single_line::SingleLine;
 pub use single_svg::SingleSvg;
 pub use stack_layout::StackLayout;
+pub use stroke_hint::{StrokeHint, StrokeHintItem, StrokeHintOverlay};
 pub use text_input::TextInput;
 pub use textedit::TextEdit;
 
+use font_collector::FontStyle;
 use font_rasterizer::color_theme::{ColorTheme, ThemedColor};
 use text_buffer::{buffer::BufferChar, caret::CaretType, editor::ChangeEvent};
 
@@ -148,15 +156,31 @@ pub enum Decoration {
     Strikethrough,
 }
 
+impl Decoration {
+    /// 装飾に対応するフォントのスタイル
+    pub fn font_style(&self) -> FontStyle {
+        match self {
+            Decoration::Bold => FontStyle::BOLD,
+            Decoration::Italic => FontStyle::ITALIC,
+            Decoration::None | Decoration::Underline | Decoration::Strikethrough => {
+                FontStyle::REGULAR
+            }
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
 pub struct CharAttribute {
     pub color: ThemedColor,
     pub decoration: Decoration,
+    /// 文字を描くフォントのスタイル。フォントに無いスタイルは大きさや傾きで似せて表す
+    pub font_style: FontStyle,
 }
 
 pub const DEFAULT_CHAR_ATTRIBUTE: CharAttribute = CharAttribute {
     color: ThemedColor::Text,
     decoration: Decoration::None,
+    font_style: FontStyle::REGULAR,
 };
 
 impl Default for CharAttribute {
@@ -167,7 +191,15 @@ impl Default for CharAttribute {
 
 impl CharAttribute {
     pub fn new(color: ThemedColor, decoration: Decoration) -> Self {
-        Self { color, decoration }
+        Self {
+            color,
+            decoration,
+            font_style: decoration.font_style(),
+        }
+    }
+
+    pub fn with_font_style(self, font_style: FontStyle) -> Self {
+        Self { font_style, ..self }
     }
 }
 
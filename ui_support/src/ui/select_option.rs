@@ -1,29 +1,72 @@
 use stroke_parser::{Action, ActionArgument};
 
+use super::fuzzy_match::fuzzy_score_with_kana;
+
 pub struct SelectOption {
     pub(crate) text: String,
     pub(crate) actions: Vec<Action>,
+    /// 表示はしないが検索の対象にする文字列(読みがななど)
+    pub(crate) keywords: Vec<String>,
+    /// 選択肢に対応するキー操作の表示
+    pub(crate) key_hint: Option<String>,
+    /// 複数の Action を持つ場合も先頭の Action を表示する
+    pub(crate) shows_first_action: bool,
 }
 
 impl SelectOption {
     pub fn new(text: String, action: Action) -> Self {
+        Self::new_multiple(text, vec![action])
+    }
+
+    pub fn new_multiple(text: String, actions: Vec<Action>) -> Self {
         Self {
             text,
-            actions: vec![action],
+            actions,
+            keywords: Vec::new(),
+            key_hint: None,
+            shows_first_action: false,
         }
     }
 
-    pub fn new_multiple(text: String, actions: Vec<Action>) -> Self {
-        Self { text, actions }
+    /// action を表示し、選択したときは続けて trailing_actions も送る。
+    /// 履歴の記録など、表示する必要のない Action を後ろに続ける場合に使う
+    pub fn new_with_trailing_actions(
+        text: String,
+        action: Action,
+        trailing_actions: Vec<Action>,
+    ) -> Self {
+        let mut actions = vec![action];
+        actions.extend(trailing_actions);
+        Self {
+            shows_first_action: true,
+            ..Self::new_multiple(text, actions)
+        }
+    }
+
+    pub fn with_keywords(mut self, keywords: Vec<String>) -> Self {
+        self.keywords = keywords;
+        self
+    }
+
+    pub fn with_key_hint(mut self, key_hint: String) -> Self {
+        self.key_hint = Some(key_hint);
+        self
+    }
+
+    fn text_with_key_hint(&self) -> String {
+        match &self.key_hint {
+            Some(key_hint) => format!("{} [{}]", self.text, key_hint),
+            None => self.text.to_string(),
+        }
     }
 
     pub fn option_string_short(&self) -> String {
-        self.text.to_string()
+        self.text_with_key_hint()
     }
 
     pub fn option_string(&self, padding: usize) -> String {
-        if self.actions.len() == 1
-            && let Action::Command(namespace, name, arg) = &self.actions[0]
+        if (self.actions.len() == 1 || self.shows_first_action)
+            && let Some(Action::Command(namespace, name, arg)) = self.actions.first()
         {
             match arg {
                 ActionArgument::String(_)
@@ -35,7 +78,7 @@ impl SelectOption {
                 | ActionArgument::Point(_) => {
                     return format!(
                         "{} {padding}{}:{}({})",
-                        self.text,
+                        self.text_with_key_hint(),
                         **namespace,
                         **name,
                         arg,
@@ -45,7 +88,7 @@ impl SelectOption {
                 ActionArgument::None => {
                     return format!(
                         "{} {padding}{}:{}",
-                        self.text,
+                        self.text_with_key_hint(),
                         **namespace,
                         **name,
                         padding = " ".repeat(padding)
@@ -53,7 +96,7 @@ impl SelectOption {
                 }
             }
         }
-        self.text.to_string()
+        self.text_with_key_hint()
     }
 
     pub fn contains_all_for_short(&self, keywords: &[&str]) -> bool {
@@ -72,6 +115,25 @@ impl SelectOption {
             .map(|keyword| keyword.to_lowercase())
             .all(|keyword| text.contains(&keyword))
     }
+
+    pub fn fuzzy_score_for_short(&self, keywords: &[&str]) -> Option<i32> {
+        self.inner_fuzzy_score(self.option_string_short(), keywords)
+    }
+
+    /// 全てのキーワードがあいまいに一致する場合にスコアの合計を返す
+    pub fn fuzzy_score(&self, keywords: &[&str]) -> Option<i32> {
+        self.inner_fuzzy_score(self.option_string(0), keywords)
+    }
+
+    fn inner_fuzzy_score(&self, text: String, keywords: &[&str]) -> Option<i32> {
+        keywords.iter().try_fold(0, |total, keyword| {
+            std::iter::once(&text)
+                .chain(self.keywords.iter())
+                .filter_map(|target| fuzzy_score_with_kana(target, keyword))
+                .max()
+                .map(|score| total + score)
+        })
+    }
 }
 
 #[cfg(test)]
@@ -97,4 +159,42 @@ mod test {
         assert!(!option.contains_all_for_short(&["namespace:name"]));
         assert!(!option.contains_all_for_short(&["name"]));
     }
+
+    #[test]
+    fn test_key_hint() {
+        let option = SelectOption::new("改行".to_string(), Action::new_command("edit", "return"))
+            .with_key_hint("Return, C-M".to_string());
+        assert_eq!(option.option_string_short(), "改行 [Return, C-M]");
+        assert_eq!(option.option_string(1), "改行 [Return, C-M]  edit:return");
+        assert!(option.contains_all(&["c-m"]));
+    }
+
+    #[test]
+    fn test_option_string_multiple_actions() {
+        let actions = vec![
+            Action::new_command("edit", "copy"),
+            Action::new_command("edit", "paste"),
+        ];
+        let option = SelectOption::new_multiple("複製".to_string(), actions);
+        assert_eq!(option.option_string(1), "複製");
+
+        let option = SelectOption::new_with_trailing_actions(
+            "コピー".to_string(),
+            Action::new_command("edit", "copy"),
+            vec![Action::new_command("kashikishi", "record-command-history")],
+        );
+        assert_eq!(option.option_string(1), "コピー  edit:copy");
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        let option = SelectOption::new("改行".to_string(), Action::new_command("edit", "return"))
+            .with_keywords(vec!["かいぎょう".to_string()]);
+        assert!(option.fuzzy_score(&["kaigyou"]).is_some());
+        assert!(option.fuzzy_score(&["edit", "rtn"]).is_some());
+        assert!(option.fuzzy_score(&["kaigyou", "copy"]).is_none());
+        // short の場合は Action の名前は検索対象にならない
+        assert!(option.fuzzy_score_for_short(&["rtn"]).is_none());
+        assert!(option.fuzzy_score_for_short(&["kai"]).is_some());
+    }
 }
@@ -39,6 +39,7 @@ pub struct SelectBox {
     char_width_calcurator: Arc<CharWidthCalculator>,
     show_action_name: bool,
     cancellable: bool,
+    fuzzy_match: bool,
     max_line: usize,
 }
 
@@ -76,6 +77,15 @@ impl SelectBox {
         }
     }
 
+    /// 絞り込みをあいまい検索にして、一致度の高い順に並べ替える。
+    /// 一致度が同じ場合は options の順序を保つ。
+    pub fn with_fuzzy_match(mut self) -> Self {
+        self.fuzzy_match = true;
+        self.update_select_items_text_edit();
+        self.update_current_selection();
+        self
+    }
+
     fn inner_new(
         context: &UiContext,
         message: String,
@@ -118,6 +128,7 @@ impl SelectBox {
             char_width_calcurator: context.char_width_calcurator().clone(),
             show_action_name,
             cancellable,
+            fuzzy_match: false,
             max_line: 10,
         };
         result.update_select_items_text_edit();
@@ -148,6 +159,23 @@ impl SelectBox {
         if search_keywords.is_empty() {
             return self.options.iter().collect::<Vec<_>>();
         }
+        if self.fuzzy_match {
+            let mut scored = self
+                .options
+                .iter()
+                .filter_map(|op| {
+                    let score = if self.show_action_name {
+                        op.fuzzy_score(&search_keywords)
+                    } else {
+                        op.fuzzy_score_for_short(&search_keywords)
+                    };
+                    score.map(|score| (score, op))
+                })
+                .collect::<Vec<_>>();
+            // sort_by は安定ソートなので同じスコアの場合は元の順序が保たれる
+            scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+            return scored.into_iter().map(|(_, op)| op).collect::<Vec<_>>();
+        }
         self.options
             .iter()
             .filter(|op| {
@@ -494,6 +522,7 @@ impl Model for SelectBox {
                 narrowed_option_count: self.narrowd_options().len(),
                 show_action_name: self.show_action_name,
                 cancellable: self.cancellable,
+                fuzzy_match: self.fuzzy_match,
                 max_line: self.max_line,
             }),
             camera,
@@ -0,0 +1,249 @@
+//! TextEdit の行をまとめてシェーピングし、文字を単独で表示するときと字形が変わるところを求める。
+//!
+//! 合字のように複数の文字をひとつのグリフで表すところは、先頭の文字の位置にグリフを描き、
+//! 残りの文字は描かない。グリフはクラスタの文字の中央を基準に、シェーピングの結果のずらし幅で置く。
+//! 太字などのスタイルを指定した文字も、通常とは別のグリフとして差し替える。
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Range,
+    sync::Arc,
+};
+
+use font_collector::FontStyle;
+use font_rasterizer::{
+    glyph_vertex_buffer::Direction,
+    shaping::{GlyphKey, GlyphShaper, ShapedGlyph},
+};
+use text_buffer::buffer::{BufferChar, CellPosition};
+
+use crate::text_instances::GlyphOverride;
+
+/// シェーピングで字形や位置が変わる文字
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ShapedCell {
+    pub(crate) glyph: GlyphOverride,
+    /// 同じクラスタの文字の列の範囲
+    pub(crate) cluster: Range<usize>,
+    /// クラスタの中央からのずらし幅。行の進む方向と、それに直交する方向の順で em を 1 とした値
+    pub(crate) offset: [f32; 2],
+}
+
+#[derive(Default)]
+pub(crate) struct ShapedText {
+    shaper: Option<Arc<GlyphShaper>>,
+    direction: Direction,
+    // 行の文字列と文字ごとのスタイルに対するシェーピングの結果
+    lines: HashMap<(String, Vec<FontStyle>), Vec<ShapedGlyph>>,
+    cells: BTreeMap<CellPosition, ShapedCell>,
+}
+
+impl ShapedText {
+    /// シェーピングに使うフォントが変わったか
+    pub(crate) fn is_shaper_changed(&self, shaper: &Arc<GlyphShaper>) -> bool {
+        !self
+            .shaper
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, shaper))
+    }
+
+    /// 行をシェーピングし直す。font_styles は通常とは違うスタイルの文字のスタイル。
+    /// 字形や位置が変わる文字が変わった場合は true を返す
+    pub(crate) fn update(
+        &mut self,
+        lines: &[Vec<BufferChar>],
+        font_styles: &BTreeMap<CellPosition, FontStyle>,
+        shaper: &Arc<GlyphShaper>,
+        direction: Direction,
+    ) -> bool {
+        if self.is_shaper_changed(shaper) || self.direction != direction {
+            // フォントが変わるとグリフを登録し直す必要があるので、すべて変わったものとして扱う
+            self.shaper = Some(shaper.clone());
+            self.direction = direction;
+            self.lines.clear();
+            self.cells.clear();
+        }
+
+        let mut shaped_lines = HashMap::new();
+        let mut cells = BTreeMap::new();
+        for line in lines {
+            let chars: Vec<char> = line.iter().map(|c| c.c).collect();
+            let text: String = chars.iter().collect();
+            let styles: Vec<FontStyle> = line
+                .iter()
+                .map(|c| font_styles.get(&c.position).copied().unwrap_or_default())
+                .collect();
+            let key = (text, styles);
+            let glyphs = self.lines.remove(&key).unwrap_or_else(|| {
+                let (text, styles) = &key;
+                shaper.shape_with_styles(text, direction, styles)
+            });
+            for (index, cell) in
+                shaped_cells(&chars, &glyphs, |c| shaper.nominal_glyph(c, direction))
+            {
+                cells.insert(line[index].position, cell);
+            }
+            shaped_lines.insert(key, glyphs);
+        }
+        self.lines = shaped_lines;
+
+        let changed = self.cells != cells;
+        self.cells = cells;
+        changed
+    }
+
+    pub(crate) fn cell(&self, position: &CellPosition) -> Option<&ShapedCell> {
+        self.cells.get(position)
+    }
+
+    /// 文字の代わりに描くグリフ
+    pub(crate) fn glyphs(&self) -> Vec<GlyphKey> {
+        self.cells
+            .values()
+            .filter_map(|cell| match cell.glyph {
+                GlyphOverride::Glyph(key) => Some(key),
+                GlyphOverride::Hidden => None,
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub(crate) fn glyph_overrides(&self) -> BTreeMap<CellPosition, GlyphOverride> {
+        self.cells
+            .iter()
+            .map(|(position, cell)| (*position, cell.glyph))
+            .collect()
+    }
+}
+
+/// 行の文字と、そのシェーピングの結果から、字形や位置が変わる文字を求める
+pub(crate) fn shaped_cells(
+    chars: &[char],
+    glyphs: &[ShapedGlyph],
+    nominal_glyph: impl Fn(char) -> Option<GlyphKey>,
+) -> Vec<(usize, ShapedCell)> {
+    // クラスタごとにグリフをまとめる
+    let mut clusters: Vec<(Range<usize>, Vec<&ShapedGlyph>)> = Vec::new();
+    for glyph in glyphs {
+        match clusters.iter_mut().find(|(range, _)| *range == glyph.chars) {
+            Some((_, cluster_glyphs)) => cluster_glyphs.push(glyph),
+            None => clusters.push((glyph.chars.clone(), vec![glyph])),
+        }
+    }
+
+    let mut cells = Vec::new();
+    for (range, cluster_glyphs) in clusters {
+        // 表示できるグリフの無いクラスタは文字ごとの表示のままにする
+        if range.end > chars.len() || cluster_glyphs.iter().any(|glyph| glyph.key.glyph_id == 0) {
+            continue;
+        }
+        if let ([glyph], 1) = (cluster_glyphs.as_slice(), range.len())
+            && Some(glyph.key) == nominal_glyph(chars[range.start])
+            && glyph.offset == [0.0, 0.0]
+        {
+            continue;
+        }
+
+        // グリフの中心の位置を、クラスタの中央からの距離で表す
+        let total: f32 = cluster_glyphs.iter().map(|glyph| glyph.advance).sum();
+        let mut pen = 0.0;
+        for (index, col) in range.clone().enumerate() {
+            let cell = match cluster_glyphs.get(index) {
+                Some(glyph) => {
+                    let [along, cross] = glyph.offset;
+                    let offset = [pen + along + glyph.advance / 2.0 - total / 2.0, cross];
+                    pen += glyph.advance;
+                    ShapedCell {
+                        glyph: GlyphOverride::Glyph(glyph.key),
+                        cluster: range.clone(),
+                        offset,
+                    }
+                }
+                None => ShapedCell {
+                    glyph: GlyphOverride::Hidden,
+                    cluster: range.clone(),
+                    offset: [0.0, 0.0],
+                },
+            };
+            cells.push((col, cell));
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(glyph_id: u16) -> GlyphKey {
+        GlyphKey {
+            font_index: 0,
+            glyph_id,
+            style: FontStyle::REGULAR,
+        }
+    }
+
+    fn glyph(glyph_id: u16, chars: Range<usize>, advance: f32) -> ShapedGlyph {
+        ShapedGlyph {
+            key: key(glyph_id),
+            chars,
+            advance,
+            offset: [0.0, 0.0],
+        }
+    }
+
+    #[test]
+    fn test_shaped_cells() {
+        // "office" の "ffi" が合字になり、"e" は通常のグリフのまま
+        let chars: Vec<char> = "office".chars().collect();
+        let glyphs = vec![
+            glyph(1, 0..1, 0.6),
+            glyph(100, 1..4, 0.9),
+            glyph(3, 4..5, 0.5),
+            glyph(4, 5..6, 0.5),
+        ];
+        let nominal = |c: char| match c {
+            'o' => Some(key(1)),
+            'c' => Some(key(3)),
+            'e' => Some(key(4)),
+            _ => Some(key(2)),
+        };
+        let cells = shaped_cells(&chars, &glyphs, nominal);
+        assert_eq!(
+            cells,
+            vec![
+                (
+                    1,
+                    ShapedCell {
+                        glyph: GlyphOverride::Glyph(key(100)),
+                        cluster: 1..4,
+                        offset: [0.0, 0.0],
+                    }
+                ),
+                (
+                    2,
+                    ShapedCell {
+                        glyph: GlyphOverride::Hidden,
+                        cluster: 1..4,
+                        offset: [0.0, 0.0],
+                    }
+                ),
+                (
+                    3,
+                    ShapedCell {
+                        glyph: GlyphOverride::Hidden,
+                        cluster: 1..4,
+                        offset: [0.0, 0.0],
+                    }
+                ),
+            ]
+        );
+
+        // 基底の文字と結合文字がひとつのクラスタで別々のグリフになる場合は、進み幅で並べる
+        let chars: Vec<char> = "a\u{0301}".chars().collect();
+        let glyphs = vec![glyph(1, 0..2, 0.5), glyph(2, 0..2, 0.0)];
+        let cells = shaped_cells(&chars, &glyphs, |_| None);
+        assert_eq!(cells[0].1.offset, [0.0, 0.0]);
+        assert_eq!(cells[1].1.offset, [0.25, 0.0]);
+    }
+}
@@ -139,6 +139,13 @@ impl Model for StackLayout {
             .collect()
     }
 
+    fn image_instances(&self) -> Vec<&font_rasterizer::image_instances::ImageInstances> {
+        self.models
+            .iter()
+            .flat_map(|model| model.image_instances())
+            .collect()
+    }
+
     fn update(&mut self, context: &crate::ui_context::UiContext) {
         let position: glam::Vec3 = self.last_position();
         let layout_bound: (f32, f32) = self.bound();
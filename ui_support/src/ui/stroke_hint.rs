@@ -0,0 +1,267 @@
+use glam::{Quat, Vec3};
+use text_buffer::action::EditorOperation;
+
+use font_rasterizer::{
+    glyph_instances::GlyphInstances, time::now_millis, vector_instances::VectorInstances,
+};
+
+use crate::camera::CameraAdjustment;
+use crate::editor_settings::EditorTextContextProfile;
+use crate::layout_engine::{
+    DebugModelDetails, DebugModelNode, Model, ModelBorder, ModelOperation, ModelOperationResult,
+    World,
+};
+use crate::ui::StackLayout;
+use crate::ui_context::{CharEasingsPreset, UiContext};
+
+use super::textedit::TextEdit;
+
+const TITLE_TEXT_INDEX: usize = 0;
+const ITEMS_TEXT_INDEX: usize = 1;
+
+/// 入力途中のストロークに続けて入力できるキーと、その説明
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrokeHintItem {
+    pub keys: String,
+    pub description: String,
+}
+
+impl StrokeHintItem {
+    pub fn new(keys: String, description: String) -> Self {
+        Self { keys, description }
+    }
+}
+
+/// 入力途中のストロークの続きを一覧表示するモーダル。
+/// 表示するだけで入力は受け付けない。
+pub struct StrokeHint {
+    layout: StackLayout,
+}
+
+impl StrokeHint {
+    pub fn new(context: &UiContext, stroke: &str, items: &[StrokeHintItem]) -> Self {
+        let mut layout = StackLayout::new(context.global_direction());
+
+        let title_text_edit = {
+            let mut text_edit =
+                TextEdit::new(context.text_context(EditorTextContextProfile::ModalLabel));
+            text_edit.editor_operation(&EditorOperation::InsertString(format!("{} -", stroke)));
+            text_edit
+        };
+        layout.add_model(Box::new(title_text_edit));
+
+        let items_text_edit = {
+            let char_width_calcurator = context.char_width_calcurator();
+            let max_keys_len = items
+                .iter()
+                .map(|item| char_width_calcurator.len(&item.keys))
+                .max()
+                .unwrap_or(0);
+            let text = items
+                .iter()
+                .map(|item| {
+                    format!(
+                        "{}{padding}  {}",
+                        item.keys,
+                        item.description,
+                        padding = " ".repeat(max_keys_len - char_width_calcurator.len(&item.keys))
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let mut text_edit =
+                TextEdit::new(context.text_context(EditorTextContextProfile::SelectBoxItem));
+            text_edit.editor_operation(&EditorOperation::InsertString(text));
+            text_edit.editor_operation(&EditorOperation::BufferHead);
+            text_edit
+        };
+        layout.add_model(Box::new(items_text_edit));
+        layout.set_focus_model_index(TITLE_TEXT_INDEX, false);
+
+        Self { layout }
+    }
+
+    fn title_text_edit(&self) -> &dyn Model {
+        self.layout.models()[TITLE_TEXT_INDEX].as_ref()
+    }
+
+    fn items_text_edit(&self) -> &dyn Model {
+        self.layout.models()[ITEMS_TEXT_INDEX].as_ref()
+    }
+}
+
+impl Model for StrokeHint {
+    fn set_position(&mut self, position: Vec3) {
+        self.layout.set_position(position);
+    }
+
+    fn position(&self) -> Vec3 {
+        self.layout.position()
+    }
+
+    fn last_position(&self) -> Vec3 {
+        self.layout.last_position()
+    }
+
+    fn focus_position(&self) -> Vec3 {
+        self.layout.focus_position()
+    }
+
+    fn set_rotation(&mut self, rotation: Quat) {
+        self.layout.set_rotation(rotation);
+    }
+
+    fn rotation(&self) -> Quat {
+        self.layout.rotation()
+    }
+
+    fn bound(&self) -> (f32, f32) {
+        self.layout.bound()
+    }
+
+    fn glyph_instances(&self) -> Vec<&GlyphInstances> {
+        self.layout.glyph_instances()
+    }
+
+    fn vector_instances(&self) -> Vec<&VectorInstances<String>> {
+        self.layout.vector_instances()
+    }
+
+    fn update(&mut self, context: &UiContext) {
+        self.layout.update(context);
+    }
+
+    // ストロークの入力中はキー入力が ActionStore で止まるので、ここには何も来ない想定
+    fn editor_operation(&mut self, _op: &EditorOperation) {}
+
+    fn model_operation(&mut self, op: &ModelOperation) -> ModelOperationResult {
+        self.layout.model_operation(op)
+    }
+
+    fn to_string(&self) -> String {
+        [
+            self.title_text_edit().to_string(),
+            self.items_text_edit().to_string(),
+        ]
+        .concat()
+    }
+
+    fn in_animation(&self) -> bool {
+        self.layout.in_animation()
+    }
+
+    fn set_border(&mut self, border: ModelBorder) {
+        self.layout.set_border(border);
+    }
+
+    fn border(&self) -> ModelBorder {
+        self.layout.border()
+    }
+
+    fn set_easing_preset(&mut self, preset: CharEasingsPreset) {
+        self.layout.set_easing_preset(preset);
+    }
+
+    fn debug_node(&self, camera: &crate::camera::Camera) -> DebugModelNode {
+        let position = self.position().to_array();
+        let last_position = self.last_position().to_array();
+        let focus_position = self.focus_position().to_array();
+        let rotation = self.rotation().to_array();
+        let bound: [f32; 2] = self.bound().into();
+        DebugModelNode::new(
+            "StrokeHint",
+            self.border(),
+            position,
+            last_position,
+            focus_position,
+            rotation,
+            bound,
+            bound,
+            self.in_animation(),
+            vec![self.layout.debug_node(camera)],
+            DebugModelDetails::None,
+            camera,
+        )
+    }
+}
+
+struct PendingStroke {
+    stroke: String,
+    items: Vec<StrokeHintItem>,
+    since: u32,
+    // 直前のストロークでヒントを表示していた場合は待たずに表示する
+    immediate: bool,
+}
+
+/// 複数キーのストロークの入力途中に、一定時間が経過したら StrokeHint をモーダルとして表示する。
+///
+/// ```ignore
+/// // 入力のたびに ActionStore の状態を伝える
+/// overlay.set_pending(pending, world);
+/// // 毎フレーム呼び出すと、待ち時間を過ぎた時点でモーダルを追加する
+/// overlay.update(context, world);
+/// ```
+pub struct StrokeHintOverlay {
+    delay_millis: u32,
+    pending: Option<PendingStroke>,
+    shown: bool,
+}
+
+impl StrokeHintOverlay {
+    pub fn new(delay_millis: u32) -> Self {
+        Self {
+            delay_millis,
+            pending: None,
+            shown: false,
+        }
+    }
+
+    /// 入力途中のストロークとその続きを設定する。ストロークが終わった場合は None を渡す。
+    ///
+    /// 表示中のヒントがあれば取り除く。表示中のヒントは常にモーダルの最前面にある前提で
+    /// World::remove_current で取り除くので、表示中に他のモーダルを追加しないこと。
+    pub fn set_pending(
+        &mut self,
+        pending: Option<(String, Vec<StrokeHintItem>)>,
+        world: &mut dyn World,
+    ) {
+        let current_stroke = self.pending.as_ref().map(|p| p.stroke.as_str());
+        let next_stroke = pending.as_ref().map(|(stroke, _)| stroke.as_str());
+        if current_stroke == next_stroke {
+            return;
+        }
+
+        let was_shown = self.shown;
+        if self.shown {
+            world.remove_current();
+            self.shown = false;
+        }
+        self.pending = pending
+            .filter(|(_, items)| !items.is_empty())
+            .map(|(stroke, items)| PendingStroke {
+                stroke,
+                items,
+                since: now_millis(),
+                immediate: was_shown,
+            });
+    }
+
+    pub fn update(&mut self, context: &UiContext, world: &mut dyn World) {
+        if self.shown {
+            return;
+        }
+        let Some(pending) = self.pending.as_ref() else {
+            return;
+        };
+        if !pending.immediate && now_millis().saturating_sub(pending.since) < self.delay_millis {
+            return;
+        }
+
+        let modal = StrokeHint::new(context, &pending.stroke, &pending.items);
+        context.register_string(modal.to_string());
+        world.add_modal(Box::new(modal));
+        world.re_layout();
+        world.look_modal(CameraAdjustment::FitBoth);
+        self.shown = true;
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::BTreeMap,
     ops::Range,
     sync::{
         Arc,
@@ -6,6 +7,7 @@ use std::{
     },
 };
 
+use font_collector::FontStyle;
 use glam::{Quat, Vec3};
 use phisical_layouter::{
     CharWidthResolver, PhysicalLayout, calc_phisical_layout as calc_editor_layout,
@@ -22,10 +24,12 @@ use font_rasterizer::{
     color_theme::{ColorTheme, ThemedColor},
     glyph_instances::GlyphInstances,
     glyph_vertex_buffer::Direction,
+    image_instances::ImageInstances,
     vector_instances::VectorInstances,
 };
 
 use crate::{
+    text_instances::CharOverlay,
     ui::{
         BulkedChangeEvent, SortOrder, bulk_change_events, detect_sort_order, split_preedit_string,
     },
@@ -38,13 +42,27 @@ use crate::{
         DebugModelDetails, DebugModelNode, DebugTextEditSnapshot, Model, ModelAttributes,
         ModelBorder, ModelOperation, ModelOperationResult,
     },
-    ui::{CharAttribute, Decoration},
+    ui::CharAttribute,
     ui_context::{HighlightMode, TextContext},
 };
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::layout_engine::SectionOperation;
+
 use super::{
     caret_char,
-    view_element_state::{BorderStates, CaretStates, CharStates, ViewElementStateUpdateRequest},
+    inline_image::image_links,
+    shaped_text::ShapedText,
+    view_element_state::{
+        BorderStates, CaretStates, CharStates, ImageStates, OverlayStates,
+        ViewElementStateUpdateRequest,
+    },
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use super::char_appearance::{
+    CharAppearance, markdown_preview_appearances, reserve_scaled_rows, row_scales,
+    scaled_physical_rows,
 };
 
 // IME プレエディットの状態
@@ -89,6 +107,9 @@ pub struct TextEdit {
     char_states: CharStates,
     caret_states: CaretStates,
     border_states: Option<BorderStates>,
+    overlay_states: OverlayStates,
+    image_states: ImageStates,
+    shaped_text: ShapedText,
 
     preedit: Option<PreeditState>,
 
@@ -97,6 +118,21 @@ pub struct TextEdit {
     // テキストが更新されたかどうか。ハイライトの再計算に使う
     text_updated: bool,
     config_updated: bool,
+    // 前回のハイライト以降に文字が追加・移動・削除された行(変更前と変更後の行を合わせた範囲)
+    highlight_changed_rows: Option<Range<usize>>,
+    // 前回の構文木を持ち、変更された部分だけハイライトを計算し直す
+    #[cfg(not(target_arch = "wasm32"))]
+    highlighter: Option<highlighter::Highlighter>,
+    // Markdown のライブプレビューで記法の文字を表示している行
+    #[cfg(not(target_arch = "wasm32"))]
+    preview_caret_row: Option<usize>,
+    // キャレットが出入りしたので、ライブプレビューの見た目を計算し直す行
+    #[cfg(not(target_arch = "wasm32"))]
+    preview_dirty_rows: Vec<usize>,
+    // 見出しのように大きく描く行ごとの倍率
+    row_scales: BTreeMap<usize, f32>,
+    // 大きく描く文字のある物理行と、その下に空けた行の数
+    scaled_rows: BTreeMap<usize, usize>,
 
     position: EasingPointN<3>,
     rotation: EasingPointN<4>,
@@ -185,9 +221,15 @@ impl Model for TextEdit {
         if let Some(states) = self.border_states.as_ref() {
             result.extend(states.instances.to_instances());
         }
+        result.extend(self.overlay_states.instances.to_instances());
+        result.extend(self.image_states.to_instances());
         result
     }
 
+    fn image_instances(&self) -> Vec<&ImageInstances> {
+        self.image_states.to_image_instances()
+    }
+
     fn update(&mut self, context: &UiContext) {
         if self.border != ModelBorder::None && self.border_states.is_none() {
             // border が None 以外のときは border_states を初期化する
@@ -203,10 +245,80 @@ impl Model for TextEdit {
             self.config.color_theme = *color_theme;
             self.char_states.update_char_theme(color_theme);
             self.config_updated = true;
+            // テーマごとにハイライトの色を変えられるので、すべて計算し直す
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                self.highlighter = None;
+            }
+            self.text_updated = true;
         }
 
         self.sync_editor_events(device, color_theme);
 
+        // ライブプレビューではキャレットのある行だけ記法の文字を表示するので、行が変わったら表示し直す
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.config.highlight_mode == HighlightMode::MarkdownPreview {
+            let caret_row = self
+                .caret_states
+                .main_caret_logical_position()
+                .map(|[row, _]| row);
+            if self.preview_caret_row != caret_row {
+                self.preview_dirty_rows
+                    .extend(self.preview_caret_row.iter().chain(caret_row.iter()));
+                self.preview_caret_row = caret_row;
+                self.text_updated = true;
+            }
+        }
+
+        // Markdown の画像のリンクの下に画像を表示する。画像が変わると行の位置が変わるのでレイアウトし直す
+        if self.image_states.receive_decoded_images(context) {
+            self.buffer_updated = true;
+        }
+        if self.text_updated {
+            let links = match self.config.highlight_mode {
+                HighlightMode::Markdown | HighlightMode::MarkdownPreview => {
+                    image_links(&self.editor.to_buffer_string())
+                }
+                HighlightMode::None | HighlightMode::Language(_) => Vec::new(),
+            };
+            if self.image_states.sync(links, context) {
+                self.buffer_updated = true;
+            }
+        }
+
+        // 太字などのフォントのスタイルはシェーピングに使うので、ハイライトを先に済ませる
+        if self.text_updated {
+            self.highlight(context);
+        }
+
+        // 行をまとめてシェーピングし、合字やフォントのスタイルで字形や位置が変わる文字のグリフを差し替える
+        let glyph_shaper = context.glyph_shaper();
+        if self.text_updated
+            || self.config_updated
+            || self.shaped_text.is_shaper_changed(glyph_shaper)
+        {
+            let lines = self.editor.buffer_chars();
+            if self.shaped_text.update(
+                &lines,
+                &self.char_states.font_styles(),
+                glyph_shaper,
+                self.config.direction,
+            ) {
+                context.register_glyphs(self.shaped_text.glyphs());
+                self.char_states.instances.set_glyph_overrides(
+                    self.shaped_text.glyph_overrides(),
+                    |position| {
+                        lines
+                            .get(position.row)
+                            .and_then(|line| line.get(position.col))
+                            .map(|c| c.c)
+                    },
+                    device,
+                );
+                self.buffer_updated = true;
+            }
+        }
+
         if self.buffer_updated || self.config_updated {
             let layout = self.calc_phisical_layout(context.char_width_calcurator().clone());
             let (preedit_chars, has_selection) = self.collect_preedit_chars(&layout);
@@ -229,8 +341,13 @@ impl Model for TextEdit {
                 &preedit_chars,
             );
         }
-        if self.text_updated {
-            self.highlight();
+        if self.buffer_updated || self.config_updated || self.text_updated {
+            self.overlay_states.sync(
+                &self.char_states.overlays(),
+                context.char_width_calcurator(),
+                &self.config,
+                device,
+            );
         }
         self.calc_instance_positions(context.char_width_calcurator());
         self.char_states.instances.update(device, queue);
@@ -238,6 +355,8 @@ impl Model for TextEdit {
         if let Some(state) = self.border_states.as_mut() {
             state.instances.update(device, queue);
         }
+        self.overlay_states.instances.update(device, queue);
+        self.image_states.update(device, queue);
 
         self.buffer_updated = false;
         self.text_updated = false;
@@ -374,6 +493,10 @@ impl Model for TextEdit {
             }
             ModelOperation::SetHighlightMode(highlight_mode) => {
                 self.config.highlight_mode = highlight_mode.clone();
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    self.highlighter = None;
+                }
                 // バッファを更新したわけではないがハイライトが変わるため text_updated を true にする
                 self.text_updated = true;
                 ModelOperationResult::RequireReLayout
@@ -381,15 +504,36 @@ impl Model for TextEdit {
             ModelOperation::ToggleHighlightMode => {
                 self.config.highlight_mode = match self.config.highlight_mode {
                     HighlightMode::None => HighlightMode::Markdown,
-                    HighlightMode::Markdown | HighlightMode::Language(_) => {
+                    HighlightMode::Markdown
+                    | HighlightMode::MarkdownPreview
+                    | HighlightMode::Language(_) => {
                         self.reset_highlight();
                         HighlightMode::None
                     }
                 };
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    self.highlighter = None;
+                }
                 // バッファを更新したわけではないがハイライトが変わるため text_updated を true にする
                 self.text_updated = true;
                 ModelOperationResult::RequireReLayout
             }
+            ModelOperation::ToggleMarkdownPreview => {
+                // プレビューの大きさや装飾を残さないよう、一度ハイライトを戻してから切り替える
+                self.reset_highlight();
+                self.config.highlight_mode = match self.config.highlight_mode {
+                    HighlightMode::MarkdownPreview => HighlightMode::Markdown,
+                    _ => HighlightMode::MarkdownPreview,
+                };
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    self.highlighter = None;
+                    self.preview_caret_row = None;
+                }
+                self.text_updated = true;
+                ModelOperationResult::RequireReLayout
+            }
             ModelOperation::SetPreedit(opt) => {
                 self.preedit = opt
                     .clone()
@@ -398,6 +542,19 @@ impl Model for TextEdit {
                 self.buffer_updated = true;
                 ModelOperationResult::RequireReLayout
             }
+            ModelOperation::SetBaseDir(base_dir) => {
+                self.image_states.set_base_dir(base_dir.clone());
+                // 画像のパスが変わるので読み込み直す
+                self.text_updated = true;
+                ModelOperationResult::RequireReLayout
+            }
+            ModelOperation::EditSection(section_operation) => {
+                #[cfg(not(target_arch = "wasm32"))]
+                self.edit_section(*section_operation);
+                #[cfg(target_arch = "wasm32")]
+                let _ = section_operation;
+                ModelOperationResult::RequireReLayout
+            }
         }
     }
 
@@ -488,12 +645,24 @@ impl TextEdit {
             char_states: CharStates::default(),
             caret_states: CaretStates::default(),
             border_states: None,
+            overlay_states: OverlayStates::default(),
+            image_states: ImageStates::default(),
+            shaped_text: ShapedText::default(),
 
             preedit: None,
 
             buffer_updated: true,
             text_updated: true,
             config_updated: true,
+            highlight_changed_rows: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            highlighter: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            preview_caret_row: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            preview_dirty_rows: Vec::new(),
+            row_scales: BTreeMap::new(),
+            scaled_rows: BTreeMap::new(),
 
             position,
             rotation,
@@ -515,6 +684,7 @@ impl TextEdit {
         match self.config.highlight_mode {
             HighlightMode::None => "none",
             HighlightMode::Markdown => "markdown",
+            HighlightMode::MarkdownPreview => "markdown-preview",
             HighlightMode::Language(_) => "language",
         }
     }
@@ -524,16 +694,15 @@ impl TextEdit {
         move_operation: PhysicalMoveOperation,
         width_resolver: Arc<dyn CharWidthResolver>,
     ) {
-        let layout = calc_editor_layout(
-            &self.editor,
-            self.max_display_width(),
-            &self.config.line_prohibited_chars,
-            width_resolver.clone(),
-            self.preedit.as_ref().map(|p| p.preedit_string()),
-        );
+        let layout = self.calc_phisical_layout(width_resolver.clone());
 
-        let Some(target) = self.find_target_position_in_physical_layout(
+        let Some(logical_position) = self.caret_states.main_caret_logical_position() else {
+            return;
+        };
+        let Some(target) = find_target_position_in_physical_layout(
             &layout,
+            &self.editor.buffer_chars(),
+            logical_position,
             width_resolver.as_ref(),
             move_operation,
         ) else {
@@ -547,78 +716,34 @@ impl TextEdit {
         self.buffer_updated = true;
     }
 
-    fn find_target_position_in_physical_layout(
-        &self,
-        layout: &PhysicalLayout,
-        width_resolver: &dyn CharWidthResolver,
-        move_operation: PhysicalMoveOperation,
-    ) -> Option<CellPosition> {
-        let [logical_row, logical_col] = self.caret_states.main_caret_logical_position()?;
-        let current_physical_pos = layout.main_caret_pos;
-        let target_row = match move_operation {
-            PhysicalMoveOperation::Previous => current_physical_pos.row.checked_sub(1),
-            PhysicalMoveOperation::Next => Some(current_physical_pos.row + 1),
-        }?;
-
-        let row_candidates = self.collect_row_candidates(layout, width_resolver, target_row);
-        if row_candidates.is_empty() {
-            return self.find_fallback_position(move_operation, logical_row, logical_col);
-        }
-
-        match move_operation {
-            PhysicalMoveOperation::Previous | PhysicalMoveOperation::Next => row_candidates
-                .iter()
-                .min_by_key(|(_, col)| col.abs_diff(current_physical_pos.col))
-                .map(|(position, _)| *position),
-        }
-    }
-
-    fn collect_row_candidates(
-        &self,
-        layout: &PhysicalLayout,
-        width_resolver: &dyn CharWidthResolver,
-        target_row: usize,
-    ) -> Vec<(CellPosition, usize)> {
-        let mut candidates = Vec::new();
-        for (buffer_char, pos) in layout.chars.iter() {
-            if pos.row != target_row {
-                continue;
-            }
-            candidates.push((buffer_char.position, pos.col));
-            let char_width = width_resolver.resolve_width(buffer_char.c);
-            candidates.push((
-                CellPosition {
-                    row: buffer_char.position.row,
-                    col: buffer_char.position.col + 1,
-                },
-                pos.col + char_width,
-            ));
-        }
-        candidates
-    }
-
-    fn find_fallback_position(
-        &self,
-        move_operation: PhysicalMoveOperation,
-        logical_row: usize,
-        logical_col: usize,
-    ) -> Option<CellPosition> {
-        let lines = self.editor.buffer_chars();
-        match move_operation {
-            PhysicalMoveOperation::Previous => logical_row.checked_sub(1).and_then(|row| {
-                lines.get(row).map(|line| CellPosition {
-                    row,
-                    col: logical_col.min(line.len()),
-                })
-            }),
-            PhysicalMoveOperation::Next => {
-                let row = logical_row + 1;
-                lines.get(row).map(|line| CellPosition {
-                    row,
-                    col: logical_col.min(line.len()),
-                })
-            }
-        }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn edit_section(&mut self, section_operation: SectionOperation) {
+        let text = self.editor.to_buffer_string();
+        let row = self.editor.main_caret().position.row;
+        let rewrite = match section_operation {
+            SectionOperation::Promote => markdown_heading_splitter::promote_section(&text, row),
+            SectionOperation::Demote => markdown_heading_splitter::demote_section(&text, row),
+            SectionOperation::MoveUp => markdown_heading_splitter::move_section_up(&text, row),
+            SectionOperation::MoveDown => markdown_heading_splitter::move_section_down(&text, row),
+        };
+        let Some(rewrite) = rewrite else {
+            return;
+        };
+        let Some(last_row) = rewrite.rows.end.checked_sub(1) else {
+            return;
+        };
+        let last_col = self
+            .editor
+            .buffer_chars()
+            .get(last_row)
+            .map(|line| line.len())
+            .unwrap_or_default();
+        // 一回の Undo で元に戻せるよう、セクションの範囲とキャレットの移動をまとめて置き換える
+        self.editor_operation(&EditorOperation::Replace(
+            CellPosition::new(rewrite.rows.start, 0)..CellPosition::new(last_row, last_col),
+            rewrite.lines.join("\n"),
+            CellPosition::new(rewrite.caret_row, 0),
+        ));
     }
 
     // editor から受け取ったイベントを TextEdit の caret, buffer_chars, instances に同期する。
@@ -638,14 +763,12 @@ impl TextEdit {
         for event in event.into_iter() {
             self.buffer_updated = true;
             // 変更イベントがバッファを変更するかどうかを判定する
-            if matches!(
-                event,
-                BulkedChangeEvent::SingleEvent(ChangeEvent::AddChar(_))
-                    | BulkedChangeEvent::SingleEvent(ChangeEvent::MoveChar { .. })
-                    | BulkedChangeEvent::SingleEvent(ChangeEvent::RemoveChar(_))
-                    | BulkedChangeEvent::MultipleMoveCharEvents(_)
-            ) {
+            if let Some(rows) = changed_rows(&event) {
                 self.text_updated = true;
+                self.highlight_changed_rows = Some(match self.highlight_changed_rows.take() {
+                    Some(changed) => changed.start.min(rows.start)..changed.end.max(rows.end),
+                    None => rows,
+                });
             }
 
             match event {
@@ -690,6 +813,7 @@ impl TextEdit {
                         c,
                         char_change_counter.remove_char,
                         &self.config,
+                        device,
                     );
                     char_change_counter.remove_char += 1;
                 }
@@ -762,13 +886,26 @@ impl TextEdit {
         &mut self,
         char_width_calcurator: Arc<dyn CharWidthResolver>,
     ) -> PhysicalLayout {
-        calc_editor_layout(
+        let max_display_width = self.max_display_width();
+        let mut layout = calc_editor_layout(
             &self.editor,
-            self.max_display_width(),
+            max_display_width,
             &self.config.line_prohibited_chars,
-            char_width_calcurator,
+            char_width_calcurator.clone(),
             self.preedit.as_ref().map(|p| p.preedit_string()),
-        )
+        );
+        // 見出しのように大きく描く行は、隣の文字や行に重ならないように間隔を広げる
+        reserve_scaled_rows(
+            &mut layout,
+            &self.row_scales,
+            max_display_width,
+            char_width_calcurator.as_ref(),
+        );
+        // 画像を表示する行は文字を置かないので、キャレットの上下の移動でも画像を一つのブロックとして飛び越える
+        self.image_states
+            .reserve_rows(&mut layout, max_display_width, &self.config);
+        self.scaled_rows = scaled_physical_rows(&layout, &self.row_scales);
+        layout
     }
 
     // レイアウト情報から bound の計算を行い更新する
@@ -780,6 +917,16 @@ impl TextEdit {
         });
         // 行末にメインキャレットだけある場合に画面外にキャレットがいかないように結果を補正する
         let (max_col, max_row) = (max_col, max_row.max(layout.main_caret_pos.row));
+        // 最後の行の画像や大きく描く行が収まるようにする
+        let max_row = max_row
+            .max(self.image_states.max_row().unwrap_or_default())
+            .max(
+                self.scaled_rows
+                    .iter()
+                    .map(|(row, extra)| row + extra)
+                    .max()
+                    .unwrap_or_default(),
+            );
         let (max_col, max_row) = if self.border != ModelBorder::None {
             // border がある場合は border の幅を考慮して bound を大きくする
             (max_col + 1, max_row + 1)
@@ -819,11 +966,27 @@ impl TextEdit {
         preedit_chars: &[BufferChar],
     ) {
         // update char position
-        layout.chars.iter().for_each(|(c, pos)| {
-            let width = char_width_calcurator.get_width(c.c);
-            let position =
-                Self::get_adjusted_position(&self.config, width, bound, [pos.col, pos.row]);
-            let position = Self::apply_render_anchor_offset(&self.config, position);
+        let positions: BTreeMap<CellPosition, [f32; 3]> = layout
+            .chars
+            .iter()
+            .map(|(c, pos)| {
+                let width = char_width_calcurator.get_width(c.c);
+                let position =
+                    Self::get_adjusted_position(&self.config, width, bound, [pos.col, pos.row]);
+                let position = self.apply_scaled_row_offset(position, pos.row);
+                (
+                    c.position,
+                    Self::apply_render_anchor_offset(&self.config, position),
+                )
+            })
+            .collect();
+        layout.chars.iter().for_each(|(c, _)| {
+            let Some(position) = self
+                .shaped_position(c, &positions)
+                .or_else(|| positions.get(&c.position).copied())
+            else {
+                return;
+            };
             self.char_states.update_state(
                 c,
                 &ViewElementStateUpdateRequest {
@@ -843,6 +1006,7 @@ impl TextEdit {
                 let width = char_width_calcurator.get_width(c.c);
                 let position =
                     Self::get_adjusted_position(&self.config, width, bound, [pos.col, pos.row]);
+                let position = self.apply_scaled_row_offset(position, pos.row);
                 let position = Self::apply_render_anchor_offset(&self.config, position);
                 self.char_states.update_state(
                     c,
@@ -864,6 +1028,7 @@ impl TextEdit {
                 bound,
                 [layout.main_caret_pos.col, layout.main_caret_pos.row],
             );
+            let position = self.apply_scaled_row_offset(position, layout.main_caret_pos.row);
             let position = Self::apply_render_anchor_offset(&self.config, position);
             self.caret_states.update_state_position_and_scale(
                 CaretType::Primary,
@@ -879,6 +1044,7 @@ impl TextEdit {
                 bound,
                 [mark_pos.col, mark_pos.row],
             );
+            let position = self.apply_scaled_row_offset(position, mark_pos.row);
             let position = Self::apply_render_anchor_offset(&self.config, position);
             self.caret_states.update_state_position_and_scale(
                 CaretType::Mark,
@@ -890,6 +1056,8 @@ impl TextEdit {
         if let Some(state) = self.border_states.as_mut() {
             state.update_state([0.0, 0.0, 0.0], bound, &self.config);
         }
+
+        self.image_states.update_state(&self.config);
     }
 
     /// レイアウトのセル位置を TextEdit 内の位置に変換する。direction や char_width を考慮している。
@@ -908,6 +1076,53 @@ impl TextEdit {
         }
     }
 
+    /// 大きく描く行の位置を、下に空けた行との中央にずらす
+    fn apply_scaled_row_offset(&self, [x, y, z]: [f32; 3], row: usize) -> [f32; 3] {
+        let Some(extra) = self.scaled_rows.get(&row) else {
+            return [x, y, z];
+        };
+        let offset = *extra as f32 / 2.0 * self.config.row_interval;
+        match self.config.direction {
+            Direction::Horizontal => [x, y - offset, z],
+            Direction::Vertical => [x - offset, y, z],
+        }
+    }
+
+    /// シェーピングで字形や位置が変わる文字の位置。クラスタの文字の中央からシェーピングの結果の分だけずらす
+    fn shaped_position(
+        &self,
+        c: &BufferChar,
+        positions: &BTreeMap<CellPosition, [f32; 3]>,
+    ) -> Option<[f32; 3]> {
+        let cell = self.shaped_text.cell(&c.position)?;
+        let cluster: Vec<[f32; 3]> = cell
+            .cluster
+            .clone()
+            .filter_map(|col| {
+                positions
+                    .get(&CellPosition {
+                        row: c.position.row,
+                        col,
+                    })
+                    .copied()
+            })
+            .collect();
+        if cluster.is_empty() {
+            return None;
+        }
+        let count = cluster.len() as f32;
+        let [x, y, z] = cluster
+            .iter()
+            .fold([0.0; 3], |[sx, sy, sz], [x, y, z]| [sx + x, sy + y, sz + z])
+            .map(|sum| sum / count);
+        // em を 1 としたずらし幅を、全角の文字の幅を 1 em として TextEdit 内の距離にする
+        let [along, cross] = cell.offset.map(|offset| offset * self.config.col_interval);
+        Some(match self.config.direction {
+            Direction::Horizontal => [x + along, y + cross, z],
+            Direction::Vertical => [x + cross, y - along, z],
+        })
+    }
+
     /// レンダリングアンカーのオフセットを適用する
     #[inline]
     fn apply_render_anchor_offset(config: &TextContext, [x, y, z]: [f32; 3]) -> [f32; 3] {
@@ -963,6 +1178,14 @@ impl TextEdit {
         if let Some(state) = self.border_states.as_mut() {
             state.update_instances(update_environment, &model_attributes);
         }
+
+        // update overlays
+        self.overlay_states
+            .update_instances(update_environment, &model_attributes);
+
+        // update images
+        self.image_states
+            .update_instances(update_environment, &model_attributes);
     }
 
     fn max_display_width(&self) -> usize {
@@ -1015,76 +1238,87 @@ impl TextEdit {
     }
 
     #[cfg(target_arch = "wasm32")]
-    fn highlight(&mut self) {}
+    fn highlight(&mut self, _context: &UiContext) {}
 
     #[cfg(not(target_arch = "wasm32"))]
     #[inline]
-    fn highlight(&mut self) {
+    fn highlight(&mut self, context: &UiContext) {
         use crate::ui_context::HighlightMode;
 
-        if self.config.highlight_mode != HighlightMode::Markdown {
-            return;
+        let changed_rows = self.highlight_changed_rows.take();
+        let dirty_rows = std::mem::take(&mut self.preview_dirty_rows);
+        let preview = self.config.highlight_mode == HighlightMode::MarkdownPreview;
+        if !preview && !self.row_scales.is_empty() {
+            self.row_scales.clear();
+            self.buffer_updated = true;
         }
+        let language = match &self.config.highlight_mode {
+            HighlightMode::None => return,
+            HighlightMode::Markdown | HighlightMode::MarkdownPreview => "markdown",
+            HighlightMode::Language(language) => language.as_str(),
+        };
 
-        // ハイライト情報を取得し、範囲順にソート
-        let mut highlight_ranges: Vec<_> = highlighter::markdown_highlight(
-            &self.editor.to_buffer_string(),
-            &highlighter::settings::HighlightSettings::default(),
-        )
-        .into_iter()
-        .map(|(category, range)| {
-            let attr = match category.as_str() {
-                "markdown.emphasis" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "markdown.list" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "markdown.literal" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "markdown.reference" => CharAttribute::new(ThemedColor::Magenta, Decoration::None),
-                "markdown.strong" => CharAttribute::new(ThemedColor::Green, Decoration::None),
-                "markdown.title" => CharAttribute::new(ThemedColor::Green, Decoration::None),
-                "markdown.uri" => CharAttribute::new(ThemedColor::Magenta, Decoration::None),
-                "markdown.checked" => CharAttribute::new(ThemedColor::Green, Decoration::None),
-                "markdown.unchecked" => CharAttribute::new(ThemedColor::Yellow, Decoration::None),
-                "markdown.block_quote" => {
-                    CharAttribute::new(ThemedColor::TextComment, Decoration::None)
-                }
-                "comment" => CharAttribute::new(ThemedColor::TextComment, Decoration::None),
-                "constant" => CharAttribute::new(ThemedColor::Blue, Decoration::None),
-                "constant.builtin" => CharAttribute::new(ThemedColor::Blue, Decoration::None),
-                "escape" => CharAttribute::new(ThemedColor::TextComment, Decoration::None),
-                "string.escape" => CharAttribute::new(ThemedColor::TextComment, Decoration::None),
-                "number" => CharAttribute::new(ThemedColor::Green, Decoration::None),
-                "string" => CharAttribute::new(ThemedColor::Green, Decoration::None),
-                "attribute" => CharAttribute::new(ThemedColor::Yellow, Decoration::None),
-                "function" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "function.builtin" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "function.macro" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "function.method" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "identifier" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "keyword" => CharAttribute::new(ThemedColor::Blue, Decoration::None),
-                "label" => CharAttribute::new(ThemedColor::TextComment, Decoration::None),
-                "operator" => CharAttribute::new(ThemedColor::TextEmphasized, Decoration::None),
-                "property" => CharAttribute::new(ThemedColor::Yellow, Decoration::None),
-                "punctuation.bracket" => CharAttribute::new(ThemedColor::Cyan, Decoration::None),
-                "type" => CharAttribute::new(ThemedColor::Green, Decoration::None),
-                "type.builtin" => CharAttribute::new(ThemedColor::Green, Decoration::None),
-                "variable" => CharAttribute::new(ThemedColor::Yellow, Decoration::None),
-                "variable.builtin" => CharAttribute::new(ThemedColor::Yellow, Decoration::None),
-                "variable.parameter" => CharAttribute::new(ThemedColor::Yellow, Decoration::None),
-                _ => CharAttribute::default(),
-            };
-            (range, attr)
-        })
-        .filter(|(_, attr)| *attr != CharAttribute::default())
-        .collect();
+        let lines = self.editor.buffer_chars();
+        // 作り直したハイライタにはすべての行を渡す
+        let changed_rows = if self.highlighter.is_none() {
+            self.highlighter =
+                highlighter::Highlighter::new(language, context.highlight_settings());
+            0..lines.len()
+        } else {
+            changed_rows.unwrap_or_default()
+        };
+        // 対応していない言語はハイライトしない
+        let Some(highlighter) = self.highlighter.as_mut() else {
+            return;
+        };
+        // テキスト全体を作り直さず、文字の変わった行だけをハイライタに渡す
+        let update = highlighter.update(changed_rows, lines.len(), |row| {
+            lines[row].iter().map(|c| c.c).collect()
+        });
+        let text = highlighter.text();
+        // 変更のあった範囲と、ライブプレビューでキャレットが出入りした行の文字だけ計算し直す
+        if update.changed_ranges.is_empty() && dirty_rows.is_empty() {
+            return;
+        }
+        let appearances = if preview {
+            let appearances = markdown_preview_appearances(text, self.preview_caret_row);
+            let scales = row_scales(text, &appearances);
+            if self.row_scales != scales {
+                self.row_scales = scales;
+                self.buffer_updated = true;
+            }
+            appearances
+        } else {
+            Vec::new()
+        };
 
-        // 範囲の開始位置でソート
-        highlight_ranges.sort_by_key(|(range, _)| range.start);
+        // 変更のあった範囲の外のハイライトも使うので、ハイライタの持つテキスト全体のハイライトを引く
+        let styles = context.editor_settings().highlight_styles;
+        let color_theme = *context.color_theme();
+        let highlight_ranges: Vec<_> = highlighter
+            .highlights()
+            .iter()
+            .map(|(category, range)| (range, styles.attribute(color_theme, category)))
+            .filter(|(_, attr)| *attr != CharAttribute::default())
+            .collect();
 
-        // 一回のループで処理
+        // ハイライトは範囲の開始位置の順に並んでいるので、一回のループで処理する
         let mut position = 0;
         let mut highlight_index = 0;
 
-        for line in self.editor.buffer_chars().iter() {
+        for (row, line) in lines.iter().enumerate() {
+            let dirty_row = dirty_rows.contains(&row);
             for c in line.iter() {
+                // 範囲の外側の文字は、移動しても前回の見た目を保っているのでそのままにする
+                if !dirty_row
+                    && !update
+                        .changed_ranges
+                        .iter()
+                        .any(|range| range.contains(&position))
+                {
+                    position += 1;
+                    continue;
+                }
                 let mut attr = CharAttribute::default();
 
                 // 現在の位置に適用されるハイライトを検索
@@ -1103,13 +1337,15 @@ impl TextEdit {
                     }
                 }
 
-                // TODO decoration の対応
-                let CharAttribute { color, .. } = attr;
-
-                let request = &ViewElementStateUpdateRequest {
-                    base_color: Some(color),
-                    ..Default::default()
+                let appearance = if preview {
+                    appearances.get(position).copied().unwrap_or_default()
+                } else {
+                    CharAppearance::from_attribute(attr)
                 };
+                let available_style = context
+                    .glyph_shaper()
+                    .available_style(c.c, appearance.font_style());
+                let request = &appearance.update_request(attr.color, available_style);
 
                 self.char_states.update_state(c, request, &self.config);
 
@@ -1123,6 +1359,10 @@ impl TextEdit {
     fn reset_highlight(&mut self) {
         let default_text = &ViewElementStateUpdateRequest {
             base_color: Some(ThemedColor::Text),
+            style_scale: Some([1.0, 1.0]),
+            slant: Some([0.0]),
+            overlay: Some(CharOverlay::None),
+            font_style: Some(FontStyle::REGULAR),
             ..Default::default()
         };
         self.editor.buffer_chars().iter().flatten().for_each(|c| {
@@ -1131,7 +1371,194 @@ impl TextEdit {
     }
 }
 
+// テキストを変更するイベントであれば、変更前と変更後の行を合わせた範囲を返す
+fn changed_rows(event: &BulkedChangeEvent) -> Option<Range<usize>> {
+    let rows = |from: usize, to: usize| from.min(to)..from.max(to) + 1;
+    match event {
+        BulkedChangeEvent::SingleEvent(ChangeEvent::AddChar(c))
+        | BulkedChangeEvent::SingleEvent(ChangeEvent::RemoveChar(c)) => {
+            Some(rows(c.position.row, c.position.row))
+        }
+        BulkedChangeEvent::SingleEvent(ChangeEvent::MoveChar { from, to }) => {
+            Some(rows(from.position.row, to.position.row))
+        }
+        BulkedChangeEvent::MultipleMoveCharEvents(events) => events
+            .iter()
+            .map(|(from, to)| rows(from.position.row, to.position.row))
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end)),
+        _ => None,
+    }
+}
+
 pub enum TextEditOperation {
     // テーマカラーを Range の範囲で設定する
     SetThemedColor(Range<CellPosition>, ThemedColor),
 }
+
+// 物理行で一つ上または下の行のうち、今の物理行の列に一番近い位置を返す。
+// 画像のために空けた行のように文字のない物理行に移るときは、論理行で一つ上または下の行に移る
+fn find_target_position_in_physical_layout(
+    layout: &PhysicalLayout,
+    lines: &[Vec<BufferChar>],
+    [logical_row, logical_col]: [usize; 2],
+    width_resolver: &dyn CharWidthResolver,
+    move_operation: PhysicalMoveOperation,
+) -> Option<CellPosition> {
+    let current_physical_pos = layout.main_caret_pos;
+    let target_row = match move_operation {
+        PhysicalMoveOperation::Previous => current_physical_pos.row.checked_sub(1),
+        PhysicalMoveOperation::Next => Some(current_physical_pos.row + 1),
+    }?;
+
+    let row_candidates = collect_row_candidates(layout, width_resolver, target_row);
+    if row_candidates.is_empty() {
+        return find_fallback_position(lines, move_operation, logical_row, logical_col);
+    }
+
+    match move_operation {
+        PhysicalMoveOperation::Previous | PhysicalMoveOperation::Next => row_candidates
+            .iter()
+            .min_by_key(|(_, col)| col.abs_diff(current_physical_pos.col))
+            .map(|(position, _)| *position),
+    }
+}
+
+fn collect_row_candidates(
+    layout: &PhysicalLayout,
+    width_resolver: &dyn CharWidthResolver,
+    target_row: usize,
+) -> Vec<(CellPosition, usize)> {
+    let mut candidates = Vec::new();
+    for (buffer_char, pos) in layout.chars.iter() {
+        if pos.row != target_row {
+            continue;
+        }
+        candidates.push((buffer_char.position, pos.col));
+        let char_width = width_resolver.resolve_width(buffer_char.c);
+        candidates.push((
+            CellPosition {
+                row: buffer_char.position.row,
+                col: buffer_char.position.col + 1,
+            },
+            pos.col + char_width,
+        ));
+    }
+    candidates
+}
+
+fn find_fallback_position(
+    lines: &[Vec<BufferChar>],
+    move_operation: PhysicalMoveOperation,
+    logical_row: usize,
+    logical_col: usize,
+) -> Option<CellPosition> {
+    match move_operation {
+        PhysicalMoveOperation::Previous => logical_row.checked_sub(1).and_then(|row| {
+            lines.get(row).map(|line| CellPosition {
+                row,
+                col: logical_col.min(line.len()),
+            })
+        }),
+        PhysicalMoveOperation::Next => {
+            let row = logical_row + 1;
+            lines.get(row).map(|line| CellPosition {
+                row,
+                col: logical_col.min(line.len()),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use phisical_layouter::LineBoundaryProhibitedChars;
+
+    use crate::ui::view_element_state::reserve_image_rows;
+
+    use super::*;
+
+    struct WidthResolver;
+
+    impl CharWidthResolver for WidthResolver {
+        fn resolve_width(&self, c: char) -> usize {
+            if c.is_ascii() { 1 } else { 2 }
+        }
+    }
+
+    // キャレットを caret に置き、論理行ごとに (論理行, 行数) の画像の行を空けたレイアウトで上下に移動した先を返す
+    fn move_caret(
+        text: &str,
+        caret: CellPosition,
+        images: &[(usize, usize)],
+        move_operation: PhysicalMoveOperation,
+    ) -> Option<CellPosition> {
+        let (sender, _receiver) = channel();
+        let mut editor = Editor::new(sender);
+        editor.operation(&EditorOperation::InsertString(text.to_string()));
+        editor.operation(&EditorOperation::MoveTo(Caret::new_without_event(
+            caret,
+            CaretType::Primary,
+        )));
+        let mut layout = calc_editor_layout(
+            &editor,
+            40,
+            &LineBoundaryProhibitedChars::default(),
+            Arc::new(WidthResolver),
+            None,
+        );
+        reserve_image_rows(&mut layout, images);
+        find_target_position_in_physical_layout(
+            &layout,
+            &editor.buffer_chars(),
+            [caret.row, caret.col],
+            &WidthResolver,
+            move_operation,
+        )
+    }
+
+    #[test]
+    fn test_move_caret_over_image_rows() {
+        let text = "あいう\n![図](a.png)\nかきく";
+        // 画像の無いときは物理行で一つ下の行に移る
+        assert_eq!(
+            move_caret(
+                text,
+                CellPosition::new(0, 1),
+                &[],
+                PhysicalMoveOperation::Next
+            ),
+            Some(CellPosition::new(1, 2))
+        );
+        // 画像のために空けた行は一つのブロックとして飛び越える
+        assert_eq!(
+            move_caret(
+                text,
+                CellPosition::new(1, 2),
+                &[(1, 3)],
+                PhysicalMoveOperation::Next
+            ),
+            Some(CellPosition::new(2, 2))
+        );
+        assert_eq!(
+            move_caret(
+                text,
+                CellPosition::new(2, 1),
+                &[(1, 3)],
+                PhysicalMoveOperation::Previous
+            ),
+            Some(CellPosition::new(1, 1))
+        );
+        // 画像より前の行の移動は変わらない
+        assert_eq!(
+            move_caret(
+                text,
+                CellPosition::new(1, 2),
+                &[(1, 3)],
+                PhysicalMoveOperation::Previous
+            ),
+            Some(CellPosition::new(0, 1))
+        );
+    }
+}
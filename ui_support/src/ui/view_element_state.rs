@@ -1,31 +1,48 @@
-use std::{collections::BTreeMap, collections::BTreeSet, ops::Range};
+use std::{
+    collections::BTreeMap,
+    collections::BTreeSet,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, Sender, channel},
+    time::SystemTime,
+};
 
+use font_collector::FontStyle;
 use glam::{Mat4, Quat, Vec3};
+use log::warn;
+use phisical_layouter::PhysicalLayout;
 use rand::RngExt;
 use text_buffer::{
     buffer::{BufferChar, CellPosition},
     caret::{Caret, CaretType},
 };
 use web_time::Duration;
-use wgpu::Device;
+use wgpu::{Device, Queue};
 
 use font_rasterizer::{
     char_width_calcurator::{CharWidth, CharWidthCalculator},
     color_theme::{ColorTheme, ThemedColor},
     glyph_vertex_buffer::Direction,
+    image_instances::ImageInstances,
     motion::MotionFlags,
     time::now_millis,
-    vector_instances::InstanceAttributes,
+    vector_instances::{InstanceAttributes, InstanceKey, VectorInstances},
 };
 
 use crate::{
     easing_value::EasingPointN,
     layout_engine::ModelAttributes,
-    text_instances::{BorderFragment, BorderInstances, BorderType, CaretInstances, TextInstances},
-    ui_context::{CharEasings, GpuEasingConfig, RemoveCharMode, TextContext},
+    text_instances::{
+        BorderFragment, BorderInstances, BorderType, CaretInstances, CharOverlay, OverlayInstances,
+        TextInstances,
+    },
+    ui_context::{CharEasings, GpuEasingConfig, RemoveCharMode, TextContext, UiContext},
 };
 
-use super::caret_char;
+use super::{
+    caret_char,
+    inline_image::{DecodedImage, ImageLink, decode_image, image_key, resolve_image_path},
+};
 
 #[derive(Default)]
 pub(crate) struct ViewElementStateUpdateRequest {
@@ -34,6 +51,13 @@ pub(crate) struct ViewElementStateUpdateRequest {
     pub(crate) color: Option<[f32; 3]>,
     pub(crate) scale: Option<[f32; 2]>,
     pub(crate) motion_gain: Option<[f32; 1]>,
+    // レイアウトで決まる scale に掛ける倍率。見出しを大きくするのに使う
+    pub(crate) style_scale: Option<[f32; 2]>,
+    // 斜体として傾ける角度(ラジアン)
+    pub(crate) slant: Option<[f32; 1]>,
+    pub(crate) overlay: Option<CharOverlay>,
+    // 文字を描くフォントのスタイル
+    pub(crate) font_style: Option<FontStyle>,
 }
 
 struct ViewElementState {
@@ -43,6 +67,7 @@ struct ViewElementState {
     pub(crate) color: EasingPointN<3>,
     pub(crate) scale: EasingPointN<2>,
     pub(crate) motion_gain: EasingPointN<1>,
+    pub(crate) style: CharStyle,
 }
 
 impl ViewElementState {
@@ -51,7 +76,34 @@ impl ViewElementState {
         let color_animation = self.color.in_animation();
         let scale_animation = self.scale.in_animation();
         let motion_gain_animation = self.motion_gain.in_animation();
-        position_animation | color_animation | scale_animation | motion_gain_animation
+        let slant_animation = self.style.slant.in_animation();
+        position_animation
+            | color_animation
+            | scale_animation
+            | motion_gain_animation
+            | slant_animation
+    }
+}
+
+// ハイライトで文字ごとに加える見た目の変化
+struct CharStyle {
+    // レイアウトで決まる scale。実際の scale はこれに style_scale を掛けたものになる
+    layout_scale: [f32; 2],
+    style_scale: [f32; 2],
+    slant: EasingPointN<1>,
+    overlay: CharOverlay,
+    font_style: FontStyle,
+}
+
+impl Default for CharStyle {
+    fn default() -> Self {
+        Self {
+            layout_scale: [1.0, 1.0],
+            style_scale: [1.0, 1.0],
+            slant: [0.0].into(),
+            overlay: CharOverlay::None,
+            font_style: FontStyle::REGULAR,
+        }
     }
 }
 
@@ -221,6 +273,10 @@ impl CharStates {
             color: easing_color,
             scale: easing_scale,
             motion_gain: easing_motion_gain,
+            style: CharStyle {
+                layout_scale: text_context.instance_scale(),
+                ..Default::default()
+            },
         };
         let instance = InstanceAttributes {
             color,
@@ -332,12 +388,54 @@ impl CharStates {
         if let Some(color) = update_request.color {
             c_pos.color.update(color);
         }
-        if let Some(scale) = update_request.scale {
-            c_pos.scale.update(scale);
+        if update_request.scale.is_some() || update_request.style_scale.is_some() {
+            if let Some(scale) = update_request.scale {
+                c_pos.style.layout_scale = scale;
+            }
+            if let Some(style_scale) = update_request.style_scale {
+                c_pos.style.style_scale = style_scale;
+            }
+            let [layout_x, layout_y] = c_pos.style.layout_scale;
+            let [style_x, style_y] = c_pos.style.style_scale;
+            c_pos.scale.update([layout_x * style_x, layout_y * style_y]);
         }
         if let Some(motion_gain) = update_request.motion_gain {
             c_pos.motion_gain.update(motion_gain);
         }
+        if let Some(slant) = update_request.slant {
+            c_pos.style.slant.update(slant);
+        }
+        if let Some(overlay) = update_request.overlay {
+            c_pos.style.overlay = overlay;
+        }
+        if let Some(font_style) = update_request.font_style {
+            c_pos.style.font_style = font_style;
+        }
+    }
+
+    // 通常とは違うフォントのスタイルで描く文字の位置とスタイル
+    pub(crate) fn font_styles(&self) -> BTreeMap<CellPosition, FontStyle> {
+        self.chars
+            .iter()
+            .filter(|(_, state)| state.style.font_style != FontStyle::REGULAR)
+            .map(|(c, state)| (c.position, state.style.font_style))
+            .collect()
+    }
+
+    // 図形を重ねる文字と、その図形、文字の位置と色
+    pub(crate) fn overlays(&self) -> Vec<(BufferChar, CharOverlay, [f32; 3], [f32; 3])> {
+        self.chars
+            .iter()
+            .filter(|(_, state)| state.style.overlay != CharOverlay::None)
+            .map(|(c, state)| {
+                (
+                    *c,
+                    state.style.overlay,
+                    state.position.last(),
+                    state.color.last(),
+                )
+            })
+            .collect()
     }
 
     pub(crate) fn update_char_theme(&mut self, color_theme: &ColorTheme) {
@@ -409,6 +507,7 @@ impl CharStates {
         c: BufferChar,
         counter: u32,
         text_context: &TextContext,
+        device: &Device,
     ) {
         if text_context.char_easings.remove_char_mode == RemoveCharMode::Immediate {
             self.chars.remove(&c);
@@ -432,7 +531,7 @@ impl CharStates {
             instance.motion = text_context.char_easings.remove_char.motion;
             instance.duration = text_context.char_easings.remove_char.duration;
         };
-        self.instances.pre_remove(&c.into());
+        self.instances.pre_remove(&c.into(), device);
     }
 
     // ゴミ箱の文字の削除モーションが完了しているものを削除する
@@ -617,6 +716,7 @@ impl CaretStates {
             color: easing_color,
             scale: easing_scale,
             motion_gain: easing_motion_gain,
+            style: CharStyle::default(),
         };
         if c.caret_type == CaretType::Primary {
             self.main_caret.replace((c, state));
@@ -831,6 +931,10 @@ fn update_instance(
     instance.rotation = match char_rotation {
         Some(r) => model_attribuetes.rotation * r,
         None => model_attribuetes.rotation,
+    };
+    let [slant] = view_char_state.style.slant.current();
+    if slant != 0.0 {
+        instance.rotation *= Quat::from_axis_angle(Vec3::Z, slant);
     }
 }
 
@@ -1038,6 +1142,7 @@ impl BorderStates {
             color: base_color.get_color(&text_context.color_theme).into(),
             scale: [1.0, 1.0].into(),
             motion_gain: [1.0].into(),
+            style: CharStyle::default(),
         };
         self.elements.insert(fragment.clone(), state);
         self.instances
@@ -1045,6 +1150,555 @@ impl BorderStates {
     }
 }
 
+/// 下線や箇条書きの黒丸など、文字に重ねて描く図形の 3 次元上の位置と画面上のインスタンスを管理する構造体
+#[derive(Default)]
+pub(crate) struct OverlayStates {
+    elements: BTreeMap<CellPosition, (CharOverlay, ViewElementState)>,
+    pub(crate) instances: OverlayInstances,
+}
+
+impl OverlayStates {
+    /// 文字に設定された図形に合わせて、図形を追加、移動、削除する。
+    /// 縦書きでは下線の向きが変わるため、今のところ図形を描かない
+    pub(crate) fn sync(
+        &mut self,
+        overlays: &[(BufferChar, CharOverlay, [f32; 3], [f32; 3])],
+        char_width_calcurator: &CharWidthCalculator,
+        text_context: &TextContext,
+        device: &Device,
+    ) {
+        let overlays = match text_context.direction {
+            Direction::Horizontal => overlays,
+            Direction::Vertical => &overlays[..0],
+        };
+
+        let next: BTreeMap<CellPosition, CharOverlay> = overlays
+            .iter()
+            .map(|(c, overlay, _, _)| (c.position, *overlay))
+            .collect();
+        let removed: Vec<(CellPosition, CharOverlay)> = self
+            .elements
+            .iter()
+            .filter(|(position, (overlay, _))| next.get(position) != Some(overlay))
+            .map(|(position, (overlay, _))| (*position, *overlay))
+            .collect();
+        for (position, overlay) in removed {
+            self.elements.remove(&position);
+            self.instances.remove(overlay, position);
+        }
+
+        for (c, overlay, char_position, color) in overlays.iter() {
+            let (position, scale) = Self::position_and_scale(
+                *overlay,
+                *char_position,
+                char_width_calcurator.get_width(c.c),
+                text_context,
+            );
+            match self.elements.get_mut(&c.position) {
+                Some((_, state)) => {
+                    update_easings(state, &text_context.char_easings);
+                    state.position.update(position);
+                    state.scale.update(scale);
+                    state.color.update(*color);
+                }
+                None => {
+                    let mut state = ViewElementState {
+                        base_color: ThemedColor::Text,
+                        in_selection: false,
+                        position: position.into(),
+                        color: (*color).into(),
+                        scale: scale.into(),
+                        motion_gain: [0.0].into(),
+                        style: CharStyle::default(),
+                    };
+                    update_easings(&mut state, &text_context.char_easings);
+                    self.elements.insert(c.position, (*overlay, state));
+                    self.instances.add(
+                        *overlay,
+                        c.position,
+                        InstanceAttributes {
+                            color: *color,
+                            ..InstanceAttributes::default()
+                        },
+                        device,
+                    );
+                }
+            }
+        }
+    }
+
+    // 文字の中心の位置から図形の位置と大きさを求める。線は文字の幅いっぱいに引く
+    fn position_and_scale(
+        overlay: CharOverlay,
+        [x, y, z]: [f32; 3],
+        char_width: CharWidth,
+        text_context: &TextContext,
+    ) -> ([f32; 3], [f32; 2]) {
+        let [scale_x, scale_y] = text_context.instance_scale();
+        let width = match char_width {
+            CharWidth::Regular => 0.5,
+            CharWidth::Wide => 1.0,
+        } * text_context.col_interval;
+        match overlay {
+            CharOverlay::Underline => ([x, y - text_context.row_scale * 0.5, z], [width, scale_y]),
+            CharOverlay::Strikethrough => ([x, y, z], [width, scale_y]),
+            CharOverlay::Bullet | CharOverlay::None => ([x, y, z], [scale_x, scale_y]),
+        }
+    }
+
+    pub(crate) fn update_instances(
+        &mut self,
+        update_environment: bool,
+        model_attribuetes: &ModelAttributes,
+    ) {
+        for (position, (overlay, state)) in self.elements.iter_mut() {
+            if !update_environment && !state.in_animation() {
+                continue;
+            }
+            if let Some(instance) = self.instances.get_mut(*overlay, *position) {
+                update_instance(instance, state, model_attribuetes, None);
+            }
+        }
+    }
+}
+
+// 画像の幅の上限 (全角の文字数)
+const IMAGE_MAX_CELLS: usize = 20;
+// 画像の高さの上限 (行数)
+const IMAGE_MAX_ROWS: usize = 12;
+
+// 画像のパスと、読み込み始めたときのファイルの更新日時と、読み込んだ画像
+type DecodeResult = (PathBuf, Option<SystemTime>, Option<DecodedImage>);
+
+// 画像の読み込みの状態
+enum ImageLoad {
+    Loading,
+    Loaded(DecodedImage),
+    // 読み込めなかった。ファイルの更新日時が変わったら読み込み直す
+    Failed(Option<SystemTime>),
+}
+
+/// Markdown の画像を表示する行と、画像の 3 次元上の位置と画面上のインスタンスを管理する構造体
+pub(crate) struct ImageStates {
+    // 相対パスで書かれた画像の基準にするディレクトリ
+    base_dir: Option<PathBuf>,
+    links: Vec<ImageLink>,
+    // リンクごとの画像。読み込みが終わるまでは表示しない
+    images: Vec<ImageState>,
+    loads: BTreeMap<PathBuf, ImageLoad>,
+    decoded_sender: Sender<DecodeResult>,
+    decoded_receiver: Receiver<DecodeResult>,
+}
+
+impl Default for ImageStates {
+    fn default() -> Self {
+        let (decoded_sender, decoded_receiver) = channel();
+        Self {
+            base_dir: None,
+            links: Vec::new(),
+            images: Vec::new(),
+            loads: BTreeMap::new(),
+            decoded_sender,
+            decoded_receiver,
+        }
+    }
+}
+
+struct ImageState {
+    link: ImageLink,
+    path: PathBuf,
+    // 画像を表示する物理行の範囲
+    rows: Range<usize>,
+    size: [f32; 2],
+    state: ViewElementState,
+    content: Option<ImageContent>,
+}
+
+enum ImageContent {
+    // SVG はベクターとして描く
+    Vector {
+        aspect: f32,
+        instances: VectorInstances<String>,
+    },
+    // ラスター画像はテクスチャを貼った四角形として描く
+    Texture {
+        aspect: f32,
+        instances: ImageInstances,
+    },
+}
+
+impl ImageContent {
+    fn new(key: String, decoded: &DecodedImage, context: &UiContext) -> Self {
+        let aspect = decoded.aspect();
+        let mut content = match decoded {
+            DecodedImage::Svg { .. } => ImageContent::Vector {
+                aspect,
+                instances: VectorInstances::new(key, context.device()),
+            },
+            DecodedImage::Raster(image) => ImageContent::Texture {
+                aspect,
+                instances: ImageInstances::new(key, image, context.device(), context.queue()),
+            },
+        };
+        content
+            .instances_mut()
+            .insert(InstanceKey::Monotonic(0), InstanceAttributes::default());
+        content
+    }
+
+    fn aspect(&self) -> f32 {
+        match self {
+            ImageContent::Vector { aspect, .. } | ImageContent::Texture { aspect, .. } => *aspect,
+        }
+    }
+
+    fn instances_mut(&mut self) -> &mut VectorInstances<String> {
+        match self {
+            ImageContent::Vector { instances, .. } => instances,
+            ImageContent::Texture { instances, .. } => instances.instances_mut(),
+        }
+    }
+}
+
+impl ImageStates {
+    /// 相対パスで書かれた画像の基準にするディレクトリを設定する。次の sync で画像を読み込み直す
+    pub(crate) fn set_base_dir(&mut self, base_dir: Option<PathBuf>) {
+        if self.base_dir != base_dir {
+            self.base_dir = base_dir;
+            self.links.clear();
+        }
+    }
+
+    /// 画像のリンクに合わせて画像の読み込みを始め、追加、削除する。表示する画像が変わったら true を返す
+    pub(crate) fn sync(&mut self, links: Vec<ImageLink>, context: &UiContext) -> bool {
+        if self.links == links && !self.has_stale_failure() {
+            return false;
+        }
+        self.links = links.clone();
+
+        let mut previous = std::mem::take(&mut self.images);
+        for link in links {
+            let path = resolve_image_path(self.base_dir.as_deref(), &link.path);
+            // 行が変わっただけの画像は、読み込み直さずに位置だけ変える
+            if let Some(index) = previous.iter().position(|image| image.path == path) {
+                let mut image = previous.remove(index);
+                image.link = link;
+                self.images.push(image);
+                continue;
+            }
+            self.images.push(ImageState {
+                link,
+                path,
+                rows: 0..0,
+                size: [0.0, 0.0],
+                state: ViewElementState {
+                    base_color: ThemedColor::Text,
+                    in_selection: false,
+                    position: [0.0, 0.0, 0.0].into(),
+                    color: ThemedColor::Text.get_color(context.color_theme()).into(),
+                    scale: [0.0, 0.0].into(),
+                    motion_gain: [0.0].into(),
+                    style: CharStyle::default(),
+                },
+                content: None,
+            });
+        }
+
+        // まだ読み込んでいない画像と、読み込めなかった後にファイルが変わった画像を読み込む
+        let paths: BTreeSet<PathBuf> = self
+            .images
+            .iter()
+            .filter(|image| image.content.is_none())
+            .map(|image| image.path.clone())
+            .collect();
+        for path in paths {
+            let need_load = match self.loads.get(&path) {
+                None => true,
+                Some(ImageLoad::Failed(modified)) => *modified != file_modified(&path),
+                Some(ImageLoad::Loading | ImageLoad::Loaded(_)) => false,
+            };
+            if need_load {
+                self.loads.insert(path.clone(), ImageLoad::Loading);
+                self.start_decode(path);
+            }
+        }
+        self.attach_contents(context);
+        true
+    }
+
+    // 読み込めなかった画像のうち、ファイルが変わったものがあるか
+    fn has_stale_failure(&self) -> bool {
+        self.images.iter().any(|image| {
+            matches!(
+                self.loads.get(&image.path),
+                Some(ImageLoad::Failed(modified)) if *modified != file_modified(&image.path)
+            )
+        })
+    }
+
+    // 画像を別のスレッドで読み込む。
+    // グリフの変換に使う rayon のスレッドを画像の読み込みで埋めないように、専用のスレッドを使う
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_decode(&self, path: PathBuf) {
+        let sender = self.decoded_sender.clone();
+        std::thread::spawn(move || {
+            let modified = file_modified(&path);
+            let decoded = decode_image(&path);
+            let _ = sender.send((path, modified, decoded));
+        });
+    }
+
+    // wasm ではスレッドを作れないので、その場で読み込む
+    #[cfg(target_arch = "wasm32")]
+    fn start_decode(&self, path: PathBuf) {
+        let modified = file_modified(&path);
+        let decoded = decode_image(&path);
+        let _ = self.decoded_sender.send((path, modified, decoded));
+    }
+
+    /// 読み込みが終わった画像を受け取り、表示できるようにする。表示する画像が増えたら true を返す
+    pub(crate) fn receive_decoded_images(&mut self, context: &UiContext) -> bool {
+        let mut received = false;
+        for (path, modified, decoded) in self.decoded_receiver.try_iter() {
+            let load = match decoded {
+                Some(decoded) => {
+                    if let DecodedImage::Svg { svg, .. } = &decoded {
+                        context.register_svg(image_key(&path), svg.clone());
+                    }
+                    ImageLoad::Loaded(decoded)
+                }
+                None => {
+                    warn!("Failed to load image: {}", path.display());
+                    ImageLoad::Failed(modified)
+                }
+            };
+            self.loads.insert(path, load);
+            received = true;
+        }
+        received && self.attach_contents(context)
+    }
+
+    // 読み込み済みの画像のインスタンスを作る。作ったら true を返す
+    fn attach_contents(&mut self, context: &UiContext) -> bool {
+        let mut attached = false;
+        for image in self
+            .images
+            .iter_mut()
+            .filter(|image| image.content.is_none())
+        {
+            if let Some(ImageLoad::Loaded(decoded)) = self.loads.get(&image.path) {
+                image.content = Some(ImageContent::new(image_key(&image.path), decoded, context));
+                attached = true;
+            }
+        }
+        attached
+    }
+
+    /// 画像のある行の下に画像の高さ分の行を空け、それより後ろの文字とキャレットを下にずらす。
+    /// 縦書きでは画像を描かない
+    pub(crate) fn reserve_rows(
+        &mut self,
+        layout: &mut PhysicalLayout,
+        max_line_width: usize,
+        text_context: &TextContext,
+    ) {
+        for image in self.images.iter_mut() {
+            image.rows = 0..0;
+        }
+        if text_context.direction == Direction::Vertical {
+            return;
+        }
+
+        let max_cells = (max_line_width / 2).clamp(1, IMAGE_MAX_CELLS);
+        let mut loaded: Vec<&mut ImageState> = self
+            .images
+            .iter_mut()
+            .filter(|image| image.content.is_some())
+            .collect();
+        let requests: Vec<(usize, usize)> = loaded
+            .iter_mut()
+            .map(|image| {
+                let aspect = image.content.as_ref().map_or(1.0, ImageContent::aspect);
+                let (size, row_count) = image_size(aspect, max_cells, text_context);
+                image.size = size;
+                (image.link.row, row_count)
+            })
+            .collect();
+        let ranges = reserve_image_rows(layout, &requests);
+        for (image, rows) in loaded.into_iter().zip(ranges) {
+            image.rows = rows;
+        }
+    }
+
+    /// 画像が使う最後の物理行
+    pub(crate) fn max_row(&self) -> Option<usize> {
+        self.images
+            .iter()
+            .filter(|image| !image.rows.is_empty())
+            .map(|image| image.rows.end - 1)
+            .max()
+    }
+
+    // 画像は左端に寄せ、空けた行の先頭から描く
+    pub(crate) fn update_state(&mut self, text_context: &TextContext) {
+        for image in self.images.iter_mut() {
+            let [width, height] = image.size;
+            let position = [
+                width / 2.0,
+                -(image.rows.start as f32) * text_context.row_interval - height / 2.0,
+                0.0,
+            ];
+            let scale = match image.content {
+                // SVG は長辺の長さが 1 になるように変換されている
+                Some(ImageContent::Vector { .. }) | None => {
+                    let long_side = width.max(height);
+                    [long_side, long_side]
+                }
+                // テクスチャを貼る四角形は一辺の長さが 1
+                Some(ImageContent::Texture { .. }) => [width, height],
+            };
+            update_easings(&mut image.state, &text_context.char_easings);
+            image.state.position.update(position);
+            image.state.scale.update(scale);
+            image
+                .state
+                .color
+                .update(ThemedColor::Text.get_color(&text_context.color_theme));
+        }
+    }
+
+    pub(crate) fn update_instances(
+        &mut self,
+        update_environment: bool,
+        model_attribuetes: &ModelAttributes,
+    ) {
+        for image in self.images.iter_mut() {
+            if !update_environment && !image.state.in_animation() {
+                continue;
+            }
+            let Some(content) = image.content.as_mut() else {
+                continue;
+            };
+            if let Some(instance) = content.instances_mut().get_mut(&InstanceKey::Monotonic(0)) {
+                update_instance(instance, &mut image.state, model_attribuetes, None);
+            }
+        }
+    }
+
+    pub(crate) fn update(&mut self, device: &Device, queue: &Queue) {
+        for content in self
+            .images
+            .iter_mut()
+            .filter_map(|image| image.content.as_mut())
+        {
+            content.instances_mut().update_buffer(device, queue);
+        }
+    }
+
+    pub(crate) fn to_instances(&self) -> Vec<&VectorInstances<String>> {
+        self.images
+            .iter()
+            .filter(|image| !image.rows.is_empty())
+            .filter_map(|image| match image.content.as_ref() {
+                Some(ImageContent::Vector { instances, .. }) => Some(instances),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn to_image_instances(&self) -> Vec<&ImageInstances> {
+        self.images
+            .iter()
+            .filter(|image| !image.rows.is_empty())
+            .filter_map(|image| match image.content.as_ref() {
+                Some(ImageContent::Texture { instances, .. }) => Some(instances),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn file_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// 画像ごとに、リンクのある論理行の最後の物理行の下へ画像の行数分の物理行を空け、
+/// それより後ろの文字とキャレットを下にずらす。
+/// images は (リンクのある論理行, 画像の行数) の並びで、画像ごとに空けた物理行の範囲を返す
+pub(crate) fn reserve_image_rows(
+    layout: &mut PhysicalLayout,
+    images: &[(usize, usize)],
+) -> Vec<Range<usize>> {
+    // 論理行ごとの最後の物理行
+    let mut line_ends: BTreeMap<usize, usize> = BTreeMap::new();
+    for (c, position) in layout.chars.iter() {
+        let end = line_ends.entry(c.position.row).or_default();
+        *end = (*end).max(position.row);
+    }
+
+    // 空けた行の直前の物理行と行数
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    let ranges = images
+        .iter()
+        .map(|(row, row_count)| {
+            let Some(end) = line_ends.get(row).copied() else {
+                return 0..0;
+            };
+            // 同じ行の画像や前の画像の分だけ、表示する行を後ろにずらす
+            let shift: usize = blocks
+                .iter()
+                .filter(|(block_end, _)| *block_end <= end)
+                .map(|(_, count)| count)
+                .sum();
+            let start = end + 1 + shift;
+            blocks.push((end, *row_count));
+            start..start + row_count
+        })
+        .collect();
+    shift_rows(layout, &blocks);
+    ranges
+}
+
+// 空けた行より後ろの文字とキャレットを下にずらす
+fn shift_rows(layout: &mut PhysicalLayout, blocks: &[(usize, usize)]) {
+    if blocks.is_empty() {
+        return;
+    }
+    let shifted = |row: usize| -> usize {
+        row + blocks
+            .iter()
+            .filter(|(end, _)| *end < row)
+            .map(|(_, count)| count)
+            .sum::<usize>()
+    };
+    for (_, position) in layout
+        .chars
+        .iter_mut()
+        .chain(layout.preedit_chars.iter_mut())
+    {
+        position.row = shifted(position.row);
+    }
+    layout.main_caret_pos.row = shifted(layout.main_caret_pos.row);
+    if let Some(mark_pos) = layout.mark_pos.as_mut() {
+        mark_pos.row = shifted(mark_pos.row);
+    }
+}
+
+// 画像の幅を max_cells 文字分にし、高さが上限を超えるときは縮める。大きさと使う行数を返す
+fn image_size(aspect: f32, max_cells: usize, text_context: &TextContext) -> ([f32; 2], usize) {
+    let width = max_cells as f32 * text_context.col_interval;
+    let max_height = IMAGE_MAX_ROWS as f32 * text_context.row_interval;
+    let [width, height] = if width * aspect > max_height {
+        [max_height / aspect, max_height]
+    } else {
+        [width, width * aspect]
+    };
+    let rows = (height / text_context.row_interval).ceil().max(1.0) as usize;
+    ([width, height], rows)
+}
+
 fn update_easings(view_char_state: &mut ViewElementState, char_easings: &CharEasings) {
     view_char_state.position.update_duration_and_easing_func(
         char_easings.position_easing.duration,
@@ -1062,4 +1716,62 @@ fn update_easings(view_char_state: &mut ViewElementState, char_easings: &CharEas
         char_easings.motion_gain_easing.duration,
         char_easings.motion_gain_easing.easing_func,
     );
+    view_char_state.style.slant.update_duration_and_easing_func(
+        char_easings.scale_easing.duration,
+        char_easings.scale_easing.easing_func,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use phisical_layouter::PhysicalPosition;
+    use text_buffer::buffer::{BufferChar, CellPosition};
+
+    use super::*;
+
+    fn layout_char(
+        row: usize,
+        col: usize,
+        c: char,
+        physical: [usize; 2],
+    ) -> (BufferChar, PhysicalPosition) {
+        (
+            BufferChar {
+                position: CellPosition::new(row, col),
+                c,
+            },
+            PhysicalPosition {
+                row: physical[0],
+                col: physical[1],
+            },
+        )
+    }
+
+    #[test]
+    fn test_reserve_image_rows() {
+        // 2 行目は 2 つの物理行に折り返している
+        let mut layout = PhysicalLayout {
+            chars: vec![
+                layout_char(0, 0, 'あ', [0, 0]),
+                layout_char(1, 0, 'い', [1, 0]),
+                layout_char(1, 1, 'う', [2, 0]),
+                layout_char(2, 0, 'え', [3, 0]),
+            ],
+            preedit_chars: vec![],
+            main_caret_pos: PhysicalPosition { row: 3, col: 2 },
+            mark_pos: Some(PhysicalPosition { row: 0, col: 0 }),
+        };
+        // 同じ行に 2 つの画像があり、最後の画像はリンクのある行が無い
+        let ranges = reserve_image_rows(&mut layout, &[(0, 3), (1, 2), (1, 1), (5, 1)]);
+        assert_eq!(ranges, vec![1..4, 6..8, 8..9, 0..0]);
+
+        let rows: Vec<usize> = layout
+            .chars
+            .iter()
+            .map(|(_, position)| position.row)
+            .collect();
+        assert_eq!(rows, vec![0, 4, 5, 9]);
+        assert_eq!(layout.main_caret_pos, PhysicalPosition { row: 9, col: 2 });
+        assert_eq!(layout.mark_pos, Some(PhysicalPosition { row: 0, col: 0 }));
+    }
 }
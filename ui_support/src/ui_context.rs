@@ -10,6 +10,7 @@ use font_rasterizer::{
     context::{StateContext, WindowSize},
     glyph_vertex_buffer::Direction,
     motion::{CameraDetail, EasingFuncType, MotionDetail, MotionFlags, MotionTarget, MotionType},
+    shaping::{GlyphKey, GlyphShaper},
 };
 use glam::Vec2;
 use phisical_layouter::LineBoundaryProhibitedChars;
@@ -750,9 +751,23 @@ pub struct TextContext {
 pub enum HighlightMode {
     None,
     Markdown,
+    /// Markdown をハイライトし、さらにキャレットの無い行の記法の文字を目立たなくして
+    /// 見出しや強調、箇条書き、リンクを装飾する。テキストそのものは変えない
+    MarkdownPreview,
     Language(String),
 }
 
+impl HighlightMode {
+    /// ファイルの拡張子からハイライトモードを推測する。推測できなければ None を返す
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        highlighter::language_from_path(path).map(|language| match language {
+            "markdown" => HighlightMode::Markdown,
+            language => HighlightMode::Language(language.to_string()),
+        })
+    }
+}
+
 const TEXT_CONTEXT_ZERO_BOUND: Vec2 = Vec2::new(0.0, 0.0);
 const TEXT_CONTEXT_DEFAULT_BOUND: Vec2 = Vec2::new(10.0, 5.0);
 
@@ -815,6 +830,12 @@ impl TextContext {
         self
     }
 
+    #[inline]
+    pub fn with_hyde_caret(mut self, hyde_caret: bool) -> Self {
+        self.hyde_caret = hyde_caret;
+        self
+    }
+
     #[inline]
     pub fn set_char_easings_preset(&mut self, preset: CharEasingsPreset) {
         self.char_easings = CharEasings::from_preset(preset);
@@ -840,6 +861,7 @@ impl TextContext {
 pub struct Senders {
     ui_string_sender: Sender<String>,
     ui_svg_sender: Sender<(String, String)>,
+    ui_glyph_sender: Sender<Vec<GlyphKey>>,
     action_queue_sender: Sender<Action>,
     post_action_queue_sender: Sender<Action>,
 }
@@ -848,12 +870,14 @@ impl Senders {
     pub fn new(
         ui_string_sender: Sender<String>,
         ui_svg_sender: Sender<(String, String)>,
+        ui_glyph_sender: Sender<Vec<GlyphKey>>,
         action_queue_sender: Sender<Action>,
         post_action_queue_sender: Sender<Action>,
     ) -> Self {
         Self {
             ui_string_sender,
             ui_svg_sender,
+            ui_glyph_sender,
             action_queue_sender,
             post_action_queue_sender,
         }
@@ -866,6 +890,8 @@ pub struct UiContext {
     state_context: StateContext,
     senders: Senders,
     editor_settings: Arc<RwLock<EditorSettings>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    highlight_settings: Arc<RwLock<highlighter::settings::HighlightSettings>>,
 }
 
 impl UiContext {
@@ -879,6 +905,8 @@ impl UiContext {
             state_context,
             senders,
             editor_settings: Arc::new(RwLock::new(editor_settings)),
+            #[cfg(not(target_arch = "wasm32"))]
+            highlight_settings: Arc::new(RwLock::new(Default::default())),
         }
     }
 
@@ -899,6 +927,12 @@ impl UiContext {
         &self.state_context.char_width_calcurator
     }
 
+    /// 行などをまとめてシェーピングするためのもの。フォントが変わると別のものに置き換わる
+    #[inline]
+    pub fn glyph_shaper(&self) -> &Arc<GlyphShaper> {
+        &self.state_context.glyph_shaper
+    }
+
     #[inline]
     pub fn color_theme(&self) -> &ColorTheme {
         &self.state_context.color_theme
@@ -952,6 +986,26 @@ impl UiContext {
         update(&mut editor_settings);
     }
 
+    /// TextEdit がハイライトに使う定義
+    #[cfg(not(target_arch = "wasm32"))]
+    #[inline]
+    pub fn highlight_settings(&self) -> highlighter::settings::HighlightSettings {
+        self.highlight_settings
+            .read()
+            .expect("highlight settings lock poisoned")
+            .clone()
+    }
+
+    /// ハイライトの定義を差し替える。既に作られている TextEdit には反映されない
+    #[cfg(not(target_arch = "wasm32"))]
+    #[inline]
+    pub fn set_highlight_settings(&self, settings: highlighter::settings::HighlightSettings) {
+        *self
+            .highlight_settings
+            .write()
+            .expect("highlight settings lock poisoned") = settings;
+    }
+
     #[inline]
     pub fn text_context(&self, profile: EditorTextContextProfile) -> TextContext {
         self.editor_settings().text_context(profile)
@@ -974,6 +1028,15 @@ impl UiContext {
         }
     }
 
+    /// シェーピングで求めたグリフを描画できるように登録する
+    #[inline]
+    pub fn register_glyphs(&self, keys: Vec<GlyphKey>) {
+        match self.senders.ui_glyph_sender.send(keys) {
+            Ok(_) => {}
+            Err(err) => warn!("Failed to send glyphs: {}", err),
+        }
+    }
+
     #[inline]
     pub fn register_action(&self, action: Action) {
         match self.senders.action_queue_sender.send(action) {